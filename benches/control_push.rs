@@ -0,0 +1,84 @@
+// 控制包立即发送的基准测试 (`nodelay_control_packets`)
+// 本地回环上先写一个 PUBLISH 再写 PINGREQ, 测量到收到 PINGRESP 的往返时间:
+// 只用 Nagle 时 PINGREQ 要等 PUBLISH 的 ACK (对端延迟 ACK), 立即发送时 PINGREQ 随即发出
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// clippy --all-targets 以 cfg(test) 编译基准测试, 被引入模块的测试的导入在这里没有使用
+#[allow(dead_code, unused_imports)]
+#[path = "../src/codec.rs"]
+mod codec;
+#[allow(dead_code)]
+#[path = "../src/tap.rs"]
+mod tap;
+#[path = "../src/control_push.rs"]
+mod control_push;
+
+use control_push::{ControlPush, PushSocket};
+
+const PINGREQ: [u8; 2] = [0xC0, 0x00];
+const PINGRESP: [u8; 2] = [0xD0, 0x00];
+
+/// QoS 0 PUBLISH, 主题 "bench", 64 字节负载
+fn publish() -> Vec<u8> {
+    let mut variable = vec![0x00, 0x05];
+    variable.extend_from_slice(b"bench");
+    variable.extend_from_slice(&[0xAB; 64]);
+    let mut packet = vec![0x30, variable.len() as u8];
+    packet.extend_from_slice(&variable);
+    packet
+}
+
+/// 建立一条回环连接 (保持 Nagle), 对端每收到一个 PUBLISH 和 PINGREQ 回复一个 PINGRESP
+fn connect(request_len: usize) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut peer, _) = listener.accept().unwrap();
+    thread::spawn(move || {
+        let mut request = vec![0u8; request_len];
+        while peer.read_exact(&mut request).is_ok() {
+            if peer.write_all(&PINGRESP).is_err() {
+                break;
+            }
+        }
+    });
+    client
+}
+
+/// 一次往返: 分两次写 PUBLISH 和 PINGREQ, 读到 PINGRESP 为止
+fn round_trip(stream: &mut TcpStream, publish: &[u8], mut push: Option<&mut ControlPush>) {
+    for chunk in [publish, &PINGREQ] {
+        stream.write_all(chunk).unwrap();
+        // 与转发循环相同: 每块数据写出后检测, 含控制包时立即发送
+        if let Some(push) = push.as_deref_mut()
+            && push.has_control_packet(chunk)
+        {
+            push.push();
+        }
+    }
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).unwrap();
+}
+
+fn control_packet_rtt(c: &mut Criterion) {
+    let publish = publish();
+    let mut group = c.benchmark_group("control_packet_rtt");
+    // 只用 Nagle 时每次往返可能等待一个延迟 ACK (Linux 上约 40 ms)
+    group.sample_size(20).measurement_time(Duration::from_secs(10));
+
+    let mut stream = connect(publish.len() + PINGREQ.len());
+    group.bench_function("nagle", |b| b.iter(|| round_trip(&mut stream, &publish, None)));
+
+    let mut stream = connect(publish.len() + PINGREQ.len());
+    let mut push = ControlPush::new(PushSocket::new(&stream).unwrap());
+    group.bench_function("push", |b| b.iter(|| round_trip(&mut stream, &publish, Some(&mut push))));
+
+    group.finish();
+}
+
+criterion_group!(benches, control_packet_rtt);
+criterion_main!(benches);
@@ -0,0 +1,131 @@
+// 空闲检测方式的基准测试 (`idle_reaper`)
+// 登记大量活跃连接 (每个连接一个等待空闲的任务, 与转发循环相同), 测量检测本身的开销:
+// - CPU: tokio 时钟暂停并自动前进, 一次迭代模拟 60 个 idle_timeout_ms, 耗时即 sweep 扫描或 timer 重新计时的 CPU 时间
+//   (空闲判断使用真实时钟, 迭代远短于超时, 连接不会被判定空闲)
+// - 内存: 计数分配器统计登记连接并启动等待任务后每个连接占用的堆内存, 运行前打印
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+#[allow(dead_code)]
+#[path = "../src/idle.rs"]
+mod idle;
+#[allow(dead_code)]
+#[path = "../src/pause.rs"]
+mod pause;
+
+// 空闲检测只用到这三个配置项
+mod adapter_config {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum IdleReaperMode {
+        Sweep,
+        Timer,
+    }
+
+    pub struct AdapterConfig {
+        pub idle_timeout_ms: u64,
+        pub idle_reaper: IdleReaperMode,
+        pub reaper_interval_ms: u64,
+    }
+}
+
+use adapter_config::{AdapterConfig, IdleReaperMode};
+use idle::IdleReaper;
+
+/// 当前已分配的堆内存
+struct CountingAllocator;
+
+static ALLOCATED: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CONNECTIONS: u64 = 10_000;
+/// idle_timeout_ms 和 reaper_interval_ms
+const TIMEOUT_MS: u64 = 10_000;
+/// 一次迭代模拟的时间
+const WINDOW: Duration = Duration::from_millis(60 * TIMEOUT_MS);
+
+/// 时钟暂停的单线程运行时, 没有就绪任务时时钟直接前进到下一个定时器
+fn paused_runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_time().start_paused(true).build().unwrap()
+}
+
+/// 登记 `CONNECTIONS` 个连接并启动扫描任务和每个连接的等待任务, 返回所有任务
+fn register(mode: IdleReaperMode) -> Vec<JoinHandle<()>> {
+    let config = AdapterConfig { idle_timeout_ms: TIMEOUT_MS, idle_reaper: mode, reaper_interval_ms: TIMEOUT_MS };
+    let reaper: Arc<IdleReaper> = IdleReaper::from_config(&config).unwrap().unwrap();
+    let mut tasks = vec![tokio::spawn(reaper.clone().run_sweeper())];
+    for id in 0..CONNECTIONS {
+        let handle = reaper.register(id, None);
+        tasks.push(tokio::spawn(async move {
+            handle.expired().await;
+            panic!("active connection {} reaped", id);
+        }));
+    }
+    tasks
+}
+
+/// 登记连接并让每个任务运行到第一次等待, 返回每个连接的堆内存
+fn bytes_per_connection(mode: IdleReaperMode) -> isize {
+    let runtime = paused_runtime();
+    runtime.block_on(async {
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let tasks = register(mode);
+        tokio::task::yield_now().await;
+        let used = ALLOCATED.load(Ordering::Relaxed) - before;
+        for task in tasks {
+            task.abort();
+        }
+        used / CONNECTIONS as isize
+    })
+}
+
+/// 模拟 `WINDOW` 的时间, 返回其间实际耗费的时间 (不含登记)
+fn reap_window(mode: IdleReaperMode) -> Duration {
+    let runtime = paused_runtime();
+    runtime.block_on(async {
+        let tasks = register(mode);
+        tokio::task::yield_now().await;
+        let start = Instant::now();
+        tokio::time::sleep(WINDOW).await;
+        let elapsed = start.elapsed();
+        for task in tasks {
+            task.abort();
+        }
+        elapsed
+    })
+}
+
+fn idle_reaper(c: &mut Criterion) {
+    for (name, mode) in [("sweep", IdleReaperMode::Sweep), ("timer", IdleReaperMode::Timer)] {
+        println!("idle_reaper/{}: {} bytes per connection", name, bytes_per_connection(mode));
+    }
+
+    let mut group = c.benchmark_group("idle_reaper");
+    group.sample_size(20).measurement_time(Duration::from_secs(10));
+    for (name, mode) in [("sweep", IdleReaperMode::Sweep), ("timer", IdleReaperMode::Timer)] {
+        group.bench_function(name, |b| b.iter_custom(|iters| (0..iters).map(|_| reap_window(mode)).sum()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, idle_reaper);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+// passthrough 与 smart 监听器的基准测试 (`mode = "passthrough"`)
+// 本地回环上客户端经代理连接模拟后端, 发送 CONNECT 并等待 CONNACK, 测量每个连接的握手时间:
+// passthrough 从第一个字节起原样转发; smart 先识别协议、读出并解析 CONNECT、重新编码后转发
+// 只模拟两种模式握手路径的差别, 不包含适配器的其它功能 (认证、路由、统计等)
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+// clippy --all-targets 以 cfg(test) 编译基准测试, 被引入模块的测试的导入在这里没有使用
+#[allow(dead_code, unused_imports)]
+#[path = "../src/codec.rs"]
+mod codec;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/properties.rs"]
+mod properties;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/connect_packet.rs"]
+mod connect_packet;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/classify.rs"]
+mod classify;
+#[allow(dead_code)]
+#[path = "../src/prefixed_stream.rs"]
+mod prefixed_stream;
+
+use classify::{Protocol, read_and_classify};
+use codec::{encode_packet, read_remaining_length};
+use connect_packet::{encode_connect, parse_connect};
+use prefixed_stream::PrefixedStream;
+
+/// MQTT 3.1.1 CONNECT, 客户端 ID "bench"
+const CONNECT: [u8; 19] =
+    [0x10, 0x11, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3C, 0x00, 0x05, b'b', b'e', b'n', b'c', b'h'];
+const CONNACK: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+
+/// 读一个完整的包 (固定头之后的部分)
+async fn read_packet<R: tokio::io::AsyncRead + Unpin>(stream: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut first_byte = [0u8; 1];
+    stream.read_exact(&mut first_byte).await?;
+    let length = read_remaining_length(stream).await?;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+/// 模拟后端: 每个连接读 CONNECT, 回复 CONNACK, 等待关闭
+async fn backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                if read_packet(&mut stream).await.is_ok() && stream.write_all(&CONNACK).await.is_ok() {
+                    let mut rest = [0u8; 1];
+                    let _ = stream.read(&mut rest).await;
+                }
+            });
+        }
+    });
+    address
+}
+
+/// passthrough: 连接后端, 从第一个字节起双向转发
+async fn passthrough(mut client: TcpStream, backend: SocketAddr) -> std::io::Result<()> {
+    let mut broker = TcpStream::connect(backend).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut broker).await?;
+    Ok(())
+}
+
+/// smart: 识别协议, 读出并解析 CONNECT, 重新编码后发给后端, 再双向转发
+async fn smart(client: TcpStream, backend: SocketAddr) -> std::io::Result<()> {
+    let mut client = client;
+    let Some((protocol, peeked)) = read_and_classify(&mut client).await? else {
+        return Ok(());
+    };
+    if protocol != Protocol::Mqtt {
+        return Ok(());
+    }
+    let mut client = PrefixedStream::new(peeked, client);
+    let payload = read_packet(&mut client).await?;
+    let connect = parse_connect(&payload)?;
+    let connect = encode_packet(0x10, &encode_connect(&payload, &connect)?);
+    let mut broker = TcpStream::connect(backend).await?;
+    broker.write_all(&connect).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut broker).await?;
+    Ok(())
+}
+
+/// 启动代理, 每个连接一个任务
+async fn proxy<F, Fut>(handle: F) -> SocketAddr
+where
+    F: Fn(TcpStream, SocketAddr) -> Fut + Send + 'static,
+    Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+{
+    let backend = backend().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream, backend));
+        }
+    });
+    address
+}
+
+/// 一次握手: 连接代理, 发送 CONNECT, 读到 CONNACK 为止
+async fn handshake(address: SocketAddr) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(&CONNECT).await.unwrap();
+    let mut connack = [0u8; CONNACK.len()];
+    stream.read_exact(&mut connack).await.unwrap();
+}
+
+fn connect_handshake(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("connect_handshake");
+    group.measurement_time(Duration::from_secs(10));
+
+    let address = runtime.block_on(proxy(passthrough));
+    group.bench_function("passthrough", |b| b.iter(|| runtime.block_on(handshake(address))));
+
+    let address = runtime.block_on(proxy(smart));
+    group.bench_function("smart", |b| b.iter(|| runtime.block_on(handshake(address))));
+
+    group.finish();
+}
+
+criterion_group!(benches, connect_handshake);
+criterion_main!(benches);
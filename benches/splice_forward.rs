@@ -0,0 +1,120 @@
+// splice 零拷贝转发的基准测试 (`splice_forward`, 仅 Linux, 需要 --features splice)
+// 本地回环上发送端经转发任务向接收端持续发送数据, 测量每搬运 16 MiB 整个进程耗费的 CPU 时间 (用户态 + 内核态):
+// read/write 循环 (与 forward_loop 相同的 8 KiB 缓冲区) 或经由管道 splice; 发送端和接收端的开销两者相同
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+#[allow(dead_code)]
+#[path = "../src/prefixed_stream.rs"]
+mod prefixed_stream;
+#[allow(dead_code)]
+#[path = "../src/splice.rs"]
+mod splice;
+
+/// 每次迭代搬运的字节数
+const TOTAL: usize = 16 * 1024 * 1024;
+const CHUNK: usize = 64 * 1024;
+
+/// 建立一对回环连接, 返回 (一端, 另一端)
+async fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let connect = TcpStream::connect(listener.local_addr().unwrap());
+    let (connected, accepted) = tokio::join!(connect, listener.accept());
+    (connected.unwrap(), accepted.unwrap().0)
+}
+
+/// read/write 循环转发
+async fn copy_loop(mut from: TcpStream, mut to: TcpStream) {
+    let mut buffer = [0u8; 8192];
+    while let Ok(n) = from.read(&mut buffer).await {
+        if n == 0 || to.write_all(&buffer[..n]).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// 经由管道 splice 转发
+async fn splice_loop(from: TcpStream, to: TcpStream) {
+    let mut pipe = splice::Pipe::new().unwrap();
+    while let Ok(n) = pipe.fill(&from).await {
+        if n == 0 || pipe.drain(&to, n).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// 进程已耗费的 CPU 时间
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let time = |t: libc::timeval| Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64);
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+/// 启动转发任务, 返回 (发送端, 接收端)
+async fn forwarding<F, Fut>(forward: F) -> (TcpStream, TcpStream)
+where
+    F: FnOnce(TcpStream, TcpStream) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (sender, forward_in) = socket_pair().await;
+    let (forward_out, receiver) = socket_pair().await;
+    tokio::spawn(forward(forward_in, forward_out));
+    (sender, receiver)
+}
+
+/// 发送并接收 `TOTAL` 字节
+async fn transfer(sender: &mut TcpStream, receiver: &mut TcpStream, chunk: &[u8]) {
+    let send = async {
+        for _ in 0..TOTAL / CHUNK {
+            sender.write_all(chunk).await.unwrap();
+        }
+    };
+    let receive = async {
+        let mut buffer = vec![0u8; CHUNK];
+        let mut received = 0;
+        while received < TOTAL {
+            received += receiver.read(&mut buffer).await.unwrap();
+        }
+    };
+    tokio::join!(send, receive);
+}
+
+fn forward_cpu(c: &mut Criterion) {
+    assert!(splice::available(), "splice is not available on this kernel");
+    let runtime = Runtime::new().unwrap();
+    let chunk = vec![0xABu8; CHUNK];
+    let mut group = c.benchmark_group("forward_cpu");
+    group.throughput(Throughput::Bytes(TOTAL as u64)).sample_size(20).measurement_time(Duration::from_secs(10));
+
+    let (mut sender, mut receiver) = runtime.block_on(forwarding(copy_loop));
+    group.bench_function("read_write_loop", |b| {
+        b.iter_custom(|iters| {
+            let start = cpu_time();
+            for _ in 0..iters {
+                runtime.block_on(transfer(&mut sender, &mut receiver, &chunk));
+            }
+            cpu_time() - start
+        })
+    });
+
+    let (mut sender, mut receiver) = runtime.block_on(forwarding(splice_loop));
+    group.bench_function("splice", |b| {
+        b.iter_custom(|iters| {
+            let start = cpu_time();
+            for _ in 0..iters {
+                runtime.block_on(transfer(&mut sender, &mut receiver, &chunk));
+            }
+            cpu_time() - start
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, forward_cpu);
+criterion_main!(benches);
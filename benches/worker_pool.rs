@@ -0,0 +1,117 @@
+// 连接工作池的基准测试 (`worker_pool_size`)
+// 本地回环上同时发起一批连接 (连接风暴), 每个连接发送 CONNECT、等待 CONNACK 后关闭,
+// 测量整批连接处理完的时间: 每个连接一个任务, 或放入固定大小的工作池
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+#[path = "../src/worker_pool.rs"]
+mod worker_pool;
+
+use worker_pool::WorkerPool;
+
+// 工作池只用到这两个计数
+mod metrics {
+    use std::sync::atomic::AtomicU64;
+
+    pub struct Metrics {
+        pub worker_pool_queued: AtomicU64,
+        pub worker_pool_busy: AtomicU64,
+    }
+
+    static METRICS: Metrics = Metrics { worker_pool_queued: AtomicU64::new(0), worker_pool_busy: AtomicU64::new(0) };
+
+    pub fn metrics() -> &'static Metrics {
+        &METRICS
+    }
+}
+
+/// 同时发起的连接数
+const STORM: usize = 500;
+/// 工作池大小
+const POOL_SIZE: usize = 64;
+
+/// MQTT 3.1.1 CONNECT, 客户端 ID "b"
+const CONNECT: [u8; 15] = [0x10, 0x0D, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3C, 0x00, 0x01, b'b'];
+const CONNACK: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+
+/// 一个连接的处理: 读 CONNECT, 回复 CONNACK, 等待客户端关闭
+async fn handle(mut stream: TcpStream) {
+    let mut connect = [0u8; CONNECT.len()];
+    if stream.read_exact(&mut connect).await.is_err() {
+        return;
+    }
+    if stream.write_all(&CONNACK).await.is_err() {
+        return;
+    }
+    let mut rest = [0u8; 1];
+    let _ = stream.read(&mut rest).await;
+}
+
+/// 启动 accept 循环, 每个连接交给 `dispatch`
+async fn listen<F, Fut>(dispatch: F) -> SocketAddr
+where
+    F: Fn(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    // backlog 容纳整批连接, 工作池暂停 accept 时客户端不会被拒绝
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener: TcpListener = socket.listen(STORM as u32).unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            dispatch(stream).await;
+        }
+    });
+    address
+}
+
+/// 一次连接风暴: 所有客户端同时连接, 等待全部收到 CONNACK
+async fn storm(address: SocketAddr) {
+    let clients: Vec<_> = (0..STORM)
+        .map(|_| {
+            tokio::spawn(async move {
+                let mut stream = TcpStream::connect(address).await.unwrap();
+                stream.write_all(&CONNECT).await.unwrap();
+                let mut connack = [0u8; CONNACK.len()];
+                stream.read_exact(&mut connack).await.unwrap();
+            })
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+}
+
+fn connect_storm(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("connect_storm");
+    group.sample_size(20).measurement_time(Duration::from_secs(10));
+
+    let address = runtime.block_on(listen(|stream| async move {
+        tokio::spawn(handle(stream));
+    }));
+    group.bench_function("spawn_per_connection", |b| b.iter(|| runtime.block_on(storm(address))));
+
+    let address = runtime.block_on(async {
+        let pool = Arc::new(WorkerPool::new(POOL_SIZE));
+        listen(move |stream| {
+            let pool = pool.clone();
+            async move { pool.submit(Box::pin(handle(stream))).await }
+        })
+        .await
+    });
+    group.bench_function("worker_pool", |b| b.iter(|| runtime.block_on(storm(address))));
+
+    group.finish();
+}
+
+criterion_group!(benches, connect_storm);
+criterion_main!(benches);
@@ -0,0 +1,165 @@
+// accept 错误处理
+// accept 失败时记录并退避后继续, 不让单次错误 (如文件描述符耗尽) 结束整个监听器
+
+use log::warn;
+use rand::Rng;
+use std::time::Duration;
+
+use crate::metrics::metrics;
+
+/// 连续错误的初始退避时间
+const BASE_DELAY: Duration = Duration::from_millis(10);
+/// 退避上限
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// accept 错误类别 (`accept_errors_total` 的 kind 标签)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptErrorKind {
+    /// 进程或系统的文件描述符耗尽 (EMFILE / ENFILE)
+    FdExhausted,
+    /// 连接在 accept 之前被对端中止 (ECONNABORTED)
+    ConnectionAborted,
+    Other,
+}
+
+impl AcceptErrorKind {
+    pub const ALL: [AcceptErrorKind; 3] = [
+        AcceptErrorKind::FdExhausted,
+        AcceptErrorKind::ConnectionAborted,
+        AcceptErrorKind::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AcceptErrorKind::FdExhausted => "fd_exhausted",
+            AcceptErrorKind::ConnectionAborted => "connection_aborted",
+            AcceptErrorKind::Other => "other",
+        }
+    }
+
+    pub fn classify(error: &std::io::Error) -> Self {
+        match error.raw_os_error() {
+            Some(libc::EMFILE | libc::ENFILE) => AcceptErrorKind::FdExhausted,
+            _ if error.kind() == std::io::ErrorKind::ConnectionAborted => AcceptErrorKind::ConnectionAborted,
+            _ => AcceptErrorKind::Other,
+        }
+    }
+}
+
+/// accept 错误退避
+/// 文件描述符耗尽时立即重试只会空转, 总是退避; 其它错误从第二次连续出现开始退避
+#[derive(Debug, Default)]
+pub struct AcceptBackoff {
+    consecutive_errors: u32,
+}
+
+impl AcceptBackoff {
+    /// 记录一次错误, 返回重试前需要等待的时间 (带随机抖动)
+    pub fn on_error(&mut self, kind: AcceptErrorKind) -> Duration {
+        self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+        if kind != AcceptErrorKind::FdExhausted && self.consecutive_errors == 1 {
+            return Duration::ZERO;
+        }
+
+        let exponent = self.consecutive_errors.saturating_sub(1).min(16);
+        let delay = BASE_DELAY.saturating_mul(1 << exponent).min(MAX_DELAY);
+        // 在 [delay/2, delay] 之间抖动, 避免多个监听器同步重试
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// accept 成功后重置
+    pub fn on_success(&mut self) {
+        self.consecutive_errors = 0;
+    }
+}
+
+/// 调用 `accept` 直到成功并返回接受的连接; 每次失败计入 `accept_errors_total{kind}`, 记录警告并按 `backoff` 退避后重试
+pub async fn accept_with_backoff<T, F, Fut>(backoff: &mut AcceptBackoff, mut accept: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    loop {
+        match accept().await {
+            Ok(accepted) => {
+                backoff.on_success();
+                return accepted;
+            }
+            Err(e) => {
+                let kind = AcceptErrorKind::classify(&e);
+                metrics().record_accept_error(kind);
+                let delay = backoff.on_error(kind);
+                warn!("Adapter: accept failed ({}): {}, retrying in {:?}", kind.as_str(), e, delay);
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::{Error, ErrorKind};
+    use std::time::Instant;
+
+    #[test]
+    fn classifies_errors() {
+        assert_eq!(AcceptErrorKind::classify(&Error::from_raw_os_error(libc::EMFILE)), AcceptErrorKind::FdExhausted);
+        assert_eq!(AcceptErrorKind::classify(&Error::from_raw_os_error(libc::ENFILE)), AcceptErrorKind::FdExhausted);
+        assert_eq!(
+            AcceptErrorKind::classify(&Error::from_raw_os_error(libc::ECONNABORTED)),
+            AcceptErrorKind::ConnectionAborted,
+        );
+        assert_eq!(AcceptErrorKind::classify(&Error::other("boom")), AcceptErrorKind::Other);
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter_and_is_capped() {
+        let mut backoff = AcceptBackoff::default();
+        // 偶发的错误立即重试
+        assert_eq!(backoff.on_error(AcceptErrorKind::Other), Duration::ZERO);
+        for attempt in 1..20u32 {
+            let delay = backoff.on_error(AcceptErrorKind::Other);
+            let full = BASE_DELAY.saturating_mul(1 << attempt.min(16)).min(MAX_DELAY);
+            assert!(delay >= full / 2 && delay <= full, "attempt {}: {:?} not in [{:?}, {:?}]", attempt, delay, full / 2, full);
+        }
+
+        backoff.on_success();
+        assert_eq!(backoff.on_error(AcceptErrorKind::ConnectionAborted), Duration::ZERO);
+    }
+
+    #[test]
+    fn fd_exhaustion_always_backs_off() {
+        let mut backoff = AcceptBackoff::default();
+        let delay = backoff.on_error(AcceptErrorKind::FdExhausted);
+        assert!(delay >= BASE_DELAY / 2 && delay <= BASE_DELAY, "{:?}", delay);
+    }
+
+    #[tokio::test]
+    async fn wrapped_accept_keeps_going_after_errors() {
+        // 模拟的监听器: 先失败几次, 然后接受一个连接
+        let mut results: VecDeque<std::io::Result<u32>> = VecDeque::from([
+            Err(Error::from_raw_os_error(libc::EMFILE)),
+            Err(Error::from_raw_os_error(libc::EMFILE)),
+            Err(Error::new(ErrorKind::ConnectionAborted, "aborted")),
+            Ok(7),
+        ]);
+        let mut calls = 0;
+        let mut backoff = AcceptBackoff::default();
+        let started = Instant::now();
+        let accepted = accept_with_backoff(&mut backoff, || {
+            calls += 1;
+            let result = results.pop_front().expect("accept called after success");
+            async move { result }
+        })
+        .await;
+
+        assert_eq!(accepted, 7);
+        assert_eq!(calls, 4);
+        // 三次连续错误的退避至少为 (10 + 20 + 40) / 2 毫秒
+        assert!(started.elapsed() >= Duration::from_millis(35), "{:?}", started.elapsed());
+        // 成功后退避已重置
+        assert_eq!(backoff.on_error(AcceptErrorKind::Other), Duration::ZERO);
+    }
+}
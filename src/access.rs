@@ -0,0 +1,126 @@
+// 基于 CIDR 的访问控制
+// 在读取 CONNECT 之前按对端 IP 过滤连接
+
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::adapter_config::AccessConfig;
+
+/// 访问控制列表
+/// 拒绝列表优先; 允许列表为空表示允许所有地址
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    /// 从配置解析 CIDR 列表
+    pub fn from_config(config: &AccessConfig) -> std::io::Result<Self> {
+        Ok(AccessList {
+            allow: parse_cidrs(&config.allow_cidrs)?,
+            deny: parse_cidrs(&config.deny_cidrs)?,
+        })
+    }
+
+    /// 检查对端地址是否允许连接
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        // IPv4 映射的 IPv6 地址 (::ffff:a.b.c.d) 按 IPv4 处理
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// 解析 CIDR 列表, 也接受不带前缀长度的单个地址
+fn parse_cidrs(cidrs: &[String]) -> std::io::Result<Vec<IpNet>> {
+    cidrs
+        .iter()
+        .map(|cidr| {
+            cidr.parse::<IpNet>()
+                .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|e| std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid CIDR {:?}: {}", cidr, e)
+                ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_list(allow: &[&str], deny: &[&str]) -> AccessList {
+        let config = AccessConfig {
+            allow_cidrs: allow.iter().map(|cidr| cidr.to_string()).collect(),
+            deny_cidrs: deny.iter().map(|cidr| cidr.to_string()).collect(),
+        };
+        AccessList::from_config(&config).unwrap()
+    }
+
+    fn allowed(list: &AccessList, ip: &str) -> bool {
+        list.is_allowed(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn empty_lists_allow_everything() {
+        let list = access_list(&[], &[]);
+        assert!(allowed(&list, "203.0.113.7"));
+        assert!(allowed(&list, "2001:db8::1"));
+    }
+
+    #[test]
+    fn allow_list_restricts_ipv4_and_ipv6() {
+        let list = access_list(&["10.0.0.0/8", "2001:db8::/32"], &[]);
+        assert!(allowed(&list, "10.1.2.3"));
+        assert!(allowed(&list, "2001:db8:1::5"));
+        assert!(!allowed(&list, "192.168.1.1"));
+        assert!(!allowed(&list, "2001:db9::1"));
+    }
+
+    #[test]
+    fn deny_wins_over_overlapping_allow() {
+        // 拒绝的子网位于允许的网段之内
+        let list = access_list(
+            &["10.0.0.0/8", "2001:db8::/32"],
+            &["10.1.0.0/16", "2001:db8:bad::/48"],
+        );
+        assert!(allowed(&list, "10.2.0.1"));
+        assert!(!allowed(&list, "10.1.0.1"));
+        assert!(!allowed(&list, "10.1.255.255"));
+        assert!(allowed(&list, "2001:db8:1::1"));
+        assert!(!allowed(&list, "2001:db8:bad::1"));
+    }
+
+    #[test]
+    fn deny_only_list_allows_the_rest() {
+        let list = access_list(&[], &["192.0.2.0/24", "fe80::/10"]);
+        assert!(!allowed(&list, "192.0.2.10"));
+        assert!(!allowed(&list, "fe80::1"));
+        assert!(allowed(&list, "192.0.3.1"));
+        assert!(allowed(&list, "2001:db8::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_matches_ipv4_rules() {
+        let list = access_list(&["10.0.0.0/8"], &["10.9.0.0/16"]);
+        assert!(allowed(&list, "::ffff:10.1.1.1"));
+        assert!(!allowed(&list, "::ffff:10.9.1.1"));
+    }
+
+    #[test]
+    fn single_addresses_and_invalid_cidrs() {
+        let list = access_list(&["198.51.100.4", "2001:db8::4"], &[]);
+        assert!(allowed(&list, "198.51.100.4"));
+        assert!(!allowed(&list, "198.51.100.5"));
+        assert!(allowed(&list, "2001:db8::4"));
+
+        let config = AccessConfig { allow_cidrs: vec!["10.0.0.0/33".to_string()], deny_cidrs: Vec::new() };
+        assert!(AccessList::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,1115 @@
+// 适配器配置
+// 与 rumqttd 共用 config.toml,所有适配器相关设置都放在 [adapter] 段下,避免与 broker 的字段冲突
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::snapshot::RuntimeSnapshot;
+
+/// config.toml 的适配器部分
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub adapter: AdapterConfig,
+    /// 运行的适配器实例 ([adapters]), 不配置则按 [adapter] enabled / listen 运行一个智能适配器
+    pub adapters: Option<AdaptersConfig>,
+    /// 快照文件中的运行时状态 (POST /snapshot 生成), 普通配置文件中没有
+    pub runtime: Option<RuntimeSnapshot>,
+}
+
+impl AppConfig {
+    /// 要启动的适配器实例; 智能适配器最多一个 (它使用 [adapter] 的全部设置)
+    pub fn adapter_instances(&self) -> Result<Vec<AdapterInstance>, String> {
+        let Some(adapters) = &self.adapters else {
+            return Ok(if self.adapter.enabled {
+                vec![AdapterInstance { kind: AdapterKind::Smart, listen: self.adapter.listen.clone() }]
+            } else {
+                Vec::new()
+            });
+        };
+        let smart = adapters.instances.iter().filter(|instance| instance.kind == AdapterKind::Smart).count();
+        if smart > 1 {
+            return Err(format!("[adapters] lists {} smart instances; at most one is supported", smart));
+        }
+        Ok(adapters.instances.clone())
+    }
+}
+
+/// 适配器实例列表 ([adapters])
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptersConfig {
+    /// 空列表表示不运行任何适配器, 客户端直接连接 broker 监听器
+    pub instances: Vec<AdapterInstance>,
+}
+
+/// 一个适配器实例
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdapterInstance {
+    #[serde(rename = "type")]
+    pub kind: AdapterKind,
+    /// 明文监听地址; 智能适配器的 TLS 监听器仍由 [adapter.tls] listen 设置
+    pub listen: String,
+}
+
+/// 适配器实例类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdapterKind {
+    /// 智能适配器: 自动识别 3.1.0 / 3.1.1 / 5.0, 支持 [adapter] 的全部功能
+    Smart,
+    /// 旧的 3.1.0 适配器: 只把 MQIsdp CONNECT 升级为 3.1.1, 其余原样转发
+    Legacy,
+}
+
+impl AdapterKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdapterKind::Smart => "smart",
+            AdapterKind::Legacy => "legacy",
+        }
+    }
+}
+
+/// 适配器配置 ([adapter])
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdapterConfig {
+    /// 是否启动智能适配器, false 时只运行 broker (和管理接口)
+    pub enabled: bool,
+    /// 智能适配器的明文监听地址
+    pub listen: String,
+    /// 明文监听器的处理方式: 识别并转换协议, 或不解析直接转发
+    pub mode: ListenerMode,
+    /// 管理接口监听地址 (提供 /metrics), 不配置则不启动
+    pub admin_listen: Option<String>,
+    /// 额外的管理接口监听器 ([[adapter.admin_listeners]]), 各自开放部分接口并可要求令牌
+    pub admin_listeners: Vec<AdminListenerConfig>,
+    /// 慢 CONNACK 告警阈值 (毫秒), 0 表示不告警
+    pub slow_connack_threshold_ms: u64,
+    /// 启动时是否处于维护模式 (可通过管理接口切换)
+    pub maintenance: bool,
+    /// 启动时等待后端 broker 监听就绪的最长时间 (毫秒)
+    pub backend_ready_timeout_ms: u64,
+    /// 启动后经由自己的监听端口发送一个合成的 CONNECT, 确认后端返回 CONNACK; 完成之前 /healthz 返回 503
+    pub startup_self_test: bool,
+    /// 自检失败时退出进程 (否则只记录错误, /healthz 保持 503)
+    pub startup_self_test_exit_on_failure: bool,
+    /// 后端连接绑定的本地 IP, 不配置则由系统选择
+    pub forward_bind_addr: Option<String>,
+    /// 后端连接使用的本地端口范围 [first, last], 不配置则使用临时端口
+    pub forward_bind_port_range: Option<[u16; 2]>,
+    /// 连接后端或发送 CONNECT 失败时换新连接重试的次数, 0 = 不重试
+    pub backend_connect_retries: u32,
+    /// 两次后端连接尝试之间的间隔 (毫秒)
+    pub backend_connect_retry_delay_ms: u64,
+    /// 单次后端连接尝试的超时 (毫秒), 0 = 使用系统的连接超时
+    pub backend_connect_timeout_ms: u64,
+    /// 发送 CONNECT 后等待后端 CONNACK 的超时 (毫秒), 0 = 不限
+    pub backend_connack_timeout_ms: u64,
+    /// 缓存后端地址的 DNS 解析结果, false = 每次连接都解析
+    pub dns_cache: bool,
+    /// DNS 解析结果的保留时间 (秒), 到期前被使用时在后台刷新
+    pub dns_cache_ttl_sec: u64,
+    /// 最大保活时间 (秒): 5.0 通过 CONNACK 的 Server Keep Alive 下发, 并同样改写转发给后端的 CONNECT; 3.x 不支持
+    pub max_keepalive_sec: Option<u16>,
+    /// 拒绝不启用保活 (keep-alive 为 0) 的 CONNECT (5.0 且配置了 max_keepalive_sec 时改为上限, 不拒绝)
+    pub require_keepalive: bool,
+    /// 5.0 遗嘱延迟间隔上限 (秒): 遗嘱的实际延迟 (遗嘱延迟间隔和会话过期间隔中较小的一个) 超过时改写遗嘱延迟间隔
+    pub max_will_delay_sec: Option<u32>,
+    /// 最大报文长度 (字节): 拒绝更大的 CONNECT, 并把 5.0 客户端声明的 Maximum Packet Size 降到该值
+    pub max_packet_size: Option<u32>,
+    /// CONNECT 剩余长度字段最多占用的字节数 (1-4, 规范允许 4), 更长时拒绝连接
+    pub max_remaining_length_bytes: usize,
+    /// 明文监听器上客户端 PUBLISH 的最大剩余长度 (字节), 超过时在转发之前断开连接 (5.0 客户端先收到 DISCONNECT 0x95), 不配置则由后端限制
+    pub max_publish_size: Option<usize>,
+    /// 通过 5.0 CONNACK 的 Maximum QoS 声明的最大 QoS (0 或 1), 只声明不拦截
+    pub max_qos: Option<u8>,
+    /// false 时通过 5.0 CONNACK 声明不支持保留消息 (Retain Available = 0), 只声明不拦截
+    pub retain_available: bool,
+    /// 连接错误日志采样窗口 (秒): 同类错误每个窗口只输出一条并汇总次数, 0 = 不采样
+    pub error_log_window_sec: u64,
+    /// 允许通过管理接口把空闲的 clean session 连接迁移到其他后端 (启用后转发时解析包边界)
+    pub backend_migration: bool,
+    /// 通过管理接口暂停的连接最长暂停多久 (毫秒) 后自动恢复, 0 = 直到手动恢复
+    pub max_pause_ms: u64,
+    /// 通过管理接口对单个连接记录包序列时每次最多记录的包数, 0 = 不启用 (启用后转发时跟踪包边界)
+    pub packet_trace_max_packets: usize,
+    /// 客户端和后端连接的接收缓冲区大小 (字节, SO_RCVBUF), 不配置则使用系统默认值
+    pub so_rcvbuf: Option<usize>,
+    /// 客户端和后端连接的发送缓冲区大小 (字节, SO_SNDBUF), 不配置则使用系统默认值
+    pub so_sndbuf: Option<usize>,
+    /// 保持 Nagle 算法, 只在转发控制包 (PUBLISH 以外的包) 时立即发送 (短暂打开 TCP_NODELAY)
+    pub nodelay_control_packets: bool,
+    /// 启动时把进程的打开文件数软限制 (RLIMIT_NOFILE) 提高到该值, 0 = 提高到硬限制, 不配置则不修改 (仅 Unix)
+    pub max_open_files: Option<u64>,
+    /// 每个 CPU 核心一个 SO_REUSEPORT 监听器和 accept 循环 (仅 Linux, 其它系统使用单个监听器)
+    pub reuse_port: bool,
+    /// 等待连接发送足够识别协议的字节的最长时间 (毫秒), 0 = 不限制
+    pub banner_grace_ms: u64,
+    /// 从 accept 到收到完整 CONNECT 的最长时间 (毫秒), 0 = 不限制
+    pub connect_deadline_ms: u64,
+    /// 用固定数量的工作任务处理连接 (同时处理的连接数上限), 0 = 每个连接一个任务
+    pub worker_pool_size: usize,
+    /// 一个方向读到 EOF 后只关闭该方向, 另一方向继续转发的最长时间 (毫秒), 0 = 立即断开整个连接
+    pub half_close_grace_ms: u64,
+    /// 排空开始后关闭已有连接: 等待未完成的 QoS 1/2 交互结束的最长时间 (毫秒), 0 = 排空时不关闭已有连接
+    pub qos_drain_grace_ms: u64,
+    /// Linux 上用 splice 转发不需要检查数据的明文 TCP 连接 (需要 `splice` feature)
+    pub splice_forward: bool,
+    /// 客户端在连接上再次发送 CONNECT 时由适配器断开, 不转发给后端; MQTT 5.0 客户端先收到 DISCONNECT (0x82)
+    pub reject_second_connect: bool,
+    /// 后端在稳定转发阶段关闭连接时, 先给 MQTT 5.0 客户端发送 DISCONNECT (0x8B / 0x89) 再关闭 (需要解析两个方向的包)
+    pub disconnect_on_backend_close: bool,
+    /// 两个方向都没有数据超过该时间 (毫秒) 的连接由适配器断开, 0 = 不检测
+    pub idle_timeout_ms: u64,
+    /// 空闲检测方式: 单个后台任务定期扫描, 或每个连接一个定时器
+    pub idle_reaper: IdleReaperMode,
+    /// `idle_reaper = "sweep"` 时的扫描间隔 (毫秒)
+    pub reaper_interval_ms: u64,
+    /// 在 debug 级别记录 5.0 请求/响应属性 (Request Response Information、Response Topic), 需要解析转发的包
+    pub log_request_response: bool,
+    /// 何时向事件订阅者发布 connected: 转发 CONNECT 后, 或后端 CONNACK 接受连接后
+    pub observer_on: ObserverOn,
+    /// 接受的 MQTT 协议版本 ("3.1.0"、"3.1.1"、"5.0"), 其它版本以 CONNACK 不接受的协议版本拒绝
+    pub allowed_versions: Vec<String>,
+    /// MQTT 3.1.0 停用日期 ("YYYY-MM-DD", UTC), 之前升级并警告, 之后拒绝; 不配置则一直升级
+    pub v310_sunset_date: Option<String>,
+    /// 接受 WebSocket 上的 MQTT (明文监听器上为 ws, TLS 监听器上为 wss), false 时按不支持的协议拒绝
+    pub websocket: bool,
+    /// 接受 MQIsdp 协议名的非标准变体 (大小写不同、末尾多出 NUL), 按 3.1.0 升级
+    pub lenient_legacy: bool,
+    /// 把 3.1.0 (MQIsdp) CONNECT 升级为 3.1.1 后转发, false 时原样转发 (后端需要支持 3.1.0)
+    pub upgrade_v310: bool,
+    /// 严格检查 CONNECT 标志 (保留位、无遗嘱时的遗嘱 QoS/保留位、遗嘱 QoS 3、3.x 无用户名时的密码), 违反时断开
+    pub strict_connect_flags: bool,
+    /// 运行时客户端 ID 拒绝列表的保存文件 (每行一个 ID), 不配置则只保存在内存中
+    pub client_id_denylist_path: Option<String>,
+    /// POST /snapshot 写入快照的文件, 不配置则不能生成快照
+    pub snapshot_path: Option<String>,
+    /// 匹配这些客户端 ID 模式 (支持 `*` 和 `?`) 的连接强制 clean session, 后端不保留会话
+    pub force_clean_session: Vec<String>,
+    /// 在转发给后端的 5.0 CONNECT 中以该名称的用户属性附加客户端 IP (替换客户端自带的同名属性), 不配置则不附加
+    pub forwarded_for_property: Option<String>,
+    /// 明文监听器上转发给后端的客户端 ID 前缀模板 (支持 `{listener}` 和 `{cert.*}`), 不配置则不加前缀
+    pub client_id_prefix: Option<String>,
+    /// 加上前缀后客户端 ID 的最大长度 (字节)
+    pub max_client_id_len: usize,
+    /// 按客户端 ID 记录状态 (如接管检测) 的最大 ID 数 (只记录有活动连接的 ID), 超出时淘汰最久未使用的 ID
+    pub max_tracked_client_ids: usize,
+    /// 被拒绝 (访问控制、准入速率、拒绝列表、认证) 的连接保持打开多久 (毫秒) 后再回复并关闭, 0 = 立即关闭
+    pub tarpit_ms: u64,
+    /// 同时滞留在 tarpit 中的连接数上限, 超出时立即关闭
+    pub tarpit_max_sockets: usize,
+    /// 单连接带宽限制 ([adapter.throttle])
+    pub throttle: ThrottleConfig,
+    /// 全局 CONNECT 准入速率 ([adapter.connect_rate])
+    pub connect_rate: ConnectRateConfig,
+    /// 访问控制 ([adapter.access])
+    pub access: AccessConfig,
+    /// 分布式追踪 ([adapter.otel])
+    pub otel: OtelConfig,
+    /// 连接认证 ([adapter.auth])
+    pub auth: AuthConfig,
+    /// 日志脱敏 ([adapter.logging])
+    pub logging: LoggingConfig,
+    /// 指标输出 ([adapter.metrics])
+    pub metrics: MetricsConfig,
+    /// TLS 监听器 ([adapter.tls]), 不配置则只监听明文端口
+    pub tls: Option<TlsConfig>,
+    /// 按主题前缀选择后端 ([adapter.topic_routing]), 不配置则立即连接默认后端
+    /// 配置后适配器先回复自己的 CONNACK 再连接后端, 只接受不保留会话的连接
+    pub topic_routing: Option<TopicRoutingConfig>,
+    /// 按源 IP 哈希选择后端 ([adapter.source_ip_routing]), 不配置则使用默认后端
+    pub source_ip_routing: Option<SourceIpRoutingConfig>,
+    /// 按权重选择后端 ([adapter.weighted_routing]), 不配置则使用默认后端
+    pub weighted_routing: Option<WeightedRoutingConfig>,
+    /// 影子后端 ([adapter.shadow]), 不配置则不复制流量
+    pub shadow: Option<ShadowConfig>,
+    /// 后端预热连接池 ([adapter.warm_pool]), 不配置则每个客户端单独连接后端
+    pub warm_pool: Option<WarmPoolConfig>,
+    /// CONNECT 洪水检测 ([adapter.connect_flood]), 不配置则不检测
+    pub connect_flood: Option<ConnectFloodConfig>,
+    /// 活动连接上限和按租户公平排队 ([adapter.admission]), 不配置则不限制
+    pub admission: Option<AdmissionConfig>,
+    /// 转发给后端的 CONNECT 中注入的用户名/密码 ([adapter.backend_auth]), 不配置则原样转发
+    pub backend_auth: Option<BackendAuthConfig>,
+    /// 明文监听器上客户端发往 broker 的包类型过滤 ([adapter.packet_firewall]), 不配置则不过滤
+    pub packet_firewall: Option<PacketFirewallConfig>,
+    /// 把适配器主动关闭连接的事件发布到后端主题 ([adapter.event_publish]), 不配置则只通过管理接口提供
+    pub event_publish: Option<EventPublishConfig>,
+    /// 定期把适配器健康状态以保留消息发布到后端主题 ([adapter.health_publish]), 不配置则不发布
+    pub health_publish: Option<HealthPublishConfig>,
+}
+
+impl Default for AdapterConfig {
+    fn default() -> Self {
+        AdapterConfig {
+            enabled: true,
+            listen: "0.0.0.0:1882".to_string(),
+            mode: ListenerMode::Smart,
+            admin_listen: None,
+            admin_listeners: Vec::new(),
+            slow_connack_threshold_ms: 0,
+            maintenance: false,
+            backend_ready_timeout_ms: 10_000,
+            startup_self_test: false,
+            startup_self_test_exit_on_failure: false,
+            forward_bind_addr: None,
+            forward_bind_port_range: None,
+            backend_connect_retries: 0,
+            backend_connect_retry_delay_ms: 200,
+            backend_connect_timeout_ms: 3000,
+            backend_connack_timeout_ms: 10_000,
+            dns_cache: true,
+            dns_cache_ttl_sec: 30,
+            max_keepalive_sec: None,
+            require_keepalive: false,
+            max_will_delay_sec: None,
+            max_packet_size: None,
+            max_remaining_length_bytes: 4,
+            max_publish_size: None,
+            max_qos: None,
+            retain_available: true,
+            error_log_window_sec: 10,
+            backend_migration: false,
+            max_pause_ms: 300_000,
+            packet_trace_max_packets: 0,
+            so_rcvbuf: None,
+            so_sndbuf: None,
+            nodelay_control_packets: false,
+            max_open_files: None,
+            reuse_port: false,
+            banner_grace_ms: 10_000,
+            connect_deadline_ms: 0,
+            worker_pool_size: 0,
+            half_close_grace_ms: 0,
+            qos_drain_grace_ms: 0,
+            splice_forward: false,
+            reject_second_connect: false,
+            disconnect_on_backend_close: false,
+            idle_timeout_ms: 0,
+            idle_reaper: IdleReaperMode::Sweep,
+            reaper_interval_ms: 1000,
+            log_request_response: false,
+            observer_on: ObserverOn::Connect,
+            allowed_versions: vec!["3.1.0".to_string(), "3.1.1".to_string(), "5.0".to_string()],
+            v310_sunset_date: None,
+            websocket: false,
+            lenient_legacy: false,
+            upgrade_v310: true,
+            strict_connect_flags: false,
+            client_id_denylist_path: None,
+            snapshot_path: None,
+            force_clean_session: Vec::new(),
+            forwarded_for_property: None,
+            client_id_prefix: None,
+            max_client_id_len: 256,
+            max_tracked_client_ids: 100_000,
+            tarpit_ms: 0,
+            tarpit_max_sockets: 1024,
+            throttle: ThrottleConfig::default(),
+            connect_rate: ConnectRateConfig::default(),
+            access: AccessConfig::default(),
+            otel: OtelConfig::default(),
+            auth: AuthConfig::default(),
+            logging: LoggingConfig::default(),
+            metrics: MetricsConfig::default(),
+            tls: None,
+            topic_routing: None,
+            source_ip_routing: None,
+            weighted_routing: None,
+            shadow: None,
+            warm_pool: None,
+            connect_flood: None,
+            admission: None,
+            backend_auth: None,
+            packet_firewall: None,
+            event_publish: None,
+            health_publish: None,
+        }
+    }
+}
+
+/// 访问控制配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// 允许的网段, 为空表示允许所有
+    pub allow_cidrs: Vec<String>,
+    /// 拒绝的网段, 优先于允许列表
+    pub deny_cidrs: Vec<String>,
+}
+
+/// 连接认证配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// nonce 防重放认证 ([adapter.auth.nonce]), 不配置则不启用
+    pub nonce: Option<NonceAuthConfig>,
+}
+
+/// nonce 防重放认证配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NonceAuthConfig {
+    /// 携带 nonce 的 5.0 用户属性名
+    pub property: String,
+    /// 防重放时间窗口 (秒)
+    pub window_sec: u64,
+    /// 最多记录的 nonce 数量, 已满时拒绝新连接 (应不小于窗口内的连接数: 每秒连接数 × window_sec)
+    pub max_entries: usize,
+}
+
+impl Default for NonceAuthConfig {
+    fn default() -> Self {
+        NonceAuthConfig {
+            property: "nonce".to_string(),
+            window_sec: 300,
+            max_entries: 100_000,
+        }
+    }
+}
+
+/// 后端凭据注入配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackendAuthConfig {
+    /// 后端用户名
+    pub username: String,
+    /// 后端密码, 不配置则只注入用户名
+    pub password: Option<String>,
+    /// 何时注入
+    pub mode: BackendAuthMode,
+}
+
+/// 后端凭据的注入时机
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendAuthMode {
+    /// 只在客户端没有提供用户名时注入
+    #[default]
+    Missing,
+    /// 总是替换客户端提供的用户名和密码
+    Always,
+}
+
+/// 包类型过滤配置, allow 和 deny 只能配置一个
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PacketFirewallConfig {
+    /// 只允许这些包类型 (如 "publish"), 其它类型都被拒绝
+    pub allow: Vec<String>,
+    /// 拒绝这些包类型, 其它类型都允许
+    pub deny: Vec<String>,
+    /// 收到被拒绝的包时如何处理
+    pub action: PacketFirewallAction,
+}
+
+/// 被拒绝的包的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketFirewallAction {
+    /// 断开连接, 该包不转发
+    #[default]
+    Close,
+    /// 丢弃该包, 继续转发其它包
+    Discard,
+}
+
+/// 管理接口监听器 ([[adapter.admin_listeners]])
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminListenerConfig {
+    /// 监听地址
+    pub listen: String,
+    /// 访问令牌: 请求需要带 `Authorization: Bearer <token>`, 不配置则不认证
+    pub token: Option<String>,
+    /// 开放的接口组, 为空时开放全部
+    pub endpoints: Vec<AdminEndpoint>,
+}
+
+/// 管理接口的接口组
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminEndpoint {
+    /// GET /metrics、GET /scale-metric
+    Metrics,
+    /// GET /healthz
+    Health,
+    /// 只读的状态查询: GET /overview、/connections、/config、/events 等
+    Status,
+    /// 修改运行时状态的接口: 维护模式、排空、迁移、暂停、拒绝列表、快照
+    Control,
+    /// 网页面板 (admin-ui feature)
+    Dashboard,
+}
+
+impl AdminEndpoint {
+    pub const ALL: [AdminEndpoint; 5] =
+        [AdminEndpoint::Metrics, AdminEndpoint::Health, AdminEndpoint::Status, AdminEndpoint::Control, AdminEndpoint::Dashboard];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdminEndpoint::Metrics => "metrics",
+            AdminEndpoint::Health => "health",
+            AdminEndpoint::Status => "status",
+            AdminEndpoint::Control => "control",
+            AdminEndpoint::Dashboard => "dashboard",
+        }
+    }
+}
+
+/// TLS 监听器配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// TLS 监听地址
+    pub listen: String,
+    /// PEM 证书链
+    pub cert_path: String,
+    /// PEM 私钥 (PKCS#8 / PKCS#1 / SEC1 / 加密的 PKCS#8, 按 PEM 头识别)
+    pub key_path: String,
+    /// 加密私钥的密码
+    pub key_password: Option<String>,
+    /// 握手超时 (毫秒), 超时的连接直接关闭
+    pub handshake_timeout_ms: u64,
+    /// 同时进行的握手数上限, 0 = CPU 核心数 × 64
+    pub max_concurrent_handshakes: usize,
+    /// 握手数已满时等待开始握手的最长时间 (毫秒), 超时的连接直接关闭
+    pub handshake_queue_timeout_ms: u64,
+    /// 校验客户端证书的 PEM CA 证书 (双向 TLS), 配置后没有有效客户端证书的连接在握手时被拒绝
+    pub client_ca_path: Option<String>,
+    /// 按客户端证书字段选择后端 ([adapter.tls.cert_routing]), 需要 client_ca_path
+    pub cert_routing: Option<CertRoutingConfig>,
+    /// TLS 监听器上的包类型过滤 ([adapter.tls.packet_firewall]), 与明文监听器的配置相互独立
+    pub packet_firewall: Option<PacketFirewallConfig>,
+    /// TLS 监听器上客户端 PUBLISH 的最大剩余长度 (字节), 与明文监听器的 max_publish_size 相互独立
+    pub max_publish_size: Option<usize>,
+    /// TLS 监听器上的客户端 ID 前缀模板, 与 [adapter] client_id_prefix 相互独立
+    pub client_id_prefix: Option<String>,
+    /// 拒绝携带 TLS 1.3 0-RTT early data 的握手 (early data 可被重放); false 时只由 rustls 忽略 early data
+    pub reject_early_data: bool,
+    /// 断开开头不是 TLS 记录的明文连接; false 时这些连接按明文监听器处理 (同一端口同时接受 TLS 和明文 MQTT)
+    pub require_tls: bool,
+    /// TLS 监听器的协议识别策略 ([adapter.tls.detection]), 未设置的字段沿用 [adapter] 的设置
+    pub detection: Option<DetectionConfig>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            listen: "0.0.0.0:8883".to_string(),
+            cert_path: String::new(),
+            key_path: String::new(),
+            key_password: None,
+            handshake_timeout_ms: 10_000,
+            max_concurrent_handshakes: 0,
+            handshake_queue_timeout_ms: 1_000,
+            client_ca_path: None,
+            cert_routing: None,
+            packet_firewall: None,
+            max_publish_size: None,
+            client_id_prefix: None,
+            reject_early_data: true,
+            require_tls: true,
+            detection: None,
+        }
+    }
+}
+
+/// 单个监听器的协议识别策略, 每个字段覆盖 [adapter] 中的同名设置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    pub upgrade_v310: Option<bool>,
+    pub allowed_versions: Option<Vec<String>>,
+    pub lenient_legacy: Option<bool>,
+    pub strict_connect_flags: Option<bool>,
+    pub connect_deadline_ms: Option<u64>,
+}
+
+/// 按客户端证书路由配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertRoutingConfig {
+    /// 作为路由键的证书字段
+    pub attribute: CertAttribute,
+    /// 字段值 -> 后端地址, 证书没有该字段或没有匹配时使用默认后端
+    pub backends: BTreeMap<String, String>,
+}
+
+/// 客户端证书字段, 有多个值时取第一个
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CertAttribute {
+    /// 主题的 commonName
+    #[serde(rename = "CN")]
+    CommonName,
+    /// 主题的 organizationName
+    #[serde(rename = "O")]
+    Organization,
+    /// 主题的 organizationalUnitName
+    #[default]
+    #[serde(rename = "OU")]
+    OrganizationalUnit,
+    /// subjectAltName 中的 dNSName
+    #[serde(rename = "DNS")]
+    DnsName,
+    /// subjectAltName 中的 uniformResourceIdentifier
+    #[serde(rename = "URI")]
+    Uri,
+}
+
+impl CertAttribute {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CertAttribute::CommonName => "CN",
+            CertAttribute::Organization => "O",
+            CertAttribute::OrganizationalUnit => "OU",
+            CertAttribute::DnsName => "DNS",
+            CertAttribute::Uri => "URI",
+        }
+    }
+}
+
+/// 按主题前缀路由配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TopicRoutingConfig {
+    /// 主题前缀 -> 后端地址, 取最长匹配, 没有匹配时使用默认后端
+    pub backends: BTreeMap<String, String>,
+    /// 选择后端之前最多缓存的客户端数据 (字节)
+    pub max_buffer_bytes: usize,
+    /// 等待第一个 PUBLISH/SUBSCRIBE 的最长时间 (毫秒)
+    pub max_wait_ms: u64,
+}
+
+impl Default for TopicRoutingConfig {
+    fn default() -> Self {
+        TopicRoutingConfig {
+            backends: BTreeMap::new(),
+            max_buffer_bytes: 65_536,
+            max_wait_ms: 5_000,
+        }
+    }
+}
+
+/// 按源 IP 路由配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceIpRoutingConfig {
+    /// 候选后端地址, 按客户端 IP 的哈希选择其中一个
+    pub backends: Vec<String>,
+}
+
+/// 按权重路由配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeightedRoutingConfig {
+    /// 候选后端和权重, 每个新连接按权重随机选择其中一个
+    pub backends: Vec<WeightedBackend>,
+    /// 按客户端 IP 固定后端 (加权 rendezvous 哈希), 同一地址总是连接同一后端
+    pub sticky: bool,
+}
+
+/// 一个带权重的后端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedBackend {
+    /// 后端地址 (host:port)
+    pub address: String,
+    /// 相对权重, 0 表示暂不分配新连接
+    pub weight: u32,
+}
+
+/// 影子后端配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    /// 影子 broker 地址 (host:port)
+    pub backend: String,
+    /// 除 CONNECT 外是否复制客户端发往 broker 的后续流量
+    pub forward_traffic: bool,
+    /// 每个连接最多排队的数据块数, 超出时停止复制该连接
+    pub queue_chunks: usize,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        ShadowConfig {
+            backend: String::new(),
+            forward_traffic: true,
+            queue_chunks: 256,
+        }
+    }
+}
+
+/// 按租户公平准入配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdmissionConfig {
+    /// 同时转发的最大连接数
+    pub max_connections: usize,
+    /// 客户端 ID 中租户前缀的分隔符 (第一个分隔符之前为租户), 没有分隔符的客户端归入默认租户
+    pub tenant_delimiter: String,
+    /// 每个租户最多排队的连接数, 超出时立即拒绝
+    pub max_queue_per_tenant: usize,
+    /// 每个租户的连接数上限 (活动和排队的连接), 超出时立即拒绝, 0 = 不限制
+    pub max_connections_per_tenant: usize,
+    /// 排队等待的最长时间 (毫秒), 超过则拒绝
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        AdmissionConfig {
+            max_connections: 10_000,
+            tenant_delimiter: "-".to_string(),
+            max_queue_per_tenant: 100,
+            max_connections_per_tenant: 0,
+            queue_timeout_ms: 5_000,
+        }
+    }
+}
+
+/// 后端预热连接池配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarmPoolConfig {
+    /// 保持的预热连接数
+    pub size: usize,
+    /// 连接在池中的最长时间 (毫秒), 超过后丢弃并重新连接
+    pub max_idle_ms: u64,
+}
+
+impl Default for WarmPoolConfig {
+    fn default() -> Self {
+        WarmPoolConfig {
+            size: 4,
+            max_idle_ms: 30_000,
+        }
+    }
+}
+
+/// 适配器事件发布配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventPublishConfig {
+    /// 主题前缀, 事件发布到 `<topic_prefix>/disconnect`
+    pub topic_prefix: String,
+    /// 控制连接的客户端 ID, 为空时使用 `adapter-events-<进程 ID>` (多个适配器实例不会互相踢掉)
+    pub client_id: String,
+    /// 控制连接的保活时间 (秒)
+    pub keep_alive_sec: u16,
+}
+
+impl Default for EventPublishConfig {
+    fn default() -> Self {
+        EventPublishConfig {
+            topic_prefix: "adapter/events".to_string(),
+            client_id: String::new(),
+            keep_alive_sec: 60,
+        }
+    }
+}
+
+/// 适配器健康状态发布配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthPublishConfig {
+    /// 保留消息的主题, 多个适配器实例应使用不同的主题
+    pub topic: String,
+    /// 发布间隔 (秒)
+    pub interval_sec: u64,
+    /// 控制连接的客户端 ID, 为空时使用 `adapter-health-<进程 ID>`
+    pub client_id: String,
+    /// 控制连接的保活时间 (秒)
+    pub keep_alive_sec: u16,
+}
+
+impl Default for HealthPublishConfig {
+    fn default() -> Self {
+        HealthPublishConfig {
+            topic: "adapter/health".to_string(),
+            interval_sec: 30,
+            client_id: String::new(),
+            keep_alive_sec: 60,
+        }
+    }
+}
+
+/// 日志配置: 字段脱敏、连接日志级别和连接采样日志
+/// 密码从不解析也从不记录, 不需要配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// 需要脱敏的 CONNECT 字段: "client_id", "username"
+    pub redact: Vec<String>,
+    /// 脱敏方式
+    pub redact_mode: RedactMode,
+    /// 成功建立连接的日志 (识别出的协议版本、TLS 握手结果) 的级别, 拒绝和错误日志不受影响
+    pub connection_log_level: ConnectionLogLevel,
+    /// 按比例在 info 级别记录连接的来源和协议版本 (0.01 = 1%), 0 = 不采样, 不受 `connection_log_level` 影响
+    pub accept_log_sample_rate: f64,
+}
+
+/// 成功连接日志的级别
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionLogLevel {
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl ConnectionLogLevel {
+    pub fn level(self) -> log::Level {
+        match self {
+            ConnectionLogLevel::Info => log::Level::Info,
+            ConnectionLogLevel::Debug => log::Level::Debug,
+            ConnectionLogLevel::Trace => log::Level::Trace,
+        }
+    }
+}
+
+/// 日志脱敏方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactMode {
+    /// 输出 SHA-256 前缀, 同一个值在日志中可以关联
+    #[default]
+    Hash,
+    /// 只保留前 3 个字符
+    Truncate,
+}
+
+/// 监听器模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerMode {
+    /// 读出 CONNECT, 识别协议版本并按需转换, 支持认证、路由等所有功能
+    #[default]
+    Smart,
+    /// 从第一个字节起原样转发到后端, 不读取 CONNECT; 只适用于可信的原生 MQTT 流量
+    Passthrough,
+}
+
+/// 空闲连接的检测方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleReaperMode {
+    /// 一个后台任务每 `reaper_interval_ms` 扫描所有连接的最后活动时间, 适合大量连接
+    #[default]
+    Sweep,
+    /// 每个连接一个定时器, 到期即断开
+    Timer,
+}
+
+/// connected 事件的发布时机
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObserverOn {
+    /// 转发 CONNECT 后立即发布, 不检查后端是否接受
+    #[default]
+    Connect,
+    /// 后端 CONNACK 原因码为 0 时发布, 否则发布 connect_rejected
+    Connack,
+}
+
+/// 指标输出配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// 指标后端; 管理接口的 /metrics 总是可用
+    pub backend: MetricsBackend,
+    /// StatsD 接收端 (UDP)
+    pub statsd_target: String,
+    /// StatsD 指标名前缀
+    pub statsd_prefix: String,
+    /// 以 DogStatsD 标签 (`|#key:value`) 输出标签, 为 false 时标签值拼接到指标名中
+    pub statsd_tags: bool,
+    /// 推送间隔 (毫秒)
+    pub flush_interval_ms: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            backend: MetricsBackend::Prometheus,
+            statsd_target: "127.0.0.1:8125".to_string(),
+            statsd_prefix: "mqtt_adapter".to_string(),
+            statsd_tags: true,
+            flush_interval_ms: 10_000,
+        }
+    }
+}
+
+/// 指标后端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsBackend {
+    /// 只通过 /metrics 提供 Prometheus 文本格式
+    #[default]
+    Prometheus,
+    /// 另外定期以 StatsD 行协议推送
+    Statsd,
+}
+
+/// 分布式追踪配置 (需要 `otel` feature)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtelConfig {
+    /// OTLP gRPC 接收端地址, 不配置则不导出
+    pub endpoint: Option<String>,
+    /// 上报的 service.name
+    pub service_name: String,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        OtelConfig {
+            endpoint: None,
+            service_name: "rustmqttserverdemo".to_string(),
+        }
+    }
+}
+
+/// CONNECT 洪水检测配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectFloodConfig {
+    /// 统计连接速率的间隔 (毫秒)
+    pub sample_interval_ms: u64,
+    /// 基线 (指数加权平均) 的时间窗口 (秒), 越大越平滑
+    pub baseline_window_sec: u64,
+    /// 速率超过基线的该倍数时告警
+    pub multiplier: f64,
+    /// 速率低于该值 (连接/秒) 时不告警, 避免空闲时的基线过低
+    pub min_rate: f64,
+    /// 速率恢复后告警状态保持的时间 (秒)
+    pub cooldown_sec: u64,
+    /// 告警期间临时的准入速率 (连接/秒), 0 = 只告警不限制
+    pub tighten_max_connects_per_sec: u64,
+}
+
+impl Default for ConnectFloodConfig {
+    fn default() -> Self {
+        ConnectFloodConfig {
+            sample_interval_ms: 1000,
+            baseline_window_sec: 300,
+            multiplier: 5.0,
+            min_rate: 20.0,
+            cooldown_sec: 300,
+            tighten_max_connects_per_sec: 0,
+        }
+    }
+}
+
+/// 全局 CONNECT 准入速率配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectRateConfig {
+    /// 每秒最多接受的新连接数, 0 表示不限制
+    pub max_connects_per_sec: u64,
+    /// 允许的突发连接数
+    pub burst: u64,
+    /// 超出速率时最多排队等待的时间 (毫秒), 超过则拒绝
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for ConnectRateConfig {
+    fn default() -> Self {
+        ConnectRateConfig {
+            max_connects_per_sec: 0,
+            burst: 100,
+            queue_timeout_ms: 100,
+        }
+    }
+}
+
+/// 单连接带宽限制配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// 全局单连接速率上限 (字节/秒), 0 表示不限制
+    pub max_bytes_per_sec: u64,
+    /// 按客户端 ID 模式覆盖的规则, 按顺序取第一条匹配的
+    pub rules: Vec<ThrottleRule>,
+    /// 所有连接合计的转发速率上限 (字节/秒, 两个方向合计), 0 表示不限制
+    pub max_total_bytes_per_sec: u64,
+    /// 单连接两个方向合计转发的字节上限, 超过时断开连接, 0 表示不限制
+    pub max_bytes_per_connection: u64,
+}
+
+/// 按客户端 ID 模式设置的限速规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottleRule {
+    /// 客户端 ID 模式, 支持 `*` 和 `?` 通配符
+    pub client_id_pattern: String,
+    /// 速率上限 (字节/秒), 0 表示不限制
+    pub max_bytes_per_sec: u64,
+}
+
+impl ThrottleConfig {
+    /// 返回指定客户端的速率上限, 0 表示不限制
+    pub fn limit_for(&self, client_id: &str) -> u64 {
+        self.rules
+            .iter()
+            .find(|rule| glob_match(&rule.client_id_pattern, client_id))
+            .map(|rule| rule.max_bytes_per_sec)
+            .unwrap_or(self.max_bytes_per_sec)
+    }
+}
+
+/// 简单通配符匹配: `*` 匹配任意长度, `?` 匹配单个字符
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut star_t = 0;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p);
+            star_t = t;
+            p += 1;
+        } else if let Some(s) = star {
+            // 回溯: 让上一个 `*` 多吞一个字符
+            p = s + 1;
+            star_t += 1;
+            t = star_t;
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+
+    p == pattern.len()
+}
+
+/// 判断配置键是否为敏感字段
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["password", "token", "secret"].iter().any(|word| key.contains(word))
+}
+
+/// 递归隐藏配置中的敏感字段 (密码、令牌等), 用于对外输出
+/// rumqttd 监听器的 `connections.auth` 表是 用户名 → 密码, 其中所有值都会被隐藏
+pub fn redact_secrets(value: &mut Value) {
+    redact_secrets_in(value, None);
+}
+
+/// 递归移除值为 null 的字段 (TOML 没有 null, 未设置的可选字段直接省略)
+pub fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, child| !child.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+fn redact_secrets_in(value: &mut Value, parent_key: Option<&str>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if is_secret_key(key) && !child.is_null() {
+                    *child = Value::String("<redacted>".to_string());
+                } else if key == "auth"
+                    && parent_key == Some("connections")
+                    && let Value::Object(users) = child
+                {
+                    for password in users.values_mut() {
+                        *password = Value::String("<redacted>".to_string());
+                    }
+                } else {
+                    redact_secrets_in(child, Some(key));
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| redact_secrets_in(item, parent_key)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instances(toml: &str) -> Result<Vec<AdapterInstance>, String> {
+        toml::from_str::<AppConfig>(toml).unwrap().adapter_instances()
+    }
+
+    fn instance(kind: AdapterKind, listen: &str) -> AdapterInstance {
+        AdapterInstance { kind, listen: listen.to_string() }
+    }
+
+    #[test]
+    fn without_adapters_section_runs_the_configured_smart_adapter() {
+        assert_eq!(instances("").unwrap(), [instance(AdapterKind::Smart, "0.0.0.0:1882")]);
+        assert_eq!(
+            instances("[adapter]\nlisten = \"127.0.0.1:2000\"\n").unwrap(),
+            [instance(AdapterKind::Smart, "127.0.0.1:2000")]
+        );
+        assert!(instances("[adapter]\nenabled = false\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn adapters_section_lists_instances() {
+        let toml = r#"
+            [adapters]
+            instances = [
+                { type = "smart", listen = "0.0.0.0:1882" },
+                { type = "legacy", listen = "0.0.0.0:1885" },
+                { type = "legacy", listen = "0.0.0.0:1886" },
+            ]
+        "#;
+        assert_eq!(
+            instances(toml).unwrap(),
+            [
+                instance(AdapterKind::Smart, "0.0.0.0:1882"),
+                instance(AdapterKind::Legacy, "0.0.0.0:1885"),
+                instance(AdapterKind::Legacy, "0.0.0.0:1886"),
+            ]
+        );
+    }
+
+    #[test]
+    fn adapters_section_overrides_adapter_enabled() {
+        let legacy_only = "[adapters]\ninstances = [{ type = \"legacy\", listen = \"0.0.0.0:1885\" }]\n[adapter]\nenabled = true\n";
+        assert_eq!(instances(legacy_only).unwrap(), [instance(AdapterKind::Legacy, "0.0.0.0:1885")]);
+        let none = "[adapters]\ninstances = []\n";
+        assert!(instances(none).unwrap().is_empty());
+        let smart_only = "[adapters]\ninstances = [{ type = \"smart\", listen = \"0.0.0.0:1890\" }]\n[adapter]\nenabled = false\n";
+        assert_eq!(instances(smart_only).unwrap(), [instance(AdapterKind::Smart, "0.0.0.0:1890")]);
+    }
+
+    #[test]
+    fn rejects_more_than_one_smart_instance() {
+        let toml = r#"
+            [adapters]
+            instances = [
+                { type = "smart", listen = "0.0.0.0:1882" },
+                { type = "smart", listen = "0.0.0.0:1887" },
+            ]
+        "#;
+        assert!(instances(toml).unwrap_err().contains("at most one"));
+    }
+
+    #[test]
+    fn rejects_unknown_instance_type() {
+        let toml = "[adapters]\ninstances = [{ type = \"bridge\", listen = \"0.0.0.0:1885\" }]\n";
+        assert!(toml::from_str::<AppConfig>(toml).is_err());
+    }
+}
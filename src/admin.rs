@@ -0,0 +1,808 @@
+// 适配器管理接口
+// HTTP 接口: 指标、健康检查和运行时控制
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, Request, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::{MethodRouter, get, post}};
+use futures_util::stream::{self, Stream};
+use log::{debug, info, warn};
+use rumqttd::Config;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+
+use crate::adapter_config::{AdminEndpoint, redact_secrets, strip_nulls};
+use crate::config_reload::Reloader;
+use crate::config_source::http_get;
+use crate::metrics::metrics;
+use crate::migration::MigrateRequest;
+use crate::packet_trace::{PacketTrace, TraceSnapshot};
+use crate::pause::PauseState;
+use crate::runtime::RuntimeState;
+use crate::self_test::SelfTestStatus;
+use crate::snapshot::{self, RuntimeSnapshot};
+
+/// 获取 broker 控制台数据的时限
+const CONSOLE_TIMEOUT: Duration = Duration::from_secs(2);
+/// broker 控制台响应的最大长度
+const MAX_CONSOLE_BYTES: usize = 1024 * 1024;
+
+/// 管理接口共享状态
+#[derive(Clone)]
+pub struct AdminState {
+    pub runtime: Arc<RuntimeState>,
+    /// 生效的 broker 配置
+    pub broker_config: Arc<Config>,
+    /// POST /reload 重新读取配置 (生效的适配器配置保存在 `runtime` 中)
+    pub reloader: Arc<Reloader>,
+}
+
+/// 启动管理接口: 只开放 `endpoints` 中的接口组 (为空时全部开放), 配置了 `token` 时所有请求都需要该令牌
+pub async fn start_admin_server(
+    listen: SocketAddr,
+    state: AdminState,
+    endpoints: &[AdminEndpoint],
+    token: Option<String>,
+) -> std::io::Result<()> {
+    let endpoints = if endpoints.is_empty() { &AdminEndpoint::ALL[..] } else { endpoints };
+    let app = app(state, endpoints, token.clone());
+
+    let server = axum::Server::try_bind(&listen)
+        .map_err(std::io::Error::other)?;
+    let names: Vec<&str> = endpoints.iter().map(|endpoint| endpoint.as_str()).collect();
+    info!(
+        "Admin API listening on {} ({}, {})",
+        listen,
+        names.join(", "),
+        if token.is_some() { "token required" } else { "no auth" }
+    );
+
+    server
+        .serve(app.into_make_service())
+        .await
+        .map_err(std::io::Error::other)
+}
+
+/// 管理接口的完整应用: 开放的路由, 配置了 `token` 时再加上令牌认证
+fn app(state: AdminState, endpoints: &[AdminEndpoint], token: Option<String>) -> Router {
+    let app = router(endpoints).with_state(state);
+    match token {
+        Some(token) => app.layer(middleware::from_fn_with_state(Arc::<str>::from(token), require_token)),
+        None => app,
+    }
+}
+
+/// 按开放的接口组注册路由; 同一路径的查询 (status) 和修改 (control) 方法分别注册, 未开放的方法返回 405
+fn router(endpoints: &[AdminEndpoint]) -> Router<AdminState> {
+    let status = endpoints.contains(&AdminEndpoint::Status);
+    let control = endpoints.contains(&AdminEndpoint::Control);
+    let mut app = Router::new();
+    if endpoints.contains(&AdminEndpoint::Metrics) {
+        app = app
+            .route("/metrics", get(metrics_handler))
+            .route("/scale-metric", get(scale_metric_handler));
+    }
+    if endpoints.contains(&AdminEndpoint::Health) {
+        app = app.route("/healthz", get(healthz_handler));
+    }
+    if status || control {
+        let mut maintenance = MethodRouter::new();
+        let mut drain = MethodRouter::new();
+        let mut trace = MethodRouter::new();
+        if status {
+            maintenance = maintenance.get(get_maintenance);
+            drain = drain.get(get_drain);
+            trace = trace.get(get_trace);
+        }
+        if control {
+            maintenance = maintenance.post(set_maintenance);
+            drain = drain.post(start_drain);
+            trace = trace.post(start_trace).delete(stop_trace);
+        }
+        app = app
+            .route("/maintenance", maintenance)
+            .route("/drain-and-handoff", drain)
+            .route("/connections/:id/trace", trace);
+    }
+    if status {
+        app = app
+            .route("/config", get(config_handler))
+            .route("/connections", get(connections_handler))
+            .route("/tenants", get(tenants_handler))
+            .route("/deny/client-id", get(denylist_handler))
+            .route("/events", get(events_handler))
+            .route("/recent", get(recent_handler))
+            .route("/overview", get(overview_handler));
+    }
+    if control {
+        app = app
+            .route("/reload", post(reload_handler))
+            .route("/snapshot", post(snapshot_handler))
+            .route("/connections/:id/migrate", post(migrate_handler))
+            .route("/connections/:id/pause", post(pause_handler))
+            .route("/connections/:id/resume", post(resume_handler))
+            .route("/trace/client-id/:id", post(start_trace_by_client_id))
+            .route("/deny/client-id/:id", post(deny_client_id).delete(allow_client_id));
+    }
+    #[cfg(feature = "admin-ui")]
+    if endpoints.contains(&AdminEndpoint::Dashboard) {
+        app = app
+            .route("/", get(dashboard_handler))
+            .route("/dashboard.js", get(dashboard_script_handler));
+    }
+    app
+}
+
+/// 令牌认证: 请求头 `Authorization: Bearer <token>` 与配置一致才继续处理, 否则返回 401
+async fn require_token<B>(State(token): State<Arc<str>>, request: Request<B>, next: Next<B>) -> Response {
+    let presented = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if presented.is_some_and(|presented| tokens_match(presented.as_bytes(), token.as_bytes())) {
+        return next.run(request).await;
+    }
+    debug!("Admin API: rejected {} {} without a valid token", request.method(), request.uri().path());
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(json!({ "error": "a valid bearer token is required" })),
+    )
+        .into_response()
+}
+
+/// 比较令牌, 耗时与第一个不同字节的位置无关
+fn tokens_match(presented: &[u8], expected: &[u8]) -> bool {
+    presented.len() == expected.len()
+        && presented.iter().zip(expected).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// GET /metrics
+/// Accept 请求头包含 application/openmetrics-text 时输出带 exemplar 的 OpenMetrics 格式
+async fn metrics_handler(headers: HeaderMap) -> Response {
+    let open_metrics = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+    if open_metrics {
+        (
+            [(header::CONTENT_TYPE, "application/openmetrics-text; version=1.0.0; charset=utf-8")],
+            metrics().render_openmetrics(),
+        )
+            .into_response()
+    } else {
+        metrics().render_prometheus().into_response()
+    }
+}
+
+/// GET / (admin-ui feature)
+/// 只读的管理面板, 页面只调用 GET 接口
+#[cfg(feature = "admin-ui")]
+async fn dashboard_handler() -> axum::response::Html<&'static str> {
+    axum::response::Html(include_str!("admin_ui/index.html"))
+}
+
+/// GET /dashboard.js (admin-ui feature)
+#[cfg(feature = "admin-ui")]
+async fn dashboard_script_handler() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/javascript; charset=utf-8")], include_str!("admin_ui/dashboard.js"))
+}
+
+/// GET /healthz
+/// 维护模式、排空或启动自检未通过时返回 503, 便于负载均衡器摘除该实例
+async fn healthz_handler(State(state): State<AdminState>) -> (StatusCode, Json<Value>) {
+    let maintenance = state.runtime.maintenance();
+    let draining = state.runtime.draining();
+    let self_test = state.runtime.self_test();
+    let status = if maintenance || draining || self_test.blocks_readiness() { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK };
+    let label = if draining {
+        "draining"
+    } else if maintenance {
+        "maintenance"
+    } else if self_test == SelfTestStatus::Failed {
+        "self_test_failed"
+    } else if self_test == SelfTestStatus::Running {
+        "starting"
+    } else {
+        "ok"
+    };
+    (status, Json(json!({
+        "status": label,
+        "maintenance": maintenance,
+        "draining": draining,
+        "self_test": self_test,
+    })))
+}
+
+#[derive(Deserialize)]
+struct ScaleMetricQuery {
+    /// active_connections (默认)、open_connections 或 connections_per_sec
+    metric: Option<String>,
+    /// 输出格式: text (默认, 只有数值) 或 json
+    format: Option<String>,
+}
+
+/// GET /scale-metric[?metric=...&format=json]
+/// 供自动扩缩容读取的单个数值, 默认为活动连接数
+async fn scale_metric_handler(State(state): State<AdminState>, Query(query): Query<ScaleMetricQuery>) -> Response {
+    let metric = query.metric.as_deref().unwrap_or("active_connections");
+    let value = match metric {
+        "active_connections" => json!(metrics().active_connections.load(Ordering::Relaxed)),
+        "open_connections" => json!(state.runtime.open_connections()),
+        // 最近 10 秒的平均值
+        "connections_per_sec" => json!(metrics().connections_per_sec()),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown metric {:?} (expected active_connections, open_connections or connections_per_sec)\n", metric),
+            )
+                .into_response();
+        }
+    };
+    if query.format.as_deref() == Some("json") {
+        Json(json!({ "metric": metric, "value": value })).into_response()
+    } else {
+        format!("{}\n", value).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+}
+
+/// GET /maintenance
+async fn get_maintenance(State(state): State<AdminState>) -> Json<Value> {
+    Json(json!({ "enabled": state.runtime.maintenance() }))
+}
+
+/// POST /maintenance {"enabled": true|false}
+async fn set_maintenance(
+    State(state): State<AdminState>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Json<Value> {
+    state.runtime.set_maintenance(request.enabled);
+    info!("Maintenance mode {}", if request.enabled { "enabled" } else { "disabled" });
+    Json(json!({ "enabled": request.enabled }))
+}
+
+/// GET /drain-and-handoff
+/// 排空进度: `open_connections` 降到 0 后可以停止该实例
+async fn get_drain(State(state): State<AdminState>) -> Json<Value> {
+    Json(drain_status(&state.runtime))
+}
+
+/// POST /drain-and-handoff
+/// 开始排空: 所有监听器关闭, 新连接由共享端口的其它实例 (或负载均衡的其它后端) 接受, 已有连接继续转发
+async fn start_drain(State(state): State<AdminState>) -> Json<Value> {
+    if state.runtime.start_drain() {
+        info!("Drain started: listeners closing, {} connections open", state.runtime.open_connections());
+    }
+    Json(drain_status(&state.runtime))
+}
+
+fn drain_status(runtime: &RuntimeState) -> Value {
+    let open = runtime.open_connections();
+    let draining = runtime.draining();
+    json!({
+        "draining": draining,
+        "open_connections": open,
+        "active_connections": runtime.connections().len(),
+        "drained": draining && open == 0,
+    })
+}
+
+#[derive(Deserialize)]
+struct ConfigQuery {
+    /// 输出格式: json (默认) 或 toml
+    format: Option<String>,
+}
+
+/// GET /config[?format=toml]
+/// 输出当前生效的完整配置, 敏感字段已隐藏
+async fn config_handler(State(state): State<AdminState>, Query(query): Query<ConfigQuery>) -> Response {
+    let mut config = json!({
+        "broker": &*state.broker_config,
+        "adapter": &*state.runtime.config(),
+    });
+    redact_secrets(&mut config);
+
+    if query.format.as_deref() == Some("toml") {
+        // TOML 没有 null, 未设置的可选字段直接省略
+        strip_nulls(&mut config);
+        match toml::to_string_pretty(&config) {
+            Ok(text) => ([(header::CONTENT_TYPE, "application/toml")], text).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    } else {
+        Json(config).into_response()
+    }
+}
+
+/// POST /reload
+/// 重新读取配置来源并应用可热加载的字段 (`config_reload::RELOADABLE_FIELDS`), 响应中列出已应用的字段和需要重启才能生效的改动
+/// 读取、解析或检查失败时返回 400 和全部错误, 生效的配置保持不变
+async fn reload_handler(State(state): State<AdminState>) -> (StatusCode, Json<Value>) {
+    match state.reloader.reload(&state.runtime).await {
+        Ok(summary) => {
+            info!(
+                "Configuration reloaded: applied [{}], restart required for [{}]",
+                summary.applied.join(", "),
+                summary.restart_required.join(", ")
+            );
+            (StatusCode::OK, Json(json!(summary)))
+        }
+        Err(errors) => {
+            warn!("Configuration reload rejected: {}", errors.join("; "));
+            (StatusCode::BAD_REQUEST, Json(json!({ "errors": errors })))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotQuery {
+    /// 快照中保留密码等敏感字段 (默认隐藏)
+    #[serde(default)]
+    include_secrets: bool,
+}
+
+/// POST /snapshot[?include_secrets=true]
+/// 把生效的配置和运行时状态写入 `snapshot_path`, 以该文件启动即可恢复; 响应中只有摘要, 不含快照内容
+async fn snapshot_handler(State(state): State<AdminState>, Query(query): Query<SnapshotQuery>) -> (StatusCode, Json<Value>) {
+    let adapter_config = state.runtime.config();
+    let Some(path) = &adapter_config.snapshot_path else {
+        return (StatusCode::CONFLICT, Json(json!({ "error": "snapshot_path is not configured" })));
+    };
+    let runtime = RuntimeSnapshot::capture(&state.runtime);
+    let written = snapshot::render(&state.broker_config, &adapter_config, &runtime, query.include_secrets)
+        .map_err(std::io::Error::other)
+        .and_then(|content| snapshot::write(path, &content));
+    if let Err(e) = written {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("failed to write snapshot: {}", e) })));
+    }
+    info!("Snapshot written to {} (secrets {})", path, if query.include_secrets { "included" } else { "redacted" });
+    (StatusCode::OK, Json(json!({
+        "path": path,
+        "taken_at_ms": runtime.taken_at_ms,
+        "maintenance": runtime.maintenance,
+        "denied_client_ids": runtime.denied_client_ids.len(),
+        "secrets_included": query.include_secrets,
+    })))
+}
+
+/// GET /connections
+async fn connections_handler(State(state): State<AdminState>) -> Json<Value> {
+    Json(json!({ "connections": state.runtime.connections() }))
+}
+
+/// GET /overview
+/// 适配器的状态和全部指标, 加上 broker 控制台 (rumqttd [console]) 返回的数据; 控制台不可达时只有适配器部分和一条说明
+async fn overview_handler(State(state): State<AdminState>) -> Json<Value> {
+    let connections = state.runtime.connections();
+    let adapter = json!({
+        "maintenance": state.runtime.maintenance(),
+        "draining": state.runtime.draining(),
+        "connections": connections.len(),
+        "paused_connections": connections.iter().filter(|connection| connection.paused).count(),
+        "tracked_client_ids": state.runtime.tracked_client_ids(),
+        "metrics": metrics().render_json(),
+    });
+    Json(json!({ "adapter": adapter, "broker": broker_console(&state.broker_config).await }))
+}
+
+/// 获取 broker 控制台的根路径 (rumqttd 0.19 只在这里返回 JSON, 其余路径把状态打印到 broker 日志)
+async fn broker_console(config: &Config) -> Value {
+    let Some(console) = &config.console else {
+        return json!({ "console": null, "reachable": false, "note": "no [console] configured" });
+    };
+    let url = format!("http://{}/", console_address(&console.listen));
+    let fetched = match tokio::time::timeout(CONSOLE_TIMEOUT, http_get(&url, "application/json", MAX_CONSOLE_BYTES)).await {
+        Err(_) => Err(format!("no response within {:?}", CONSOLE_TIMEOUT)),
+        Ok(Err(e)) => Err(e.to_string()),
+        Ok(Ok(body)) => serde_json::from_slice::<Value>(&body).map_err(|e| format!("invalid JSON: {}", e)),
+    };
+    match fetched {
+        Ok(settings) => json!({ "console": console.listen, "reachable": true, "settings": settings }),
+        Err(e) => {
+            debug!("Broker console {} unreachable: {}", url, e);
+            json!({ "console": console.listen, "reachable": false, "note": format!("broker console unreachable: {}", e) })
+        }
+    }
+}
+
+/// 控制台监听在通配地址时通过本机回环地址访问
+fn console_address(listen: &str) -> String {
+    match listen.parse::<SocketAddr>() {
+        Ok(addr) if addr.ip().is_unspecified() => {
+            let loopback = if addr.is_ipv4() { IpAddr::V4(Ipv4Addr::LOCALHOST) } else { IpAddr::V6(Ipv6Addr::LOCALHOST) };
+            SocketAddr::new(loopback, addr.port()).to_string()
+        }
+        _ => listen.to_string(),
+    }
+}
+
+/// GET /tenants
+/// 每个租户持有准入许可的连接数, 未配置 [adapter.admission] 时返回 404
+async fn tenants_handler(State(state): State<AdminState>) -> (StatusCode, Json<Value>) {
+    match state.runtime.admission() {
+        Some(admission) => (StatusCode::OK, Json(json!({ "tenants": admission.tenant_connections() }))),
+        None => (StatusCode::NOT_FOUND, Json(json!({ "error": "admission is disabled" }))),
+    }
+}
+
+/// GET /deny/client-id
+async fn denylist_handler(State(state): State<AdminState>) -> Json<Value> {
+    Json(json!({ "client_ids": state.runtime.denylist().ids() }))
+}
+
+/// POST /deny/client-id/{id}
+/// 之后该客户端 ID 的 CONNECT 被拒绝; 已有的连接不受影响, 响应中列出它们的连接 ID
+async fn deny_client_id(State(state): State<AdminState>, Path(client_id): Path<String>) -> (StatusCode, Json<Value>) {
+    // 持久化文件每行一个 ID
+    if client_id.contains(['\r', '\n']) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "client ID must not contain line breaks" })));
+    }
+    let added = match state.runtime.denylist().insert(&client_id) {
+        Ok(added) => added,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("failed to save denylist: {}", e) }))),
+    };
+    if added {
+        info!("Denylist: added client ID {:?}", client_id);
+    }
+    let active: Vec<u64> = state.runtime.connections().into_iter()
+        .filter(|connection| connection.client_id == client_id)
+        .map(|connection| connection.id)
+        .collect();
+    (StatusCode::OK, Json(json!({ "client_id": client_id, "denied": true, "active_connections": active })))
+}
+
+/// DELETE /deny/client-id/{id}
+async fn allow_client_id(State(state): State<AdminState>, Path(client_id): Path<String>) -> (StatusCode, Json<Value>) {
+    match state.runtime.denylist().remove(&client_id) {
+        Ok(true) => {
+            info!("Denylist: removed client ID {:?}", client_id);
+            (StatusCode::OK, Json(json!({ "client_id": client_id, "denied": false })))
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(json!({ "error": "client ID is not denied" }))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("failed to save denylist: {}", e) }))),
+    }
+}
+
+#[derive(Deserialize)]
+struct MigrateBody {
+    /// 新后端地址 (host:port)
+    backend: String,
+}
+
+/// POST /connections/{id}/migrate {"backend": "host:port"}
+/// 把连接切换到新后端, 连接不满足迁移条件时返回 409 和原因
+async fn migrate_handler(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+    Json(body): Json<MigrateBody>,
+) -> (StatusCode, Json<Value>) {
+    let control = match state.runtime.migration_control(id) {
+        None => return (StatusCode::NOT_FOUND, Json(json!({ "error": "connection not found" }))),
+        Some(None) => {
+            return (StatusCode::CONFLICT, Json(json!({ "error": "backend migration is disabled" })));
+        }
+        Some(Some(control)) => control,
+    };
+
+    let (reply, result) = oneshot::channel();
+    let request = MigrateRequest { backend: body.backend.clone(), reply };
+    // 转发任务同时只处理一个迁移请求
+    if control.try_send(request).is_err() {
+        return (StatusCode::CONFLICT, Json(json!({ "error": "migration already in progress" })));
+    }
+
+    // 转发任务在 5 秒内完成或放弃迁移, 这里多留一些余量
+    match tokio::time::timeout(Duration::from_secs(10), result).await {
+        Ok(Ok(Ok(()))) => (StatusCode::OK, Json(json!({ "id": id, "backend": body.backend }))),
+        Ok(Ok(Err(reason))) => (StatusCode::CONFLICT, Json(json!({ "error": reason }))),
+        // 连接在处理请求前已关闭
+        Ok(Err(_)) => (StatusCode::NOT_FOUND, Json(json!({ "error": "connection closed" }))),
+        Err(_) => (StatusCode::GATEWAY_TIMEOUT, Json(json!({ "error": "migration timed out" }))),
+    }
+}
+
+/// POST /connections/{id}/pause
+/// 停止该连接两个方向的转发 (连接保持打开), `max_pause_ms` 后自动恢复; 重复暂停会重新计时
+async fn pause_handler(State(state): State<AdminState>, Path(id): Path<u64>) -> (StatusCode, Json<Value>) {
+    let max_pause = Duration::from_millis(state.runtime.config().max_pause_ms);
+    set_paused(&state, id, PauseState::paused_for(max_pause))
+}
+
+/// POST /connections/{id}/resume
+async fn resume_handler(State(state): State<AdminState>, Path(id): Path<u64>) -> (StatusCode, Json<Value>) {
+    set_paused(&state, id, PauseState::Running)
+}
+
+fn set_paused(state: &AdminState, id: u64, pause: PauseState) -> (StatusCode, Json<Value>) {
+    let paused = pause.is_paused();
+    match state.runtime.set_paused(id, pause) {
+        None => (StatusCode::NOT_FOUND, Json(json!({ "error": "connection not found" }))),
+        Some(false) => (StatusCode::CONFLICT, Json(json!({ "error": "pausing is not supported with backend_migration" }))),
+        Some(true) => {
+            info!("Connection {}: forwarding {}", id, if paused { "paused" } else { "resumed" });
+            (StatusCode::OK, Json(json!({ "id": id, "paused": paused })))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TraceQuery {
+    /// 最多记录的包数, 默认且不超过 `packet_trace_max_packets`
+    packets: Option<usize>,
+}
+
+/// POST /connections/{id}/trace?packets=N
+/// 开始记录该连接转发的包 (丢弃上一次的记录), 记满后自动停止
+async fn start_trace(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+    Query(query): Query<TraceQuery>,
+) -> (StatusCode, Json<Value>) {
+    arm_trace(&state, id, query.packets)
+}
+
+/// POST /trace/client-id/{client_id}?packets=N
+/// 同 POST /connections/{id}/trace, 记录使用该客户端 ID 的活动连接 (有多个时取 ID 最小的)
+async fn start_trace_by_client_id(
+    State(state): State<AdminState>,
+    Path(client_id): Path<String>,
+    Query(query): Query<TraceQuery>,
+) -> (StatusCode, Json<Value>) {
+    match state.runtime.connection_id_for_client(&client_id) {
+        None => (StatusCode::NOT_FOUND, Json(json!({ "error": "no active connection with this client ID" }))),
+        Some(id) => arm_trace(&state, id, query.packets),
+    }
+}
+
+fn arm_trace(state: &AdminState, id: u64, packets: Option<usize>) -> (StatusCode, Json<Value>) {
+    let max_packets = state.runtime.config().packet_trace_max_packets;
+    let trace = match find_trace(state, id) {
+        Ok(trace) => trace,
+        Err(response) => return response,
+    };
+    let limit = packets.unwrap_or(max_packets).clamp(1, max_packets);
+    trace.arm(limit);
+    info!("Connection {}: tracing up to {} packets", id, limit);
+    (StatusCode::OK, Json(json!({ "id": id, "armed": true, "limit": limit })))
+}
+
+/// GET /connections/{id}/trace
+/// 已记录的包, 记录中的连接也可以查询
+async fn get_trace(State(state): State<AdminState>, Path(id): Path<u64>) -> (StatusCode, Json<Value>) {
+    match find_trace(&state, id) {
+        Ok(trace) => (StatusCode::OK, trace_json(id, trace.snapshot())),
+        Err(response) => response,
+    }
+}
+
+/// DELETE /connections/{id}/trace
+/// 停止记录并返回已记录的包
+async fn stop_trace(State(state): State<AdminState>, Path(id): Path<u64>) -> (StatusCode, Json<Value>) {
+    match find_trace(&state, id) {
+        Ok(trace) => (StatusCode::OK, trace_json(id, trace.disarm())),
+        Err(response) => response,
+    }
+}
+
+fn find_trace(state: &AdminState, id: u64) -> Result<Arc<PacketTrace>, (StatusCode, Json<Value>)> {
+    if state.runtime.config().packet_trace_max_packets == 0 {
+        return Err((StatusCode::CONFLICT, Json(json!({ "error": "packet tracing is disabled (packet_trace_max_packets = 0)" }))));
+    }
+    match state.runtime.packet_trace(id) {
+        None => Err((StatusCode::NOT_FOUND, Json(json!({ "error": "connection not found" })))),
+        Some(None) => Err((StatusCode::CONFLICT, Json(json!({ "error": "packet tracing is not supported with backend_migration" })))),
+        Some(Some(trace)) => Ok(trace),
+    }
+}
+
+fn trace_json(id: u64, snapshot: TraceSnapshot) -> Json<Value> {
+    Json(json!({ "id": id, "armed": snapshot.armed, "limit": snapshot.limit, "packets": snapshot.packets }))
+}
+
+/// GET /events
+/// 以 Server-Sent Events 推送连接事件, 每个事件的 data 是一个 JSON 对象
+/// 订阅者落后超过广播通道容量时断开该订阅者, 不会阻塞连接处理
+async fn events_handler(
+    State(state): State<AdminState>,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    let events = stream::unfold(state.runtime.subscribe_events(), |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((Event::default().json_data(event), receiver)),
+            Err(RecvError::Lagged(skipped)) => {
+                info!("Dropping slow event stream subscriber ({} events behind)", skipped);
+                None
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// GET /recent
+/// 最近的连接事件 (最多 `RECENT_EVENTS_CAPACITY` 个, 最早的在前), 格式与 GET /events 的 data 相同
+async fn recent_handler(State(state): State<AdminState>) -> Json<Value> {
+    Json(json!({ "events": state.runtime.recent_events() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Method;
+    use tower::ServiceExt;
+    use crate::adapter_config::AdapterConfig;
+    use crate::config_source::ConfigSource;
+
+    const TOKEN: &str = "test-token";
+
+    fn admin_state() -> AdminState {
+        admin_state_from(ConfigSource::Stdin, AdapterConfig::default())
+    }
+
+    fn admin_state_from(source: ConfigSource, adapter_config: AdapterConfig) -> AdminState {
+        let broker_config: Arc<Config> = Arc::new(toml::from_str(include_str!("../config.toml")).unwrap());
+        AdminState {
+            runtime: Arc::new(RuntimeState::new(&adapter_config).unwrap()),
+            reloader: Arc::new(Reloader::new(source, broker_config.clone(), Vec::new())),
+            broker_config,
+        }
+    }
+
+    /// 对新建的管理接口发送一个请求, 返回状态码
+    async fn status_of(endpoints: &[AdminEndpoint], token: Option<&str>, method: Method, path: &str, bearer: Option<&str>) -> StatusCode {
+        let app = app(admin_state(), endpoints, token.map(str::to_string));
+        let mut request = Request::builder().method(method.clone()).uri(path);
+        if let Some(bearer) = bearer {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", bearer));
+        }
+        let body = if method == Method::POST {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+            Body::from(r#"{"enabled": false}"#)
+        } else {
+            Body::empty()
+        };
+        app.oneshot(request.body(body).unwrap()).await.unwrap().status()
+    }
+
+    /// 各接口组的一个代表请求
+    fn probes() -> [(AdminEndpoint, Method, &'static str); 4] {
+        [
+            (AdminEndpoint::Metrics, Method::GET, "/metrics"),
+            (AdminEndpoint::Health, Method::GET, "/healthz"),
+            (AdminEndpoint::Status, Method::GET, "/maintenance"),
+            (AdminEndpoint::Control, Method::POST, "/maintenance"),
+        ]
+    }
+
+    fn is_served(status: StatusCode) -> bool {
+        status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED && status != StatusCode::UNAUTHORIZED
+    }
+
+    #[tokio::test]
+    async fn each_group_serves_only_its_routes() {
+        for (group, _, _) in probes() {
+            for (probe_group, method, path) in probes() {
+                let status = status_of(&[group], None, method.clone(), path, None).await;
+                assert_eq!(is_served(status), probe_group == group, "{:?} listener, {} {} -> {}", group, method, path, status);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn status_without_control_rejects_writes_with_405() {
+        let status = status_of(&[AdminEndpoint::Status], None, Method::POST, "/maintenance", None).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let status = status_of(&[AdminEndpoint::Metrics], None, Method::GET, "/connections", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn all_groups_without_token_serve_everything() {
+        for (_, method, path) in probes() {
+            let status = status_of(&AdminEndpoint::ALL, None, method.clone(), path, None).await;
+            assert!(is_served(status), "{} {} -> {}", method, path, status);
+        }
+    }
+
+    #[tokio::test]
+    async fn token_is_required_on_every_group() {
+        for (group, method, path) in probes() {
+            let missing = status_of(&[group], Some(TOKEN), method.clone(), path, None).await;
+            assert_eq!(missing, StatusCode::UNAUTHORIZED, "{:?} without a token", group);
+            let wrong = status_of(&[group], Some(TOKEN), method.clone(), path, Some("wrong-token")).await;
+            assert_eq!(wrong, StatusCode::UNAUTHORIZED, "{:?} with a wrong token", group);
+            let valid = status_of(&[group], Some(TOKEN), method.clone(), path, Some(TOKEN)).await;
+            assert!(is_served(valid), "{:?} with the token -> {}", group, valid);
+        }
+    }
+
+    #[tokio::test]
+    async fn unopened_routes_stay_hidden_behind_the_token() {
+        // 令牌正确但接口组未开放时仍返回 404
+        let status = status_of(&[AdminEndpoint::Metrics], Some(TOKEN), Method::GET, "/connections", Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn tokens_match_compares_whole_tokens() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secret", b"secreT"));
+        assert!(!tokens_match(b"secret", b"secret-longer"));
+        assert!(!tokens_match(b"", b"secret"));
+    }
+
+    /// 以随仓库提供的 config.toml 启动, 之后把配置文件改为 `content` 并 POST /reload
+    async fn reload_with(content: &str, bearer: Option<&str>) -> (StatusCode, Value, AdminState) {
+        let path = std::env::temp_dir().join(format!("reload-{}-{}.toml", std::process::id(), next_id()));
+        let shipped = include_str!("../config.toml");
+        let (_, adapter_config, instances, _) = crate::parse_config(shipped).unwrap();
+        let source = ConfigSource::File(path.to_string_lossy().into_owned());
+        let mut state = admin_state_from(source.clone(), adapter_config);
+        state.reloader = Arc::new(Reloader::new(source, state.broker_config.clone(), instances));
+        std::fs::write(&path, content).unwrap();
+
+        let mut request = Request::builder().method(Method::POST).uri("/reload");
+        if let Some(bearer) = bearer {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", bearer));
+        }
+        let app = app(state.clone(), &[AdminEndpoint::Control], Some(TOKEN.to_string()));
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null), state)
+    }
+
+    fn next_id() -> usize {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn reload_applies_reloadable_fields_and_reports_the_rest() {
+        let content = include_str!("../config.toml")
+            .replacen("backend_connect_retries = 0", "backend_connect_retries = 3", 1)
+            .replacen("reuse_port = false", "reuse_port = true", 1);
+        let (status, body, state) = reload_with(&content, Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, json!({ "applied": ["backend_connect_retries"], "restart_required": ["reuse_port"] }));
+        let config = state.runtime.config();
+        assert_eq!(config.backend_connect_retries, 3);
+        assert!(!config.reuse_port, "non-reloadable fields keep their running value");
+    }
+
+    #[tokio::test]
+    async fn invalid_reload_is_rejected_and_leaves_the_config_untouched() {
+        let content = include_str!("../config.toml")
+            .replacen("backend_connect_retries = 0", "backend_connect_retries = 3", 1)
+            .replacen("max_remaining_length_bytes = 4", "max_remaining_length_bytes = 9", 1);
+        let (status, body, state) = reload_with(&content, Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let errors = body["errors"].as_array().unwrap();
+        assert!(errors.iter().any(|e| e.as_str().unwrap().contains("max_remaining_length_bytes")), "{}", body);
+        assert_eq!(state.runtime.config().backend_connect_retries, 0);
+
+        let (status, body, _) = reload_with("[adapter\n", Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["errors"][0].as_str().unwrap().contains("Failed to parse"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn reload_requires_the_token() {
+        let (status, _, _) = reload_with(include_str!("../config.toml"), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
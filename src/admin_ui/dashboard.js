@@ -0,0 +1,120 @@
+// 管理面板脚本: 启动时读取当前状态和最近事件, 之后按 SSE 事件流追加事件并刷新连接统计
+// 只发送 GET 请求; 客户端提供的字段 (客户端 ID 等) 一律以文本插入页面
+"use strict";
+
+// 与服务端 RECENT_EVENTS_CAPACITY 一致
+const MAX_EVENTS = 100;
+// 事件密集时合并刷新, 避免每个事件都请求一次
+const REFRESH_DEBOUNCE_MS = 500;
+// 没有事件时也定期刷新 (暂停、维护模式等不产生连接事件)
+const REFRESH_INTERVAL_MS = 10000;
+
+const $ = (id) => document.getElementById(id);
+
+async function getJson(path) {
+  const response = await fetch(path, { headers: { Accept: "application/json" } });
+  if (!response.ok) {
+    throw new Error(path + ": HTTP " + response.status);
+  }
+  return response.json();
+}
+
+function cell(row, text) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  row.appendChild(td);
+}
+
+async function refresh() {
+  try {
+    const [overview, list] = await Promise.all([getJson("/overview"), getJson("/connections")]);
+    const adapter = overview.adapter;
+    $("connections").textContent = adapter.connections;
+    $("paused").textContent = adapter.paused_connections;
+    const state = adapter.draining ? "draining" : adapter.maintenance ? "maintenance" : "ok";
+    $("state").textContent = state;
+    $("state").className = state === "ok" ? "value" : "value warn";
+    $("broker").textContent = overview.broker.reachable ? "reachable" : "unreachable";
+    $("broker").className = overview.broker.reachable ? "value" : "value warn";
+
+    const versions = new Map();
+    for (const connection of list.connections) {
+      versions.set(connection.version, (versions.get(connection.version) || 0) + 1);
+    }
+    const body = $("versions");
+    body.replaceChildren();
+    for (const [version, count] of [...versions].sort()) {
+      const row = body.insertRow();
+      cell(row, version);
+      cell(row, count);
+    }
+  } catch (e) {
+    $("status").textContent = e.message;
+  }
+}
+
+let refreshTimer = null;
+function scheduleRefresh() {
+  if (refreshTimer === null) {
+    refreshTimer = setTimeout(() => {
+      refreshTimer = null;
+      refresh();
+    }, REFRESH_DEBOUNCE_MS);
+  }
+}
+
+function describe(event) {
+  switch (event.type) {
+    case "connected":
+      return event.client_id + " (" + event.version + ") from " + event.peer + " → " + event.backend;
+    case "connect_rejected":
+      return event.client_id + " from " + event.peer + " rejected by " + event.backend + " (reason 0x" + event.reason_code.toString(16) + ")";
+    case "adapter_closed":
+      return event.client_id + " from " + event.peer + ": " + event.reason;
+    case "disconnected":
+      return event.reason;
+    case "error":
+      return event.peer + ": " + event.category + ": " + event.message;
+    default:
+      return JSON.stringify(event);
+  }
+}
+
+// 最新的事件在最上面
+function addEvent(event) {
+  const body = $("events");
+  const row = body.insertRow(0);
+  cell(row, new Date(event.timestamp_ms).toLocaleTimeString());
+  cell(row, event.type);
+  cell(row, event.id);
+  cell(row, describe(event));
+  while (body.rows.length > MAX_EVENTS) {
+    body.deleteRow(body.rows.length - 1);
+  }
+}
+
+async function start() {
+  refresh();
+  setInterval(refresh, REFRESH_INTERVAL_MS);
+  try {
+    const recent = await getJson("/recent");
+    recent.events.forEach(addEvent);
+  } catch (e) {
+    $("status").textContent = e.message;
+  }
+
+  // EventSource 断开后自动重连
+  const source = new EventSource("/events");
+  source.onopen = () => {
+    $("status").textContent = "live";
+  };
+  source.onerror = () => {
+    $("status").textContent = "event stream disconnected, reconnecting…";
+  };
+  source.onmessage = (message) => {
+    addEvent(JSON.parse(message.data));
+    scheduleRefresh();
+  };
+}
+
+start();
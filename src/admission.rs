@@ -0,0 +1,281 @@
+// 按租户公平准入
+// 活动连接数达到上限时, 新连接按租户 (客户端 ID 前缀) 分队排队, 有连接结束时在各租户之间轮流放行;
+// 还可以限制每个租户的连接数, 避免单个租户占满全局连接数
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::adapter_config::AdmissionConfig;
+use crate::metrics::metrics;
+
+/// 准入失败原因 (`admission_rejected_total` 的 reason 标签)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionRejection {
+    /// 该租户的等待队列已满
+    QueueFull,
+    /// 排队超过 `queue_timeout_ms`
+    Timeout,
+    /// 租户的活动和排队连接数已达 `max_connections_per_tenant`
+    QuotaExceeded,
+}
+
+impl AdmissionRejection {
+    pub const ALL: [AdmissionRejection; 3] =
+        [AdmissionRejection::QueueFull, AdmissionRejection::Timeout, AdmissionRejection::QuotaExceeded];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdmissionRejection::QueueFull => "queue_full",
+            AdmissionRejection::Timeout => "timeout",
+            AdmissionRejection::QuotaExceeded => "quota_exceeded",
+        }
+    }
+
+    /// adapter_closed 事件的原因名称
+    pub fn closed_reason(self) -> &'static str {
+        match self {
+            AdmissionRejection::QueueFull => "admission_queue_full",
+            AdmissionRejection::Timeout => "admission_timeout",
+            AdmissionRejection::QuotaExceeded => "admission_quota_exceeded",
+        }
+    }
+}
+
+/// 按租户轮转的准入队列
+pub struct AdmissionQueue {
+    max_connections: usize,
+    max_queue_per_tenant: usize,
+    /// 0 = 不限制
+    max_connections_per_tenant: usize,
+    queue_timeout: Duration,
+    tenant_delimiter: String,
+    state: Mutex<AdmissionState>,
+}
+
+#[derive(Default)]
+struct AdmissionState {
+    /// 持有许可的连接数
+    active: usize,
+    /// 每个租户持有许可的连接数 (没有连接的租户不在表中)
+    active_per_tenant: HashMap<String, usize>,
+    /// 每个租户的等待者, 许可通过 oneshot 直接转交
+    waiters: HashMap<String, VecDeque<oneshot::Sender<()>>>,
+    /// 有等待者的租户, 按轮转顺序
+    turn: VecDeque<String>,
+}
+
+/// 准入许可, 连接结束时释放
+pub struct AdmissionPermit {
+    queue: Arc<AdmissionQueue>,
+    tenant: String,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.queue.release(&self.tenant);
+    }
+}
+
+impl AdmissionQueue {
+    pub fn new(config: &AdmissionConfig) -> Self {
+        AdmissionQueue {
+            max_connections: config.max_connections.max(1),
+            max_queue_per_tenant: config.max_queue_per_tenant,
+            max_connections_per_tenant: config.max_connections_per_tenant,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            tenant_delimiter: config.tenant_delimiter.clone(),
+            state: Mutex::new(AdmissionState::default()),
+        }
+    }
+
+    /// 客户端 ID 所属的租户: 第一个分隔符之前的部分, 没有分隔符时为默认租户 ("")
+    pub fn tenant_of<'a>(&self, client_id: &'a str) -> &'a str {
+        if self.tenant_delimiter.is_empty() {
+            return "";
+        }
+        client_id.split_once(self.tenant_delimiter.as_str()).map_or("", |(tenant, _)| tenant)
+    }
+
+    /// 每个租户持有许可的连接数 (GET /tenants)
+    pub fn tenant_connections(&self) -> BTreeMap<String, usize> {
+        let state = self.state.lock().unwrap();
+        state.active_per_tenant.iter().map(|(tenant, count)| (tenant.clone(), *count)).collect()
+    }
+
+    /// 取得许可, 活动连接数已满时在租户队列中等待
+    pub async fn admit(self: &Arc<Self>, tenant: &str) -> Result<AdmissionPermit, AdmissionRejection> {
+        let permit = || AdmissionPermit { queue: self.clone(), tenant: tenant.to_string() };
+        let mut rx = {
+            let mut state = self.state.lock().unwrap();
+            // 排队的连接也计入租户配额, 保证转交许可时不会超出
+            if self.max_connections_per_tenant > 0 {
+                let active = state.active_per_tenant.get(tenant).copied().unwrap_or(0);
+                let queued = state.waiters.get(tenant).map_or(0, VecDeque::len);
+                if active + queued >= self.max_connections_per_tenant {
+                    metrics().record_admission_rejection(AdmissionRejection::QuotaExceeded);
+                    return Err(AdmissionRejection::QuotaExceeded);
+                }
+            }
+
+            if state.active < self.max_connections {
+                state.active += 1;
+                *state.active_per_tenant.entry(tenant.to_string()).or_default() += 1;
+                return Ok(permit());
+            }
+
+            let queue = state.waiters.entry(tenant.to_string()).or_default();
+            if queue.len() >= self.max_queue_per_tenant {
+                metrics().record_admission_rejection(AdmissionRejection::QueueFull);
+                return Err(AdmissionRejection::QueueFull);
+            }
+            let (tx, rx) = oneshot::channel();
+            queue.push_back(tx);
+            let depth = queue.len();
+            if depth == 1 {
+                state.turn.push_back(tenant.to_string());
+            }
+            metrics().set_admission_queue_depth(tenant, depth);
+            rx
+        };
+
+        match tokio::time::timeout(self.queue_timeout, &mut rx).await {
+            Ok(Ok(())) => Ok(permit()),
+            // 队列不会在等待者之前被丢弃, 这里只处理超时
+            _ => {
+                rx.close();
+                // 许可可能在超时的同时转交过来, 此时仍然接受
+                if rx.try_recv().is_ok() {
+                    return Ok(permit());
+                }
+                self.remove_abandoned(tenant);
+                metrics().record_admission_rejection(AdmissionRejection::Timeout);
+                Err(AdmissionRejection::Timeout)
+            }
+        }
+    }
+
+    /// 释放许可: 按租户轮转转交给下一个等待者, 没有等待者时归还
+    fn release(&self, released_tenant: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.active_per_tenant.get_mut(released_tenant) {
+            *count -= 1;
+            if *count == 0 {
+                state.active_per_tenant.remove(released_tenant);
+            }
+        }
+
+        while let Some(tenant) = state.turn.pop_front() {
+            let Some(queue) = state.waiters.get_mut(&tenant) else {
+                continue;
+            };
+            let waiter = queue.pop_front();
+            let depth = queue.len();
+            if depth == 0 {
+                state.waiters.remove(&tenant);
+            } else {
+                state.turn.push_back(tenant.clone());
+            }
+            metrics().set_admission_queue_depth(&tenant, depth);
+
+            // 等待者已超时离开时转交给下一个
+            if let Some(waiter) = waiter
+                && waiter.send(()).is_ok()
+            {
+                *state.active_per_tenant.entry(tenant).or_default() += 1;
+                return;
+            }
+        }
+        state.active -= 1;
+    }
+
+    /// 清理超时离开的等待者
+    fn remove_abandoned(&self, tenant: &str) {
+        let mut state = self.state.lock().unwrap();
+        let Some(queue) = state.waiters.get_mut(tenant) else {
+            return;
+        };
+        queue.retain(|waiter| !waiter.is_closed());
+        let depth = queue.len();
+        if depth == 0 {
+            state.waiters.remove(tenant);
+            state.turn.retain(|queued| queued != tenant);
+        }
+        metrics().set_admission_queue_depth(tenant, depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_connections: usize, max_connections_per_tenant: usize) -> Arc<AdmissionQueue> {
+        Arc::new(AdmissionQueue::new(&AdmissionConfig {
+            max_connections,
+            max_connections_per_tenant,
+            queue_timeout_ms: 100,
+            ..AdmissionConfig::default()
+        }))
+    }
+
+    #[test]
+    fn tenant_is_the_client_id_prefix() {
+        let queue = queue(10, 0);
+        assert_eq!(queue.tenant_of("acme-sensor-1"), "acme");
+        assert_eq!(queue.tenant_of("sensor"), "");
+    }
+
+    #[tokio::test]
+    async fn per_tenant_cap_admits_up_to_the_limit() {
+        let queue = queue(10, 2);
+        let first = queue.admit("acme").await.unwrap();
+        let second = queue.admit("acme").await.unwrap();
+        assert_eq!(queue.tenant_connections(), BTreeMap::from([("acme".to_string(), 2)]));
+        drop((first, second));
+        assert!(queue.tenant_connections().is_empty());
+    }
+
+    #[tokio::test]
+    async fn per_tenant_cap_rejects_when_exceeded() {
+        let queue = queue(10, 2);
+        let _first = queue.admit("acme").await.unwrap();
+        let second = queue.admit("acme").await.unwrap();
+        assert_eq!(queue.admit("acme").await.err(), Some(AdmissionRejection::QuotaExceeded));
+
+        // 其它租户不受影响
+        let _other = queue.admit("globex").await.unwrap();
+
+        // 连接结束后配额释放
+        drop(second);
+        let _third = queue.admit("acme").await.unwrap();
+        assert_eq!(queue.admit("acme").await.err(), Some(AdmissionRejection::QuotaExceeded));
+        assert_eq!(queue.tenant_connections().get("acme"), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn queued_connections_count_towards_the_cap() {
+        // 全局上限 1: 第二个连接排队, 排队的连接也占用租户配额
+        let queue = queue(1, 2);
+        let first = queue.admit("acme").await.unwrap();
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.admit("acme").await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.admit("acme").await.err(), Some(AdmissionRejection::QuotaExceeded));
+
+        drop(first);
+        assert_eq!(waiter.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn zero_cap_is_unlimited() {
+        let queue = queue(100, 0);
+        let mut permits = Vec::new();
+        for _ in 0..50 {
+            permits.push(queue.admit("acme").await.unwrap());
+        }
+        assert_eq!(queue.tenant_connections().get("acme"), Some(&50));
+    }
+}
@@ -0,0 +1,156 @@
+// 连接认证钩子
+// 在转发 CONNECT 到 broker 之前由适配器调用
+
+use log::warn;
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::adapter_config::NonceAuthConfig;
+use crate::connack::ConnackReason;
+use crate::connect_packet::ConnectPacket;
+
+/// 认证输入
+pub struct AuthRequest<'a> {
+    /// 客户端地址
+    pub peer: SocketAddr,
+    /// 已解析的 CONNECT (包含 5.0 用户属性)
+    pub connect: &'a ConnectPacket,
+    /// 日志中使用的客户端 ID (按 [adapter.logging] 脱敏)
+    pub log_client_id: &'a str,
+}
+
+/// 认证结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthDecision {
+    Allow,
+    /// 拒绝,以对应原因码回复 CONNACK
+    Deny(ConnackReason),
+}
+
+/// 认证钩子
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, request: &AuthRequest<'_>) -> AuthDecision;
+}
+
+/// 基于 5.0 用户属性中 nonce 的防重放认证
+/// 在时间窗口内见过的 nonce 会被拒绝; 超出窗口的记录自动过期。
+/// 记录已满时拒绝新的 nonce (丢弃窗口内的记录会让它们可以被重放)
+pub struct NonceAuthenticator {
+    property: String,
+    window: Duration,
+    max_entries: usize,
+    seen: Mutex<NonceStore>,
+}
+
+#[derive(Default)]
+struct NonceStore {
+    nonces: HashSet<String>,
+    /// 按记录时间排序, 用于过期
+    order: VecDeque<(Instant, String)>,
+}
+
+impl NonceAuthenticator {
+    pub fn new(config: &NonceAuthConfig) -> Self {
+        NonceAuthenticator {
+            property: config.property.clone(),
+            window: Duration::from_secs(config.window_sec),
+            max_entries: config.max_entries.max(1),
+            seen: Mutex::new(NonceStore::default()),
+        }
+    }
+}
+
+impl Authenticator for NonceAuthenticator {
+    fn authenticate(&self, request: &AuthRequest<'_>) -> AuthDecision {
+        let connect = request.connect;
+        let Some((_, nonce)) = connect
+            .properties
+            .user_properties()
+            .find(|(key, _)| *key == self.property)
+        else {
+            warn!(
+                "Rejecting client {:?} from {}: missing {:?} user property",
+                request.log_client_id, request.peer, self.property
+            );
+            return AuthDecision::Deny(ConnackReason::BadUsernameOrPassword);
+        };
+
+        let now = Instant::now();
+        let mut store = self.seen.lock().unwrap();
+
+        // 清理过期记录
+        while let Some((seen_at, _)) = store.order.front() {
+            if now.duration_since(*seen_at) < self.window {
+                break;
+            }
+            let (_, expired) = store.order.pop_front().unwrap();
+            store.nonces.remove(&expired);
+        }
+
+        if store.nonces.contains(nonce) {
+            warn!(
+                "Rejecting client {:?} from {}: replayed nonce {:?}",
+                request.log_client_id, request.peer, nonce
+            );
+            return AuthDecision::Deny(ConnackReason::BadUsernameOrPassword);
+        }
+
+        // 窗口内的记录已满: 拒绝新连接, 直到最旧的记录过期
+        if store.order.len() >= self.max_entries {
+            warn!(
+                "Rejecting client {:?} from {}: {} nonces recorded within {:?}",
+                request.log_client_id, request.peer, self.max_entries, self.window
+            );
+            return AuthDecision::Deny(ConnackReason::ServerBusy);
+        }
+
+        store.nonces.insert(nonce.to_string());
+        store.order.push_back((now, nonce.to_string()));
+
+        AuthDecision::Allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::properties::{Properties, PropertyValue, USER_PROPERTY};
+
+    fn authenticate(authenticator: &NonceAuthenticator, nonce: &str) -> AuthDecision {
+        let connect = ConnectPacket {
+            protocol_level: 5,
+            clean_session: true,
+            keep_alive: 60,
+            client_id: "dev".to_string(),
+            username: None,
+            password: None,
+            properties: Properties(vec![(USER_PROPERTY, PropertyValue::Pair("nonce".to_string(), nonce.to_string()))]),
+            will: None,
+        };
+        let request = AuthRequest { peer: "127.0.0.1:1883".parse().unwrap(), connect: &connect, log_client_id: "dev" };
+        authenticator.authenticate(&request)
+    }
+
+    #[test]
+    fn full_store_rejects_new_nonces_instead_of_forgetting_old_ones() {
+        let config = NonceAuthConfig { max_entries: 2, ..NonceAuthConfig::default() };
+        let authenticator = NonceAuthenticator::new(&config);
+        assert_eq!(authenticate(&authenticator, "n1"), AuthDecision::Allow);
+        assert_eq!(authenticate(&authenticator, "n2"), AuthDecision::Allow);
+        assert_eq!(authenticate(&authenticator, "n3"), AuthDecision::Deny(ConnackReason::ServerBusy));
+        // 已满时窗口内的记录仍然有效, 重放仍被拒绝
+        assert_eq!(authenticate(&authenticator, "n1"), AuthDecision::Deny(ConnackReason::BadUsernameOrPassword));
+        assert_eq!(authenticate(&authenticator, "n2"), AuthDecision::Deny(ConnackReason::BadUsernameOrPassword));
+    }
+
+    #[test]
+    fn expired_nonces_free_the_store() {
+        // 窗口为 0: 每条记录在下一次认证时都已过期
+        let config = NonceAuthConfig { window_sec: 0, max_entries: 1, ..NonceAuthConfig::default() };
+        let authenticator = NonceAuthenticator::new(&config);
+        assert_eq!(authenticate(&authenticator, "n1"), AuthDecision::Allow);
+        assert_eq!(authenticate(&authenticator, "n2"), AuthDecision::Allow);
+    }
+}
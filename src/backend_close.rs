@@ -0,0 +1,128 @@
+// 后端意外断开时通知 MQTT 5.0 客户端
+// 稳定转发阶段后端关闭连接 (不是响应客户端的 DISCONNECT) 时, 关闭前先给客户端发送带原因码的 DISCONNECT:
+// 后端正常关闭 (FIN) 为 0x8B (Server shutting down), 读取出错 (连接被重置等) 为 0x89 (Server busy)
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::metrics::metrics;
+use crate::tap::{Direction, PacketTap, packet_type};
+
+/// DISCONNECT, 原因码 0x8B (Server shutting down), 省略属性
+const SHUTTING_DOWN_DISCONNECT: [u8; 3] = [0xE0, 0x01, 0x8B];
+/// DISCONNECT, 原因码 0x89 (Server busy), 省略属性
+const BUSY_DISCONNECT: [u8; 3] = [0xE0, 0x01, 0x89];
+
+/// 读端如何结束
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderEnd {
+    /// 读到 EOF
+    Closed,
+    /// 读取出错
+    Failed,
+}
+
+/// 一个连接的后端断开通知, 两个方向共享客户端是否已经结束会话
+#[derive(Default)]
+pub struct BackendCloseNotice {
+    client_done: Arc<AtomicBool>,
+}
+
+impl BackendCloseNotice {
+    /// 一个方向的包边界跟踪, 两个方向都需要
+    pub fn tap(&self, direction: Direction) -> BackendCloseTap {
+        BackendCloseTap {
+            tap: PacketTap::headers_only(),
+            direction,
+            last_packet: None,
+            client_done: self.client_done.clone(),
+        }
+    }
+}
+
+/// 单方向的包边界跟踪
+pub struct BackendCloseTap {
+    tap: PacketTap,
+    direction: Direction,
+    /// 发往客户端方向最后一个完整的包的类型
+    last_packet: Option<u8>,
+    client_done: Arc<AtomicBool>,
+}
+
+impl BackendCloseTap {
+    /// 在数据转发之后调用
+    pub fn feed(&mut self, data: &[u8]) {
+        let packets = self.tap.feed(data);
+        match self.direction {
+            // 客户端发送了 DISCONNECT: 之后后端关闭连接是预期的
+            Direction::ClientToBroker => {
+                if packets.iter().any(|packet| packet.packet_type() == packet_type::DISCONNECT) {
+                    self.client_done.store(true, Ordering::Relaxed);
+                }
+            }
+            Direction::BrokerToClient => {
+                if let Some(packet) = packets.last() {
+                    self.last_packet = Some(packet.packet_type());
+                }
+            }
+        }
+    }
+
+    /// 该方向的读端结束时调用 (读到 EOF 或出错):
+    /// 客户端一侧结束表示会话由客户端结束; 后端一侧结束时, 如果客户端没有结束会话、后端没有自己发送 DISCONNECT
+    /// 且正好位于包边界, 给客户端发送 DISCONNECT (发送失败时忽略, 连接照常关闭)
+    pub async fn reader_ended<W: AsyncWrite + Unpin>(&self, writer: &mut W, end: ReaderEnd) {
+        if self.direction == Direction::ClientToBroker {
+            self.client_done.store(true, Ordering::Relaxed);
+            return;
+        }
+        if self.client_done.load(Ordering::Relaxed)
+            || self.last_packet == Some(packet_type::DISCONNECT)
+            || !self.tap.at_boundary()
+        {
+            return;
+        }
+        let disconnect = match end {
+            ReaderEnd::Closed => &SHUTTING_DOWN_DISCONNECT,
+            ReaderEnd::Failed => &BUSY_DISCONNECT,
+        };
+        if writer.write_all(disconnect).await.is_ok() && writer.flush().await.is_ok() {
+            metrics().backend_close_disconnects_total.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn written_on_backend_close(client: &[u8], broker: &[u8], end: ReaderEnd) -> Vec<u8> {
+        let notice = BackendCloseNotice::default();
+        let mut client_tap = notice.tap(Direction::ClientToBroker);
+        let mut broker_tap = notice.tap(Direction::BrokerToClient);
+        client_tap.feed(client);
+        broker_tap.feed(broker);
+        let mut written = Vec::new();
+        broker_tap.reader_ended(&mut written, end).await;
+        written
+    }
+
+    #[tokio::test]
+    async fn reason_code_follows_how_the_backend_closed() {
+        let connack = [0x20, 0x03, 0x00, 0x00, 0x00];
+        assert_eq!(written_on_backend_close(&[], &connack, ReaderEnd::Closed).await, SHUTTING_DOWN_DISCONNECT);
+        assert_eq!(written_on_backend_close(&[], &connack, ReaderEnd::Failed).await, BUSY_DISCONNECT);
+    }
+
+    #[tokio::test]
+    async fn expected_or_unsafe_closes_are_not_notified() {
+        let connack = [0x20, 0x03, 0x00, 0x00, 0x00];
+        // 客户端已发送 DISCONNECT
+        assert!(written_on_backend_close(&[0xE0, 0x00], &connack, ReaderEnd::Closed).await.is_empty());
+        // 后端自己发送了 DISCONNECT
+        assert!(written_on_backend_close(&[], &[0xE0, 0x01, 0x8E], ReaderEnd::Closed).await.is_empty());
+        // PUBLISH 只转发了一部分
+        assert!(written_on_backend_close(&[], &[0x30, 0x10, 0x00], ReaderEnd::Failed).await.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+// 单连接流量配额
+// 两个方向累计转发的字节超过 `max_bytes_per_connection` 时结束连接; MQTT 5.0 客户端先收到 DISCONNECT (0x97 Quota exceeded)
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+use crate::tap::{Direction, PacketTap};
+
+/// DISCONNECT, 原因码 0x97 (Quota exceeded), 省略属性
+const QUOTA_DISCONNECT: [u8; 3] = [0xE0, 0x01, 0x97];
+
+/// 两个转发方向共享的计数
+struct Usage {
+    limit: u64,
+    used: AtomicU64,
+    exceeded: watch::Sender<bool>,
+}
+
+/// 一个连接的流量配额
+pub struct ByteQuota {
+    usage: Arc<Usage>,
+    /// 客户端为 MQTT 5.0, 关闭前发送 DISCONNECT
+    disconnect: bool,
+}
+
+impl ByteQuota {
+    pub fn new(limit: u64, disconnect: bool) -> Self {
+        let usage = Usage { limit, used: AtomicU64::new(0), exceeded: watch::Sender::new(false) };
+        ByteQuota { usage: Arc::new(usage), disconnect }
+    }
+
+    /// 一个方向的计数, 两个方向都需要
+    pub fn tap(&self, direction: Direction) -> QuotaTap {
+        let boundary = (self.disconnect && direction == Direction::BrokerToClient).then(PacketTap::default);
+        QuotaTap { usage: self.usage.clone(), boundary }
+    }
+}
+
+/// 单方向的字节计数
+pub struct QuotaTap {
+    usage: Arc<Usage>,
+    /// broker 发往 MQTT 5.0 客户端的方向: 跟踪包边界, DISCONNECT 只能插在两个包之间
+    boundary: Option<PacketTap>,
+}
+
+impl QuotaTap {
+    /// 在数据转发之后调用, 两个方向合计超过配额时返回 true
+    pub fn feed(&mut self, data: &[u8]) -> bool {
+        if let Some(boundary) = &mut self.boundary {
+            boundary.feed(data);
+        }
+        let used = self.usage.used.fetch_add(data.len() as u64, Ordering::Relaxed) + data.len() as u64;
+        if used <= self.usage.limit {
+            return false;
+        }
+        self.usage.exceeded.send_replace(true);
+        true
+    }
+
+    /// 等待任一方向超过配额
+    pub async fn exceeded(&self) {
+        let mut exceeded = self.usage.exceeded.subscribe();
+        let _ = exceeded.wait_for(|exceeded| *exceeded).await;
+    }
+
+    /// 超过配额后结束该方向: 发往客户端的方向正好位于包边界时先发送 DISCONNECT
+    pub async fn close<W: AsyncWrite + Unpin>(&self, writer: &mut W) {
+        if let Some(boundary) = &self.boundary
+            && boundary.at_boundary()
+        {
+            let _ = writer.write_all(&QUOTA_DISCONNECT).await;
+            let _ = writer.flush().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn both_directions_share_the_limit() {
+        let quota = ByteQuota::new(10, false);
+        let mut client = quota.tap(Direction::ClientToBroker);
+        let mut broker = quota.tap(Direction::BrokerToClient);
+        assert!(!client.feed(&[0; 6]));
+        // 正好达到配额不算超过
+        assert!(!broker.feed(&[0; 4]));
+        assert!(broker.feed(&[0; 1]));
+    }
+
+    #[tokio::test]
+    async fn exceeding_wakes_the_other_direction() {
+        let quota = ByteQuota::new(4, false);
+        let mut client = quota.tap(Direction::ClientToBroker);
+        let broker = quota.tap(Direction::BrokerToClient);
+        let waiter = tokio::spawn(async move { broker.exceeded().await });
+        assert!(client.feed(&[0; 5]));
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn disconnect_is_sent_only_at_a_packet_boundary() {
+        let quota = ByteQuota::new(0, true);
+        let mut broker = quota.tap(Direction::BrokerToClient);
+        // 完整的 PUBACK 之后: 位于包边界
+        assert!(broker.feed(&[0x40, 0x02, 0x00, 0x01]));
+        let mut written = Vec::new();
+        broker.close(&mut written).await;
+        assert_eq!(written, QUOTA_DISCONNECT);
+
+        // PUBLISH 只转发了一部分: 插入 DISCONNECT 会破坏流
+        broker.feed(&[0x30, 0x10, 0x00]);
+        let mut written = Vec::new();
+        broker.close(&mut written).await;
+        assert!(written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_disconnect_for_older_clients_or_towards_the_broker() {
+        for (disconnect, direction) in [(false, Direction::BrokerToClient), (true, Direction::ClientToBroker)] {
+            let quota = ByteQuota::new(0, disconnect);
+            let mut tap = quota.tap(direction);
+            assert!(tap.feed(&[0xC0, 0x00]));
+            let mut written = Vec::new();
+            tap.close(&mut written).await;
+            assert!(written.is_empty());
+        }
+    }
+}
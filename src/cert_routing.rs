@@ -0,0 +1,129 @@
+// 按客户端证书选择后端
+// 双向 TLS 握手校验过的客户端证书中的字段 (如代表租户的 OU) 作为路由键, 在读取 CONNECT 之前决定后端
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::adapter_config::{CertAttribute, TlsConfig};
+use crate::tls::AdapterTlsStream;
+
+/// 客户端证书路由表
+pub struct CertRouter {
+    attribute: CertAttribute,
+    backends: BTreeMap<String, String>,
+}
+
+impl CertRouter {
+    /// 未配置 cert_routing 时返回 None; 没有配置 client_ca_path (客户端证书未经校验) 时报错
+    pub fn from_config(config: &TlsConfig) -> std::io::Result<Option<Self>> {
+        let Some(routing) = &config.cert_routing else {
+            return Ok(None);
+        };
+        if config.client_ca_path.is_none() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "[adapter.tls.cert_routing] requires client_ca_path (client certificates must be verified)",
+            ));
+        }
+        Ok(Some(CertRouter { attribute: routing.attribute, backends: routing.backends.clone() }))
+    }
+
+    /// 路由键使用的证书字段
+    pub fn attribute(&self) -> CertAttribute {
+        self.attribute
+    }
+
+    /// 配置的后端数
+    pub fn backend_count(&self) -> usize {
+        self.backends.len()
+    }
+
+    /// 返回客户端证书的字段值和对应的后端, 没有该字段或没有匹配的后端时对应项为 None
+    pub fn route(&self, stream: &AdapterTlsStream) -> (Option<String>, Option<&str>) {
+        let value = stream.get_ref().1
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(|cert| attribute_value(&cert.0, self.attribute));
+        let backend = value.as_deref()
+            .and_then(|value| self.backends.get(value))
+            .map(String::as_str);
+        (value, backend)
+    }
+}
+
+/// 读取终端证书中的字段, 证书无法解析或没有该字段时返回 None
+pub fn attribute_value(der: &[u8], attribute: CertAttribute) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    let subject = cert.subject();
+    let first = match attribute {
+        CertAttribute::CommonName => subject.iter_common_name().next(),
+        CertAttribute::Organization => subject.iter_organization().next(),
+        CertAttribute::OrganizationalUnit => subject.iter_organizational_unit().next(),
+        CertAttribute::DnsName | CertAttribute::Uri => {
+            let names = cert.subject_alternative_name().ok()??;
+            return names.value.general_names.iter().find_map(|name| match (attribute, name) {
+                (CertAttribute::DnsName, GeneralName::DNSName(dns)) => Some(dns.to_string()),
+                (CertAttribute::Uri, GeneralName::URI(uri)) => Some(uri.to_string()),
+                _ => None,
+            });
+        }
+    };
+    first?.as_str().ok().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter_config::CertRoutingConfig;
+
+    /// 测试客户端证书 (testdata/tls) 的 DER
+    fn certificate(name: &str) -> Vec<u8> {
+        let pem = std::fs::read(format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+        rustls_pemfile::certs(&mut pem.as_slice()).unwrap().remove(0)
+    }
+
+    fn tls_config(client_ca_path: Option<&str>) -> TlsConfig {
+        TlsConfig {
+            client_ca_path: client_ca_path.map(str::to_string),
+            cert_routing: Some(CertRoutingConfig {
+                attribute: CertAttribute::OrganizationalUnit,
+                backends: BTreeMap::from([("sensors".to_string(), "10.0.0.1:1883".to_string())]),
+            }),
+            ..TlsConfig::default()
+        }
+    }
+
+    #[test]
+    fn reads_subject_fields() {
+        let sensors = certificate("client_sensors.pem");
+        assert_eq!(attribute_value(&sensors, CertAttribute::CommonName).as_deref(), Some("sensor-1"));
+        assert_eq!(attribute_value(&sensors, CertAttribute::Organization).as_deref(), Some("Example"));
+        assert_eq!(attribute_value(&sensors, CertAttribute::OrganizationalUnit).as_deref(), Some("sensors"));
+        let gateways = certificate("client_gateways.pem");
+        assert_eq!(attribute_value(&gateways, CertAttribute::OrganizationalUnit).as_deref(), Some("gateways"));
+    }
+
+    #[test]
+    fn reads_subject_alternative_names() {
+        let sensors = certificate("client_sensors.pem");
+        assert_eq!(attribute_value(&sensors, CertAttribute::DnsName).as_deref(), Some("client_sensors.example"));
+        assert_eq!(attribute_value(&sensors, CertAttribute::Uri).as_deref(), Some("spiffe://example/client_sensors"));
+    }
+
+    #[test]
+    fn missing_field_or_garbage_gives_none() {
+        assert_eq!(attribute_value(&certificate("client_no_ou.pem"), CertAttribute::OrganizationalUnit), None);
+        assert_eq!(attribute_value(b"not a certificate", CertAttribute::CommonName), None);
+    }
+
+    #[test]
+    fn requires_verified_client_certificates() {
+        assert!(CertRouter::from_config(&tls_config(None)).is_err());
+        let router = CertRouter::from_config(&tls_config(Some("ca.pem"))).unwrap().unwrap();
+        assert_eq!(router.attribute(), CertAttribute::OrganizationalUnit);
+        assert_eq!(router.backend_count(), 1);
+        assert!(CertRouter::from_config(&TlsConfig::default()).unwrap().is_none());
+    }
+}
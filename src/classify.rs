@@ -0,0 +1,162 @@
+// 连接协议识别
+// 读取连接开头的若干字节判断协议族, 读到的字节随后交给对应的处理函数重放
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// 识别协议最多需要的字节数 (PROXY v2 签名为 12 字节)
+const PEEK_LIMIT: usize = 16;
+
+/// PROXY 协议 v2 签名
+const PROXY_V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// 按前缀识别的协议, 都不匹配时再按首字节的包类型判断 MQTT
+/// (TLS 记录头 0x16 的高 4 位也是 CONNECT 类型, 必须先于 MQTT 检查)
+const PREFIXES: [(&[u8], Protocol); 4] = [
+    // TLS 记录头: handshake (0x16) + 主版本 3
+    (&[0x16, 0x03], Protocol::Tls),
+    (b"GET ", Protocol::WebSocket),
+    (b"PROXY ", Protocol::ProxyV1),
+    (&PROXY_V2_SIGNATURE, Protocol::ProxyV2),
+];
+
+/// 连接开头的协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// MQTT CONNECT (固定头类型 1)
+    Mqtt,
+    /// TLS ClientHello
+    Tls,
+    /// HTTP GET (WebSocket 升级请求)
+    WebSocket,
+    /// PROXY 协议 v1 (文本)
+    ProxyV1,
+    /// PROXY 协议 v2 (二进制)
+    ProxyV2,
+    /// 无法识别
+    Unknown,
+    /// 已读字节是某个协议的前缀, 需要继续读取
+    NeedMoreData,
+}
+
+impl Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Mqtt => "mqtt",
+            Protocol::Tls => "tls",
+            Protocol::WebSocket => "websocket",
+            Protocol::ProxyV1 => "proxy_v1",
+            Protocol::ProxyV2 => "proxy_v2",
+            Protocol::Unknown => "unknown",
+            Protocol::NeedMoreData => "need_more_data",
+        }
+    }
+}
+
+/// 按连接开头的字节判断协议
+pub fn classify_first_bytes(peek: &[u8]) -> Protocol {
+    let Some(&first_byte) = peek.first() else {
+        return Protocol::NeedMoreData;
+    };
+
+    for (prefix, protocol) in PREFIXES {
+        let len = peek.len().min(prefix.len());
+        if peek[..len] == prefix[..len] {
+            return if len == prefix.len() { protocol } else { Protocol::NeedMoreData };
+        }
+    }
+
+    if first_byte >> 4 == 1 {
+        Protocol::Mqtt
+    } else {
+        Protocol::Unknown
+    }
+}
+
+/// 读取到足以判断协议为止, 返回协议和已读取的字节 (可能多于判断所需, 需要原样重放)
+/// 未发送任何数据就关闭时返回 None
+pub async fn read_and_classify<R: AsyncRead + Unpin>(stream: &mut R) -> std::io::Result<Option<(Protocol, Vec<u8>)>> {
+    let mut peeked = [0u8; PEEK_LIMIT];
+    let mut len = 0;
+    loop {
+        let protocol = classify_first_bytes(&peeked[..len]);
+        if protocol != Protocol::NeedMoreData {
+            return Ok(Some((protocol, peeked[..len].to_vec())));
+        }
+
+        // 所有前缀都短于 PEEK_LIMIT, 缓冲区不会在需要更多数据时写满
+        let n = stream.read(&mut peeked[len..]).await?;
+        if n == 0 {
+            if len == 0 {
+                return Ok(None);
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed before the protocol could be identified",
+            ));
+        }
+        len += n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn classifies_first_bytes() {
+        let cases: [(&[u8], Protocol); 19] = [
+            // 各协议的完整前缀
+            (&[0x10, 0x12, 0x00, 0x04, b'M', b'Q', b'T', b'T'], Protocol::Mqtt),
+            (&[0x10], Protocol::Mqtt),
+            (&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01], Protocol::Tls),
+            (b"GET /mqtt HTTP/1.1\r\n", Protocol::WebSocket),
+            (b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 1883\r\n", Protocol::ProxyV1),
+            (b"\r\n\r\n\0\r\nQUIT\n\x21\x11", Protocol::ProxyV2),
+            // 前缀的开头, 需要继续读取
+            (&[], Protocol::NeedMoreData),
+            (&[0x16], Protocol::NeedMoreData),
+            (b"GE", Protocol::NeedMoreData),
+            (b"PROXY", Protocol::NeedMoreData),
+            (b"\r\n", Protocol::NeedMoreData),
+            (b"\r\n\r\n\0\r\nQU", Protocol::NeedMoreData),
+            // 无法识别的开头
+            (b"GEX /", Protocol::Unknown),
+            (b"POST / HTTP/1.1\r\n", Protocol::Unknown),
+            (b"PROXZ TCP4", Protocol::Unknown),
+            (b"\r\n\r\n\0\r\nQUIX\n", Protocol::Unknown),
+            (&[0x30, 0x05], Protocol::Unknown),
+            (&[0x00], Protocol::Unknown),
+            (&[0xFF, 0xFF], Protocol::Unknown),
+        ];
+        for (peek, expected) in cases {
+            assert_eq!(classify_first_bytes(peek), expected, "{:02x?}", peek);
+        }
+    }
+
+    #[test]
+    fn tls_record_with_another_major_version_is_checked_as_mqtt() {
+        // 0x16 的高 4 位是 CONNECT 类型, 不是 TLS 记录头时按 MQTT 处理, 由握手检查拒绝保留标志位
+        assert_eq!(classify_first_bytes(&[0x16, 0x01]), Protocol::Mqtt);
+    }
+
+    #[tokio::test]
+    async fn reads_until_the_prefix_is_complete() {
+        let mut stream = (&b"PRO"[..]).chain(&b"XY TCP4 rest"[..]);
+        let (protocol, peeked) = read_and_classify(&mut stream).await.unwrap().unwrap();
+        assert_eq!(protocol, Protocol::ProxyV1);
+        // 已读的字节全部返回, 用于重放
+        assert_eq!(peeked, b"PROXY TCP4 rest");
+    }
+
+    #[tokio::test]
+    async fn closed_without_data_is_none() {
+        assert!(read_and_classify(&mut &b""[..]).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn closed_inside_a_prefix_is_an_error() {
+        let error = read_and_classify(&mut &b"GE"[..]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
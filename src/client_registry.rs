@@ -0,0 +1,127 @@
+// 按客户端 ID 记录的状态
+// 客户端 ID 由客户端任意填写, 伪造或随机的 ID 洪水会让按 ID 记录的状态无限增长;
+// 所有按客户端 ID 保存状态的功能都通过该表记录, 总条目数不超过 `max_tracked_client_ids`,
+// 超出时淘汰最久未使用的条目 (计入 tracked_client_ids_evicted_total)
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+use crate::metrics::metrics;
+
+/// 有上限的客户端 ID 状态表, 按最近使用顺序淘汰
+/// 被淘汰的 ID 的状态丢失 (如接管检测把该 ID 的下一个连接当作第一个连接), 下次使用时重新记录
+pub struct ClientIdRegistry<T> {
+    capacity: usize,
+    inner: Mutex<Lru<T>>,
+}
+
+struct Lru<T> {
+    entries: HashMap<String, (u64, T)>,
+    /// 最近使用序号 -> 客户端 ID, 最小的最久未使用
+    order: BTreeMap<u64, String>,
+    next_stamp: u64,
+}
+
+impl<T: Default> ClientIdRegistry<T> {
+    /// `capacity` 为 0 时按 1 处理
+    pub fn new(capacity: usize) -> Self {
+        ClientIdRegistry {
+            capacity: capacity.max(1),
+            inner: Mutex::new(Lru { entries: HashMap::new(), order: BTreeMap::new(), next_stamp: 0 }),
+        }
+    }
+
+    /// 取出 (不存在时创建) 该客户端 ID 的状态并标记为最近使用, 创建时表已满则先淘汰最久未使用的条目
+    pub fn with_entry<R>(&self, client_id: &str, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut lru = self.inner.lock().unwrap();
+        let stamp = lru.next_stamp;
+        lru.next_stamp += 1;
+        if let Some((old_stamp, _)) = lru.entries.get(client_id) {
+            let old_stamp = *old_stamp;
+            let key = lru.order.remove(&old_stamp).expect("every entry has an order stamp");
+            lru.order.insert(stamp, key);
+        } else {
+            if lru.entries.len() >= self.capacity
+                && let Some((_, evicted)) = lru.order.pop_first()
+            {
+                lru.entries.remove(&evicted);
+                metrics().tracked_client_ids_evicted_total.fetch_add(1, Ordering::Relaxed);
+            }
+            lru.order.insert(stamp, client_id.to_string());
+            lru.entries.insert(client_id.to_string(), (stamp, T::default()));
+        }
+        let (entry_stamp, value) = lru.entries.get_mut(client_id).expect("entry was just inserted");
+        *entry_stamp = stamp;
+        f(value)
+    }
+
+    /// 条目存在且满足 `f` 时删除 (不计入淘汰), 返回是否删除
+    /// 不再需要的状态应及时删除, 否则正常的客户端进出也会把表填满, 淘汰计数失去意义
+    pub fn remove_if(&self, client_id: &str, f: impl FnOnce(&T) -> bool) -> bool {
+        let mut lru = self.inner.lock().unwrap();
+        let Some((stamp, _)) = lru.entries.get(client_id).filter(|(_, value)| f(value)) else {
+            return false;
+        };
+        let stamp = *stamp;
+        lru.order.remove(&stamp);
+        lru.entries.remove(client_id);
+        true
+    }
+
+    /// 当前记录的客户端 ID 数
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flood_of_distinct_ids_evicts_the_least_recently_used() {
+        const CAPACITY: usize = 100;
+        let registry = ClientIdRegistry::<u32>::new(CAPACITY);
+        let evicted_before = metrics().tracked_client_ids_evicted_total.load(Ordering::Relaxed);
+        for i in 0..CAPACITY {
+            registry.with_entry(&format!("flood-{}", i), |count| *count += 1);
+        }
+        assert_eq!(registry.len(), CAPACITY);
+        // 最早的 ID 再次使用后变为最近使用, 下一个新 ID 淘汰的是 flood-1
+        registry.with_entry("flood-0", |count| *count += 1);
+        registry.with_entry("flood-new", |count| *count += 1);
+
+        assert_eq!(registry.len(), CAPACITY);
+        assert_eq!(registry.with_entry("flood-0", |count| *count), 2);
+        assert_eq!(registry.with_entry("flood-new", |count| *count), 1);
+        // 被淘汰的 ID 重新创建
+        assert_eq!(registry.with_entry("flood-1", |count| *count), 0);
+        // 指标是全局的, 其它测试可能同时淘汰条目
+        assert!(metrics().tracked_client_ids_evicted_total.load(Ordering::Relaxed) > evicted_before);
+    }
+
+    #[test]
+    fn evicted_id_starts_over() {
+        let registry = ClientIdRegistry::<u32>::new(1);
+        registry.with_entry("a", |count| *count += 5);
+        registry.with_entry("b", |count| *count += 1);
+        assert_eq!(registry.with_entry("a", |count| { *count += 1; *count }), 1);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn removed_entries_free_their_slot() {
+        let registry = ClientIdRegistry::<u32>::new(2);
+        registry.with_entry("a", |count| *count += 1);
+        registry.with_entry("b", |count| *count += 1);
+        assert!(!registry.remove_if("a", |count| *count > 1));
+        assert!(registry.remove_if("a", |count| *count == 1));
+        assert!(!registry.remove_if("a", |_| true));
+        assert_eq!(registry.len(), 1);
+        // 删除后腾出的位置直接使用, "b" 不被淘汰
+        registry.with_entry("c", |count| *count += 1);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.with_entry("b", |count| *count), 1);
+    }
+}
@@ -0,0 +1,220 @@
+// MQTT 基础编解码
+// 变长整数 (Variable Byte Integer) 和带长度前缀的字段
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// 变长整数最多占用的字节数 (最大值 268,435,455)
+pub const MAX_VARIABLE_INT_BYTES: usize = 4;
+
+/// 解码变长整数 (Variable Byte Integer)
+/// 返回: (值, 占用字节数)
+pub fn decode_variable_int(buf: &[u8]) -> std::io::Result<(usize, usize)> {
+    let mut value = 0;
+    let mut multiplier = 1;
+
+    for (i, byte) in buf.iter().take(MAX_VARIABLE_INT_BYTES).enumerate() {
+        value += ((byte & 127) as usize) * multiplier;
+        if byte & 128 == 0 {
+            return Ok((value, i + 1));
+        }
+        multiplier *= 128;
+    }
+
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Invalid variable byte integer"
+    ))
+}
+
+/// 从流中读取固定头的剩余长度 (变长整数), 超过 4 字节时视为格式错误
+pub async fn read_remaining_length<R: AsyncRead + Unpin>(stream: &mut R) -> std::io::Result<usize> {
+    read_remaining_length_bounded(stream, MAX_VARIABLE_INT_BYTES).await
+}
+
+/// 同 `read_remaining_length`, 但最多读取 `max_bytes` 个字节: 第 `max_bytes` 个字节仍带延续位时报错, 不再读取后续字节
+pub async fn read_remaining_length_bounded<R: AsyncRead + Unpin>(stream: &mut R, max_bytes: usize) -> std::io::Result<usize> {
+    let mut value = 0;
+    let mut multiplier = 1;
+
+    for _ in 0..max_bytes.min(MAX_VARIABLE_INT_BYTES) {
+        let byte = stream.read_u8().await?;
+        value += ((byte & 127) as usize) * multiplier;
+        if byte & 128 == 0 {
+            return Ok(value);
+        }
+        multiplier *= 128;
+    }
+
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Invalid remaining length"
+    ))
+}
+
+/// 编码变长整数 (Variable Byte Integer)
+pub fn encode_variable_int(mut value: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (value % 128) as u8;
+        value /= 128;
+
+        if value > 0 {
+            byte |= 128;
+        }
+
+        out.push(byte);
+
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+/// 编码完整的控制包: 固定头首字节 + 剩余长度 + 负载
+pub fn encode_packet(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 5);
+    out.push(first_byte);
+    encode_variable_int(payload.len(), &mut out);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// 读取 2 字节大端整数
+pub fn read_u16(buf: &[u8], pos: &mut usize) -> std::io::Result<u16> {
+    let bytes = buf.get(*pos..*pos + 2).ok_or_else(truncated)?;
+    *pos += 2;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// 读取 4 字节大端整数
+pub fn read_u32(buf: &[u8], pos: &mut usize) -> std::io::Result<u32> {
+    let bytes = buf.get(*pos..*pos + 4).ok_or_else(truncated)?;
+    *pos += 4;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// 读取带 2 字节长度前缀的二进制数据
+pub fn read_binary<'a>(buf: &'a [u8], pos: &mut usize) -> std::io::Result<&'a [u8]> {
+    let len = read_u16(buf, pos)? as usize;
+    let bytes = buf.get(*pos..*pos + len).ok_or_else(truncated)?;
+    *pos += len;
+    Ok(bytes)
+}
+
+/// 读取带 2 字节长度前缀的 UTF-8 字符串
+pub fn read_string(buf: &[u8], pos: &mut usize) -> std::io::Result<String> {
+    Ok(String::from_utf8_lossy(read_binary(buf, pos)?).into_owned())
+}
+
+/// 写入带 2 字节长度前缀的二进制数据
+pub fn write_binary(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// 数据不完整
+pub fn truncated() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "Packet truncated")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// 规范允许的最大剩余长度
+    const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+    /// MQTT 规范规定的编码字节数
+    fn spec_len(value: usize) -> usize {
+        match value {
+            0..=127 => 1,
+            128..=16_383 => 2,
+            16_384..=2_097_151 => 3,
+            _ => 4,
+        }
+    }
+
+    async fn assert_round_trip(value: usize) {
+        let mut encoded = Vec::new();
+        encode_variable_int(value, &mut encoded);
+        assert_eq!(encoded.len(), spec_len(value), "encoded length of {}", value);
+        assert_eq!(decode_variable_int(&encoded).unwrap(), (value, encoded.len()), "decode of {}", value);
+
+        let mut reader = encoded.as_slice();
+        assert_eq!(read_remaining_length(&mut reader).await.unwrap(), value, "async read of {}", value);
+        assert!(reader.is_empty(), "async read of {} left bytes behind", value);
+    }
+
+    #[tokio::test]
+    async fn round_trips_at_every_length_boundary() {
+        for boundary in [0, 127, 128, 16_383, 16_384, 2_097_151, 2_097_152, MAX_REMAINING_LENGTH] {
+            for value in boundary.saturating_sub(1)..=(boundary + 1).min(MAX_REMAINING_LENGTH) {
+                assert_round_trip(value).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_random_lengths() {
+        let mut rng = StdRng::seed_from_u64(0x4d51_5454);
+        for _ in 0..10_000 {
+            assert_round_trip(rng.gen_range(0..=MAX_REMAINING_LENGTH)).await;
+        }
+        // 每个字节数区间内也各取一些值
+        for (low, high) in [(0, 127), (128, 16_383), (16_384, 2_097_151), (2_097_152, MAX_REMAINING_LENGTH)] {
+            for _ in 0..1_000 {
+                assert_round_trip(rng.gen_range(low..=high)).await;
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_rejects_five_byte_continuation() {
+        assert!(decode_variable_int(&[0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+        assert!(decode_variable_int(&[0x80]).is_err());
+    }
+
+    #[tokio::test]
+    async fn async_reader_rejects_five_byte_continuation() {
+        // 超过最大值: 第 4 个字节仍带延续位
+        let mut reader: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x01];
+        let error = read_remaining_length(&mut reader).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut encoded = Vec::new();
+        encode_variable_int(MAX_REMAINING_LENGTH, &mut encoded);
+        let mut reader = encoded.as_slice();
+        assert_eq!(read_remaining_length(&mut reader).await.unwrap(), MAX_REMAINING_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn bounded_reader_stops_before_the_fifth_byte() {
+        // 第 4 个字节仍带延续位时立即拒绝, 第 5 个字节留在流中不读取
+        let mut reader: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x01];
+        let error = read_remaining_length_bounded(&mut reader, MAX_VARIABLE_INT_BYTES).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(reader, &[0x01]);
+    }
+
+    #[tokio::test]
+    async fn bounded_reader_honours_a_smaller_limit() {
+        // 限制为 2 字节: 读完 2 个带延续位的字节后拒绝
+        let mut reader: &[u8] = &[0x80, 0x80, 0x01];
+        let error = read_remaining_length_bounded(&mut reader, 2).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(reader, &[0x01]);
+
+        // 2 字节以内的长度照常读取
+        let mut reader: &[u8] = &[0xff, 0x7f, 0x00];
+        assert_eq!(read_remaining_length_bounded(&mut reader, 2).await.unwrap(), 16_383);
+        assert_eq!(reader, &[0x00]);
+    }
+
+    #[tokio::test]
+    async fn async_reader_reports_truncated_length() {
+        let mut reader: &[u8] = &[0x80, 0x80];
+        let error = read_remaining_length(&mut reader).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,150 @@
+// 配置热加载
+// POST /reload 重新读取配置来源并检查, 只应用可以在运行中替换的字段; 其它改动需要重启才能生效
+
+use rumqttd::Config;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::adapter_config::{AdapterConfig, AdapterInstance};
+use crate::config_source::ConfigSource;
+use crate::runtime::RuntimeState;
+
+/// 可热加载的 [adapter] 字段: 每个连接在开始处理时从生效的配置读取, 替换后对新连接生效
+/// 其余字段在启动时用于创建监听器、限速器、路由表等组件, 改动只在重启后生效
+pub const RELOADABLE_FIELDS: [&str; 8] = [
+    "slow_connack_threshold_ms",
+    "backend_connect_retries",
+    "backend_connect_retry_delay_ms",
+    "backend_connect_timeout_ms",
+    "backend_connack_timeout_ms",
+    "half_close_grace_ms",
+    "max_pause_ms",
+    "observer_on",
+];
+
+/// 热加载的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadSummary {
+    /// 已应用的字段 (值有变化的可热加载字段)
+    pub applied: Vec<String>,
+    /// 有改动但需要重启才能生效的字段 (broker 配置整体记为 "broker", 实例列表记为 "adapters")
+    pub restart_required: Vec<String>,
+}
+
+/// 把 `loaded` 中可热加载的字段合并到 `current`, 其余字段保持不变
+pub fn merge_reloadable(current: &AdapterConfig, loaded: &AdapterConfig) -> AdapterConfig {
+    AdapterConfig {
+        slow_connack_threshold_ms: loaded.slow_connack_threshold_ms,
+        backend_connect_retries: loaded.backend_connect_retries,
+        backend_connect_retry_delay_ms: loaded.backend_connect_retry_delay_ms,
+        backend_connect_timeout_ms: loaded.backend_connect_timeout_ms,
+        backend_connack_timeout_ms: loaded.backend_connack_timeout_ms,
+        half_close_grace_ms: loaded.half_close_grace_ms,
+        max_pause_ms: loaded.max_pause_ms,
+        observer_on: loaded.observer_on,
+        ..current.clone()
+    }
+}
+
+/// 比较两份 [adapter] 配置, 返回值不同的顶层字段名 (按名称排序)
+pub fn changed_fields(current: &AdapterConfig, loaded: &AdapterConfig) -> Vec<String> {
+    let (Ok(Value::Object(current)), Ok(Value::Object(loaded))) = (serde_json::to_value(current), serde_json::to_value(loaded)) else {
+        return Vec::new();
+    };
+    let mut changed: Vec<String> = loaded.iter()
+        .filter(|(name, value)| current.get(*name) != Some(value))
+        .map(|(name, _)| name.clone())
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// 重新读取启动时的配置来源并应用可热加载的字段
+pub struct Reloader {
+    source: ConfigSource,
+    /// 启动时的 broker 配置和适配器实例, 只用于报告需要重启的改动
+    broker: Arc<Config>,
+    instances: Vec<AdapterInstance>,
+}
+
+impl Reloader {
+    pub fn new(source: ConfigSource, broker: Arc<Config>, instances: Vec<AdapterInstance>) -> Self {
+        Reloader { source, broker, instances }
+    }
+
+    /// 重新读取配置来源, 检查通过后把可热加载的字段应用到运行时
+    /// 读取、解析或检查失败时返回全部错误, 生效的配置保持不变
+    pub async fn reload(&self, runtime: &RuntimeState) -> Result<ReloadSummary, Vec<String>> {
+        if self.source == ConfigSource::Stdin {
+            return Err(vec!["the configuration was read from stdin and cannot be read again; restart to change it".to_string()]);
+        }
+        let content = self.source.read().await
+            .map_err(|e| vec![format!("failed to read configuration from {}: {}", self.source.describe(), e)])?;
+        let (broker, loaded, instances, _) = crate::parse_config(&content).map_err(|e| vec![e])?;
+        crate::validate_config(&broker, &loaded, &instances)?;
+
+        let current = runtime.config();
+        let mut summary = ReloadSummary::default();
+        for field in changed_fields(&current, &loaded) {
+            if RELOADABLE_FIELDS.contains(&field.as_str()) {
+                summary.applied.push(field);
+            } else {
+                summary.restart_required.push(field);
+            }
+        }
+        if serde_json::to_value(&broker).ok() != serde_json::to_value(&*self.broker).ok() {
+            summary.restart_required.push("broker".to_string());
+        }
+        if instances != self.instances {
+            summary.restart_required.push("adapters".to_string());
+        }
+        runtime.set_config(merge_reloadable(&current, &loaded));
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter_config::ObserverOn;
+
+    #[test]
+    fn merge_takes_only_reloadable_fields() {
+        let current = AdapterConfig::default();
+        let loaded = AdapterConfig {
+            backend_connect_retries: 3,
+            observer_on: ObserverOn::Connack,
+            max_pause_ms: 1_000,
+            reuse_port: true,
+            tarpit_ms: 500,
+            ..AdapterConfig::default()
+        };
+        let merged = merge_reloadable(&current, &loaded);
+        assert_eq!(changed_fields(&current, &merged), ["backend_connect_retries", "max_pause_ms", "observer_on"]);
+        assert_eq!(changed_fields(&merged, &loaded), ["reuse_port", "tarpit_ms"]);
+    }
+
+    #[test]
+    fn every_reloadable_field_is_merged() {
+        // 每个可热加载字段都设为非默认值, 合并后与 loaded 没有差异, 列表和 merge_reloadable 保持一致
+        let loaded = AdapterConfig {
+            slow_connack_threshold_ms: 1,
+            backend_connect_retries: 1,
+            backend_connect_retry_delay_ms: 1,
+            backend_connect_timeout_ms: 1,
+            backend_connack_timeout_ms: 1,
+            half_close_grace_ms: 1,
+            max_pause_ms: 1,
+            observer_on: ObserverOn::Connack,
+            ..AdapterConfig::default()
+        };
+        let current = AdapterConfig::default();
+        assert_eq!(changed_fields(&current, &loaded), {
+            let mut fields = RELOADABLE_FIELDS.map(str::to_string).to_vec();
+            fields.sort();
+            fields
+        });
+        assert!(changed_fields(&merge_reloadable(&current, &loaded), &loaded).is_empty());
+    }
+}
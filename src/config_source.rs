@@ -0,0 +1,229 @@
+// 配置来源
+// 配置可以来自文件 (默认 config.toml)、标准输入 (`-`) 或启动时获取一次的 http(s) URL
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, ServerName};
+
+/// 获取远程配置的总时限 (连接、TLS 握手和读取响应)
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// 远程配置的最大长度
+const MAX_CONFIG_BYTES: usize = 4 * 1024 * 1024;
+/// 未设置 SSL_CERT_FILE 时依次尝试的系统 CA 证书文件
+const CA_BUNDLE_PATHS: [&str; 4] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// 配置来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    File(String),
+    Stdin,
+    Url(String),
+}
+
+impl ConfigSource {
+    /// 按命令行参数确定来源: `-` 为标准输入, http(s):// 开头为 URL, 其余为文件路径
+    pub fn parse(arg: &str) -> Self {
+        if arg == "-" {
+            ConfigSource::Stdin
+        } else if arg.starts_with("http://") || arg.starts_with("https://") {
+            ConfigSource::Url(arg.to_string())
+        } else {
+            ConfigSource::File(arg.to_string())
+        }
+    }
+
+    /// 用于日志的描述, URL 不包含查询参数 (可能带有访问令牌)
+    pub fn describe(&self) -> String {
+        match self {
+            ConfigSource::File(path) => path.clone(),
+            ConfigSource::Stdin => "<stdin>".to_string(),
+            ConfigSource::Url(url) => match url.split_once('?') {
+                Some((base, _)) => format!("{}?...", base),
+                None => url.clone(),
+            },
+        }
+    }
+
+    /// 读取配置文本; 文件不存在时返回 `ErrorKind::NotFound`
+    pub async fn read(&self) -> std::io::Result<String> {
+        match self {
+            ConfigSource::File(path) => std::fs::read_to_string(path),
+            ConfigSource::Stdin => {
+                let mut content = String::new();
+                std::io::stdin().read_to_string(&mut content)?;
+                Ok(content)
+            }
+            ConfigSource::Url(url) => tokio::time::timeout(FETCH_TIMEOUT, fetch(url))
+                .await
+                .map_err(|_| std::io::Error::new(ErrorKind::TimedOut, format!("no response within {:?}", FETCH_TIMEOUT)))?,
+        }
+    }
+}
+
+/// 已解析的 http(s) URL
+struct Url<'a> {
+    tls: bool,
+    host: &'a str,
+    port: u16,
+    /// 路径和查询参数
+    target: &'a str,
+}
+
+fn parse_url(url: &str) -> std::io::Result<Url<'_>> {
+    let invalid = |message: &str| std::io::Error::new(ErrorKind::InvalidInput, format!("{}: {}", message, url));
+    let (tls, rest) = match url.split_once("://") {
+        Some(("https", rest)) => (true, rest),
+        Some(("http", rest)) => (false, rest),
+        _ => return Err(invalid("unsupported URL scheme")),
+    };
+    let (authority, target) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    if authority.contains('@') {
+        return Err(invalid("credentials in the URL are not supported"));
+    }
+    // [IPv6]:port 或 host:port
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, port.parse().map_err(|_| invalid("invalid port in URL"))?)
+        }
+        _ => (authority, if tls { 443 } else { 80 }),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid("missing host in URL"));
+    }
+    Ok(Url { tls, host, port, target })
+}
+
+/// 获取远程配置
+async fn fetch(url: &str) -> std::io::Result<String> {
+    let body = http_get(url, "application/toml, text/plain, */*", MAX_CONFIG_BYTES).await?;
+    String::from_utf8(body).map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "configuration is not valid UTF-8"))
+}
+
+/// HTTP/1.1 GET, 不跟随重定向, 只接受 2xx, 返回不超过 `max_body` 字节的响应体
+/// 不设时限, 由调用方用 `tokio::time::timeout` 限制 (管理接口也用它获取 broker 控制台的数据)
+pub async fn http_get(url: &str, accept: &str, max_body: usize) -> std::io::Result<Vec<u8>> {
+    let url = parse_url(url)?;
+    let stream = TcpStream::connect((url.host, url.port)).await?;
+    let body = if url.tls {
+        let server_name = ServerName::try_from(url.host)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, format!("invalid TLS server name {:?}: {}", url.host, e)))?;
+        let stream = TlsConnector::from(Arc::new(client_config()?)).connect(server_name, stream).await?;
+        get(stream, &url, accept, max_body).await?
+    } else {
+        get(stream, &url, accept, max_body).await?
+    };
+    Ok(body)
+}
+
+/// 发送请求并读取完整响应 (Connection: close), 返回响应体
+async fn get<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, url: &Url<'_>, accept: &str, max_body: usize) -> std::io::Result<Vec<u8>> {
+    let host = if url.host.contains(':') { format!("[{}]", url.host) } else { url.host.to_string() };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\nConnection: close\r\nUser-Agent: rustmqttserverdemo\r\n\r\n",
+        url.target, host, accept,
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    let read = (&mut stream).take(max_body as u64 + 64 * 1024).read_to_end(&mut response).await;
+    // 部分服务器关闭 TLS 连接时不发送 close_notify, 已读到完整响应时忽略
+    if let Err(e) = read
+        && (e.kind() != ErrorKind::UnexpectedEof || response.is_empty())
+    {
+        return Err(e);
+    }
+
+    let invalid = |message: String| std::io::Error::new(ErrorKind::InvalidData, message);
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete HTTP response".to_string()))?;
+    let head = String::from_utf8_lossy(&response[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let status: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid(format!("invalid HTTP status line {:?}", status_line)))?;
+    if !(200..300).contains(&status) {
+        return Err(invalid(format!("server responded with {:?}", status_line)));
+    }
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(value.parse::<usize>().map_err(|_| invalid(format!("invalid Content-Length {:?}", value)))?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        }
+    }
+
+    let body = &response[header_end + 4..];
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(length) = content_length {
+        body.get(..length).ok_or_else(|| invalid("response body shorter than Content-Length".to_string()))?.to_vec()
+    } else {
+        body.to_vec()
+    };
+    if body.len() > max_body {
+        return Err(invalid(format!("response body larger than {} bytes", max_body)));
+    }
+    Ok(body)
+}
+
+/// 解码 chunked 响应体 (忽略扩展和尾部字段)
+fn decode_chunked(mut body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let invalid = || std::io::Error::new(ErrorKind::InvalidData, "invalid chunked response body");
+    let mut out = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n").ok_or_else(invalid)?;
+        let size_field = std::str::from_utf8(&body[..line_end]).map_err(|_| invalid())?;
+        let size_field = size_field.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_field, 16).map_err(|_| invalid())?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        out.extend_from_slice(body.get(..size).ok_or_else(invalid)?);
+        body = body.get(size + 2..).ok_or_else(invalid)?;
+    }
+}
+
+/// 使用系统 CA 证书的 TLS 客户端配置 (SSL_CERT_FILE 优先)
+fn client_config() -> std::io::Result<ClientConfig> {
+    let path = std::env::var("SSL_CERT_FILE")
+        .ok()
+        .or_else(|| CA_BUNDLE_PATHS.iter().find(|path| std::path::Path::new(path).exists()).map(|path| path.to_string()))
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no CA certificate bundle found (set SSL_CERT_FILE)"))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&path)?))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&certs);
+    if added == 0 {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, format!("no CA certificates in {}", path)));
+    }
+    Ok(rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
@@ -0,0 +1,81 @@
+// CONNACK 包构造
+// 适配器在转发到 broker 之前拒绝客户端, 或代替 broker 先接受连接时使用
+
+/// 适配器拒绝连接的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnackReason {
+    /// 用户名或密码错误
+    BadUsernameOrPassword,
+    /// 客户端 ID 在拒绝列表中
+    NotAuthorized,
+    /// 服务不可用 (维护模式)
+    ServerUnavailable,
+    /// CONNECT 超过 `max_packet_size`
+    PacketTooLarge,
+    /// 活动连接已满且排队失败, 或 nonce 记录已满
+    ServerBusy,
+    /// 租户连接数已达 `max_connections_per_tenant`
+    QuotaExceeded,
+    /// 已停用的协议版本 (`v310_sunset_date` 之后的 3.1.0)
+    UnacceptableProtocolVersion,
+    /// 加上 `client_id_prefix` 后客户端 ID 超过 `max_client_id_len`
+    ClientIdentifierNotValid,
+    /// CONNECT 合法但适配器不能接受 (按主题路由时请求保留会话)
+    ImplementationSpecificError,
+}
+
+impl ConnackReason {
+    /// MQTT 3.1.1 的 CONNACK 返回码
+    fn v3_return_code(self) -> u8 {
+        match self {
+            ConnackReason::BadUsernameOrPassword => 0x04,
+            ConnackReason::NotAuthorized => 0x05,
+            ConnackReason::ServerUnavailable => 0x03,
+            // 3.1.1 没有对应的返回码, 适配器对 3.x 客户端直接关闭连接
+            ConnackReason::PacketTooLarge => 0x03,
+            ConnackReason::ServerBusy => 0x03,
+            // 同 PacketTooLarge, 3.x 客户端直接关闭连接
+            ConnackReason::QuotaExceeded => 0x03,
+            ConnackReason::UnacceptableProtocolVersion => 0x01,
+            ConnackReason::ClientIdentifierNotValid => 0x02,
+            // 3.1.1 没有对应的返回码
+            ConnackReason::ImplementationSpecificError => 0x03,
+        }
+    }
+
+    /// MQTT 5.0 的 CONNACK 原因码
+    fn v5_reason_code(self) -> u8 {
+        match self {
+            ConnackReason::BadUsernameOrPassword => 0x86,
+            ConnackReason::NotAuthorized => 0x87,
+            ConnackReason::ServerUnavailable => 0x88,
+            ConnackReason::PacketTooLarge => 0x95,
+            ConnackReason::ServerBusy => 0x89,
+            ConnackReason::QuotaExceeded => 0x97,
+            ConnackReason::UnacceptableProtocolVersion => 0x84,
+            ConnackReason::ClientIdentifierNotValid => 0x85,
+            ConnackReason::ImplementationSpecificError => 0x83,
+        }
+    }
+}
+
+/// 按协议级别编码拒绝连接的 CONNACK
+pub fn encode_connack(protocol_level: u8, reason: ConnackReason) -> Vec<u8> {
+    if protocol_level == 5 {
+        // 固定头, 剩余长度 3, 会话存在标志 0, 原因码, 空属性
+        vec![0x20, 0x03, 0x00, reason.v5_reason_code(), 0x00]
+    } else {
+        // 固定头, 剩余长度 2, 会话存在标志 0, 返回码
+        vec![0x20, 0x02, 0x00, reason.v3_return_code()]
+    }
+}
+
+/// 编码接受连接的 CONNACK (会话存在标志 0)
+/// 用于按主题路由: 适配器在选择后端之前代替 broker 应答
+pub fn encode_connack_accepted(protocol_level: u8) -> Vec<u8> {
+    if protocol_level == 5 {
+        vec![0x20, 0x03, 0x00, 0x00, 0x00]
+    } else {
+        vec![0x20, 0x02, 0x00, 0x00]
+    }
+}
@@ -0,0 +1,99 @@
+// CONNECT 洪水检测
+// 按固定间隔统计新连接速率并与指数加权平均的基线比较, 超出基线的倍数时告警; 可在冷却期内临时收紧准入速率
+
+use log::{error, info};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::adapter_config::ConnectFloodConfig;
+use crate::metrics::metrics;
+use crate::rate_limit::TokenBucket;
+
+/// 洪水检测器
+pub struct FloodDetector {
+    sample_interval: Duration,
+    /// 基线的平滑系数 (采样间隔 / 基线窗口)
+    alpha: f64,
+    multiplier: f64,
+    min_rate: f64,
+    cooldown: Duration,
+    /// 上次采样以来 accept 的连接数
+    accepted: AtomicU64,
+    /// 告警状态的结束时间, None 表示未触发
+    tripped_until: Mutex<Option<Instant>>,
+    /// 告警期间的准入速率 (未配置则只告警)
+    limiter: Option<TokenBucket>,
+}
+
+impl FloodDetector {
+    pub fn new(config: &ConnectFloodConfig) -> Self {
+        let sample_interval = Duration::from_millis(config.sample_interval_ms.max(100));
+        let window = Duration::from_secs(config.baseline_window_sec.max(1));
+        FloodDetector {
+            sample_interval,
+            alpha: (sample_interval.as_secs_f64() / window.as_secs_f64()).min(1.0),
+            multiplier: config.multiplier.max(1.0),
+            min_rate: config.min_rate,
+            cooldown: Duration::from_secs(config.cooldown_sec),
+            accepted: AtomicU64::new(0),
+            tripped_until: Mutex::new(None),
+            limiter: (config.tighten_max_connects_per_sec > 0)
+                .then(|| TokenBucket::new(config.tighten_max_connects_per_sec)),
+        }
+    }
+
+    /// 记录一次 accept
+    pub fn record_accept(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 告警期间按收紧的速率准入, 超出时返回 false; 未告警或未配置收紧时总是 true
+    pub fn admit(&self) -> bool {
+        let Some(limiter) = &self.limiter else {
+            return true;
+        };
+        if !self.tripped_until.lock().unwrap().is_some_and(|until| Instant::now() < until) {
+            return true;
+        }
+        limiter.try_reserve(1, Duration::ZERO).is_some()
+    }
+
+    /// 采样任务: 每个间隔计算一次速率并更新基线和告警状态
+    /// 超出阈值的采样不计入基线, 避免持续的洪水抬高基线
+    pub async fn run_sampler(&self) {
+        let mut interval = tokio::time::interval_at(Instant::now() + self.sample_interval, self.sample_interval);
+        let mut baseline: Option<f64> = None;
+        loop {
+            interval.tick().await;
+            let rate = self.accepted.swap(0, Ordering::Relaxed) as f64 / self.sample_interval.as_secs_f64();
+            let current_baseline = *baseline.get_or_insert(rate);
+            let threshold = (current_baseline * self.multiplier).max(self.min_rate);
+            let now = Instant::now();
+
+            let mut tripped_until = self.tripped_until.lock().unwrap();
+            if rate > threshold {
+                // 持续的洪水延长告警状态, 但只在开始时告警一次
+                if tripped_until.is_none() {
+                    error!(
+                        "Connect flood detected: {:.1} connects/s against a baseline of {:.1}/s (threshold {:.1}/s){}",
+                        rate,
+                        current_baseline,
+                        threshold,
+                        if self.limiter.is_some() { ", tightening connect admission" } else { "" },
+                    );
+                    metrics().connect_flood_detected_total.fetch_add(1, Ordering::Relaxed);
+                }
+                *tripped_until = Some(now + self.cooldown);
+            } else {
+                baseline = Some(current_baseline + self.alpha * (rate - current_baseline));
+                if tripped_until.is_some_and(|until| now >= until) {
+                    info!("Connect flood over: {:.1} connects/s, baseline {:.1}/s", rate, current_baseline);
+                    *tripped_until = None;
+                }
+            }
+            metrics().set_connect_flood_state(rate, baseline.unwrap_or(rate), tripped_until.is_some());
+        }
+    }
+}
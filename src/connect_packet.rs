@@ -0,0 +1,218 @@
+// CONNECT 包解析
+// 只解析适配器需要的字段,其余内容原样转发
+
+use crate::codec::{read_binary, read_string, read_u16, truncated, write_binary};
+use crate::properties::{Properties, PropertyValue, SESSION_EXPIRY_INTERVAL, WILL_DELAY_INTERVAL};
+
+/// 已解析的 CONNECT 字段
+/// `Debug` 输出不包含密码
+#[derive(Clone)]
+pub struct ConnectPacket {
+    /// 协议级别 (4 = 3.1.1, 5 = 5.0)
+    pub protocol_level: u8,
+    /// Clean Session (3.1.1) / Clean Start (5.0) 标志
+    pub clean_session: bool,
+    /// 客户端请求的保活时间 (秒)
+    pub keep_alive: u16,
+    /// 客户端 ID
+    pub client_id: String,
+    /// 用户名
+    pub username: Option<String>,
+    /// 密码 (二进制数据, 不写入日志)
+    pub password: Option<Vec<u8>>,
+    /// MQTT 5.0 CONNECT 属性 (3.1.1 为空)
+    pub properties: Properties,
+    /// 遗嘱 (遗嘱标志为 0 时为 None), 遗嘱 QoS 和保留标志重新编码时从原始连接标志复制
+    pub will: Option<Will>,
+}
+
+/// CONNECT 中的遗嘱
+#[derive(Debug, Clone)]
+pub struct Will {
+    /// MQTT 5.0 遗嘱属性 (3.1.1 为空)
+    pub properties: Properties,
+    /// 遗嘱主题 (原样保留, 不校验 UTF-8)
+    pub topic: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl std::fmt::Debug for ConnectPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectPacket")
+            .field("protocol_level", &self.protocol_level)
+            .field("clean_session", &self.clean_session)
+            .field("keep_alive", &self.keep_alive)
+            .field("client_id", &self.client_id)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("properties", &self.properties)
+            .field("will", &self.will)
+            .finish()
+    }
+}
+
+impl ConnectPacket {
+    /// 会话是否只存在于本次连接 (3.1.1 Clean Session; 5.0 Clean Start 且会话过期间隔为 0)
+    /// 这样的连接在 broker 上没有之前留下的状态, 全部订阅都经过适配器
+    pub fn has_transient_session(&self) -> bool {
+        let expires_immediately = match self.properties.get(SESSION_EXPIRY_INTERVAL) {
+            None => true,
+            Some(value) => *value == PropertyValue::U32(0),
+        };
+        self.clean_session && (self.protocol_level != 5 || expires_immediately)
+    }
+
+    /// 5.0 会话过期间隔 (秒), 未携带该属性时为 0
+    pub fn session_expiry_interval(&self) -> u32 {
+        match self.properties.get(SESSION_EXPIRY_INTERVAL) {
+            Some(PropertyValue::U32(interval)) => *interval,
+            _ => 0,
+        }
+    }
+}
+
+impl Will {
+    /// 5.0 遗嘱延迟间隔 (秒), 未携带该属性时为 0
+    pub fn delay_interval(&self) -> u32 {
+        match self.properties.get(WILL_DELAY_INTERVAL) {
+            Some(PropertyValue::U32(interval)) => *interval,
+            _ => 0,
+        }
+    }
+}
+
+/// 解析 CONNECT 包的可变头、客户端 ID、遗嘱、用户名和密码
+/// 输入为去掉固定头之后的负载 (已升级为 3.1.1 或 5.0 格式)
+pub fn parse_connect(payload: &[u8]) -> std::io::Result<ConnectPacket> {
+    let mut pos = 0;
+
+    // 协议名称
+    let protocol_name_len = read_u16(payload, &mut pos)? as usize;
+    pos += protocol_name_len;
+
+    // 协议级别 + 连接标志 + 保活时间
+    let protocol_level = *payload.get(pos).ok_or_else(truncated)?;
+    let connect_flags = *payload.get(pos + 1).ok_or_else(truncated)?;
+    pos += 1 + 1;
+    let keep_alive = read_u16(payload, &mut pos)?;
+
+    // MQTT 5.0 在可变头末尾带有属性
+    let properties = if protocol_level == 5 {
+        Properties::decode(payload, &mut pos)?
+    } else {
+        Properties::default()
+    };
+
+    // 负载以客户端 ID 开始
+    let client_id = read_string(payload, &mut pos)?;
+
+    // 遗嘱位于客户端 ID 和用户名之间: 5.0 遗嘱属性 (自带变长整数长度) + 遗嘱主题 + 遗嘱消息
+    let will = if connect_flags & 0x04 != 0 {
+        let properties = if protocol_level == 5 {
+            Properties::decode(payload, &mut pos)?
+        } else {
+            Properties::default()
+        };
+        let topic = read_binary(payload, &mut pos)?.to_vec();
+        let payload = read_binary(payload, &mut pos)?.to_vec();
+        Some(Will { properties, topic, payload })
+    } else {
+        None
+    };
+
+    let username = if connect_flags & 0x80 != 0 {
+        Some(read_string(payload, &mut pos)?)
+    } else {
+        None
+    };
+    let password = if connect_flags & 0x40 != 0 {
+        Some(read_binary(payload, &mut pos)?.to_vec())
+    } else {
+        None
+    };
+
+    Ok(ConnectPacket {
+        protocol_level,
+        clean_session: connect_flags & 0x02 != 0,
+        keep_alive,
+        client_id,
+        username,
+        password,
+        properties,
+        will,
+    })
+}
+
+/// 按 `connect` 的字段重新编码 CONNECT 负载 (不含固定头, 剩余长度由调用方重新编码固定头时计算)
+/// 协议名称、协议级别、遗嘱 QoS 和遗嘱保留标志从原始负载 `payload` 中复制
+pub fn encode_connect(payload: &[u8], connect: &ConnectPacket) -> std::io::Result<Vec<u8>> {
+    let mut pos = 0;
+    let protocol_name_len = read_u16(payload, &mut pos)? as usize;
+    pos += protocol_name_len;
+    let protocol_level = *payload.get(pos).ok_or_else(truncated)?;
+    let connect_flags = *payload.get(pos + 1).ok_or_else(truncated)?;
+    let header = payload.get(..pos + 1).ok_or_else(truncated)?;
+
+    // 遗嘱 QoS 和遗嘱保留标志只在有遗嘱时有意义, 其余按字段重新计算
+    let mut flags = 0;
+    if connect.will.is_some() {
+        flags |= 0x04 | (connect_flags & 0x38);
+    }
+    if connect.clean_session {
+        flags |= 0x02;
+    }
+    if connect.username.is_some() {
+        flags |= 0x80;
+    }
+    if connect.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut out = Vec::with_capacity(payload.len() + 64);
+    out.extend_from_slice(header);
+    out.push(flags);
+    out.extend_from_slice(&connect.keep_alive.to_be_bytes());
+    if protocol_level == 5 {
+        connect.properties.encode(&mut out);
+    }
+    write_binary(connect.client_id.as_bytes(), &mut out);
+    if let Some(will) = &connect.will {
+        if protocol_level == 5 {
+            will.properties.encode(&mut out);
+        }
+        write_binary(&will.topic, &mut out);
+        write_binary(&will.payload, &mut out);
+    }
+    if let Some(username) = &connect.username {
+        write_binary(username.as_bytes(), &mut out);
+    }
+    if let Some(password) = &connect.password {
+        write_binary(password, &mut out);
+    }
+    Ok(out)
+}
+
+/// 测试用: 编码一个 CONNECT 负载 (不含固定头), clean session, 没有遗嘱
+#[cfg(test)]
+pub fn connect_payload(
+    protocol_level: u8,
+    keep_alive: u16,
+    client_id: &str,
+    username: Option<&str>,
+    password: Option<&[u8]>,
+) -> Vec<u8> {
+    let mut header = Vec::new();
+    write_binary(b"MQTT", &mut header);
+    header.extend_from_slice(&[protocol_level, 0x02]);
+    let connect = ConnectPacket {
+        protocol_level,
+        clean_session: true,
+        keep_alive,
+        client_id: client_id.to_string(),
+        username: username.map(str::to_string),
+        password: password.map(<[u8]>::to_vec),
+        properties: Properties::default(),
+        will: None,
+    };
+    encode_connect(&header, &connect).expect("header holds the protocol name, level and flags")
+}
@@ -0,0 +1,753 @@
+// CONNECT 改写管道
+// 转发给 broker 之前按顺序对已解析的 CONNECT 应用各项改写, 每一项可以修改字段或拒绝连接, 最后统一重新编码
+
+use log::{debug, info};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+
+use crate::adapter_config::{AdapterConfig, BackendAuthConfig, BackendAuthMode, CertAttribute, glob_match};
+use crate::cert_routing::attribute_value;
+use crate::connack::ConnackReason;
+use crate::connect_packet::ConnectPacket;
+use crate::metrics::metrics;
+use crate::properties::{MAXIMUM_PACKET_SIZE, PropertyValue, SESSION_EXPIRY_INTERVAL, USER_PROPERTY, WILL_DELAY_INTERVAL};
+use crate::tls::TlsPeer;
+
+/// 改写时可用的连接信息
+pub struct TransformContext<'a> {
+    /// 客户端地址
+    pub peer: SocketAddr,
+    /// 日志中使用的客户端 ID (按 [adapter.logging] 脱敏)
+    pub log_client_id: &'a str,
+    /// TLS 监听器上的连接为 Some, 明文监听器上为 None
+    pub tls: Option<&'a TlsPeer>,
+}
+
+/// 单项改写的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformDecision {
+    Continue,
+    /// 拒绝, 以对应原因码回复 CONNACK, 不再应用后续改写
+    Reject(ConnackReason),
+}
+
+/// CONNECT 改写
+pub trait ConnectTransform: Send + Sync {
+    /// 名称, 用于日志和关闭事件的原因
+    fn name(&self) -> &'static str;
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision;
+}
+
+/// 管道拒绝连接
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransformRejection {
+    /// 拒绝连接的改写
+    pub transform: &'static str,
+    pub reason: ConnackReason,
+}
+
+/// 有序的 CONNECT 改写列表
+#[derive(Default)]
+pub struct ConnectPipeline {
+    transforms: Vec<Box<dyn ConnectTransform>>,
+}
+
+impl ConnectPipeline {
+    /// 按配置注册内置改写, 顺序固定: 报文长度、clean session、保活、遗嘱延迟、客户端 IP、后端凭据、客户端 ID 前缀
+    /// 遗嘱延迟在 clean session 之后, 按改写后的会话过期间隔判断; 客户端 ID 前缀最后应用, 之前的改写都按原始客户端 ID 匹配
+    pub fn from_config(config: &AdapterConfig) -> std::io::Result<Self> {
+        let mut pipeline = ConnectPipeline::default();
+        if let Some(max_packet_size) = config.max_packet_size {
+            pipeline.push(MaxPacketSize(max_packet_size));
+        }
+        if !config.force_clean_session.is_empty() {
+            pipeline.push(ForceCleanSession { patterns: config.force_clean_session.clone() });
+        }
+        if config.max_keepalive_sec.is_some() || config.require_keepalive {
+            pipeline.push(EnforceKeepAlive { max: config.max_keepalive_sec, require: config.require_keepalive });
+        }
+        if let Some(max) = config.max_will_delay_sec {
+            pipeline.push(MaxWillDelay(max));
+        }
+        if let Some(property) = &config.forwarded_for_property {
+            pipeline.push(ForwardedFor { property: property.clone() });
+        }
+        if let Some(backend_auth) = &config.backend_auth {
+            pipeline.push(InjectCredentials(backend_auth.clone()));
+        }
+        let tls_prefix = config.tls.as_ref().and_then(|tls| tls.client_id_prefix.as_deref());
+        if config.client_id_prefix.is_some() || tls_prefix.is_some() {
+            pipeline.push(ClientIdPrefix {
+                plain: config.client_id_prefix.as_deref().map(PrefixTemplate::parse).transpose()?,
+                tls: tls_prefix.map(PrefixTemplate::parse).transpose()?,
+                max_len: config.max_client_id_len,
+            });
+        }
+        Ok(pipeline)
+    }
+
+    /// 追加一项改写, 在已注册的改写之后应用
+    pub fn push(&mut self, transform: impl ConnectTransform + 'static) {
+        self.transforms.push(Box::new(transform));
+    }
+
+    /// 没有注册任何改写时 CONNECT 原样转发
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// 已注册改写的名称 (按应用顺序)
+    pub fn names(&self) -> Vec<&'static str> {
+        self.transforms.iter().map(|transform| transform.name()).collect()
+    }
+
+    /// 按顺序应用全部改写, 第一个拒绝的改写结束管道
+    pub fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> Result<(), TransformRejection> {
+        for transform in &self.transforms {
+            if let TransformDecision::Reject(reason) = transform.apply(connect, context) {
+                return Err(TransformRejection { transform: transform.name(), reason });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 5.0 客户端未声明或声明了更大的 Maximum Packet Size 时降到 max_packet_size, 由 broker 限制下发的报文
+struct MaxPacketSize(u32);
+
+impl ConnectTransform for MaxPacketSize {
+    fn name(&self) -> &'static str {
+        "max_packet_size"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, _context: &TransformContext<'_>) -> TransformDecision {
+        if connect.protocol_level != 5 {
+            return TransformDecision::Continue;
+        }
+        let declared = match connect.properties.get(MAXIMUM_PACKET_SIZE) {
+            Some(PropertyValue::U32(declared)) => Some(*declared),
+            _ => None,
+        };
+        if declared.is_none_or(|declared| declared > self.0) {
+            connect.properties.set(MAXIMUM_PACKET_SIZE, PropertyValue::U32(self.0));
+        }
+        TransformDecision::Continue
+    }
+}
+
+/// 强制 clean session: 客户端请求了持久会话时设置 Clean Session / Clean Start, 5.0 的会话过期间隔改为 0
+struct ForceCleanSession {
+    patterns: Vec<String>,
+}
+
+impl ConnectTransform for ForceCleanSession {
+    fn name(&self) -> &'static str {
+        "force_clean_session"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision {
+        if connect.has_transient_session() || !self.patterns.iter().any(|pattern| glob_match(pattern, &connect.client_id)) {
+            return TransformDecision::Continue;
+        }
+        info!("Forcing clean session for client {:?} from {}", context.log_client_id, context.peer);
+        metrics().forced_clean_session_total.fetch_add(1, Ordering::Relaxed);
+        connect.clean_session = true;
+        // 未携带该属性时默认即为 0
+        if connect.properties.get(SESSION_EXPIRY_INTERVAL).is_some() {
+            connect.properties.set(SESSION_EXPIRY_INTERVAL, PropertyValue::U32(0));
+        }
+        TransformDecision::Continue
+    }
+}
+
+/// 保活: 5.0 的保活时间超过上限 (或为 0) 时改为上限, 与 CONNACK 中下发的 Server Keep Alive 一致,
+/// 由 broker 按同一时间检测断线; 3.x 无法告知客户端, 只在 `require` 时拒绝不启用保活的连接
+struct EnforceKeepAlive {
+    max: Option<u16>,
+    require: bool,
+}
+
+impl ConnectTransform for EnforceKeepAlive {
+    fn name(&self) -> &'static str {
+        "require_keepalive"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision {
+        if connect.protocol_level == 5
+            && let Some(max) = self.max
+        {
+            // 0 表示不启用保活, 对上限而言等同于无穷大
+            if connect.keep_alive == 0 || connect.keep_alive > max {
+                debug!(
+                    "Lowering keep-alive of client {:?} from {}s to {}s",
+                    context.log_client_id, connect.keep_alive, max
+                );
+                connect.keep_alive = max;
+            }
+            return TransformDecision::Continue;
+        }
+        if self.require && connect.keep_alive == 0 {
+            info!("Rejecting client {:?} from {}: keep-alive is disabled", context.log_client_id, context.peer);
+            metrics().keepalive_required_rejected_total.fetch_add(1, Ordering::Relaxed);
+            return TransformDecision::Reject(ConnackReason::NotAuthorized);
+        }
+        TransformDecision::Continue
+    }
+}
+
+/// 遗嘱延迟上限: 5.0 遗嘱在会话结束或遗嘱延迟间隔到期时发布 (以先到者为准), 实际延迟超过上限时把遗嘱延迟间隔改为上限
+/// 会话过期间隔已不超过上限时不改写; 3.x 遗嘱没有延迟
+struct MaxWillDelay(u32);
+
+impl ConnectTransform for MaxWillDelay {
+    fn name(&self) -> &'static str {
+        "max_will_delay"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision {
+        let session_expiry = connect.session_expiry_interval();
+        let Some(will) = connect.will.as_mut().filter(|_| connect.protocol_level == 5) else {
+            return TransformDecision::Continue;
+        };
+        let delay = will.delay_interval();
+        if delay.min(session_expiry) > self.0 {
+            debug!(
+                "Lowering will delay of client {:?} from {}s to {}s (session expiry {}s)",
+                context.log_client_id, delay, self.0, session_expiry
+            );
+            will.properties.set(WILL_DELAY_INTERVAL, PropertyValue::U32(self.0));
+        }
+        TransformDecision::Continue
+    }
+}
+
+/// 在 5.0 CONNECT 中以用户属性附加客户端 IP, 先移除客户端自带的同名属性防止伪造; 3.x 没有用户属性, 跳过
+struct ForwardedFor {
+    property: String,
+}
+
+impl ConnectTransform for ForwardedFor {
+    fn name(&self) -> &'static str {
+        "forwarded_for"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision {
+        if connect.protocol_level != 5 {
+            return TransformDecision::Continue;
+        }
+        connect.properties.0.retain(|(_, value)| !matches!(value, PropertyValue::Pair(key, _) if *key == self.property));
+        connect.properties.0.push((
+            USER_PROPERTY,
+            PropertyValue::Pair(self.property.clone(), context.peer.ip().to_string()),
+        ));
+        TransformDecision::Continue
+    }
+}
+
+/// 后端凭据: 客户端已在适配器认证, 用配置的用户名/密码连接 broker
+struct InjectCredentials(BackendAuthConfig);
+
+impl ConnectTransform for InjectCredentials {
+    fn name(&self) -> &'static str {
+        "backend_auth"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision {
+        if self.0.mode == BackendAuthMode::Always || connect.username.is_none() {
+            debug!("Injecting backend credentials for client {:?}", context.log_client_id);
+            connect.username = Some(self.0.username.clone());
+            connect.password = self.0.password.as_ref().map(|password| password.as_bytes().to_vec());
+        }
+        TransformDecision::Continue
+    }
+}
+
+/// 客户端 ID 前缀 (如按租户加上 `tenantA/`), 明文监听器和 TLS 监听器各自配置
+/// 空客户端 ID 由 broker 分配, 不加前缀; 加上前缀后超过 `max_len` 时拒绝连接
+struct ClientIdPrefix {
+    plain: Option<PrefixTemplate>,
+    tls: Option<PrefixTemplate>,
+    max_len: usize,
+}
+
+impl ConnectTransform for ClientIdPrefix {
+    fn name(&self) -> &'static str {
+        "client_id_prefix"
+    }
+
+    fn apply(&self, connect: &mut ConnectPacket, context: &TransformContext<'_>) -> TransformDecision {
+        let template = match context.tls {
+            Some(_) => &self.tls,
+            None => &self.plain,
+        };
+        let Some(template) = template.as_ref().filter(|_| !connect.client_id.is_empty()) else {
+            return TransformDecision::Continue;
+        };
+        let prefix = match template.render(context.tls) {
+            Ok(prefix) => prefix,
+            Err(attribute) => {
+                info!(
+                    "Rejecting client {:?} from {}: client certificate has no {} for client_id_prefix",
+                    context.log_client_id, context.peer, attribute.as_str()
+                );
+                metrics().client_id_prefix_rejected_total.fetch_add(1, Ordering::Relaxed);
+                return TransformDecision::Reject(ConnackReason::NotAuthorized);
+            }
+        };
+        let client_id = format!("{}{}", prefix, connect.client_id);
+        // CONNECT 中的字符串长度不能超过 65535
+        let max_len = self.max_len.min(u16::MAX as usize);
+        if client_id.len() > max_len {
+            info!(
+                "Rejecting client {:?} from {}: prefixed client ID is {} bytes, over max_client_id_len {}",
+                context.log_client_id, context.peer, client_id.len(), max_len
+            );
+            metrics().client_id_prefix_rejected_total.fetch_add(1, Ordering::Relaxed);
+            return TransformDecision::Reject(ConnackReason::ClientIdentifierNotValid);
+        }
+        debug!("Prefixing client ID of {:?} with {:?}", context.log_client_id, prefix);
+        connect.client_id = client_id;
+        TransformDecision::Continue
+    }
+}
+
+/// 前缀模板的一段
+#[derive(Debug, Clone)]
+enum PrefixSegment {
+    Literal(String),
+    /// `{listener}`: "tcp" 或 "tls"
+    Listener,
+    /// `{cert.CN}` 等: 客户端证书字段
+    Cert(CertAttribute),
+}
+
+/// 客户端 ID 前缀模板, 如 `"{cert.OU}/"`
+#[derive(Debug, Clone)]
+struct PrefixTemplate(Vec<PrefixSegment>);
+
+impl PrefixTemplate {
+    fn parse(template: &str) -> std::io::Result<Self> {
+        let invalid = |message: String| std::io::Error::new(ErrorKind::InvalidInput, format!("client_id_prefix {:?}: {}", template, message));
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(PrefixSegment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or_else(|| invalid("unclosed '{'".to_string()))? + start;
+            let segment = match &rest[start + 1..end] {
+                "listener" => PrefixSegment::Listener,
+                "cert.CN" => PrefixSegment::Cert(CertAttribute::CommonName),
+                "cert.O" => PrefixSegment::Cert(CertAttribute::Organization),
+                "cert.OU" => PrefixSegment::Cert(CertAttribute::OrganizationalUnit),
+                "cert.DNS" => PrefixSegment::Cert(CertAttribute::DnsName),
+                "cert.URI" => PrefixSegment::Cert(CertAttribute::Uri),
+                variable => return Err(invalid(format!("unknown variable {{{}}}", variable))),
+            };
+            segments.push(segment);
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(PrefixSegment::Literal(rest.to_string()));
+        }
+        Ok(PrefixTemplate(segments))
+    }
+
+    /// 客户端证书没有模板中的字段 (或没有证书) 时返回该字段
+    fn render(&self, tls: Option<&TlsPeer>) -> Result<String, CertAttribute> {
+        let mut prefix = String::new();
+        for segment in &self.0 {
+            match segment {
+                PrefixSegment::Literal(literal) => prefix.push_str(literal),
+                PrefixSegment::Listener => prefix.push_str(if tls.is_some() { "tls" } else { "tcp" }),
+                PrefixSegment::Cert(attribute) => {
+                    let value = tls
+                        .and_then(|peer| peer.certificate.as_deref())
+                        .and_then(|der| attribute_value(der, *attribute))
+                        .ok_or(*attribute)?;
+                    prefix.push_str(&value);
+                }
+            }
+        }
+        Ok(prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_variable_int, encode_packet, write_binary};
+    use crate::adapter_config::TlsConfig;
+    use crate::connect_packet::{connect_payload, encode_connect, parse_connect};
+    use crate::tls::TlsInfo;
+
+    fn peer() -> SocketAddr {
+        "192.0.2.10:50000".parse().unwrap()
+    }
+
+    /// 按转发路径处理 CONNECT 负载: 解析、应用管道、重新编码为完整的 CONNECT 包
+    fn forward(config: &AdapterConfig, payload: &[u8], tls: Option<&TlsPeer>) -> Result<Vec<u8>, TransformRejection> {
+        let pipeline = ConnectPipeline::from_config(config).unwrap();
+        let mut connect = parse_connect(payload).unwrap();
+        let context = TransformContext { peer: peer(), log_client_id: "client", tls };
+        pipeline.apply(&mut connect, &context)?;
+        Ok(encode_packet(0x10, &encode_connect(payload, &connect).unwrap()))
+    }
+
+    /// 检查固定头的剩余长度与负载一致, 返回解析后的 CONNECT
+    fn parse_forwarded(packet: &[u8]) -> ConnectPacket {
+        assert_eq!(packet[0], 0x10);
+        let (remaining_length, length_bytes) = decode_variable_int(&packet[1..]).unwrap();
+        let payload = &packet[1 + length_bytes..];
+        assert_eq!(remaining_length, payload.len(), "remaining length must match the re-encoded payload");
+        parse_connect(payload).unwrap()
+    }
+
+    /// 连接标志字节 (协议名 "MQTT" 之后跟协议级别)
+    fn connect_flags(packet: &[u8]) -> u8 {
+        let (_, length_bytes) = decode_variable_int(&packet[1..]).unwrap();
+        packet[1 + length_bytes + 2 + 4 + 1]
+    }
+
+    fn backend_auth(mode: BackendAuthMode, password: Option<&str>) -> AdapterConfig {
+        AdapterConfig {
+            backend_auth: Some(BackendAuthConfig {
+                username: "adapter".to_string(),
+                password: password.map(str::to_string),
+                mode,
+            }),
+            ..AdapterConfig::default()
+        }
+    }
+
+    /// 负载末尾应依次为注入的用户名和密码字段
+    fn assert_ends_with_credentials(packet: &[u8], username: &str, password: Option<&[u8]>) {
+        let mut expected = Vec::new();
+        write_binary(username.as_bytes(), &mut expected);
+        if let Some(password) = password {
+            write_binary(password, &mut expected);
+        }
+        assert!(packet.ends_with(&expected), "forwarded CONNECT {:02x?} does not end with {:02x?}", packet, expected);
+    }
+
+    #[test]
+    fn injects_credentials_for_anonymous_clients() {
+        let config = backend_auth(BackendAuthMode::Missing, Some("s3cret"));
+        for protocol_level in [4, 5] {
+            let packet = forward(&config, &connect_payload(protocol_level, 60, "sensor-1", None, None), None).unwrap();
+            let connect = parse_forwarded(&packet);
+            assert_eq!(connect.protocol_level, protocol_level);
+            assert_eq!(connect.client_id, "sensor-1");
+            assert_eq!(connect.username.as_deref(), Some("adapter"));
+            assert_eq!(connect.password.as_deref(), Some(&b"s3cret"[..]));
+            assert_eq!(connect_flags(&packet) & 0xc0, 0xc0, "username and password flags");
+            assert_ends_with_credentials(&packet, "adapter", Some(b"s3cret"));
+        }
+    }
+
+    #[test]
+    fn injects_username_only_when_no_password_is_configured() {
+        let config = backend_auth(BackendAuthMode::Missing, None);
+        for protocol_level in [4, 5] {
+            let packet = forward(&config, &connect_payload(protocol_level, 60, "sensor-1", None, None), None).unwrap();
+            let connect = parse_forwarded(&packet);
+            assert_eq!(connect.username.as_deref(), Some("adapter"));
+            assert_eq!(connect.password, None);
+            assert_eq!(connect_flags(&packet) & 0xc0, 0x80);
+            assert_ends_with_credentials(&packet, "adapter", None);
+        }
+    }
+
+    #[test]
+    fn missing_mode_keeps_client_credentials() {
+        let config = backend_auth(BackendAuthMode::Missing, Some("s3cret"));
+        for protocol_level in [4, 5] {
+            let payload = connect_payload(protocol_level, 60, "sensor-1", Some("alice"), Some(b"pw"));
+            let packet = forward(&config, &payload, None).unwrap();
+            let connect = parse_forwarded(&packet);
+            assert_eq!(connect.username.as_deref(), Some("alice"));
+            assert_eq!(connect.password.as_deref(), Some(&b"pw"[..]));
+            assert_eq!(&packet[packet.len() - payload.len()..], &payload[..], "CONNECT must be forwarded unchanged");
+        }
+    }
+
+    #[test]
+    fn always_mode_replaces_client_credentials() {
+        let config = backend_auth(BackendAuthMode::Always, Some("s3cret"));
+        for protocol_level in [4, 5] {
+            let payload = connect_payload(protocol_level, 60, "sensor-1", Some("alice"), Some(b"a-much-longer-password"));
+            let packet = forward(&config, &payload, None).unwrap();
+            let connect = parse_forwarded(&packet);
+            assert_eq!(connect.username.as_deref(), Some("adapter"));
+            assert_eq!(connect.password.as_deref(), Some(&b"s3cret"[..]));
+            assert_ends_with_credentials(&packet, "adapter", Some(b"s3cret"));
+        }
+    }
+
+    #[test]
+    fn always_mode_drops_client_password_when_none_is_configured() {
+        let config = backend_auth(BackendAuthMode::Always, None);
+        let payload = connect_payload(4, 60, "sensor-1", Some("alice"), Some(b"pw"));
+        let connect = parse_forwarded(&forward(&config, &payload, None).unwrap());
+        assert_eq!(connect.username.as_deref(), Some("adapter"));
+        assert_eq!(connect.password, None);
+    }
+
+    /// 在客户端 ID 后追加后缀, 或拒绝连接
+    struct Suffix(&'static str, Option<ConnackReason>);
+
+    impl ConnectTransform for Suffix {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn apply(&self, connect: &mut ConnectPacket, _context: &TransformContext<'_>) -> TransformDecision {
+            connect.client_id.push_str(self.0);
+            self.1.map_or(TransformDecision::Continue, TransformDecision::Reject)
+        }
+    }
+
+    fn apply(pipeline: &ConnectPipeline, payload: &[u8]) -> (Result<(), TransformRejection>, ConnectPacket) {
+        let mut connect = parse_connect(payload).unwrap();
+        let context = TransformContext { peer: peer(), log_client_id: "client", tls: None };
+        (pipeline.apply(&mut connect, &context), connect)
+    }
+
+    #[test]
+    fn chained_transforms_apply_in_registration_order() {
+        let mut pipeline = ConnectPipeline::default();
+        pipeline.push(Suffix("-a", None));
+        pipeline.push(Suffix("-b", None));
+        assert_eq!(pipeline.names(), ["-a", "-b"]);
+
+        let payload = connect_payload(4, 60, "sensor", None, None);
+        let (result, connect) = apply(&pipeline, &payload);
+        assert_eq!(result, Ok(()));
+        let packet = encode_packet(0x10, &encode_connect(&payload, &connect).unwrap());
+        assert_eq!(packet, encode_packet(0x10, &connect_payload(4, 60, "sensor-a-b", None, None)));
+    }
+
+    #[test]
+    fn rejecting_transform_stops_the_chain() {
+        let mut pipeline = ConnectPipeline::default();
+        pipeline.push(Suffix("-a", Some(ConnackReason::NotAuthorized)));
+        pipeline.push(Suffix("-b", None));
+        let (result, connect) = apply(&pipeline, &connect_payload(4, 60, "sensor", None, None));
+        assert_eq!(result, Err(TransformRejection { transform: "-a", reason: ConnackReason::NotAuthorized }));
+        assert_eq!(connect.client_id, "sensor-a");
+    }
+
+    /// 内置改写的组合: 凭据注入按原始客户端 ID 应用, 前缀最后应用, 转发的 CONNECT 同时带有两项改写
+    #[test]
+    fn builtin_transforms_combine_in_the_documented_order() {
+        let config = AdapterConfig { client_id_prefix: Some("tenantA/".to_string()), ..backend_auth(BackendAuthMode::Missing, Some("s3cret")) };
+        assert_eq!(ConnectPipeline::from_config(&config).unwrap().names(), ["backend_auth", "client_id_prefix"]);
+
+        let packet = forward(&config, &connect_payload(5, 60, "sensor-1", None, None), None).unwrap();
+        let expected = encode_packet(0x10, &connect_payload(5, 60, "tenantA/sensor-1", Some("adapter"), Some(b"s3cret")));
+        assert_eq!(packet, expected);
+    }
+
+    /// 客户端 ID 为 `client_id` 的 CONNECT 负载, 按参数设置 Clean Session / Clean Start 和 5.0 的会话过期间隔
+    fn session_payload(protocol_level: u8, client_id: &str, clean_session: bool, session_expiry: Option<u32>) -> Vec<u8> {
+        let base = connect_payload(protocol_level, 60, client_id, None, None);
+        let mut connect = parse_connect(&base).unwrap();
+        connect.clean_session = clean_session;
+        if let Some(interval) = session_expiry {
+            connect.properties.set(SESSION_EXPIRY_INTERVAL, PropertyValue::U32(interval));
+        }
+        encode_connect(&base, &connect).unwrap()
+    }
+
+    fn clean_session_config() -> AdapterConfig {
+        AdapterConfig { force_clean_session: vec!["ephemeral-*".to_string()], ..AdapterConfig::default() }
+    }
+
+    #[test]
+    fn forces_clean_session_for_matching_v311_clients() {
+        let forced_before = metrics().forced_clean_session_total.load(Ordering::Relaxed);
+        let packet = forward(&clean_session_config(), &session_payload(4, "ephemeral-1", false, None), None).unwrap();
+        assert_eq!(connect_flags(&packet), 0x02, "clean session bit is set");
+        assert!(parse_forwarded(&packet).clean_session);
+        assert!(metrics().forced_clean_session_total.load(Ordering::Relaxed) > forced_before);
+
+        // 不匹配的客户端 ID 保持持久会话
+        let payload = session_payload(4, "durable-1", false, None);
+        let packet = forward(&clean_session_config(), &payload, None).unwrap();
+        assert_eq!(connect_flags(&packet), 0x00);
+        assert_eq!(&packet[packet.len() - payload.len()..], &payload[..]);
+    }
+
+    #[test]
+    fn zeroes_v5_session_expiry_for_matching_clients() {
+        // 请求了会话过期间隔的 5.0 客户端: 不论 Clean Start 是否设置, 都改为 Clean Start + 过期间隔 0
+        for clean_start in [false, true] {
+            let packet = forward(&clean_session_config(), &session_payload(5, "ephemeral-1", clean_start, Some(3600)), None).unwrap();
+            assert_eq!(connect_flags(&packet), 0x02);
+            let connect = parse_forwarded(&packet);
+            assert!(connect.clean_session);
+            assert_eq!(connect.properties.get(SESSION_EXPIRY_INTERVAL), Some(&PropertyValue::U32(0)));
+            assert!(connect.has_transient_session());
+        }
+
+        // 未携带会话过期间隔 (默认 0) 时只设置 Clean Start, 不添加属性
+        let packet = forward(&clean_session_config(), &session_payload(5, "ephemeral-1", false, None), None).unwrap();
+        let connect = parse_forwarded(&packet);
+        assert!(connect.clean_session);
+        assert_eq!(connect.properties.get(SESSION_EXPIRY_INTERVAL), None);
+    }
+
+    #[test]
+    fn transient_or_unmatched_v5_sessions_are_forwarded_unchanged() {
+        for payload in [
+            session_payload(5, "ephemeral-1", true, Some(0)),
+            session_payload(5, "ephemeral-1", true, None),
+            session_payload(5, "durable-1", false, Some(3600)),
+        ] {
+            let packet = forward(&clean_session_config(), &payload, None).unwrap();
+            assert_eq!(&packet[packet.len() - payload.len()..], &payload[..]);
+        }
+    }
+
+    /// 带遗嘱的 CONNECT 负载 (逐字节编码): 会话过期间隔 `session_expiry`, 5.0 遗嘱属性中的遗嘱延迟间隔 `will_delay`
+    fn will_payload(protocol_level: u8, session_expiry: u32, will_delay: Option<u32>) -> Vec<u8> {
+        let mut payload = vec![0x00, 0x04, b'M', b'Q', b'T', b'T', protocol_level, 0x06, 0x00, 0x3C];
+        if protocol_level == 5 {
+            payload.extend_from_slice(&[0x05, SESSION_EXPIRY_INTERVAL]);
+            payload.extend_from_slice(&session_expiry.to_be_bytes());
+        }
+        payload.extend_from_slice(b"\x00\x04will");
+        if protocol_level == 5 {
+            match will_delay {
+                Some(delay) => {
+                    payload.extend_from_slice(&[0x05, WILL_DELAY_INTERVAL]);
+                    payload.extend_from_slice(&delay.to_be_bytes());
+                }
+                None => payload.push(0x00),
+            }
+        }
+        payload.extend_from_slice(b"\x00\x01t\x00\x03bye");
+        payload
+    }
+
+    fn will_delay_config(max: u32) -> AdapterConfig {
+        AdapterConfig { max_will_delay_sec: Some(max), ..AdapterConfig::default() }
+    }
+
+    #[test]
+    fn will_delay_above_the_cap_is_rewritten() {
+        let packet = forward(&will_delay_config(60), &will_payload(5, 7200, Some(3600)), None).unwrap();
+        assert_eq!(packet, encode_packet(0x10, &will_payload(5, 7200, Some(60))));
+        // 遗嘱属性: 长度 5, Will Delay Interval = 60
+        let will_properties = [0x05, WILL_DELAY_INTERVAL, 0x00, 0x00, 0x00, 0x3C];
+        assert!(packet.windows(will_properties.len()).any(|window| window == will_properties), "{:02x?}", packet);
+        assert_eq!(parse_forwarded(&packet).will.unwrap().delay_interval(), 60);
+    }
+
+    #[test]
+    fn will_delay_at_or_below_the_cap_is_forwarded_unchanged() {
+        for delay in [Some(30), Some(60), None] {
+            let payload = will_payload(5, 7200, delay);
+            let packet = forward(&will_delay_config(60), &payload, None).unwrap();
+            assert_eq!(packet, encode_packet(0x10, &payload), "will delay {:?}", delay);
+        }
+    }
+
+    /// 遗嘱最迟在会话结束时发布: 会话过期间隔不超过上限时, 再长的遗嘱延迟也不改写; 3.1.1 遗嘱没有延迟
+    #[test]
+    fn will_delay_cap_follows_the_session_expiry() {
+        for payload in [will_payload(5, 30, Some(3600)), will_payload(5, 0, Some(3600)), will_payload(4, 0, None)] {
+            let packet = forward(&will_delay_config(60), &payload, None).unwrap();
+            assert_eq!(packet, encode_packet(0x10, &payload));
+        }
+    }
+
+    /// TLS 连接的信息, 带上测试目录中的客户端证书 (None = 没有客户端证书)
+    fn tls_peer(certificate: Option<&str>) -> TlsPeer {
+        let certificate = certificate.map(|name| {
+            let pem = std::fs::read(format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+            rustls_pemfile::certs(&mut pem.as_slice()).unwrap().remove(0)
+        });
+        TlsPeer { info: TlsInfo { version: "1.3", cipher: "TLS13_AES_128_GCM_SHA256" }, certificate }
+    }
+
+    fn prefix_config(plain: Option<&str>, tls: Option<&str>) -> AdapterConfig {
+        AdapterConfig {
+            client_id_prefix: plain.map(str::to_string),
+            tls: tls.map(|prefix| TlsConfig { client_id_prefix: Some(prefix.to_string()), ..TlsConfig::default() }),
+            ..AdapterConfig::default()
+        }
+    }
+
+    #[test]
+    fn forwards_the_prefixed_client_id() {
+        let config = prefix_config(Some("tenantA/"), None);
+        for protocol_level in [4, 5] {
+            let payload = connect_payload(protocol_level, 60, "sensor-1", Some("alice"), Some(b"pw"));
+            let connect = parse_forwarded(&forward(&config, &payload, None).unwrap());
+            assert_eq!(connect.protocol_level, protocol_level);
+            assert_eq!(connect.client_id, "tenantA/sensor-1");
+            assert_eq!(connect.username.as_deref(), Some("alice"));
+            assert_eq!(connect.password.as_deref(), Some(&b"pw"[..]));
+        }
+    }
+
+    #[test]
+    fn empty_client_id_is_not_prefixed() {
+        let config = prefix_config(Some("tenantA/"), None);
+        let connect = parse_forwarded(&forward(&config, &connect_payload(4, 60, "", None, None), None).unwrap());
+        assert_eq!(connect.client_id, "");
+    }
+
+    #[test]
+    fn listeners_use_their_own_templates() {
+        let config = prefix_config(Some("{listener}/"), Some("{listener}/{cert.OU}/{cert.CN}/"));
+        let payload = connect_payload(5, 60, "device", None, None);
+        let plain = parse_forwarded(&forward(&config, &payload, None).unwrap());
+        assert_eq!(plain.client_id, "tcp/device");
+        let peer = tls_peer(Some("client_sensors.pem"));
+        let tls = parse_forwarded(&forward(&config, &payload, Some(&peer)).unwrap());
+        assert_eq!(tls.client_id, "tls/sensors/sensor-1/device");
+
+        // 只配置了明文监听器的前缀时 TLS 连接不加前缀
+        let config = prefix_config(Some("{listener}/"), None);
+        assert_eq!(parse_forwarded(&forward(&config, &payload, Some(&peer)).unwrap()).client_id, "device");
+    }
+
+    #[test]
+    fn certificate_templates_read_subject_alternative_names() {
+        let config = prefix_config(None, Some("{cert.DNS}|{cert.URI}|{cert.O}/"));
+        let peer = tls_peer(Some("client_gateways.pem"));
+        let connect = parse_forwarded(&forward(&config, &connect_payload(4, 60, "gw", None, None), Some(&peer)).unwrap());
+        assert_eq!(connect.client_id, "client_gateways.example|spiffe://example/client_gateways|Example/gw");
+    }
+
+    #[test]
+    fn missing_certificate_field_rejects_the_connection() {
+        let config = prefix_config(None, Some("{cert.OU}/"));
+        let payload = connect_payload(5, 60, "device", None, None);
+        for peer in [tls_peer(Some("client_no_ou.pem")), tls_peer(None)] {
+            let rejection = forward(&config, &payload, Some(&peer)).unwrap_err();
+            assert_eq!(rejection, TransformRejection { transform: "client_id_prefix", reason: ConnackReason::NotAuthorized });
+        }
+    }
+
+    #[test]
+    fn prefixed_client_id_over_max_len_is_rejected() {
+        let config = AdapterConfig { max_client_id_len: 16, ..prefix_config(Some("tenantA/"), None) };
+        // 8 字节前缀 + 8 字节客户端 ID 正好等于上限
+        let connect = parse_forwarded(&forward(&config, &connect_payload(4, 60, "12345678", None, None), None).unwrap());
+        assert_eq!(connect.client_id, "tenantA/12345678");
+        let rejection = forward(&config, &connect_payload(4, 60, "123456789", None, None), None).unwrap_err();
+        assert_eq!(rejection, TransformRejection { transform: "client_id_prefix", reason: ConnackReason::ClientIdentifierNotValid });
+    }
+
+    #[test]
+    fn unknown_template_variable_is_a_config_error() {
+        let error = ConnectPipeline::from_config(&prefix_config(Some("{tenant}/"), None)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("unknown variable {tenant}"), "{}", error);
+        assert!(ConnectPipeline::from_config(&prefix_config(Some("{listener"), None)).is_err());
+    }
+}
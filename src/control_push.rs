@@ -0,0 +1,66 @@
+// 控制包立即发送
+// 连接保持 Nagle 算法 (批量 PUBLISH 合并成大的 TCP 段), 转发的数据中含有控制包 (PUBLISH 以外的包) 时
+// 短暂打开 TCP_NODELAY, 让内核立即发出已缓存的数据, 控制包不必等待上一个段的 ACK
+
+use log::debug;
+
+use crate::tap::{PacketTap, packet_type};
+
+/// 转发期间切换 TCP_NODELAY 用的套接字句柄
+/// 创建时复制连接的文件描述符, 句柄自己持有副本, 不依赖转发中的流何时关闭
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct PushSocket(socket2::Socket);
+
+#[cfg(unix)]
+impl PushSocket {
+    pub fn new(stream: &impl std::os::fd::AsFd) -> std::io::Result<Self> {
+        socket2::SockRef::from(stream).try_clone().map(PushSocket)
+    }
+
+    /// 打开 TCP_NODELAY 时 Linux 立即发送已缓存的数据, 随后恢复 Nagle
+    fn push(&self) -> std::io::Result<()> {
+        self.0.set_nodelay(true).and_then(|()| self.0.set_nodelay(false))
+    }
+}
+
+/// 非 Unix 系统上不切换 TCP_NODELAY, 控制包按 Nagle 算法发送
+#[cfg(not(unix))]
+#[derive(Debug)]
+pub struct PushSocket;
+
+#[cfg(not(unix))]
+impl PushSocket {
+    pub fn new<S>(_stream: &S) -> std::io::Result<Self> {
+        Ok(PushSocket)
+    }
+
+    fn push(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 单个转发方向的控制包检测, 写入端为 `socket` 对应的连接
+#[derive(Debug)]
+pub struct ControlPush {
+    socket: PushSocket,
+    tap: PacketTap,
+}
+
+impl ControlPush {
+    pub fn new(socket: PushSocket) -> Self {
+        ControlPush { socket, tap: PacketTap::default() }
+    }
+
+    /// 喂入即将转发的一块数据, 其中含有控制包时返回 true; 流无法解析之后不再检测
+    pub fn has_control_packet(&mut self, data: &[u8]) -> bool {
+        self.tap.feed_spans(data).iter().any(|span| span.packet_type() != packet_type::PUBLISH)
+    }
+
+    /// 立即发出套接字发送缓冲区中的数据
+    pub fn push(&self) {
+        if let Err(e) = self.socket.push() {
+            debug!("Failed to toggle TCP_NODELAY: {}", e);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// 客户端 ID 拒绝列表
+// 通过管理接口在运行时添加/移除, 可选保存到文件 (每行一个客户端 ID), 重启后仍然生效
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// 运行时客户端 ID 拒绝列表
+pub struct ClientIdDenylist {
+    ids: Mutex<HashSet<String>>,
+    /// 持久化文件 (未配置则只保存在内存中)
+    path: Option<PathBuf>,
+}
+
+impl ClientIdDenylist {
+    /// 从持久化文件加载, 文件不存在时为空列表
+    pub fn load(path: Option<&str>) -> std::io::Result<Self> {
+        let mut ids = HashSet::new();
+        if let Some(path) = path {
+            match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    ids.extend(content.lines().filter(|line| !line.is_empty()).map(str::to_string));
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(std::io::Error::new(e.kind(), format!("Failed to read client ID denylist {}: {}", path, e)));
+                }
+            }
+        }
+        Ok(ClientIdDenylist { ids: Mutex::new(ids), path: path.map(PathBuf::from) })
+    }
+
+    /// 客户端 ID 是否被拒绝
+    pub fn contains(&self, client_id: &str) -> bool {
+        self.ids.lock().unwrap().contains(client_id)
+    }
+
+    /// 所有被拒绝的客户端 ID (已排序)
+    pub fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.ids.lock().unwrap().iter().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// 添加客户端 ID, 返回是否为新添加的
+    /// 保存失败时内存中的列表仍然生效, 返回错误
+    pub fn insert(&self, client_id: &str) -> std::io::Result<bool> {
+        let mut ids = self.ids.lock().unwrap();
+        if !ids.insert(client_id.to_string()) {
+            return Ok(false);
+        }
+        self.save(&ids)?;
+        Ok(true)
+    }
+
+    /// 移除客户端 ID, 返回是否存在
+    pub fn remove(&self, client_id: &str) -> std::io::Result<bool> {
+        let mut ids = self.ids.lock().unwrap();
+        if !ids.remove(client_id) {
+            return Ok(false);
+        }
+        self.save(&ids)?;
+        Ok(true)
+    }
+
+    /// 写入临时文件后重命名, 避免中途失败留下不完整的文件
+    /// 客户端 ID 中的换行符会破坏文件格式, 由调用方拒绝
+    fn save(&self, ids: &HashSet<String>) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut sorted: Vec<&String> = ids.iter().collect();
+        sorted.sort();
+        let mut content = String::new();
+        for id in sorted {
+            content.push_str(id);
+            content.push('\n');
+        }
+
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, content)?;
+        std::fs::rename(&temp, path)
+    }
+}
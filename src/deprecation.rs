@@ -0,0 +1,60 @@
+// MQTT 3.1.0 (MQIsdp) 停用
+// 停用日期之前继续升级到 3.1.1 并记录弃用警告, 到期之后用 CONNACK 0x01 拒绝
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 3.1.0 停用策略
+#[derive(Debug, Clone, Copy, Default)]
+pub struct V310Sunset {
+    /// 开始拒绝的时间 (停用日期的 UTC 零点), None 表示一直升级
+    sunset_at: Option<SystemTime>,
+}
+
+impl V310Sunset {
+    /// 解析 `v310_sunset_date` ("YYYY-MM-DD")
+    pub fn from_config(sunset_date: Option<&str>) -> std::io::Result<Self> {
+        let Some(date) = sunset_date else {
+            return Ok(V310Sunset::default());
+        };
+        let days = parse_date(date).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid v310_sunset_date {:?} (expected YYYY-MM-DD)", date),
+            )
+        })?;
+        Ok(V310Sunset { sunset_at: Some(UNIX_EPOCH + Duration::from_secs(days * 86_400)) })
+    }
+
+    /// 是否已过停用日期
+    pub fn is_past(&self) -> bool {
+        self.sunset_at.is_some_and(|sunset_at| SystemTime::now() >= sunset_at)
+    }
+}
+
+/// 解析 "YYYY-MM-DD", 返回 1970-01-01 以来的天数
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if year < 1970 || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    // 公历日期转天数 (Howard Hinnant 的 days_from_civil)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some((era * 146_097 + day_of_era - 719_468) as u64)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
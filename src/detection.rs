@@ -0,0 +1,209 @@
+// 协议识别策略
+// 每个监听器一份: 是否升级 3.1.0、接受哪些协议版本、协议名和 CONNECT 标志检查的宽严, 以及读出 CONNECT 的时限
+
+use std::time::Duration;
+
+use crate::adapter_config::{AdapterConfig, DetectionConfig};
+use crate::deprecation::V310Sunset;
+use crate::smart_adapter::MqttVersion;
+
+/// 监听器的协议识别策略
+#[derive(Debug, Clone)]
+pub struct DetectionPolicy {
+    /// 3.1.0 升级为 3.1.1 后转发, false 时原样转发
+    pub upgrade_v310: bool,
+    /// 接受的 MQTT 协议版本
+    pub allowed_versions: Vec<MqttVersion>,
+    /// 接受 MQIsdp 协议名的非标准变体
+    pub lenient_legacy: bool,
+    /// 严格检查 CONNECT 标志
+    pub strict_connect_flags: bool,
+    /// 从 accept 到收到完整 CONNECT 的时限, None = 不限制
+    pub connect_deadline: Option<Duration>,
+    /// MQTT 3.1.0 停用日期 (所有监听器相同)
+    pub v310_sunset: V310Sunset,
+}
+
+impl DetectionPolicy {
+    /// [adapter] 中的设置, `overrides` 为监听器自己的配置 (如 [adapter.tls.detection]), 其中设置的字段优先
+    pub fn from_config(config: &AdapterConfig, overrides: Option<&DetectionConfig>) -> std::io::Result<Self> {
+        let overrides = overrides.cloned().unwrap_or_default();
+        let allowed_versions = overrides.allowed_versions.as_ref().unwrap_or(&config.allowed_versions);
+        let connect_deadline_ms = overrides.connect_deadline_ms.unwrap_or(config.connect_deadline_ms);
+        Ok(DetectionPolicy {
+            upgrade_v310: overrides.upgrade_v310.unwrap_or(config.upgrade_v310),
+            allowed_versions: MqttVersion::parse_allowed(allowed_versions)?,
+            lenient_legacy: overrides.lenient_legacy.unwrap_or(config.lenient_legacy),
+            strict_connect_flags: overrides.strict_connect_flags.unwrap_or(config.strict_connect_flags),
+            connect_deadline: (connect_deadline_ms > 0).then(|| Duration::from_millis(connect_deadline_ms)),
+            v310_sunset: V310Sunset::from_config(config.v310_sunset_date.as_deref())?,
+        })
+    }
+
+    pub fn allows(&self, version: MqttVersion) -> bool {
+        self.allowed_versions.contains(&version)
+    }
+
+    /// 启动日志中的摘要, 如 "MQTT 3.1.0 / 3.1.1 / 5.0, 3.1.0 upgraded, strict flags"
+    pub fn describe(&self) -> String {
+        let versions: Vec<&str> = self.allowed_versions.iter().map(|version| version.as_str()).collect();
+        let mut parts = vec![format!("MQTT {}", versions.join(" / "))];
+        if self.allows(MqttVersion::V310) {
+            parts.push(if self.upgrade_v310 { "3.1.0 upgraded" } else { "3.1.0 forwarded unchanged" }.to_string());
+        }
+        if self.lenient_legacy {
+            parts.push("lenient MQIsdp names".to_string());
+        }
+        if self.strict_connect_flags {
+            parts.push("strict flags".to_string());
+        }
+        if let Some(deadline) = self.connect_deadline {
+            parts.push(format!("CONNECT within {:?}", deadline));
+        }
+        parts.join(", ")
+    }
+
+    /// 严格模式下检查 CONNECT 标志, 违反规范时返回原因; `payload` 为去掉固定头之后的 CONNECT
+    /// 负载过短时不在这里报错, 由之后的解析处理
+    pub fn check_connect_flags(&self, payload: &[u8]) -> Result<(), &'static str> {
+        if !self.strict_connect_flags || payload.len() < 2 {
+            return Ok(());
+        }
+        let name_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+        let (Some(&protocol_level), Some(&flags)) = (payload.get(2 + name_len), payload.get(3 + name_len)) else {
+            return Ok(());
+        };
+        let will = flags & 0x04 != 0;
+        let will_qos = (flags >> 3) & 0x03;
+        if flags & 0x01 != 0 {
+            Err("reserved flag is set")
+        } else if !will && flags & 0x38 != 0 {
+            Err("will QoS or will retain set without a will")
+        } else if will_qos == 3 {
+            Err("will QoS is 3")
+        } else if protocol_level < 5 && flags & 0x40 != 0 && flags & 0x80 == 0 {
+            Err("password flag set without a username")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AdapterError;
+    use crate::smart_adapter::detect_and_convert_protocol;
+
+    /// 按握手的顺序应用策略的结果: 识别协议、检查标志、检查版本
+    #[derive(Debug, PartialEq, Eq)]
+    enum Outcome {
+        /// 接受, 转发的协议名和级别
+        Forward(MqttVersion, &'static str, u8),
+        Unknown,
+        BadFlags,
+        NotAllowed,
+    }
+    use Outcome::*;
+
+    fn connect(name: &[u8], level: u8, flags: u8) -> Vec<u8> {
+        let mut payload = (name.len() as u16).to_be_bytes().to_vec();
+        payload.extend_from_slice(name);
+        payload.extend_from_slice(&[level, flags, 0x00, 0x3C, 0x00, 0x03]);
+        payload.extend_from_slice(b"dev");
+        payload
+    }
+
+    fn outcome(policy: &DetectionPolicy, payload: &[u8]) -> Outcome {
+        let (version, forwarded) = match detect_and_convert_protocol(payload, policy) {
+            Ok(detected) => detected,
+            Err(AdapterError::UnknownProtocol { .. }) => return Unknown,
+            Err(e) => panic!("{}", e),
+        };
+        if policy.check_connect_flags(payload).is_err() {
+            return BadFlags;
+        }
+        if !policy.allows(version) {
+            return NotAllowed;
+        }
+        let name_len = u16::from_be_bytes([forwarded[0], forwarded[1]]) as usize;
+        let name = match &forwarded[2..2 + name_len] {
+            b"MQTT" => "MQTT",
+            b"MQIsdp" => "MQIsdp",
+            b"mqisdp" => "mqisdp",
+            other => panic!("unexpected forwarded protocol name {:?}", other),
+        };
+        Forward(version, name, forwarded[2 + name_len])
+    }
+
+    #[test]
+    fn policy_flags_by_protocol_version() {
+        use MqttVersion::*;
+        let inputs = [
+            connect(b"MQIsdp", 3, 0x02),
+            connect(b"MQTT", 4, 0x02),
+            connect(b"MQTT", 5, 0x02),
+            connect(b"MQXX", 4, 0x02),
+            // 非标准的 3.1.0 协议名, 以及设置了保留标志的 3.1.1
+            connect(b"mqisdp", 3, 0x02),
+            connect(b"MQTT", 4, 0x03),
+        ];
+        let rows: [(&str, AdapterConfig, Option<DetectionConfig>, [Outcome; 6]); 7] = [
+            (
+                "defaults",
+                AdapterConfig::default(),
+                None,
+                [Forward(V310, "MQTT", 4), Forward(V311, "MQTT", 4), Forward(V500, "MQTT", 5), Unknown, Unknown, Forward(V311, "MQTT", 4)],
+            ),
+            (
+                "upgrade_v310 = false",
+                AdapterConfig { upgrade_v310: false, ..AdapterConfig::default() },
+                None,
+                [Forward(V310, "MQIsdp", 3), Forward(V311, "MQTT", 4), Forward(V500, "MQTT", 5), Unknown, Unknown, Forward(V311, "MQTT", 4)],
+            ),
+            (
+                "lenient_legacy",
+                AdapterConfig { lenient_legacy: true, ..AdapterConfig::default() },
+                None,
+                [Forward(V310, "MQTT", 4), Forward(V311, "MQTT", 4), Forward(V500, "MQTT", 5), Unknown, Forward(V310, "MQTT", 4), Forward(V311, "MQTT", 4)],
+            ),
+            (
+                "strict_connect_flags",
+                AdapterConfig { strict_connect_flags: true, ..AdapterConfig::default() },
+                None,
+                [Forward(V310, "MQTT", 4), Forward(V311, "MQTT", 4), Forward(V500, "MQTT", 5), Unknown, Unknown, BadFlags],
+            ),
+            (
+                "allowed_versions = [5.0]",
+                AdapterConfig { allowed_versions: vec!["5.0".to_string()], ..AdapterConfig::default() },
+                None,
+                [NotAllowed, NotAllowed, Forward(V500, "MQTT", 5), Unknown, Unknown, NotAllowed],
+            ),
+            (
+                "allowed_versions = [3.1.0, 3.1.1]",
+                AdapterConfig { allowed_versions: vec!["3.1.0".to_string(), "3.1.1".to_string()], ..AdapterConfig::default() },
+                None,
+                [Forward(V310, "MQTT", 4), Forward(V311, "MQTT", 4), NotAllowed, Unknown, Unknown, Forward(V311, "MQTT", 4)],
+            ),
+            (
+                "listener overrides",
+                AdapterConfig { allowed_versions: vec!["5.0".to_string()], ..AdapterConfig::default() },
+                Some(DetectionConfig {
+                    upgrade_v310: Some(false),
+                    allowed_versions: Some(vec!["3.1.0".to_string()]),
+                    lenient_legacy: Some(true),
+                    strict_connect_flags: Some(true),
+                    ..DetectionConfig::default()
+                }),
+                // 不升级时非标准的协议名也原样转发
+                [Forward(V310, "MQIsdp", 3), NotAllowed, NotAllowed, Unknown, Forward(V310, "mqisdp", 3), BadFlags],
+            ),
+        ];
+
+        for (name, config, listener, expected) in rows {
+            let policy = DetectionPolicy::from_config(&config, listener.as_ref()).unwrap();
+            let actual: Vec<Outcome> = inputs.iter().map(|payload| outcome(&policy, payload)).collect();
+            assert_eq!(actual, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// 后端地址的 DNS 缓存
+// 系统解析器 (getaddrinfo) 不返回记录的 TTL, 解析结果统一保留 `dns_cache_ttl_sec`; 到期前被使用时在后台刷新, 到期后重新解析
+
+use futures_util::future::BoxFuture;
+use log::debug;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::adapter_config::AdapterConfig;
+use crate::metrics::metrics;
+
+/// 启动时配置的缓存, 未设置时每次连接都解析
+static CACHE: OnceLock<Arc<DnsCache>> = OnceLock::new();
+
+/// 启用 `dns_cache` 时创建全局缓存, 启动时调用一次
+pub fn init(config: &AdapterConfig) {
+    if config.dns_cache && config.dns_cache_ttl_sec > 0 {
+        let _ = CACHE.set(Arc::new(DnsCache::new(Duration::from_secs(config.dns_cache_ttl_sec))));
+    }
+}
+
+/// 解析后端地址 (host:port); IP 地址不经过缓存
+pub async fn resolve(addr: &str) -> std::io::Result<Vec<SocketAddr>> {
+    if let Ok(addr) = addr.parse() {
+        return Ok(vec![addr]);
+    }
+    match CACHE.get() {
+        Some(cache) => cache.resolve(addr).await,
+        None => lookup(addr).await,
+    }
+}
+
+/// 系统解析器, 没有地址时返回 `NotFound`
+async fn lookup(addr: &str) -> std::io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(addr).await?.collect();
+    if addrs.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} did not resolve to an address", addr)));
+    }
+    Ok(addrs)
+}
+
+/// 缓存使用的解析器
+pub trait Resolver: Send + Sync {
+    fn lookup(&self, addr: String) -> BoxFuture<'static, std::io::Result<Vec<SocketAddr>>>;
+}
+
+/// 系统解析器 (getaddrinfo)
+struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn lookup(&self, addr: String) -> BoxFuture<'static, std::io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { lookup(&addr).await })
+    }
+}
+
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    /// 后台刷新进行中, 同一名称只刷新一次
+    refreshing: bool,
+}
+
+/// 按名称缓存的解析结果
+pub struct DnsCache {
+    ttl: Duration,
+    resolver: Arc<dyn Resolver>,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl DnsCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_resolver(ttl, Arc::new(SystemResolver))
+    }
+
+    pub fn with_resolver(ttl: Duration, resolver: Arc<dyn Resolver>) -> Self {
+        DnsCache { ttl, resolver, entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// 过了 TTL 的 3/4 之后被使用时开始后台刷新
+    fn refresh_after(&self) -> Duration {
+        self.ttl - self.ttl / 4
+    }
+
+    pub async fn resolve(self: &Arc<Self>, addr: &str) -> std::io::Result<Vec<SocketAddr>> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.get_mut(addr) {
+                let age = entry.resolved_at.elapsed();
+                if age < self.ttl {
+                    metrics().dns_cache_hits_total.fetch_add(1, Ordering::Relaxed);
+                    if age >= self.refresh_after() && !entry.refreshing {
+                        entry.refreshing = true;
+                        tokio::spawn(self.clone().refresh(addr.to_string()));
+                    }
+                    return Ok(entry.addrs.clone());
+                }
+            }
+        }
+
+        metrics().dns_cache_misses_total.fetch_add(1, Ordering::Relaxed);
+        let addrs = self.resolver.lookup(addr.to_string()).await?;
+        self.store(addr.to_string(), addrs.clone());
+        Ok(addrs)
+    }
+
+    /// 后台刷新; 失败时保留原来的结果直到到期, 之后被使用时再次刷新
+    async fn refresh(self: Arc<Self>, addr: String) {
+        match self.resolver.lookup(addr.clone()).await {
+            Ok(addrs) => {
+                debug!("DNS cache: refreshed {} ({} addresses)", addr, addrs.len());
+                self.store(addr, addrs);
+            }
+            Err(e) => {
+                debug!("DNS cache: refreshing {} failed: {}", addr, e);
+                if let Some(entry) = self.entries.lock().unwrap().get_mut(&addr) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+    }
+
+    fn store(&self, addr: String, addrs: Vec<SocketAddr>) {
+        let entry = Entry { addrs, resolved_at: Instant::now(), refreshing: false };
+        self.entries.lock().unwrap().insert(addr, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use std::sync::atomic::{AtomicBool, AtomicU8};
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    /// 计数的解析器: 第 n 次解析返回 10.0.0.n, `fail` 为 true 时解析失败
+    #[derive(Default)]
+    struct CountingResolver {
+        lookups: AtomicU8,
+        fail: AtomicBool,
+    }
+
+    impl Resolver for CountingResolver {
+        fn lookup(&self, _addr: String) -> BoxFuture<'static, std::io::Result<Vec<SocketAddr>>> {
+            let n = self.lookups.fetch_add(1, Ordering::SeqCst) + 1;
+            let fail = self.fail.load(Ordering::SeqCst);
+            Box::pin(async move {
+                if fail {
+                    return Err(std::io::Error::new(ErrorKind::NotFound, "lookup failed"));
+                }
+                Ok(vec![SocketAddr::from(([10, 0, 0, n], 1883))])
+            })
+        }
+    }
+
+    impl CountingResolver {
+        fn lookups(&self) -> u8 {
+            self.lookups.load(Ordering::SeqCst)
+        }
+    }
+
+    fn cache() -> (Arc<DnsCache>, Arc<CountingResolver>) {
+        let resolver = Arc::new(CountingResolver::default());
+        (Arc::new(DnsCache::with_resolver(TTL, resolver.clone())), resolver)
+    }
+
+    fn address(n: u8) -> Vec<SocketAddr> {
+        vec![SocketAddr::from(([10, 0, 0, n], 1883))]
+    }
+
+    /// 让后台刷新任务运行完
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_lookups_within_the_ttl_hit_the_cache() {
+        let (cache, resolver) = cache();
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(1));
+        tokio::time::advance(TTL / 2).await;
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(1));
+        settle().await;
+        assert_eq!(resolver.lookups(), 1);
+        // 不同名称分别缓存
+        assert_eq!(cache.resolve("other:1883").await.unwrap(), address(2));
+        assert_eq!(resolver.lookups(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_entries_are_resolved_again() {
+        let (cache, resolver) = cache();
+        cache.resolve("broker:1883").await.unwrap();
+        tokio::time::advance(TTL).await;
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(2));
+        assert_eq!(resolver.lookups(), 2);
+    }
+
+    /// 过了 TTL 的 3/4 之后被使用: 先返回缓存的结果, 后台只刷新一次, 刷新后的结果重新计算 TTL
+    #[tokio::test(start_paused = true)]
+    async fn entries_near_expiry_are_refreshed_in_the_background() {
+        let (cache, resolver) = cache();
+        cache.resolve("broker:1883").await.unwrap();
+        tokio::time::advance(TTL * 3 / 4).await;
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(1));
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(1));
+        settle().await;
+        assert_eq!(resolver.lookups(), 2);
+
+        tokio::time::advance(TTL / 2).await;
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(2));
+        settle().await;
+        assert_eq!(resolver.lookups(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_refresh_keeps_the_entry_until_it_expires() {
+        let (cache, resolver) = cache();
+        cache.resolve("broker:1883").await.unwrap();
+        resolver.fail.store(true, Ordering::SeqCst);
+        tokio::time::advance(TTL * 3 / 4).await;
+        assert_eq!(cache.resolve("broker:1883").await.unwrap(), address(1));
+        settle().await;
+        assert_eq!(resolver.lookups(), 2);
+
+        tokio::time::advance(TTL / 4).await;
+        let error = cache.resolve("broker:1883").await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+}
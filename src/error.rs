@@ -0,0 +1,88 @@
+// 适配器连接处理错误
+// 按类别区分, 便于日志采样和指标统计
+
+use std::fmt;
+
+use crate::packet_firewall::PACKET_TYPE_NAMES;
+
+/// 处理单个连接时的错误
+#[derive(Debug)]
+pub enum AdapterError {
+    /// 首个包不是 CONNECT
+    NotConnect { first_byte: u8 },
+    /// 未知的协议名称或级别
+    UnknownProtocol { name: String, level: u8 },
+    /// 识别出的协议族在该监听器上不支持 (如明文端口上的 TLS)
+    UnsupportedProtocol { protocol: &'static str },
+    /// 包格式错误 (长度、字段越界等)
+    MalformedPacket(String),
+    /// 客户端在发送完 CONNECT 之前断开 (扫描器、不稳定的网络)
+    HandshakeAborted(std::io::Error),
+    /// 无法连接后端 broker
+    BackendConnect(std::io::Error),
+    /// 其它网络错误 (对端断开、重置等)
+    Io(std::io::Error),
+}
+
+impl AdapterError {
+    /// 错误类别, 用作日志采样和指标的键
+    pub fn category(&self) -> &'static str {
+        match self {
+            AdapterError::NotConnect { .. } => "not_connect",
+            AdapterError::UnknownProtocol { .. } => "unknown_protocol",
+            AdapterError::UnsupportedProtocol { .. } => "unsupported_protocol",
+            AdapterError::MalformedPacket(_) => "malformed_packet",
+            AdapterError::HandshakeAborted(_) => "handshake_aborted",
+            AdapterError::BackendConnect(_) => "backend_connect",
+            AdapterError::Io(_) => "io",
+        }
+    }
+
+    /// 日志级别: 后端不可用是运维问题, 其余多为客户端问题; 握手中途断开很常见, 只在 debug 级别记录
+    pub fn log_level(&self) -> log::Level {
+        match self {
+            AdapterError::BackendConnect(_) => log::Level::Error,
+            AdapterError::HandshakeAborted(_) => log::Level::Debug,
+            _ => log::Level::Warn,
+        }
+    }
+}
+
+impl fmt::Display for AdapterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterError::NotConnect { first_byte } => {
+                // 按固定头解读首字节, 便于识别把其它包当作首个包发送的客户端 (如未连接就 PUBLISH)
+                write!(
+                    f,
+                    "Expected CONNECT packet, got first byte 0x{:02X} (packet type {})",
+                    first_byte,
+                    PACKET_TYPE_NAMES[(first_byte >> 4) as usize]
+                )
+            }
+            AdapterError::UnknownProtocol { name, level } => {
+                write!(f, "Unknown MQTT protocol: {:?}, level {}", name, level)
+            }
+            AdapterError::UnsupportedProtocol { protocol } => {
+                write!(f, "Unsupported protocol on this listener: {}", protocol)
+            }
+            AdapterError::MalformedPacket(reason) => write!(f, "Malformed packet: {}", reason),
+            AdapterError::HandshakeAborted(e) => write!(f, "Client disconnected before sending a complete CONNECT: {}", e),
+            AdapterError::BackendConnect(e) => write!(f, "Failed to connect to backend broker: {}", e),
+            AdapterError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AdapterError {}
+
+impl From<std::io::Error> for AdapterError {
+    /// 解析函数以 `InvalidData` 报告格式错误, 其余视为网络错误
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::InvalidData {
+            AdapterError::MalformedPacket(e.to_string())
+        } else {
+            AdapterError::Io(e)
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// 适配器事件发布
+// 通过单独的控制连接把适配器主动关闭连接的事件发布到后端主题, 供仪表盘订阅; 控制连接断开后退避重连
+
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::adapter_config::{BackendAuthConfig, EventPublishConfig};
+use crate::codec::{encode_packet, write_binary};
+use crate::events::ConnectionEvent;
+use crate::happy_eyeballs;
+use crate::metrics::metrics;
+use crate::source_bind::SourceBind;
+
+/// 建立控制连接 (TCP 连接 + CONNACK) 的时限
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// 重连间隔的下限和上限, 每次失败翻倍
+pub const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// 事件发布任务
+pub struct EventPublisher {
+    config: EventPublishConfig,
+    backend: String,
+    source: Option<Arc<SourceBind>>,
+    /// 后端要求认证时复用注入给客户端的凭据
+    credentials: Option<BackendAuthConfig>,
+    client_id: String,
+    topic: String,
+}
+
+impl EventPublisher {
+    pub fn new(
+        config: &EventPublishConfig,
+        backend: String,
+        source: Option<Arc<SourceBind>>,
+        credentials: Option<BackendAuthConfig>,
+    ) -> Self {
+        let client_id = match config.client_id.as_str() {
+            "" => format!("adapter-events-{}", std::process::id()),
+            client_id => client_id.to_string(),
+        };
+        EventPublisher {
+            topic: format!("{}/disconnect", config.topic_prefix.trim_end_matches('/')),
+            config: config.clone(),
+            backend,
+            source,
+            credentials,
+            client_id,
+        }
+    }
+
+    /// 发布循环: 保持控制连接并发布 adapter_closed 事件, 不会返回
+    pub async fn run(self, mut events: broadcast::Receiver<ConnectionEvent>) {
+        let mut retry_delay = MIN_RETRY_DELAY;
+        loop {
+            let connect = connect_control(&self.backend, self.source.as_ref(), self.credentials.as_ref(), &self.client_id, self.config.keep_alive_sec);
+            match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+                Ok(Ok(mut stream)) => {
+                    info!("Event publisher connected to {} as {:?}, publishing to {:?}", self.backend, self.client_id, self.topic);
+                    retry_delay = MIN_RETRY_DELAY;
+                    let e = self.serve(&mut stream, &mut events).await;
+                    warn!("Event publisher connection to {} lost: {}", self.backend, e);
+                }
+                Ok(Err(e)) => warn!("Event publisher failed to connect to {}: {}", self.backend, e),
+                Err(_) => warn!("Event publisher timed out connecting to {}", self.backend),
+            }
+
+            // 断开期间的事件无法发布, 计数后丢弃
+            let reconnect_at = Instant::now() + retry_delay;
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(reconnect_at) => break,
+                    event = events.recv() => match event {
+                        Ok(ConnectionEvent::AdapterClosed { .. }) => {
+                            metrics().event_publish_dropped_total.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return,
+                    },
+                }
+            }
+            retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// 在已建立的控制连接上发布事件并维持保活, 返回断开原因
+    async fn serve(&self, stream: &mut TcpStream, events: &mut broadcast::Receiver<ConnectionEvent>) -> std::io::Error {
+        let (mut reader, mut writer) = stream.split();
+        let keep_alive = Duration::from_secs(u64::from(self.config.keep_alive_sec.max(1)));
+        let mut ping = tokio::time::interval_at(Instant::now() + keep_alive / 2, keep_alive / 2);
+        let mut last_received = Instant::now();
+        let mut buf = [0u8; 64];
+
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event @ ConnectionEvent::AdapterClosed { .. }) => event,
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Event publisher fell behind, {} connection events skipped", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => {
+                            return std::io::Error::other("event channel closed");
+                        }
+                    };
+                    let body = serde_json::to_vec(&event).expect("connection events serialize to JSON");
+                    let mut publish = Vec::with_capacity(self.topic.len() + body.len() + 2);
+                    write_binary(self.topic.as_bytes(), &mut publish);
+                    publish.extend_from_slice(&body);
+                    if let Err(e) = writer.write_all(&encode_packet(0x30, &publish)).await {
+                        metrics().event_publish_dropped_total.fetch_add(1, Ordering::Relaxed);
+                        return e;
+                    }
+                    metrics().event_publish_total.fetch_add(1, Ordering::Relaxed);
+                    debug!("Published adapter event to {:?}", self.topic);
+                }
+                _ = ping.tick() => {
+                    // 超过 1.5 倍保活时间没有收到任何数据 (包括 PINGRESP), 认为连接已失效
+                    if last_received.elapsed() > keep_alive * 3 / 2 {
+                        return std::io::Error::new(std::io::ErrorKind::TimedOut, "no PINGRESP from backend");
+                    }
+                    if let Err(e) = writer.write_all(&[0xc0, 0]).await {
+                        return e;
+                    }
+                }
+                // 只会收到 PINGRESP, 内容不需要解析
+                read = reader.read(&mut buf) => match read {
+                    Ok(0) => return std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "backend closed the connection"),
+                    Ok(_) => last_received = Instant::now(),
+                    Err(e) => return e,
+                },
+            }
+        }
+    }
+}
+
+/// 连接后端并完成 3.1.1 CONNECT / CONNACK (clean session, 不保留会话), 事件发布和健康发布的控制连接共用
+pub async fn connect_control(
+    backend: &str,
+    source: Option<&Arc<SourceBind>>,
+    credentials: Option<&BackendAuthConfig>,
+    client_id: &str,
+    keep_alive_sec: u16,
+) -> std::io::Result<TcpStream> {
+    let mut stream = happy_eyeballs::connect(backend, source).await?;
+
+    let mut connect_flags = 0x02;
+    if let Some(credentials) = credentials {
+        connect_flags |= 0x80;
+        if credentials.password.is_some() {
+            connect_flags |= 0x40;
+        }
+    }
+    let mut payload = Vec::new();
+    write_binary(b"MQTT", &mut payload);
+    payload.extend_from_slice(&[4, connect_flags]);
+    payload.extend_from_slice(&keep_alive_sec.to_be_bytes());
+    write_binary(client_id.as_bytes(), &mut payload);
+    if let Some(credentials) = credentials {
+        write_binary(credentials.username.as_bytes(), &mut payload);
+        if let Some(password) = &credentials.password {
+            write_binary(password.as_bytes(), &mut payload);
+        }
+    }
+    stream.write_all(&encode_packet(0x10, &payload)).await?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack).await?;
+    if connack[0] != 0x20 || connack[1] != 2 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "expected CONNACK from backend"));
+    }
+    if connack[3] != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            format!("backend rejected the control connection with return code 0x{:02x}", connack[3]),
+        ));
+    }
+    Ok(stream)
+}
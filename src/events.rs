@@ -0,0 +1,89 @@
+// 连接事件
+// 连接处理过程中发布到广播通道, 供管理接口以 SSE 推送 (GET /events), 最近的事件另外保存在内存中 (GET /recent)
+
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 广播通道容量, 落后超过该数量的订阅者会被断开
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// 保存在内存中的最近事件数, 超出时丢弃最早的
+pub const RECENT_EVENTS_CAPACITY: usize = 100;
+
+/// 连接事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConnectionEvent {
+    /// 已连接后端并开始转发
+    Connected {
+        id: u64,
+        peer: String,
+        client_id: String,
+        version: &'static str,
+        backend: String,
+        timestamp_ms: u64,
+    },
+    /// 后端 CONNACK 拒绝了连接 (仅 `observer_on = "connack"`)
+    ConnectRejected {
+        id: u64,
+        peer: String,
+        client_id: String,
+        backend: String,
+        reason_code: u8,
+        timestamp_ms: u64,
+    },
+    /// 适配器主动关闭了连接, 未转发到后端 (维护模式、拒绝列表、认证、准入等)
+    AdapterClosed {
+        id: u64,
+        peer: String,
+        client_id: String,
+        reason: &'static str,
+        timestamp_ms: u64,
+    },
+    /// 转发结束
+    Disconnected {
+        id: u64,
+        reason: CloseReason,
+        timestamp_ms: u64,
+    },
+    /// 连接处理出错 (协议错误、后端不可用等)
+    Error {
+        id: u64,
+        peer: String,
+        category: &'static str,
+        message: String,
+        timestamp_ms: u64,
+    },
+}
+
+/// 连接结束原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// 客户端关闭连接
+    ClientClosed,
+    /// 后端关闭连接
+    BackendClosed,
+    /// 处理出错 (详见同一连接的 error 事件)
+    Error,
+    /// 客户端发送了第二个 CONNECT, 由适配器断开 (`reject_second_connect`)
+    SecondConnect,
+    /// 两个方向都空闲超过 `idle_timeout_ms`, 由适配器断开
+    Idle,
+    /// 客户端发送了被包类型过滤拒绝的包, 由适配器断开 (`packet_firewall`)
+    PacketDenied,
+    /// 客户端发送了超过 `max_publish_size` 的 PUBLISH, 由适配器断开
+    PublishTooLarge,
+    /// 排空时由适配器关闭 (`qos_drain_grace_ms`)
+    Drained,
+    /// 两个方向合计转发的字节超过 `max_bytes_per_connection`, 由适配器断开
+    QuotaExceeded,
+}
+
+/// 当前 Unix 时间 (毫秒)
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,48 @@
+// 文件描述符上限
+// 启动时按配置提高进程的 RLIMIT_NOFILE 软限制, 避免连接数接近默认上限 (常见为 1024) 时 accept 因 EMFILE 失败
+
+use log::{info, warn};
+
+/// 提高 RLIMIT_NOFILE 软限制: `requested` 为 0 时提高到硬限制, 超过硬限制时警告并使用硬限制
+/// 只提高不降低, 失败时保留原值继续运行
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // rlim_t 在部分平台上不是 u64
+pub fn raise(requested: u64) {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit 只写入传入的结构体
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        warn!("max_open_files: getrlimit failed: {}", std::io::Error::last_os_error());
+        return;
+    }
+    let (soft, hard) = (limit.rlim_cur as u64, limit.rlim_max as u64);
+
+    let target = if requested == 0 {
+        hard
+    } else if requested > hard {
+        warn!("max_open_files {} exceeds the hard limit {}, using the hard limit", requested, hard);
+        hard
+    } else {
+        requested
+    };
+    // macOS 上硬限制可能是 RLIM_INFINITY, 但软限制不能超过 OPEN_MAX
+    #[cfg(target_os = "macos")]
+    let target = target.min(libc::OPEN_MAX as u64);
+
+    if target <= soft {
+        info!("Open file limit: {} (hard limit {}), already at least {}", soft, hard, target);
+        return;
+    }
+    limit.rlim_cur = target as libc::rlim_t;
+    // SAFETY: setrlimit 只读取传入的结构体
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        warn!("max_open_files: raising the open file limit from {} to {} failed: {}", soft, target, std::io::Error::last_os_error());
+        return;
+    }
+    info!("Open file limit raised from {} to {} (hard limit {})", soft, target, hard);
+}
+
+/// 非 Unix 系统没有 RLIMIT_NOFILE, 忽略该设置
+#[cfg(not(unix))]
+pub fn raise(_requested: u64) {
+    warn!("max_open_files is only supported on Unix, ignoring");
+}
@@ -0,0 +1,138 @@
+// 后端连接
+// 后端地址解析出多个 IP 时 (如 Kubernetes headless service) 按 Happy Eyeballs (RFC 8305) 错开并发尝试, 使用最先成功的连接
+
+use log::debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use crate::dns_cache;
+use crate::source_bind::SourceBind;
+
+/// 启动下一个地址之前等待当前尝试的时间 (RFC 8305 建议 250ms)
+const CONNECT_STAGGER: Duration = Duration::from_millis(250);
+
+/// 带超时的 `connect`, `timeout` 为 None 时使用系统的连接超时; 超时返回 `TimedOut`
+pub async fn connect_timeout(addr: &str, source: Option<&Arc<SourceBind>>, timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return connect(addr, source).await;
+    };
+    tokio::time::timeout(timeout, connect(addr, source)).await.unwrap_or_else(|_| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("connecting to {} timed out after {}ms", addr, timeout.as_millis()),
+        ))
+    })
+}
+
+/// 连接后端 (host:port), `source` 不为空时从配置的本地地址/端口范围连接; 名称经过 DNS 缓存解析 (`dns_cache`)
+/// 只有一个地址时等同于 `TcpStream::connect`; 多个地址时按解析顺序每隔 `CONNECT_STAGGER` 启动一个尝试,
+/// 某个尝试失败时立即启动下一个, 第一个成功的连接胜出, 其余尝试随之取消
+pub async fn connect(addr: &str, source: Option<&Arc<SourceBind>>) -> std::io::Result<TcpStream> {
+    let addrs = dns_cache::resolve(addr).await?;
+    if addrs.len() <= 1 {
+        return match (source, addrs.first()) {
+            (Some(source), Some(&target)) => source.connect(target).await,
+            _ => TcpStream::connect(addrs.as_slice()).await,
+        };
+    }
+
+    let mut attempts = JoinSet::new();
+    let mut pending = addrs.iter().copied();
+    let mut last_error = None;
+    loop {
+        if let Some(target) = pending.next() {
+            let source = source.cloned();
+            attempts.spawn(async move {
+                let result = match source {
+                    Some(source) => source.connect(target).await,
+                    None => TcpStream::connect(target).await,
+                };
+                (target, result)
+            });
+        }
+
+        let finished = if pending.len() > 0 {
+            tokio::select! {
+                finished = attempts.join_next() => finished,
+                _ = tokio::time::sleep(CONNECT_STAGGER) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+
+        match finished {
+            Some(Ok((target, Ok(stream)))) => {
+                debug!("Connected to backend {} via {} ({} addresses)", addr, target, addrs.len());
+                return Ok(stream);
+            }
+            Some(Ok((target, Err(e)))) => {
+                debug!("Connecting to backend {} via {} failed: {}", addr, target, e);
+                last_error = Some(e);
+            }
+            Some(Err(e)) => last_error = Some(std::io::Error::other(e)),
+            // 所有尝试都已结束
+            None if pending.len() == 0 => {
+                return Err(last_error.unwrap_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} did not resolve to an address", addr))
+                }));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// 模拟不可路由的地址: 不 accept 且 backlog 已满的监听器, 之后的 SYN 被丢弃, 连接既不成功也不失败
+    fn blackhole() -> (socket2::Socket, Vec<std::net::TcpStream>, String) {
+        let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        socket.bind(&std::net::SocketAddr::from(([127, 0, 0, 1], 0)).into()).unwrap();
+        socket.listen(0).unwrap();
+        let address = socket.local_addr().unwrap().as_socket().unwrap();
+        // 填满 accept 队列
+        let mut filled = Vec::new();
+        for _ in 0..4 {
+            let stream = std::net::TcpStream::connect_timeout(&address, Duration::from_millis(100));
+            match stream {
+                Ok(stream) => filled.push(stream),
+                Err(_) => break,
+            }
+        }
+        (socket, filled, address.to_string())
+    }
+
+    #[tokio::test]
+    async fn unanswered_address_times_out_in_time() {
+        let (_listener, _filled, address) = blackhole();
+        let timeout = Duration::from_millis(300);
+        let started = Instant::now();
+        let error = connect_timeout(&address, None, Some(timeout)).await.unwrap_err();
+        let elapsed = started.elapsed();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut, "{}", error);
+        assert!(error.to_string().contains("timed out after 300ms"), "{}", error);
+        assert!(elapsed >= timeout && elapsed < timeout + Duration::from_millis(500), "took {:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn connects_within_the_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let stream = connect_timeout(&address.to_string(), None, Some(Duration::from_secs(1))).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), address);
+    }
+
+    #[tokio::test]
+    async fn refused_connect_fails_immediately() {
+        let address = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let error = connect_timeout(&address.to_string(), None, Some(Duration::from_secs(5))).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+}
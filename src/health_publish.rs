@@ -0,0 +1,133 @@
+// 适配器健康状态发布
+// 通过单独的控制连接定期把适配器的状态和连接统计以保留消息发布到后端主题, 任何 MQTT 客户端订阅即可监控; 断开后退避重连
+
+use log::{debug, info, warn};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::adapter_config::{BackendAuthConfig, HealthPublishConfig};
+use crate::codec::{encode_packet, write_binary};
+use crate::event_publish::{CONNECT_TIMEOUT, MAX_RETRY_DELAY, MIN_RETRY_DELAY, connect_control};
+use crate::events::now_ms;
+use crate::metrics::metrics;
+use crate::runtime::RuntimeState;
+use crate::source_bind::SourceBind;
+
+/// 健康发布任务
+pub struct HealthPublisher {
+    config: HealthPublishConfig,
+    backend: String,
+    source: Option<Arc<SourceBind>>,
+    /// 后端要求认证时复用注入给客户端的凭据
+    credentials: Option<BackendAuthConfig>,
+    client_id: String,
+    runtime: Arc<RuntimeState>,
+}
+
+impl HealthPublisher {
+    pub fn new(
+        config: &HealthPublishConfig,
+        backend: String,
+        source: Option<Arc<SourceBind>>,
+        credentials: Option<BackendAuthConfig>,
+        runtime: Arc<RuntimeState>,
+    ) -> Self {
+        let client_id = match config.client_id.as_str() {
+            "" => format!("adapter-health-{}", std::process::id()),
+            client_id => client_id.to_string(),
+        };
+        HealthPublisher { config: config.clone(), backend, source, credentials, client_id, runtime }
+    }
+
+    /// 发布循环: 保持控制连接并按 `interval_sec` 发布状态, 不会返回
+    pub async fn run(self) {
+        let mut retry_delay = MIN_RETRY_DELAY;
+        loop {
+            let connect = connect_control(&self.backend, self.source.as_ref(), self.credentials.as_ref(), &self.client_id, self.config.keep_alive_sec);
+            match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+                Ok(Ok(mut stream)) => {
+                    info!("Health publisher connected to {} as {:?}, publishing to {:?}", self.backend, self.client_id, self.config.topic);
+                    retry_delay = MIN_RETRY_DELAY;
+                    let e = self.serve(&mut stream).await;
+                    warn!("Health publisher connection to {} lost: {}", self.backend, e);
+                }
+                Ok(Err(e)) => warn!("Health publisher failed to connect to {}: {}", self.backend, e),
+                Err(_) => warn!("Health publisher timed out connecting to {}", self.backend),
+            }
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// 当前状态: 实例状态、活动连接数和按协议版本的连接数
+    fn snapshot(&self) -> Value {
+        let mut versions = BTreeMap::new();
+        for connection in self.runtime.connections() {
+            *versions.entry(connection.version).or_insert(0u64) += 1;
+        }
+        let status = if self.runtime.draining() {
+            "draining"
+        } else if self.runtime.maintenance() {
+            "maintenance"
+        } else {
+            "ok"
+        };
+        json!({
+            "instance": self.client_id,
+            "status": status,
+            "active_connections": metrics().active_connections.load(Ordering::Relaxed),
+            "open_connections": self.runtime.open_connections(),
+            "versions": versions,
+            "timestamp_ms": now_ms(),
+        })
+    }
+
+    /// 在已建立的控制连接上定期发布保留消息 (连接建立后立即发布一次) 并维持保活, 返回断开原因
+    async fn serve(&self, stream: &mut TcpStream) -> std::io::Error {
+        let (mut reader, mut writer) = stream.split();
+        let keep_alive = Duration::from_secs(u64::from(self.config.keep_alive_sec.max(1)));
+        let mut ping = tokio::time::interval_at(Instant::now() + keep_alive / 2, keep_alive / 2);
+        let mut publish = tokio::time::interval(Duration::from_secs(self.config.interval_sec.max(1)));
+        publish.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_received = Instant::now();
+        let mut buf = [0u8; 64];
+
+        loop {
+            tokio::select! {
+                _ = publish.tick() => {
+                    let body = serde_json::to_vec(&self.snapshot()).expect("health snapshot serializes to JSON");
+                    let mut packet = Vec::with_capacity(self.config.topic.len() + body.len() + 2);
+                    write_binary(self.config.topic.as_bytes(), &mut packet);
+                    packet.extend_from_slice(&body);
+                    // QoS 0, retain
+                    if let Err(e) = writer.write_all(&encode_packet(0x31, &packet)).await {
+                        return e;
+                    }
+                    metrics().health_publish_total.fetch_add(1, Ordering::Relaxed);
+                    debug!("Published adapter health to {:?}", self.config.topic);
+                }
+                _ = ping.tick() => {
+                    // 超过 1.5 倍保活时间没有收到任何数据 (包括 PINGRESP), 认为连接已失效
+                    if last_received.elapsed() > keep_alive * 3 / 2 {
+                        return std::io::Error::new(std::io::ErrorKind::TimedOut, "no PINGRESP from backend");
+                    }
+                    if let Err(e) = writer.write_all(&[0xc0, 0]).await {
+                        return e;
+                    }
+                }
+                // 只会收到 PINGRESP, 内容不需要解析
+                read = reader.read(&mut buf) => match read {
+                    Ok(0) => return std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "backend closed the connection"),
+                    Ok(_) => last_received = Instant::now(),
+                    Err(e) => return e,
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// 空闲连接检测
+// 转发循环每转发一块数据更新连接的最后活动时间, 超过 idle_timeout_ms 没有活动的连接由适配器断开
+// sweep 模式由一个后台任务定期扫描所有连接; timer 模式每个连接一个定时器
+
+use log::debug;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::time::MissedTickBehavior;
+
+use crate::adapter_config::{AdapterConfig, IdleReaperMode};
+use crate::pause::PauseGate;
+
+/// 空闲检测, 所有连接共用
+pub struct IdleReaper {
+    timeout: Duration,
+    mode: IdleReaperMode,
+    interval: Duration,
+    /// 最后活动时间的起点
+    epoch: Instant,
+    /// sweep 模式下登记的连接 (timer 模式不登记)
+    trackers: Mutex<HashMap<u64, Arc<IdleTracker>>>,
+}
+
+/// 单个连接的最后活动时间, 两个转发方向共用
+pub struct IdleTracker {
+    epoch: Instant,
+    /// 最后活动时间 (`epoch` 之后的毫秒数)
+    last_activity_ms: AtomicU64,
+    /// sweep 模式下扫描任务判定空闲时通知
+    reaped: Notify,
+    /// 暂停中的连接不算空闲
+    pause: Option<PauseGate>,
+}
+
+impl IdleTracker {
+    /// 记录一次活动 (转发了数据)
+    pub fn touch(&self) {
+        self.last_activity_ms.store(elapsed_ms(self.epoch), Ordering::Relaxed);
+    }
+
+    /// 距最后一次活动的时间; 暂停中的连接视为刚有活动
+    fn idle_ms(&self) -> u64 {
+        if self.pause.as_ref().is_some_and(PauseGate::is_paused) {
+            self.touch();
+            return 0;
+        }
+        elapsed_ms(self.epoch).saturating_sub(self.last_activity_ms.load(Ordering::Relaxed))
+    }
+}
+
+/// 连接的空闲检测登记, 离开作用域时注销
+pub struct IdleHandle {
+    reaper: Arc<IdleReaper>,
+    id: u64,
+    tracker: Arc<IdleTracker>,
+}
+
+impl IdleHandle {
+    /// 供转发循环更新活动时间
+    pub fn tracker(&self) -> Arc<IdleTracker> {
+        self.tracker.clone()
+    }
+
+    /// 连接空闲超过 `idle_timeout_ms` 时返回
+    pub async fn expired(&self) {
+        match self.reaper.mode {
+            IdleReaperMode::Sweep => self.tracker.reaped.notified().await,
+            IdleReaperMode::Timer => {
+                let timeout_ms = self.reaper.timeout.as_millis() as u64;
+                loop {
+                    // 定时器期间有过活动时按最后活动时间重新计时
+                    let idle_ms = self.tracker.idle_ms();
+                    if idle_ms >= timeout_ms {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(timeout_ms - idle_ms)).await;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for IdleHandle {
+    fn drop(&mut self) {
+        if self.reaper.mode == IdleReaperMode::Sweep {
+            self.reaper.trackers.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+impl IdleReaper {
+    /// `idle_timeout_ms` 为 0 时返回 None (不检测)
+    pub fn from_config(config: &AdapterConfig) -> std::io::Result<Option<Arc<Self>>> {
+        if config.idle_timeout_ms == 0 {
+            return Ok(None);
+        }
+        if config.idle_reaper == IdleReaperMode::Sweep && config.reaper_interval_ms == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "reaper_interval_ms must be greater than 0 with idle_reaper = \"sweep\"",
+            ));
+        }
+        Ok(Some(Arc::new(IdleReaper {
+            timeout: Duration::from_millis(config.idle_timeout_ms),
+            mode: config.idle_reaper,
+            interval: Duration::from_millis(config.reaper_interval_ms),
+            epoch: Instant::now(),
+            trackers: Mutex::new(HashMap::new()),
+        })))
+    }
+
+    /// 登记连接, 从现在开始计时
+    pub fn register(self: &Arc<Self>, id: u64, pause: Option<PauseGate>) -> IdleHandle {
+        let tracker = Arc::new(IdleTracker {
+            epoch: self.epoch,
+            last_activity_ms: AtomicU64::new(elapsed_ms(self.epoch)),
+            reaped: Notify::new(),
+            pause,
+        });
+        if self.mode == IdleReaperMode::Sweep {
+            self.trackers.lock().unwrap().insert(id, tracker.clone());
+        }
+        IdleHandle { reaper: self.clone(), id, tracker }
+    }
+
+    /// sweep 模式的扫描任务: 每 `reaper_interval_ms` 通知所有空闲超时的连接; timer 模式直接返回
+    pub async fn run_sweeper(self: Arc<Self>) {
+        if self.mode != IdleReaperMode::Sweep {
+            return;
+        }
+        let timeout_ms = self.timeout.as_millis() as u64;
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let trackers = self.trackers.lock().unwrap();
+            let mut reaped = 0;
+            for tracker in trackers.values() {
+                if tracker.idle_ms() >= timeout_ms {
+                    tracker.reaped.notify_one();
+                    reaped += 1;
+                }
+            }
+            if reaped > 0 {
+                debug!("Idle reaper: {} of {} connections idle for more than {:?}", reaped, trackers.len(), self.timeout);
+            }
+        }
+    }
+}
+
+fn elapsed_ms(epoch: Instant) -> u64 {
+    epoch.elapsed().as_millis() as u64
+}
@@ -0,0 +1,44 @@
+// 监听器创建
+// 启用 reuse_port 时 (仅 Linux) 每个 CPU 核心绑定一个 SO_REUSEPORT 监听器, 由内核在各 accept 循环之间分配连接
+
+#[cfg(not(target_os = "linux"))]
+use log::warn;
+#[cfg(target_os = "linux")]
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// 绑定监听地址
+/// `reuse_port` 为 false 或不在 Linux 上时只返回一个监听器
+pub async fn bind(addr: &str, reuse_port: bool) -> std::io::Result<Vec<TcpListener>> {
+    if !reuse_port {
+        return Ok(vec![TcpListener::bind(addr).await?]);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let addr = tokio::net::lookup_host(addr).await?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} did not resolve to an address", addr))
+        })?;
+        (0..num_cpus::get().max(1)).map(|_| bind_reuse_port(addr)).collect()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        warn!("reuse_port is only supported on Linux, using a single listener on {}", addr);
+        Ok(vec![TcpListener::bind(addr).await?])
+    }
+}
+
+/// 创建一个设置了 SO_REUSEPORT 的监听器 (其余选项与 `TcpListener::bind` 相同)
+#[cfg(target_os = "linux")]
+fn bind_reuse_port(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
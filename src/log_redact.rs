@@ -0,0 +1,162 @@
+// 日志脱敏
+// 记录 CONNECT 字段 (客户端 ID、用户名) 前按配置哈希或截断, 密码从不记录
+
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use crate::adapter_config::{LoggingConfig, RedactMode};
+use crate::connect_packet::ConnectPacket;
+
+/// 截断方式保留的字符数
+const TRUNCATE_KEEP: usize = 3;
+
+/// CONNECT 字段脱敏器
+#[derive(Debug, Clone, Default)]
+pub struct LogRedactor {
+    client_id: bool,
+    username: bool,
+    mode: RedactMode,
+}
+
+impl LogRedactor {
+    /// 从配置创建, 未知字段名视为配置错误
+    pub fn from_config(config: &LoggingConfig) -> std::io::Result<Self> {
+        let mut redactor = LogRedactor { mode: config.redact_mode, ..Default::default() };
+        for field in &config.redact {
+            match field.as_str() {
+                "client_id" => redactor.client_id = true,
+                "username" => redactor.username = true,
+                // 密码总是不记录
+                "password" => {}
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Unknown redact field {:?} (expected client_id, username or password)", field),
+                    ));
+                }
+            }
+        }
+        Ok(redactor)
+    }
+
+    /// 用于日志的客户端 ID
+    pub fn client_id<'a>(&self, client_id: &'a str) -> Cow<'a, str> {
+        self.apply(self.client_id, client_id)
+    }
+
+    /// 用于日志的用户名
+    pub fn username<'a>(&self, username: &'a str) -> Cow<'a, str> {
+        self.apply(self.username, username)
+    }
+
+    /// debug 日志中的 CONNECT 字段摘要: 客户端 ID 和用户名经过脱敏, 只记录是否带有密码
+    pub fn connect_summary(&self, connect: &ConnectPacket) -> String {
+        format!(
+            "client_id={:?} username={:?} password={} keep_alive={}s clean_session={}",
+            self.client_id(&connect.client_id),
+            connect.username.as_deref().map(|username| self.username(username)),
+            if connect.password.is_some() { "<set>" } else { "<none>" },
+            connect.keep_alive,
+            connect.clean_session,
+        )
+    }
+
+    fn apply<'a>(&self, enabled: bool, value: &'a str) -> Cow<'a, str> {
+        if !enabled {
+            return Cow::Borrowed(value);
+        }
+
+        match self.mode {
+            RedactMode::Hash => {
+                let digest = Sha256::digest(value.as_bytes());
+                let mut out = String::from("sha256:");
+                for byte in &digest[..4] {
+                    let _ = write!(out, "{:02x}", byte);
+                }
+                Cow::Owned(out)
+            }
+            RedactMode::Truncate => {
+                let kept: String = value.chars().take(TRUNCATE_KEEP).collect();
+                // 过短的值整体隐藏, 避免截断后仍是原值
+                if kept.len() == value.len() {
+                    Cow::Borrowed("***")
+                } else {
+                    Cow::Owned(format!("{}***", kept))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connect_packet::{connect_payload, parse_connect};
+
+    const PASSWORD: &str = "s3cret-Pa55";
+
+    fn redactor(redact: &[&str], mode: RedactMode) -> LogRedactor {
+        let config = LoggingConfig {
+            redact: redact.iter().map(|field| field.to_string()).collect(),
+            redact_mode: mode,
+            ..LoggingConfig::default()
+        };
+        LogRedactor::from_config(&config).unwrap()
+    }
+
+    fn connect(protocol_level: u8) -> ConnectPacket {
+        let payload = connect_payload(protocol_level, 60, "device-42", Some("alice"), Some(PASSWORD.as_bytes()));
+        parse_connect(&payload).unwrap()
+    }
+
+    #[test]
+    fn password_never_appears_in_connect_summary() {
+        let configs: [&[&str]; 4] = [&[], &["password"], &["username"], &["client_id", "username", "password"]];
+        for redact in configs {
+            for mode in [RedactMode::Hash, RedactMode::Truncate] {
+                for protocol_level in [4, 5] {
+                    let line = redactor(redact, mode).connect_summary(&connect(protocol_level));
+                    assert!(!line.contains(PASSWORD), "password leaked with {:?} / {:?}: {}", redact, mode, line);
+                    assert!(line.contains("password=<set>"), "{}", line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn password_never_appears_in_debug_output() {
+        let output = format!("{:?}", connect(5));
+        assert!(!output.contains(PASSWORD), "{}", output);
+        assert!(output.contains("<redacted>"), "{}", output);
+    }
+
+    #[test]
+    fn summary_redacts_configured_fields() {
+        let line = redactor(&["username"], RedactMode::Truncate).connect_summary(&connect(4));
+        assert!(line.contains("client_id=\"device-42\""), "{}", line);
+        assert!(line.contains("username=Some(\"ali***\")"), "{}", line);
+        assert!(!line.contains("alice"), "{}", line);
+
+        let line = redactor(&["client_id", "username"], RedactMode::Hash).connect_summary(&connect(5));
+        assert!(!line.contains("device-42") && !line.contains("alice"), "{}", line);
+        assert_eq!(line.matches("sha256:").count(), 2, "{}", line);
+    }
+
+    #[test]
+    fn hash_is_stable_and_short_values_are_hidden() {
+        let hashed = redactor(&["client_id"], RedactMode::Hash);
+        assert_eq!(hashed.client_id("device-42"), hashed.client_id("device-42"));
+        assert_ne!(hashed.client_id("device-42"), hashed.client_id("device-43"));
+
+        let truncated = redactor(&["client_id"], RedactMode::Truncate);
+        assert_eq!(truncated.client_id("abc"), "***");
+        assert_eq!(truncated.client_id("abcd"), "abc***");
+    }
+
+    #[test]
+    fn unknown_field_is_rejected() {
+        let config = LoggingConfig { redact: vec!["email".to_string()], ..LoggingConfig::default() };
+        assert!(LogRedactor::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+// 日志采样
+// 同一类别的错误在每个窗口内只输出第一条, 其余合并为周期性的汇总日志; 成功的连接按序号每 N 个记录一个
+
+use log::{Level, log};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 按类别采样的错误日志
+pub struct LogSampler {
+    /// 日志 target, 使用调用方模块路径, 保持 RUST_LOG 过滤规则不变
+    target: &'static str,
+    window: Duration,
+    categories: Mutex<HashMap<&'static str, CategoryWindow>>,
+}
+
+struct CategoryWindow {
+    started: Instant,
+    level: Level,
+    /// 窗口内被合并 (未输出) 的次数
+    suppressed: u64,
+}
+
+impl LogSampler {
+    /// `window` 为零时不采样, 每条都输出
+    pub fn new(target: &'static str, window: Duration) -> Self {
+        LogSampler {
+            target,
+            window,
+            categories: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 采样窗口
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// 记录一条错误; 窗口内首次出现时立即输出
+    pub fn log(&self, category: &'static str, level: Level, message: &str) {
+        if self.window.is_zero() {
+            log!(target: self.target, level, "{}", message);
+            return;
+        }
+
+        let now = Instant::now();
+        let mut categories = self.categories.lock().unwrap();
+        match categories.get_mut(category) {
+            Some(entry) if now.duration_since(entry.started) < self.window => {
+                entry.suppressed += 1;
+            }
+            entry => {
+                if let Some(entry) = entry {
+                    self.report(category, entry);
+                }
+                categories.insert(category, CategoryWindow { started: now, level, suppressed: 0 });
+                log!(target: self.target, level, "{}", message);
+            }
+        }
+    }
+
+    /// 输出已结束窗口的汇总, 由后台任务周期调用
+    pub fn flush(&self) {
+        let now = Instant::now();
+        let mut categories = self.categories.lock().unwrap();
+        categories.retain(|category, entry| {
+            if now.duration_since(entry.started) < self.window {
+                return true;
+            }
+            self.report(category, entry);
+            false
+        });
+    }
+
+    fn report(&self, category: &str, entry: &CategoryWindow) {
+        if entry.suppressed > 0 {
+            log!(
+                target: self.target,
+                entry.level,
+                "{} more occurrences of {} errors in the last {}s",
+                entry.suppressed, category, self.window.as_secs()
+            );
+        }
+    }
+}
+
+/// 按比例采样成功的连接, 以 accept 循环分配的连接 ID 计数, 每 `every` 个连接采样一个
+#[derive(Debug, Clone, Copy)]
+pub struct AcceptSampler {
+    /// 0 = 不采样
+    every: u64,
+}
+
+impl AcceptSampler {
+    /// `rate` 取 0 到 1, 换算为取整的采样间隔
+    pub fn new(rate: f64) -> std::io::Result<Self> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("accept_log_sample_rate must be between 0 and 1, got {}", rate),
+            ));
+        }
+        let every = if rate == 0.0 { 0 } else { (1.0 / rate).round() as u64 };
+        Ok(AcceptSampler { every })
+    }
+
+    /// 该连接是否被采样
+    pub fn sampled(&self, connection_id: u64) -> bool {
+        self.every > 0 && connection_id.is_multiple_of(self.every)
+    }
+}
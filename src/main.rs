@@ -1,9 +1,91 @@
-use rumqttd::{Broker, Config};
-use log::{info, error};
+use rumqttd::{Broker, Config, ServerSettings};
+use log::{info, warn, error};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::fs;
-use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
 
+mod accept_backoff;
+mod adapter_config;
+mod access;
+mod admin;
+mod admission;
+mod auth;
+mod backend_close;
+mod byte_quota;
+mod cert_routing;
+mod classify;
+mod client_registry;
+mod codec;
+mod config_reload;
+mod config_source;
+mod connack;
+mod connect_flood;
+mod connect_packet;
+mod connect_transform;
+mod control_push;
+mod denylist;
+mod deprecation;
+mod detection;
+mod dns_cache;
+mod error;
+mod event_publish;
+mod events;
+mod fd_limit;
+mod happy_eyeballs;
+mod health_publish;
+mod idle;
+mod listener;
+mod log_redact;
+mod log_sampler;
+mod metrics;
+mod migration;
+mod mqtt_adapter;
+mod packet_firewall;
+mod packet_trace;
+mod protocol_close;
+mod pause;
+mod prefixed_stream;
+mod properties;
+mod qos_drain;
+mod rate_limit;
+mod request_response;
+mod response_rewriter;
+mod runtime;
+mod second_connect;
+mod self_test;
+mod shadow;
 mod smart_adapter;
+mod snapshot;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+mod splice;
+mod socket_buffers;
+mod source_bind;
+mod source_ip_routing;
+mod statsd;
+mod tap;
+mod tarpit;
+mod telemetry;
+mod tls;
+mod tls_guard;
+mod topic_routing;
+mod warm_pool;
+mod websocket;
+mod weighted_routing;
+mod worker_pool;
+
+use adapter_config::{AdapterConfig, AdapterInstance, AdapterKind, AdminEndpoint, AppConfig, MetricsBackend};
+use config_reload::Reloader;
+use config_source::ConfigSource;
+use connect_transform::ConnectPipeline;
+use runtime::RuntimeState;
+use snapshot::RuntimeSnapshot;
+
+/// 适配器转发的本地 broker 端口
+const BACKEND_PORT: u16 = 1883;
 
 #[tokio::main]
 async fn main() {
@@ -15,62 +97,350 @@ async fn main() {
         env_logger::Env::default().default_filter_or("info,rumqttd::router::routing=off,rumqttd::server::broker=info")
     ).init();
     
-    // 从配置文件加载配置
-    let config = load_config("config.toml");
+    // 加载配置: 第一个参数为配置来源 (文件路径、`-` 表示标准输入或 http(s) URL), 默认 config.toml
+    let config_source = ConfigSource::parse(&std::env::args().nth(1).unwrap_or_else(|| "config.toml".to_string()));
+    let (config, adapter_config, instances, restored) = load_config(&config_source).await;
+    if let Err(errors) = validate_config(&config, &adapter_config, &instances) {
+        for e in errors {
+            error!("{}", e);
+        }
+        std::process::exit(1);
+    }
+    let runtime = Arc::new(RuntimeState::new(&adapter_config).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    }));
+    if let Some(restored) = &restored
+        && let Err(e) = restored.restore(&runtime)
+    {
+        error!("Failed to restore runtime state from snapshot: {}", e);
+        std::process::exit(1);
+    }
+    
+    // 分布式追踪 (需要 otel feature)
+    telemetry::init(&adapter_config.otel);
+    dns_cache::init(&adapter_config);
     
     info!("Starting MQTT Broker...");
-    info!("Configuration loaded from: config.toml");
-    info!("Listening on:");
-    info!("  - TCP: 0.0.0.0:1882 (MQTT 3.1.0 - auto-upgraded to 3.1.1)");
-    info!("  - TCP: 0.0.0.0:1883 (MQTT 3.1.1 / 5.0 auto-detected)");
-    info!("  - WebSocket: 0.0.0.0:8080 (MQTT 3.1.1)");
-    info!("  - Console: 0.0.0.0:3030 (Management)");
-    info!("");
-    info!("MQTT 3.1.0 Adapter:");
-    info!("  - Port 1882 accepts MQTT 3.1.0 clients");
-    info!("  - Automatically upgrades to 3.1.1 and forwards to port 1883");
-    
-    // 启动 MQTT 3.1.0 适配器 (异步)
-    // 监听 1882 端口,专门处理 MQTT 3.1.0 客户端
-    // 自动转换为 3.1.1 并转发到 1883
-    tokio::spawn(async {
-        if let Err(e) = smart_adapter::start_smart_mqtt_adapter(1882, 1883).await {
-            error!("MQTT 3.1.0 adapter failed: {}", e);
+    info!("Configuration loaded from: {}", config_source.describe());
+    log_startup_banner(&config, &adapter_config, &instances);
+    if let Some(max_open_files) = adapter_config.max_open_files {
+        fd_limit::raise(max_open_files);
+    }
+    
+    // 启动 Broker (独立线程), 监听器就绪后通知适配器
+    let broker_config = Arc::new(config.clone());
+    let reloader = Arc::new(Reloader::new(config_source.clone(), broker_config.clone(), instances.clone()));
+    let (broker_thread, backend_ready) = run_broker(config, format!("127.0.0.1:{}", BACKEND_PORT));
+    
+    // 启动智能适配器 (异步)
+    // 监听 [adapter] listen, 自动识别 MQTT 3.1.0/3.1.1/5.0, 3.1.0 升级为 3.1.1 后转发到 BACKEND_PORT
+    if adapter_config.enabled {
+        let smart_runtime = runtime.clone();
+        tokio::spawn(async move {
+            if let Err(e) = smart_adapter::start_smart_mqtt_adapter(BACKEND_PORT, smart_runtime, backend_ready).await {
+                error!("Smart adapter failed: {}", e);
+            }
+        });
+    }
+    
+    // 旧的 3.1.0 适配器 ([adapters] 中 type = "legacy" 的实例), 同样转发到 BACKEND_PORT
+    for instance in instances.iter().filter(|instance| instance.kind == AdapterKind::Legacy) {
+        let listen = instance.listen.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mqtt_adapter::start_mqtt31_adapter(&listen, BACKEND_PORT).await {
+                error!("Legacy 3.1.0 adapter on {} failed: {}", listen, e);
+            }
+        });
+    }
+    
+    // 启动管理接口 (指标、健康检查、维护模式): admin_listen 开放全部接口且不认证, admin_listeners 按各自的配置
+    let admin_listeners = adapter_config.admin_listen.iter()
+        .map(|listen| (listen.clone(), Vec::new(), None))
+        .chain(adapter_config.admin_listeners.iter()
+            .map(|listener| (listener.listen.clone(), listener.endpoints.clone(), listener.token.clone())));
+    for (admin_listen, endpoints, token) in admin_listeners {
+        match admin_listen.parse() {
+            Ok(addr) => {
+                let admin_state = admin::AdminState {
+                    runtime: runtime.clone(),
+                    broker_config: broker_config.clone(),
+                    reloader: reloader.clone(),
+                };
+                tokio::spawn(async move {
+                    if let Err(e) = admin::start_admin_server(addr, admin_state, &endpoints, token).await {
+                        error!("Admin API on {} failed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => error!("Invalid admin listen address {:?}: {}", admin_listen, e),
         }
-    });
+    }
+    
+    // StatsD 推送 (Prometheus 后端只通过管理接口提供)
+    if adapter_config.metrics.backend == MetricsBackend::Statsd {
+        tokio::spawn(statsd::run_exporter(adapter_config.metrics.clone()));
+    }
+    
+    // 等待 broker 线程结束
+    let _ = tokio::task::spawn_blocking(move || broker_thread.join()).await;
+}
+
+/// 按实际配置输出监听器和转发目标, 未启用的组件显示为 none
+fn log_startup_banner(config: &Config, adapter: &AdapterConfig, instances: &[AdapterInstance]) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
     
-    // 启动 Broker (这是一个阻塞调用)
-    let mut broker = Broker::new(config);
+    info!("Broker listeners:");
+    let listeners = [
+        ("MQTT 3.1.1", &config.v4),
+        ("MQTT 5.0", &config.v5),
+        ("WebSocket MQTT 3.1.1", &config.ws),
+    ];
+    let mut any_listener = false;
+    for (protocol, servers) in listeners {
+        for settings in sorted_servers(servers) {
+            info!("  - {}: {} ({}, TLS {})", settings.name, settings.listen, protocol, on_off(settings.tls.is_some()));
+            any_listener = true;
+        }
+    }
+    if !any_listener {
+        info!("  - none");
+    }
+    info!("Console: {}", config.console.as_ref().map_or("none", |console| console.listen.as_str()));
+    
+    let legacy: Vec<&str> = instances.iter()
+        .filter(|instance| instance.kind == AdapterKind::Legacy)
+        .map(|instance| instance.listen.as_str())
+        .collect();
+    if !legacy.is_empty() {
+        info!("Legacy 3.1.0 adapter: {} (forward: 127.0.0.1:{})", legacy.join(", "), BACKEND_PORT);
+    }
+    if !adapter.enabled {
+        info!("Smart adapter: disabled");
+        info!("Admin API: {}", admin_addresses(adapter));
+        return;
+    }
+    info!("Smart adapter:");
+    info!("  - listen: {} (MQTT {} auto-detected, TLS off)", adapter.listen, adapter.allowed_versions.join(" / "));
+    match &adapter.tls {
+        Some(tls) => {
+            let versions = tls.detection.as_ref().and_then(|detection| detection.allowed_versions.as_ref()).unwrap_or(&adapter.allowed_versions);
+            info!("  - listen: {} (MQTT {} auto-detected, TLS on)", tls.listen, versions.join(" / "));
+        }
+        None => info!("  - TLS listen: none"),
+    }
+    info!("  - forward: 127.0.0.1:{}", BACKEND_PORT);
+    match &adapter.topic_routing {
+        Some(routing) if !routing.backends.is_empty() => {
+            for (prefix, backend) in &routing.backends {
+                info!("  - topic route: {:?} -> {}", prefix, backend);
+            }
+        }
+        _ => info!("  - topic routes: none"),
+    }
+    if let Some(routing) = &adapter.source_ip_routing
+        && !routing.backends.is_empty()
+    {
+        info!("  - source IP routing: {}", routing.backends.join(", "));
+    }
+    if let Some(routing) = &adapter.weighted_routing
+        && !routing.backends.is_empty()
+    {
+        let backends: Vec<String> = routing.backends.iter()
+            .map(|backend| format!("{} (weight {})", backend.address, backend.weight))
+            .collect();
+        info!("  - weighted routing{}: {}", if routing.sticky { " (sticky)" } else { "" }, backends.join(", "));
+    }
+    info!("  - shadow: {}", adapter.shadow.as_ref().map_or("none", |shadow| shadow.backend.as_str()));
+    // 模板错误在适配器启动时报告
+    let transforms = ConnectPipeline::from_config(adapter).map(|pipeline| pipeline.names()).unwrap_or_default();
+    info!("  - CONNECT transforms: {}", if transforms.is_empty() { "none".to_string() } else { transforms.join(" -> ") });
+    let prefixes = [adapter.client_id_prefix.as_deref(), adapter.tls.as_ref().and_then(|tls| tls.client_id_prefix.as_deref())];
+    for prefix in prefixes.into_iter().flatten().filter(|prefix| prefix.contains(['/', '+', '#', '$'])) {
+        warn!("client_id_prefix {:?} contains '/', '+', '#' or '$'; the built-in broker rejects such client IDs", prefix);
+    }
+    info!("Admin API: {}", admin_addresses(adapter));
     
-    match broker.start() {
-        Ok(_) => info!("Broker stopped gracefully"),
-        Err(e) => error!("Broker error: {}", e),
+    // 适配器总是转发到 BACKEND_PORT, 没有 TCP 监听器在该端口上时所有连接都会失败
+    let backend_bound = [&config.v4, &config.v5].into_iter()
+        .flat_map(sorted_servers)
+        .any(|settings| settings.listen.port() == BACKEND_PORT && settings.tls.is_none());
+    if !backend_bound {
+        warn!("No plain TCP broker listener on port {}; the adapter will fail to forward connections", BACKEND_PORT);
     }
 }
 
-/// 从文件加载配置
-fn load_config(config_path: &str) -> Config {
-    let path = Path::new(config_path);
+/// 检查监听地址冲突: broker 监听器、控制台、各适配器实例和管理接口的任意两个监听同一端口 (且地址重叠) 时返回错误
+/// 无法解析的地址跳过, 由绑定时报错
+fn check_listen_conflicts(config: &Config, adapter: &AdapterConfig, instances: &[AdapterInstance]) -> Result<(), String> {
+    let mut listeners: Vec<(String, SocketAddr)> = [&config.v4, &config.v5, &config.ws].into_iter()
+        .flat_map(sorted_servers)
+        .map(|settings| (format!("broker listener {}", settings.name), settings.listen))
+        .collect();
+    let mut named = vec![("console".to_string(), config.console.as_ref().map(|console| console.listen.as_str()))];
+    for instance in instances {
+        named.push((format!("{} adapter listen", instance.kind.as_str()), Some(instance.listen.as_str())));
+        if instance.kind == AdapterKind::Smart {
+            named.push(("smart adapter TLS listen".to_string(), adapter.tls.as_ref().map(|tls| tls.listen.as_str())));
+        }
+    }
+    named.push(("admin_listen".to_string(), adapter.admin_listen.as_deref()));
+    for (i, listener) in adapter.admin_listeners.iter().enumerate() {
+        named.push((format!("admin_listeners[{}]", i), Some(listener.listen.as_str())));
+    }
+    for (name, listen) in named {
+        if let Some(addr) = listen.and_then(|listen| listen.parse().ok()) {
+            listeners.push((name, addr));
+        }
+    }
     
-    if !path.exists() {
-        error!("Configuration file not found: {}", config_path);
-        error!("Creating default configuration file...");
-        create_default_config(config_path);
-        info!("Default configuration created. Please edit {} and restart.", config_path);
-        std::process::exit(1);
+    for (i, (name, addr)) in listeners.iter().enumerate() {
+        for (other_name, other_addr) in &listeners[..i] {
+            let overlaps = addr.ip() == other_addr.ip() || addr.ip().is_unspecified() || other_addr.ip().is_unspecified();
+            if addr.port() == other_addr.port() && overlaps {
+                return Err(format!("Port conflict: {} ({}) and {} ({}) listen on the same port", other_name, other_addr, name, addr));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 检查管理接口的暴露: 开放 control 接口组且不要求令牌的监听器 (包括 admin_listen) 只能绑定回环地址
+/// 无法解析的地址跳过, 由启动管理接口时报错
+fn check_admin_exposure(adapter: &AdapterConfig) -> Result<(), String> {
+    let legacy = adapter.admin_listen.as_deref()
+        .map(|listen| ("admin_listen".to_string(), listen, true));
+    let listeners = adapter.admin_listeners.iter().enumerate().map(|(i, listener)| {
+        let control = listener.endpoints.is_empty() || listener.endpoints.contains(&AdminEndpoint::Control);
+        (format!("admin_listeners[{}]", i), listener.listen.as_str(), control && listener.token.is_none())
+    });
+    for (name, listen, unauthenticated_control) in legacy.into_iter().chain(listeners) {
+        let Ok(addr) = listen.parse::<SocketAddr>() else { continue };
+        if unauthenticated_control && !addr.ip().is_loopback() {
+            return Err(format!(
+                "{} ({}) serves the control endpoints without a token on a non-loopback address; \
+                 bind it to 127.0.0.1 or use [[adapter.admin_listeners]] with a token or without \"control\"",
+                name, addr
+            ));
+        }
     }
+    Ok(())
+}
+
+/// 管理接口的全部监听地址 (admin_listen 和 admin_listeners), 启动日志使用
+fn admin_addresses(adapter: &AdapterConfig) -> String {
+    let addresses: Vec<&str> = adapter.admin_listen.as_deref().into_iter()
+        .chain(adapter.admin_listeners.iter().map(|listener| listener.listen.as_str()))
+        .collect();
+    if addresses.is_empty() { "none".to_string() } else { addresses.join(", ") }
+}
+
+/// 按监听器名称排序 (配置中是 HashMap, 顺序不固定)
+fn sorted_servers(servers: &Option<HashMap<String, ServerSettings>>) -> Vec<&ServerSettings> {
+    let mut servers: Vec<_> = servers.iter().flat_map(|servers| servers.values()).collect();
+    servers.sort_by(|a, b| a.name.cmp(&b.name));
+    servers
+}
+
+/// 在独立线程中启动 broker (broker.start() 是阻塞调用)
+/// 返回 broker 线程句柄和后端就绪信号。rumqttd 不提供监听器就绪回调,
+/// 因此通过连接 `probe_addr` 探测监听器是否已经绑定
+fn run_broker(config: Config, probe_addr: String) -> (std::thread::JoinHandle<()>, watch::Receiver<bool>) {
+    let (ready_tx, ready_rx) = watch::channel(false);
     
-    let config_content = fs::read_to_string(path)
-        .unwrap_or_else(|e| {
-            error!("Failed to read configuration file: {}", e);
-            std::process::exit(1);
-        });
+    let broker_thread = std::thread::spawn(move || {
+        let mut broker = Broker::new(config);
+        
+        match broker.start() {
+            Ok(_) => info!("Broker stopped gracefully"),
+            Err(e) => error!("Broker error: {}", e),
+        }
+    });
     
-    toml::from_str(&config_content)
-        .unwrap_or_else(|e| {
-            error!("Failed to parse configuration file: {}", e);
+    tokio::spawn(async move {
+        let started_at = Instant::now();
+        loop {
+            if TcpStream::connect(&probe_addr).await.is_ok() {
+                info!("Backend broker listening on {} (after {:?})", probe_addr, started_at.elapsed());
+                let _ = ready_tx.send(true);
+                break;
+            }
+            // 所有接收方都已退出, 不再需要探测
+            if ready_tx.is_closed() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    
+    (broker_thread, ready_rx)
+}
+
+/// 解析后的配置: broker 配置、[adapter] 段的适配器配置、适配器实例和快照文件中的运行时状态
+type ParsedConfig = (Config, AdapterConfig, Vec<AdapterInstance>, Option<RuntimeSnapshot>);
+
+/// 从配置来源加载配置, 读取或解析失败时退出进程
+async fn load_config(source: &ConfigSource) -> ParsedConfig {
+    let config_content = match source.read().await {
+        Ok(content) => content,
+        Err(e) => {
+            if let ConfigSource::File(config_path) = source
+                && e.kind() == std::io::ErrorKind::NotFound
+            {
+                error!("Configuration file not found: {}", config_path);
+                error!("Creating default configuration file...");
+                create_default_config(config_path);
+                info!("Default configuration created. Please edit {} and restart.", config_path);
+                std::process::exit(1);
+            }
+            error!("Failed to read configuration from {}: {}", source.describe(), e);
             std::process::exit(1);
-        })
+        }
+    };
+    
+    let parsed = parse_config(&config_content).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    snapshot::warn_redacted(&config_content);
+    parsed
+}
+
+/// 解析配置文本 (启动和 POST /reload 共用)
+/// 返回的 [adapter] enabled / listen 已按 [adapters] 中的智能适配器实例设置
+fn parse_config(content: &str) -> Result<ParsedConfig, String> {
+    let config = toml::from_str(content)
+        .map_err(|e| format!("Failed to parse configuration file: {}", e))?;
+    let app_config: AppConfig = toml::from_str(content)
+        .map_err(|e| format!("Failed to parse [adapter] configuration: {}", e))?;
+    let instances = app_config.adapter_instances()?;
+    let mut adapter = app_config.adapter;
+    match instances.iter().find(|instance| instance.kind == AdapterKind::Smart) {
+        Some(smart) => {
+            adapter.enabled = true;
+            adapter.listen = smart.listen.clone();
+        }
+        None => adapter.enabled = false,
+    }
+    Ok((config, adapter, instances, app_config.runtime))
+}
+
+/// 检查配置 (启动和 POST /reload 共用): 监听地址冲突、管理接口暴露和智能适配器的各项设置
+/// 返回发现的全部错误, 由调用方决定退出进程还是拒绝新配置
+fn validate_config(config: &Config, adapter: &AdapterConfig, instances: &[AdapterInstance]) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    if let Err(e) = check_listen_conflicts(config, adapter, instances) {
+        errors.push(e);
+    }
+    if let Err(e) = check_admin_exposure(adapter) {
+        errors.push(e);
+    }
+    if adapter.enabled
+        && let Err(e) = smart_adapter::check_config(adapter)
+    {
+        errors.push(format!("Invalid [adapter] configuration: {}", e));
+    }
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
 }
 
 /// 创建默认配置文件
@@ -115,6 +485,367 @@ throttle_delay_ms = 0
 # 控制台配置 (用于监控和管理)
 [console]
 listen = "0.0.0.0:3030"
+
+# 运行的适配器实例, 不配置时按 [adapter] enabled / listen 运行一个智能适配器
+# type = "smart": 智能适配器, 使用 [adapter] 的全部设置 (listen 代替 [adapter] listen), 最多一个
+# type = "legacy": 旧的 3.1.0 适配器, 只把 MQIsdp CONNECT 升级为 3.1.1, 不支持 [adapter] 的其它功能
+# instances = [] 表示不运行任何适配器; 各实例之间以及与其它监听器之间不能使用相同端口
+# [adapters]
+# instances = [
+#     { type = "smart", listen = "0.0.0.0:1882" },
+#     { type = "legacy", listen = "0.0.0.0:1885" },
+# ]
+
+# 协议适配器配置
+[adapter]
+# 启动智能适配器 (false = 只运行 broker, 客户端直接连接 broker 监听器); 配置了 [adapters] 时忽略
+enabled = true
+# 智能适配器的明文监听地址 (自动识别 3.1.0 / 3.1.1 / 5.0), 不能与 broker、控制台、TLS、管理接口的端口相同
+# 配置了 [adapters] 时使用其中 smart 实例的 listen
+listen = "0.0.0.0:1882"
+# 明文监听器模式: "smart" = 识别并转换协议; "passthrough" = 不解析, 从第一个字节起原样转发到后端
+# passthrough 跳过所有基于 CONNECT 的功能 (3.1.0 升级、认证、拒绝列表、按主题路由等), 只用于可信的原生 MQTT 流量
+mode = "smart"
+# 管理接口 (/metrics, /healthz, /maintenance, /config, /reload, /connections, /events)
+# 开放全部接口且不认证, 只能绑定回环地址; 非回环地址上开放 control 接口组且没有令牌时拒绝启动
+admin_listen = "127.0.0.1:3031"
+# 开放部分接口或需要令牌的管理接口监听器见下方 [[adapter.admin_listeners]]
+# 转发 CONNECT 后超过该时间 (毫秒) 才收到 broker 响应时记录警告, 0 = 不告警
+slow_connack_threshold_ms = 1000
+# 启动时进入维护模式 (拒绝新连接, 可通过 POST /maintenance 切换)
+maintenance = false
+# 启动时等待后端 broker 监听就绪的最长时间 (毫秒)
+backend_ready_timeout_ms = 10000
+# 启动自检: 开始 accept 后经由明文监听端口发送一个合成的 3.1.1 CONNECT, 确认后端返回接受连接的 CONNACK,
+# 在日志中报告结果; 自检通过之前 GET /healthz 返回 503; exit_on_failure = true 时自检失败即退出进程
+startup_self_test = false
+startup_self_test_exit_on_failure = false
+# 后端连接 (包括预热、影子和迁移连接) 的源地址和本地端口范围, 用于防火墙规则或按源地址路由
+# 不配置则由系统选择源地址和临时端口; 范围内的端口都被占用时连接失败
+# forward_bind_addr = "10.0.0.5"
+# forward_bind_port_range = [40000, 40999]
+# 连接后端或发送 CONNECT 失败时换一个新连接重试的次数 (0 = 不重试), 以及重试间隔 (毫秒)
+# 每次尝试都在新连接上发送完整的 CONNECT, 写入失败的连接直接丢弃, 不会出现重复或残缺的 CONNECT
+backend_connect_retries = 0
+backend_connect_retry_delay_ms = 200
+# 单次后端连接尝试的超时 (毫秒), 超时计为一次失败的尝试 (有重试时重试); 0 = 使用系统的连接超时 (可能长达数分钟)
+backend_connect_timeout_ms = 3000
+# 发送 CONNECT 后等待后端 CONNACK 的超时 (毫秒): 接受了 TCP 连接却不响应的后端计为一次失败的尝试 (有重试时重试); 0 = 不限
+backend_connack_timeout_ms = 10000
+# 后端地址为 DNS 名称时缓存解析结果 dns_cache_ttl_sec 秒 (系统解析器不提供记录的 TTL), 到期前被使用时在后台刷新
+# 需要尽快跟随 DNS 变化 (激进的故障切换) 时设为 false, 每次连接都解析; IP 地址从不解析
+dns_cache = true
+dns_cache_ttl_sec = 30
+# 强制最大保活时间 (秒): 通过 5.0 CONNACK 的 Server Keep Alive 下发, 转发给后端的 CONNECT 同样改为该值,
+# 由 broker 按同一时间检测断线; 3.x 客户端不支持
+# max_keepalive_sec = 300
+# 拒绝不启用保活 (keep-alive = 0) 的连接 (CONNACK Not authorized); 5.0 且配置了 max_keepalive_sec 时改为上限, 不拒绝
+require_keepalive = false
+# 5.0 遗嘱延迟间隔上限 (秒): 遗嘱在断线后最多延迟这么久发布; 会话先过期时遗嘱在会话结束时发布,
+# 这样的 CONNECT 不改写 (按 MQTT 5.0 规范, 实际延迟取遗嘱延迟间隔和会话过期间隔中较小的一个)
+# max_will_delay_sec = 300
+# 最大报文长度 (字节): 拒绝更大的 CONNECT (5.0 回复 CONNACK 0x95, 3.x 直接关闭),
+# 并把 5.0 客户端声明的 Maximum Packet Size 降到该值, 由 broker 限制下发的报文
+# max_packet_size = 1048576
+# CONNECT 剩余长度字段最多占用的字节数 (1-4, 默认 4 即规范上限; 2 = CONNECT 不超过 16383 字节),
+# 第 max_remaining_length_bytes 个字节仍带延续位时不再读取, 记录来源并断开 (计入 malformed_remaining_length_total)
+max_remaining_length_bytes = 4
+# 明文监听器上客户端 PUBLISH 的最大剩余长度 (字节), 应小于 broker 的 max_payload_size, 在不可信的入口上保护后端
+# 超过时在转发该包之前断开连接 (计入 publish_too_large_total), MQTT 5.0 客户端先收到 DISCONNECT (0x95 Packet too large);
+# TLS 监听器使用 [adapter.tls] max_publish_size
+# max_publish_size = 65536
+# 在 5.0 CONNACK 中声明的能力上限 (只收紧 broker 声明的值, 只声明不拦截, 见 README)
+# max_qos = 1
+# retain_available = false
+# 连接错误日志采样窗口 (秒): 同类错误每个窗口只输出第一条, 其余汇总为一条计数日志, 0 = 每条都输出
+error_log_window_sec = 10
+# 允许通过 POST /connections/{id}/migrate 把空闲的 clean session 连接迁移到其他后端 (尽力而为, 见 README)
+backend_migration = false
+# 通过 POST /connections/{id}/pause 暂停转发的连接最长暂停多久 (毫秒) 后自动恢复, 0 = 直到 /resume
+# 暂停期间客户端和 broker 的保活计时照常进行, 超过 1.5 倍保活时间后任一方都可能断开连接
+max_pause_ms = 300000
+# 包序列记录: POST /connections/{id}/trace 开始记录该连接转发的包类型、长度和时间, 每次最多记录这么多个包
+# 0 = 不启用; 启用后每个连接的两个方向都跟踪包边界 (不复制包体), 只有被选中的连接保存记录
+packet_trace_max_packets = 0
+# 客户端和后端连接的收发缓冲区大小 (字节), 用于高延迟高带宽链路 (如卫星回传), 不配置则由内核自动调整
+# 需要的大小约为带宽 × 往返时间, 如 50 Mbit/s × 600 ms ≈ 4 MB; 超过 net.core.rmem_max / wmem_max 时被内核截断
+# so_rcvbuf = 4194304
+# so_sndbuf = 4194304
+# 连接保持 Nagle 算法 (批量 PUBLISH 合并为大的 TCP 段), 转发控制包 (CONNECT、SUBSCRIBE、PINGREQ、ACK 等 PUBLISH 以外的包)
+# 时短暂打开 TCP_NODELAY 让内核立即发出, 降低控制包的往返延迟; 需要解析两个方向的包头, 每个控制包多两次系统调用
+nodelay_control_packets = false
+# 启动时提高进程的打开文件数软限制 (RLIMIT_NOFILE, 常见默认值 1024), 每个客户端连接占用两个描述符 (客户端和后端)
+# 0 = 提高到硬限制; 超过硬限制时警告并使用硬限制; 不配置则不修改 (仅 Unix)
+# max_open_files = 0
+# 每个 CPU 核心绑定一个 SO_REUSEPORT 监听器, 由内核分配连接 (仅 Linux, 其它系统忽略)
+reuse_port = false
+# 等待新连接发送足够识别协议 (MQTT / TLS / WebSocket / PROXY) 的字节的最长时间 (毫秒), 0 = 不限制
+banner_grace_ms = 10000
+# 从 accept 到收到完整 CONNECT 的最长时间 (毫秒), 超时直接断开并计入 bad_handshake_total, 0 = 不限制
+connect_deadline_ms = 0
+# 用固定数量的工作任务处理连接, 同时处理的连接数不超过该值, 其余在队列和 backlog 中等待
+# 0 = 每个连接一个任务 (默认)
+worker_pool_size = 0
+# 半关闭宽限 (毫秒): 一方发送 FIN 后只向对端转发 FIN, 另一方向继续转发直到它也关闭或宽限到期
+# 适用于发送 FIN 后仍在接收 inflight 消息的客户端; 0 = 任一方关闭即断开 (默认)
+half_close_grace_ms = 0
+# 排空 (POST /drain-and-handoff) 开始后关闭已有连接: 连接在没有未完成的 QoS 1/2 交互 (PUBACK / PUBREC / PUBREL / PUBCOMP,
+# 以及 SUBACK / UNSUBACK) 时立即关闭, 最多等待 qos_drain_grace_ms 毫秒, 之后强制关闭
+# (需要逐包解析两个方向的数据); 0 = 排空只关闭监听器, 已有连接保持到自行断开 (默认)
+qos_drain_grace_ms = 0
+# 零拷贝转发 (仅 Linux, 需要以 --features splice 编译): 数据在内核中经由管道在两个套接字之间搬运, 不复制到用户态
+# 只用于明文 TCP 连接, 且没有启用需要检查数据的处理 (影子后端、请求/响应日志、reject_second_connect、包类型过滤、
+# max_publish_size、nodelay_control_packets、qos_drain_grace_ms、disconnect_on_backend_close); 其余连接照常使用普通的转发循环
+splice_forward = false
+# 客户端在已建立的连接上再次发送 CONNECT (协议错误) 时由适配器断开, 不转发给后端;
+# MQTT 5.0 客户端先收到 DISCONNECT (0x82 Protocol Error) (需要逐包解析数据, 默认关闭, 由后端处理)
+reject_second_connect = false
+# 后端在稳定转发阶段关闭连接 (不是响应客户端的 DISCONNECT) 时, 先给 MQTT 5.0 客户端发送 DISCONNECT 再关闭:
+# 后端正常关闭为 0x8B (Server shutting down), 连接出错为 0x89 (Server busy); 3.1.x 客户端仍然直接关闭
+disconnect_on_backend_close = false
+# 空闲连接 (两个方向都没有数据, PINGREQ/PINGRESP 也算活动) 超过 idle_timeout_ms 毫秒后由适配器断开, 0 = 不检测
+# idle_reaper = "sweep": 一个后台任务每 reaper_interval_ms 扫描一次, 断开时间最多晚一个间隔 (适合大量连接)
+# idle_reaper = "timer": 每个连接一个定时器, 到期即断开
+idle_timeout_ms = 0
+idle_reaper = "sweep"
+reaper_interval_ms = 1000
+# 诊断: 在 debug 级别记录 5.0 CONNECT 的 Request Response Information 和 PUBLISH 的 Response Topic
+# (需要逐包解析转发流, 默认关闭)
+log_request_response = false
+# 何时向事件订阅者 (GET /events) 发布 connected: "connect" = 转发 CONNECT 后,
+# "connack" = 后端 CONNACK 接受连接后 (被拒绝时发布 connect_rejected)
+observer_on = "connect"
+# 接受的 MQTT 协议版本, 其它版本以 CONNACK 0x01 (5.0 为 0x84) 拒绝并计入 version_not_allowed_total
+# 去掉 "3.1.0" 即立即停止接受 MQIsdp 客户端 (不再升级到 3.1.1)
+allowed_versions = ["3.1.0", "3.1.1", "5.0"]
+# MQTT 3.1.0 (MQIsdp) 停用日期 (UTC): 之前升级到 3.1.1 并记录弃用警告, 当天起以 CONNACK 0x01 拒绝
+# v310_sunset_date = "2027-01-01"
+# 接受 WebSocket 上的 MQTT (浏览器客户端): 明文端口上为 ws://, TLS 端口上为 wss://, 任意路径, 回显 mqtt 子协议
+websocket = false
+# 兼容旧设备: 接受大小写不同或末尾带 NUL 的 MQIsdp 协议名 (如 "mqisdp"、"MQIsdp\0"), 按 3.1.0 升级并记录日志
+lenient_legacy = false
+# 3.1.0 (MQIsdp) 客户端升级为 3.1.1 后转发; false 时原样转发, 只适用于支持 3.1.0 的后端 (rumqttd 不支持)
+upgrade_v310 = true
+# 严格检查 CONNECT 标志: 保留位、无遗嘱时的遗嘱 QoS/保留位、遗嘱 QoS 3、3.x 无用户名时的密码, 违反时断开
+strict_connect_flags = false
+# 通过管理接口 (POST/DELETE /deny/client-id/{id}) 维护的客户端 ID 拒绝列表的保存文件, 重启后仍然生效
+# client_id_denylist_path = "denylist.txt"
+# POST /snapshot 把生效的配置和运行时状态 (维护模式、拒绝列表) 写入该文件, 以该文件启动即可恢复
+# snapshot_path = "adapter-snapshot.toml"
+# 匹配这些客户端 ID 模式 (支持 * 和 ?) 的连接强制不保留会话: 3.x 设置 Clean Session,
+# 5.0 设置 Clean Start 并把 Session Expiry Interval 改为 0, 防止临时客户端在后端堆积持久会话
+# force_clean_session = ["tmp-*", "probe-*"]
+# 在转发给后端的 5.0 CONNECT 中以该名称的用户属性附加客户端 IP, 客户端自带的同名属性先被移除; 3.x 没有用户属性, 不附加
+# forwarded_for_property = "x-forwarded-for"
+# 转发给后端之前给客户端 ID 加上前缀 (如按租户区分共享后端上的客户端), 客户端看不到改写后的 ID
+# 模板变量: {listener} = "tcp" / "tls"; {cert.CN} / {cert.O} / {cert.OU} / {cert.DNS} / {cert.URI} = 客户端证书字段 (只在 TLS 监听器上可用)
+# 空客户端 ID 不加前缀 (由 broker 分配); TLS 监听器使用 [adapter.tls] client_id_prefix
+# 内置 broker 拒绝包含 / + # $ 的客户端 ID, 前缀请使用其它分隔符
+# client_id_prefix = "tenantA."
+# 加上前缀后客户端 ID 的最大长度 (字节), 超过时拒绝连接 (CONNACK 0x02 / 0x85)
+# 与 broker 监听器的 max_client_id_len 保持一致 (rumqttd 0.19 不检查该长度, 由适配器检查)
+max_client_id_len = 256
+# 按客户端 ID 记录状态 (接管检测等) 的最大 ID 数 (只记录有活动连接的 ID), 超出时淘汰最久未使用的 ID (计入 tracked_client_ids_evicted_total),
+# 被淘汰的 ID 的检查暂时放宽; 防止伪造或随机客户端 ID 的洪水耗尽内存
+max_tracked_client_ids = 100000
+# tarpit: 被访问控制、准入速率 (含洪水收紧)、客户端 ID 拒绝列表或认证拒绝的连接先保持打开 tarpit_ms 毫秒,
+# 再回复 CONNACK 并关闭, 拖慢快速重试的扫描器; 0 = 立即关闭 (默认)
+# 滞留的连接不占用工作池和准入许可, 同时滞留的连接数达到 tarpit_max_sockets 后新的拒绝立即关闭
+tarpit_ms = 0
+tarpit_max_sockets = 1024
+
+# 单连接带宽限制 (字节/秒, 0 = 不限制)
+[adapter.throttle]
+max_bytes_per_sec = 0
+# 所有连接合计的上限 (字节/秒, 两个方向合计, 0 = 不限制), 用于保护计量或窄带上行链路
+max_total_bytes_per_sec = 0
+# 单连接流量配额 (字节, 两个方向合计, 0 = 不限制): 超过后断开连接, MQTT 5.0 客户端先收到 DISCONNECT (0x97 Quota exceeded)
+max_bytes_per_connection = 0
+
+# 按客户端 ID 覆盖限速 (按顺序匹配, 支持 * 和 ?)
+# [[adapter.throttle.rules]]
+# client_id_pattern = "firehose-*"
+# max_bytes_per_sec = 1048576
+
+# 全局 CONNECT 准入速率 (保护 broker 的会话创建, 0 = 不限制)
+[adapter.connect_rate]
+max_connects_per_sec = 0
+burst = 100
+# 超出速率时最多排队等待 (毫秒), 超过则直接关闭连接
+queue_timeout_ms = 100
+
+# CONNECT 洪水检测 (预警, 不同于上面的硬限制): 新连接速率超过基线 (指数加权平均) 的 multiplier 倍时
+# 记录 ERROR 日志并计入 connect_flood_detected_total; 可选在告警期间临时收紧准入速率
+# [adapter.connect_flood]
+# sample_interval_ms = 1000
+# baseline_window_sec = 300
+# multiplier = 5.0
+# min_rate = 20.0
+# cooldown_sec = 300
+# tighten_max_connects_per_sec = 0
+
+# 分布式追踪 (需要以 --features otel 编译)
+[adapter.otel]
+# endpoint = "http://localhost:4317"
+service_name = "rustmqttserverdemo"
+
+# nonce 防重放认证 (客户端在 5.0 CONNECT 用户属性中携带 nonce, 窗口内重复的 nonce 以 0x86 拒绝)
+# 窗口内的记录达到 max_entries 时以 0x89 拒绝新连接, max_entries 应不小于 每秒连接数 × window_sec
+# [adapter.auth.nonce]
+# property = "nonce"
+# window_sec = 300
+# max_entries = 100000
+
+# 后端凭据: 在转发给 broker 的 CONNECT 中注入用户名/密码 (客户端已在适配器认证, broker 需要用户名密码时)
+# mode = "missing" 只在客户端未提供用户名时注入, "always" 总是替换客户端的凭据
+# [adapter.backend_auth]
+# username = "adapter"
+# password = "changeme"
+# mode = "missing"
+
+# 包类型过滤: 只检查客户端发往 broker 的包, 配置 allow (只允许列出的类型) 或 deny (拒绝列出的类型) 之一
+# 类型名称: connect publish puback pubrec pubrel pubcomp subscribe unsubscribe pingreq disconnect auth
+# allow 列表需要包含 pingreq 和 disconnect, 否则保活和正常断开都会被拒绝
+# 第一个 CONNECT 由握手检查读出, 不经过过滤 (passthrough 监听器除外); 之后的 CONNECT 按 "connect" 过滤
+# action = "close" 断开连接 (默认), "discard" 丢弃该包继续转发 (客户端收不到对应的 SUBACK 等应答)
+# 被拒绝的包计入 packet_firewall_denied_total; 只作用于明文监听器, TLS 监听器使用 [adapter.tls.packet_firewall]
+# [adapter.packet_firewall]
+# deny = ["subscribe", "unsubscribe"]
+# action = "close"
+
+# 额外的管理接口监听器: 每个监听器只开放 endpoints 中的接口组 (metrics、health、status、control、dashboard, 为空时全部),
+# 配置 token 后请求需要带 Authorization: Bearer <token>; admin_listen 开放全部接口且不认证
+# [[adapter.admin_listeners]]
+# listen = "0.0.0.0:9100"
+# endpoints = ["metrics", "health"]
+# token = "change-me"
+
+# TLS 监听器 (适配器终结 TLS, 以明文转发给 broker)
+# [adapter.tls]
+# listen = "0.0.0.0:8883"
+# cert_path = "certs/server.pem"
+# key_path = "certs/server.key"     # PKCS#8 / PKCS#1 / SEC1 / 加密的 PKCS#8
+# key_password = "..."              # 仅加密私钥需要
+# handshake_timeout_ms = 10000
+# 拒绝 ClientHello 携带 0-RTT early data 的连接 (early data 可被重放); early data 从不被接受或转发
+# reject_early_data = true
+# 开头不是 TLS 记录的明文连接 (MQTT、HTTP 等) 在握手之前断开, 记录 WARN 日志并计入 plaintext_on_tls_listener_total
+# false = 同一端口也接受明文连接, 按明文监听器的策略处理
+# require_tls = true
+# 同时进行的握手数上限 (握手很耗 CPU, 限制后握手洪水不会挤占已建立连接的转发), 0 = CPU 核心数 × 64
+# max_concurrent_handshakes = 0
+# 握手数已满时最多等待多久开始握手 (毫秒), 超时直接关闭, 计入 tls_handshake_failures_total{reason="overloaded"}
+# handshake_queue_timeout_ms = 1000
+# 双向 TLS: 用该 CA 校验客户端证书, 没有有效证书的连接在握手时被拒绝
+# client_ca_path = "certs/client-ca.pem"
+# TLS 监听器上客户端 PUBLISH 的最大剩余长度 (字节), 与 [adapter] max_publish_size 相互独立
+# max_publish_size = 262144
+# TLS 监听器上的客户端 ID 前缀模板, 与 [adapter] client_id_prefix 相互独立; 证书没有模板中的字段时拒绝连接
+# client_id_prefix = "{cert.OU}."
+# 按客户端证书字段选择后端 (在读取 CONNECT 之前, 需要 client_ca_path)
+# attribute: "CN" / "O" / "OU" (主题) 或 "DNS" / "URI" (subjectAltName); 没有该字段或没有匹配时使用默认后端
+# [adapter.tls.cert_routing]
+# attribute = "OU"
+# [adapter.tls.cert_routing.backends]
+# "tenant-a" = "10.0.0.2:1883"
+# "tenant-b" = "10.0.0.3:1883"
+# TLS 监听器上的包类型过滤, 格式同 [adapter.packet_firewall], 两个监听器的配置相互独立
+# [adapter.tls.packet_firewall]
+# allow = ["publish", "puback", "pubrec", "pubrel", "pubcomp", "pingreq", "disconnect"]
+# action = "discard"
+# TLS 监听器的协议识别策略, 未设置的字段沿用 [adapter] 的同名设置
+# [adapter.tls.detection]
+# allowed_versions = ["3.1.1", "5.0"]
+# strict_connect_flags = true
+# connect_deadline_ms = 5000
+
+# 按主题前缀选择后端 (延迟连接: 适配器先回复 CONNACK, 按第一个 PUBLISH/SUBSCRIBE 的主题连接后端)
+# 适配器的 CONNACK 没有后端的 5.0 属性, 会话存在标志总是 0; 只接受不保留会话的连接, 其它连接以 CONNACK 0x83 (3.x 为 0x03) 拒绝
+# [adapter.topic_routing]
+# max_buffer_bytes = 65536
+# max_wait_ms = 5000
+# [adapter.topic_routing.backends]
+# "sensors/" = "10.0.0.2:1883"
+
+# 按源 IP 选择后端 (客户端亲和): 对客户端 IP 做一致性哈希, 同一地址总是连接同一后端, 不需要解析 CONNECT
+# 增删后端只会迁移原本分到该后端的地址; 与 topic_routing 同时配置时, 匹配主题前缀的连接仍按主题路由
+# [adapter.source_ip_routing]
+# backends = ["10.0.0.2:1883", "10.0.0.3:1883"]
+
+# 按权重选择后端 (灰度发布): 每个新连接按权重随机选择, 下例约 10% 的连接发往新版本; 权重和必须大于 0
+# sticky = true 时按客户端 IP 固定后端 (加权一致性哈希); 不能与 source_ip_routing 同时配置
+# [adapter.weighted_routing]
+# sticky = false
+# [[adapter.weighted_routing.backends]]
+# address = "10.0.0.2:1883"
+# weight = 90
+# [[adapter.weighted_routing.backends]]
+# address = "10.0.0.3:1883"
+# weight = 10
+
+# 影子后端 (复制 CONNECT 和客户端流量到另一个 broker, 丢弃其响应, 不影响在线连接)
+# [adapter.shadow]
+# backend = "10.0.0.9:1883"
+# forward_traffic = true
+# queue_chunks = 256
+
+# 后端预热连接池 (预先连接默认后端, 新客户端直接取用)
+# 空闲上限应小于 broker 的 connection_timeout_ms, 否则 broker 会先关闭未发送 CONNECT 的连接
+# [adapter.warm_pool]
+# size = 4
+# max_idle_ms = 30000
+
+# 活动连接上限 (达到上限后新连接按租户排队, 有连接结束时在租户之间轮流放行)
+# 租户为客户端 ID 中第一个分隔符之前的部分, 如 "acme-sensor-1" 属于租户 "acme"
+# [adapter.admission]
+# max_connections = 10000
+# tenant_delimiter = "-"
+# max_queue_per_tenant = 100
+# queue_timeout_ms = 5000
+# 每个租户的连接数上限 (活动和排队的连接), 超出时 5.0 回复 CONNACK 0x97 (Quota exceeded), 3.x 直接关闭; 0 = 不限制
+# max_connections_per_tenant = 0
+
+# 把适配器主动关闭连接 (维护模式、拒绝列表、认证、准入等) 的事件以 JSON 发布到后端主题 <topic_prefix>/disconnect
+# 适配器用单独的控制连接 (QoS 0) 连接后端, 断开后自动重连, 断开期间的事件丢弃
+# [adapter.event_publish]
+# topic_prefix = "adapter/events"
+# client_id = ""
+# keep_alive_sec = 60
+
+# 每 interval_sec 秒把适配器状态 (实例状态、活动连接数、按协议版本的连接数) 以 JSON 保留消息发布到后端主题
+# 使用单独的控制连接 (QoS 0, retain), 断开后退避重连; 多个适配器实例应配置不同的 topic
+# [adapter.health_publish]
+# topic = "adapter/health"
+# interval_sec = 30
+# client_id = ""
+# keep_alive_sec = 60
+
+# CONNECT 字段日志脱敏 (密码从不记录)
+[adapter.logging]
+# 需要脱敏的字段: "client_id", "username"
+redact = []
+# hash = 输出 SHA-256 前缀 (同一值可关联), truncate = 只保留前 3 个字符
+redact_mode = "hash"
+# 成功建立连接的日志 ("Detected MQTT 3.1.1 client"、TLS 握手结果) 的级别: "info" / "debug" / "trace"
+# 连接数很大时改为 "debug" 可以大幅减少日志量; 拒绝、策略断开和错误日志保持原级别
+connection_log_level = "info"
+# 按比例在 info 级别记录连接的来源和识别出的协议版本 (0.01 = 每 100 个连接记录 1 个), 0 = 不采样
+# 与 connection_log_level 独立, 降低连接日志级别后仍可抽样观察流量构成
+accept_log_sample_rate = 0.0
+
+# 指标输出: prometheus = 只通过管理接口 /metrics 拉取; statsd = 另外定期通过 UDP 推送到 StatsD / DogStatsD
+[adapter.metrics]
+backend = "prometheus"
+statsd_target = "127.0.0.1:8125"
+statsd_prefix = "mqtt_adapter"
+# true = DogStatsD 标签 (|#reason:timeout), false = 标签值拼接到指标名 (accept_errors_total.emfile)
+statsd_tags = true
+flush_interval_ms = 10000
+
+# 访问控制 (在读取 CONNECT 之前检查对端 IP, 拒绝列表优先, 允许列表为空表示允许所有)
+[adapter.access]
+allow_cidrs = []
+deny_cidrs = []
 "#;
     
     fs::write(config_path, default_config)
@@ -123,3 +854,90 @@ listen = "0.0.0.0:3030"
             std::process::exit(1);
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 随仓库提供的 config.toml, 加上 `extra` 中的设置 (在 [adapter] 之前插入)
+    fn configs(extra: &str) -> (Config, AdapterConfig, Vec<AdapterInstance>) {
+        let content = include_str!("../config.toml").replacen("\n[adapter]\n", &format!("\n{}\n[adapter]\n", extra), 1);
+        let config: Config = toml::from_str(&content).unwrap();
+        let app_config: AppConfig = toml::from_str(&content).unwrap();
+        let instances = app_config.adapter_instances().unwrap();
+        (config, app_config.adapter, instances)
+    }
+
+    fn conflicts(extra: &str) -> Result<(), String> {
+        let (config, adapter, instances) = configs(extra);
+        check_listen_conflicts(&config, &adapter, &instances)
+    }
+
+    #[test]
+    fn shipped_config_has_no_conflicts() {
+        assert_eq!(conflicts(""), Ok(()));
+    }
+
+    #[test]
+    fn smart_and_legacy_on_distinct_ports() {
+        let extra = "[adapters]\ninstances = [{ type = \"smart\", listen = \"0.0.0.0:1882\" }, { type = \"legacy\", listen = \"0.0.0.0:1885\" }]\n";
+        assert_eq!(conflicts(extra), Ok(()));
+        assert_eq!(conflicts("[adapters]\ninstances = []\n"), Ok(()));
+    }
+
+    #[test]
+    fn legacy_instance_on_broker_port_conflicts() {
+        let extra = "[adapters]\ninstances = [{ type = \"legacy\", listen = \"0.0.0.0:1883\" }]\n";
+        let error = conflicts(extra).unwrap_err();
+        assert!(error.contains("legacy adapter listen"), "{}", error);
+    }
+
+    #[test]
+    fn two_instances_on_one_port_conflict() {
+        let extra = "[adapters]\ninstances = [{ type = \"smart\", listen = \"0.0.0.0:1885\" }, { type = \"legacy\", listen = \"127.0.0.1:1885\" }]\n";
+        let error = conflicts(extra).unwrap_err();
+        assert!(error.contains("smart adapter listen") && error.contains("legacy adapter listen"), "{}", error);
+    }
+
+    #[test]
+    fn disabled_smart_adapter_frees_its_port() {
+        // 没有 smart 实例时 [adapter] listen 不再占用端口
+        let extra = "[adapters]\ninstances = [{ type = \"legacy\", listen = \"0.0.0.0:1882\" }]\n";
+        assert_eq!(conflicts(extra), Ok(()));
+    }
+
+    fn admin_listener(listen: &str, endpoints: &[AdminEndpoint], token: Option<&str>) -> adapter_config::AdminListenerConfig {
+        adapter_config::AdminListenerConfig { listen: listen.to_string(), endpoints: endpoints.to_vec(), token: token.map(str::to_string) }
+    }
+
+    #[test]
+    fn shipped_admin_listen_is_loopback_only() {
+        let (_, adapter, _) = configs("");
+        assert_eq!(adapter.admin_listen.as_deref(), Some("127.0.0.1:3031"));
+        assert_eq!(check_admin_exposure(&adapter), Ok(()));
+    }
+
+    #[test]
+    fn public_admin_listen_is_refused() {
+        let adapter = AdapterConfig { admin_listen: Some("0.0.0.0:3031".to_string()), ..AdapterConfig::default() };
+        let error = check_admin_exposure(&adapter).unwrap_err();
+        assert!(error.contains("admin_listen (0.0.0.0:3031)"), "{}", error);
+
+        let adapter = AdapterConfig { admin_listen: Some("[::1]:3031".to_string()), ..AdapterConfig::default() };
+        assert_eq!(check_admin_exposure(&adapter), Ok(()));
+    }
+
+    #[test]
+    fn public_admin_listeners_need_a_token_for_control() {
+        let check = |listener| check_admin_exposure(&AdapterConfig { admin_listeners: vec![listener], ..AdapterConfig::default() });
+        // 不开放 control 的公开监听器不需要令牌
+        assert_eq!(check(admin_listener("0.0.0.0:9100", &[AdminEndpoint::Metrics, AdminEndpoint::Health], None)), Ok(()));
+        assert_eq!(check(admin_listener("0.0.0.0:9100", &[AdminEndpoint::Control], Some("change-me"))), Ok(()));
+        assert_eq!(check(admin_listener("127.0.0.1:9100", &[], None)), Ok(()));
+        // endpoints 为空即开放全部接口组, 包括 control
+        for endpoints in [&[][..], &[AdminEndpoint::Status, AdminEndpoint::Control][..]] {
+            let error = check(admin_listener("0.0.0.0:9100", endpoints, None)).unwrap_err();
+            assert!(error.contains("admin_listeners[0]"), "{}", error);
+        }
+    }
+}
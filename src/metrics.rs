@@ -0,0 +1,888 @@
+// 适配器运行指标
+// 全局原子计数器,由管理接口以 Prometheus (或 OpenMetrics) 文本格式输出
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::accept_backoff::AcceptErrorKind;
+use crate::admission::AdmissionRejection;
+use crate::packet_firewall::PACKET_TYPE_NAMES;
+use crate::smart_adapter::{BadHandshake, MqttVersion};
+use crate::telemetry;
+use crate::tls::{HandshakeFailure, TlsInfo};
+
+/// `protocol_rejections_total` 最多保留的 (name, level) 组合, 之后的计入 name="other"
+/// 协议名由客户端发送, 需要限制标签数量
+const MAX_PROTOCOL_REJECTION_LABELS: usize = 64;
+/// 作为标签使用的协议名最大长度
+const MAX_PROTOCOL_NAME_LABEL_LEN: usize = 16;
+
+/// 适配器指标
+pub struct Metrics {
+    /// 当前正在转发的连接数
+    pub active_connections: AtomicI64,
+    /// 开始转发的连接总数
+    pub connections_total: AtomicU64,
+    /// 工作池中正在处理连接的工作任务数
+    pub worker_pool_busy: AtomicI64,
+    /// 在工作池队列中等待的连接数
+    pub worker_pool_queued: AtomicI64,
+    /// 当前启用了带宽限制的连接数
+    pub throttled_connections: AtomicI64,
+    /// 被访问控制列表拒绝的连接总数
+    pub connection_denied_acl_total: AtomicU64,
+    /// 客户端 ID 在运行时拒绝列表中而被拒绝的连接总数
+    pub connection_denied_client_id_total: AtomicU64,
+    /// 客户端 ID 状态表超过 max_tracked_client_ids 时淘汰的条目总数
+    pub tracked_client_ids_evicted_total: AtomicU64,
+    /// 按 force_clean_session 改写为 clean session 的 CONNECT 总数
+    pub forced_clean_session_total: AtomicU64,
+    /// 按 require_keepalive 拒绝的不启用保活的连接总数
+    pub keepalive_required_rejected_total: AtomicU64,
+    /// 按主题路由时因请求保留会话而被拒绝的连接总数
+    pub topic_routing_session_rejected_total: AtomicU64,
+    /// 因 client_id_prefix 拒绝的连接总数 (证书缺少模板中的字段或加上前缀后超过 max_client_id_len)
+    pub client_id_prefix_rejected_total: AtomicU64,
+    /// 空闲超过 idle_timeout_ms 被适配器断开的连接总数
+    pub idle_reaped_total: AtomicU64,
+    /// 排空时由适配器关闭的连接总数 (qos_drain_grace_ms)
+    pub drain_closed_total: AtomicU64,
+    /// 其中宽限到期时仍有未完成 QoS 交互、被强制关闭的连接数
+    pub drain_forced_total: AtomicU64,
+    /// 两个方向合计转发超过 max_bytes_per_connection 被适配器断开的连接总数
+    pub connections_closed_quota_total: AtomicU64,
+    /// 后端意外断开时发给 MQTT 5.0 客户端的 DISCONNECT 数 (disconnect_on_backend_close)
+    pub backend_close_disconnects_total: AtomicU64,
+    /// 后端地址解析命中 DNS 缓存的次数
+    pub dns_cache_hits_total: AtomicU64,
+    /// 后端地址解析未命中 (首次解析或已过期) 的次数
+    pub dns_cache_misses_total: AtomicU64,
+    /// 用 splice 零拷贝转发的连接总数 (splice_forward)
+    pub splice_connections_total: AtomicU64,
+    /// 发布到后端主题的 adapter_closed 事件总数
+    pub event_publish_total: AtomicU64,
+    /// 控制连接断开而未能发布的 adapter_closed 事件总数
+    pub event_publish_dropped_total: AtomicU64,
+    /// 发布到后端健康主题的保留消息总数
+    pub health_publish_total: AtomicU64,
+    /// 超出全局 CONNECT 准入速率被拒绝的连接总数
+    pub connection_rate_limited_total: AtomicU64,
+    /// 检测到 CONNECT 洪水的次数
+    pub connect_flood_detected_total: AtomicU64,
+    /// 洪水告警期间超出收紧的准入速率被拒绝的连接总数
+    pub connect_flood_rejected_total: AtomicU64,
+    /// 连接处理任务中捕获的 panic 总数
+    pub handler_panics_total: AtomicU64,
+    /// 发送完 CONNECT 之前断开的连接总数
+    pub handshake_aborted_total: AtomicU64,
+    /// CONNECT 剩余长度超过 max_remaining_length_bytes 个字节被拒绝的连接总数
+    pub malformed_remaining_length_total: AtomicU64,
+    /// 因发送第二个 CONNECT 被适配器断开的连接总数
+    pub second_connect_total: AtomicU64,
+    /// 因 PUBLISH 超过 max_publish_size 被适配器断开的连接总数
+    pub publish_too_large_total: AtomicU64,
+    /// passthrough 监听器接受并转发的连接总数
+    pub passthrough_connections_total: AtomicU64,
+    /// 交给 tarpit 延迟关闭的连接总数
+    pub tarpit_total: AtomicU64,
+    /// tarpit 已满而立即关闭的连接总数
+    pub tarpit_overflow_total: AtomicU64,
+    /// 当前滞留在 tarpit 中的连接数
+    pub tarpit_active: AtomicI64,
+    /// 成功迁移到其他后端的连接总数
+    pub backend_migrations_total: AtomicU64,
+    /// 影子后端错误次数 (连接失败、断开、跟不上复制速度)
+    pub shadow_errors_total: AtomicU64,
+    /// 直接使用预热连接的客户端数
+    pub warm_pool_hits_total: AtomicU64,
+    /// 因空闲超时或已被后端关闭而丢弃的预热连接数
+    pub warm_pool_discarded_total: AtomicU64,
+    /// MQTT 3.1.0 CONNECT 次数 (包括停用日期之后被拒绝的)
+    pub v310_deprecated_total: AtomicU64,
+    /// strict_connect_flags 下因 CONNECT 标志违反规范而断开的连接数
+    pub connect_flags_rejected_total: AtomicU64,
+    /// 连接后端或发送 CONNECT 失败后的重试次数
+    pub backend_connect_retries_total: AtomicU64,
+    /// 转发的字节总数 (两个方向合计)
+    pub forwarded_bytes_total: AtomicU64,
+    /// 最近一秒转发的字节数, 由 `run_throughput_sampler` 更新
+    forwarded_bytes_per_sec: AtomicU64,
+    /// 最近 `CONNECTION_RATE_WINDOW_SEC` 秒平均每秒新建的连接数 (f64 的位表示), 由 `run_throughput_sampler` 更新
+    connections_per_sec: AtomicU64,
+    /// 最近一个采样间隔的新连接速率和基线 (f64 的位表示), 由洪水检测更新
+    connect_rate_current: AtomicU64,
+    connect_rate_baseline: AtomicU64,
+    /// 是否处于洪水告警状态
+    connect_flood_active: AtomicI64,
+    /// accept 失败次数, 按 `AcceptErrorKind` 分类
+    accept_errors_total: [AtomicU64; AcceptErrorKind::ALL.len()],
+    /// TLS 握手失败次数, 按 `HandshakeFailure` 分类
+    tls_handshake_failures_total: [AtomicU64; HandshakeFailure::ALL.len()],
+    /// 因 ClientHello 携带 0-RTT early data 而断开的 TLS 连接数
+    pub tls_early_data_rejected_total: AtomicU64,
+    /// 因 TLS 重新协商请求而断开的 TLS 连接数
+    pub tls_renegotiation_rejected_total: AtomicU64,
+    /// TLS 监听器上开头不是 TLS 记录、按 require_tls 断开的明文连接数
+    pub plaintext_on_tls_listener_total: AtomicU64,
+    /// 完成 TLS 握手的连接数, 按协商的版本和密码套件分类
+    tls_connections_total: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    /// 完成 WebSocket 升级的连接数, [ws, wss]
+    websocket_connections_total: [AtomicU64; 2],
+    /// 未通过握手检查的连接数, 按 `BadHandshake` 分类
+    bad_handshake_total: [AtomicU64; BadHandshake::ALL.len()],
+    /// 协议版本不在 allowed_versions 中而被拒绝的 CONNECT 数, 按 `MqttVersion` 分类
+    pub version_not_allowed_total: [AtomicU64; MqttVersion::ALL.len()],
+    /// 协议名或协议级别无法识别的 CONNECT 数, 按 (协议名, 级别) 分类
+    protocol_rejections_total: Mutex<BTreeMap<(String, u8), u64>>,
+    /// 被包类型过滤拒绝的包数, 下标为包类型
+    packet_firewall_denied_total: [AtomicU64; 16],
+    /// 准入排队被拒绝的连接数, 按 `AdmissionRejection` 分类
+    admission_rejected_total: [AtomicU64; AdmissionRejection::ALL.len()],
+    /// 各租户当前在准入队列中等待的连接数 (只保留非零项)
+    admission_queue_depth: Mutex<BTreeMap<String, usize>>,
+    /// 从转发 CONNECT 到收到 broker 首个响应的耗时
+    pub connack_latency: Histogram,
+}
+
+static METRICS: Metrics = Metrics {
+    active_connections: AtomicI64::new(0),
+    connections_total: AtomicU64::new(0),
+    worker_pool_busy: AtomicI64::new(0),
+    worker_pool_queued: AtomicI64::new(0),
+    throttled_connections: AtomicI64::new(0),
+    connection_denied_acl_total: AtomicU64::new(0),
+    connection_denied_client_id_total: AtomicU64::new(0),
+    tracked_client_ids_evicted_total: AtomicU64::new(0),
+    forced_clean_session_total: AtomicU64::new(0),
+    keepalive_required_rejected_total: AtomicU64::new(0),
+    topic_routing_session_rejected_total: AtomicU64::new(0),
+    client_id_prefix_rejected_total: AtomicU64::new(0),
+    idle_reaped_total: AtomicU64::new(0),
+    drain_closed_total: AtomicU64::new(0),
+    drain_forced_total: AtomicU64::new(0),
+    connections_closed_quota_total: AtomicU64::new(0),
+    backend_close_disconnects_total: AtomicU64::new(0),
+    dns_cache_hits_total: AtomicU64::new(0),
+    dns_cache_misses_total: AtomicU64::new(0),
+    splice_connections_total: AtomicU64::new(0),
+    event_publish_total: AtomicU64::new(0),
+    event_publish_dropped_total: AtomicU64::new(0),
+    health_publish_total: AtomicU64::new(0),
+    connection_rate_limited_total: AtomicU64::new(0),
+    connect_flood_detected_total: AtomicU64::new(0),
+    connect_flood_rejected_total: AtomicU64::new(0),
+    handler_panics_total: AtomicU64::new(0),
+    handshake_aborted_total: AtomicU64::new(0),
+    malformed_remaining_length_total: AtomicU64::new(0),
+    second_connect_total: AtomicU64::new(0),
+    publish_too_large_total: AtomicU64::new(0),
+    passthrough_connections_total: AtomicU64::new(0),
+    tarpit_total: AtomicU64::new(0),
+    tarpit_overflow_total: AtomicU64::new(0),
+    tarpit_active: AtomicI64::new(0),
+    backend_migrations_total: AtomicU64::new(0),
+    shadow_errors_total: AtomicU64::new(0),
+    warm_pool_hits_total: AtomicU64::new(0),
+    warm_pool_discarded_total: AtomicU64::new(0),
+    v310_deprecated_total: AtomicU64::new(0),
+    connect_flags_rejected_total: AtomicU64::new(0),
+    backend_connect_retries_total: AtomicU64::new(0),
+    forwarded_bytes_total: AtomicU64::new(0),
+    forwarded_bytes_per_sec: AtomicU64::new(0),
+    connections_per_sec: AtomicU64::new(0),
+    connect_rate_current: AtomicU64::new(0),
+    connect_rate_baseline: AtomicU64::new(0),
+    connect_flood_active: AtomicI64::new(0),
+    accept_errors_total: [const { AtomicU64::new(0) }; AcceptErrorKind::ALL.len()],
+    tls_handshake_failures_total: [const { AtomicU64::new(0) }; HandshakeFailure::ALL.len()],
+    tls_early_data_rejected_total: AtomicU64::new(0),
+    tls_renegotiation_rejected_total: AtomicU64::new(0),
+    plaintext_on_tls_listener_total: AtomicU64::new(0),
+    tls_connections_total: Mutex::new(BTreeMap::new()),
+    websocket_connections_total: [const { AtomicU64::new(0) }; 2],
+    bad_handshake_total: [const { AtomicU64::new(0) }; BadHandshake::ALL.len()],
+    version_not_allowed_total: [const { AtomicU64::new(0) }; MqttVersion::ALL.len()],
+    protocol_rejections_total: Mutex::new(BTreeMap::new()),
+    packet_firewall_denied_total: [const { AtomicU64::new(0) }; 16],
+    admission_rejected_total: [const { AtomicU64::new(0) }; AdmissionRejection::ALL.len()],
+    admission_queue_depth: Mutex::new(BTreeMap::new()),
+    connack_latency: Histogram::new(),
+};
+
+/// 直方图桶上限 (秒)
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// 固定桶的延迟直方图
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    /// 总和 (微秒), 用整数累加避免浮点原子操作
+    sum_micros: AtomicU64,
+    /// 每个桶 (最后一个为 +Inf) 最近一次落入的观测, OpenMetrics 格式中作为 exemplar 输出
+    exemplars: Mutex<[Option<Exemplar>; LATENCY_BUCKETS.len() + 1]>,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            exemplars: Mutex::new([const { None }; LATENCY_BUCKETS.len() + 1]),
+        }
+    }
+
+    /// 记录一次观测值, `connection_id` 为产生该观测的连接
+    pub fn observe(&self, value: Duration, connection_id: u64) {
+        let secs = value.as_secs_f64();
+        for (bucket, upper) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if secs <= upper {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+
+        // exemplar 的值必须落在所在桶的区间内, 因此记在包含它的最小的桶上
+        let bucket = LATENCY_BUCKETS.iter().position(|upper| secs <= *upper).unwrap_or(LATENCY_BUCKETS.len());
+        self.exemplars.lock().unwrap()[bucket] = Some(Exemplar::new(connection_id, secs));
+    }
+}
+
+/// OpenMetrics exemplar: 把一个样本关联到具体的连接和追踪
+#[derive(Debug, Clone)]
+pub struct Exemplar {
+    /// 连接关联 ID (与日志、`/connections` 和追踪 span 的 connection_id 相同)
+    connection_id: u64,
+    /// 启用 `otel` feature 并导出追踪时观测所在 span 的 trace id
+    trace_id: Option<String>,
+    value: f64,
+    /// Unix 时间 (秒)
+    timestamp: f64,
+}
+
+impl Exemplar {
+    fn new(connection_id: u64, value: f64) -> Self {
+        Exemplar {
+            connection_id,
+            trace_id: telemetry::current_trace_id(),
+            value,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+        }
+    }
+}
+
+/// 获取全局指标
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// 计算新连接速率的窗口 (秒)
+const CONNECTION_RATE_WINDOW_SEC: usize = 10;
+
+/// 每秒把 `forwarded_bytes_total` 的增量记为当前吞吐量, 并按最近 `CONNECTION_RATE_WINDOW_SEC` 秒的 `connections_total` 计算新连接速率
+pub async fn run_throughput_sampler() {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut last = METRICS.forwarded_bytes_total.load(Ordering::Relaxed);
+    // 窗口内每秒的连接总数, 最早的在前
+    let mut connection_totals = VecDeque::with_capacity(CONNECTION_RATE_WINDOW_SEC + 1);
+    loop {
+        interval.tick().await;
+        let total = METRICS.forwarded_bytes_total.load(Ordering::Relaxed);
+        METRICS.forwarded_bytes_per_sec.store(total - last, Ordering::Relaxed);
+        last = total;
+
+        connection_totals.push_back(METRICS.connections_total.load(Ordering::Relaxed));
+        if connection_totals.len() > CONNECTION_RATE_WINDOW_SEC + 1 {
+            connection_totals.pop_front();
+        }
+        // 启动后不足一个窗口时按已有的秒数计算
+        let seconds = connection_totals.len() - 1;
+        if seconds > 0 {
+            let rate = (connection_totals[seconds] - connection_totals[0]) as f64 / seconds as f64;
+            METRICS.connections_per_sec.store(rate.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl Metrics {
+    /// 最近 `CONNECTION_RATE_WINDOW_SEC` 秒平均每秒新建的连接数
+    pub fn connections_per_sec(&self) -> f64 {
+        f64::from_bits(self.connections_per_sec.load(Ordering::Relaxed))
+    }
+
+    /// 记录一次 accept 失败
+    pub fn record_accept_error(&self, kind: AcceptErrorKind) {
+        self.accept_errors_total[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次 TLS 握手失败
+    pub fn record_tls_handshake_failure(&self, reason: HandshakeFailure) {
+        self.tls_handshake_failures_total[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次成功的 TLS 握手
+    pub fn record_tls_connection(&self, tls: TlsInfo) {
+        *self.tls_connections_total.lock().unwrap().entry((tls.version, tls.cipher)).or_default() += 1;
+    }
+
+    /// 记录一次完成的 WebSocket 升级, `tls` 为 TLS 监听器上的 wss
+    pub fn record_websocket_connection(&self, tls: bool) {
+        self.websocket_connections_total[usize::from(tls)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 更新洪水检测的速率、基线和告警状态
+    pub fn set_connect_flood_state(&self, rate: f64, baseline: f64, active: bool) {
+        self.connect_rate_current.store(rate.to_bits(), Ordering::Relaxed);
+        self.connect_rate_baseline.store(baseline.to_bits(), Ordering::Relaxed);
+        self.connect_flood_active.store(i64::from(active), Ordering::Relaxed);
+    }
+
+    /// 记录一次握手检查失败
+    pub fn record_bad_handshake(&self, reason: BadHandshake) {
+        self.bad_handshake_total[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次因协议版本不在 allowed_versions 中的拒绝
+    pub fn record_version_not_allowed(&self, version: MqttVersion) {
+        self.version_not_allowed_total[version as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次无法识别的协议
+    /// 不可打印或过长的协议名记为 "invalid", 组合数超过上限后新的组合记为 "other"
+    pub fn record_protocol_rejection(&self, name: &str, level: u8) {
+        let printable = !name.is_empty()
+            && name.len() <= MAX_PROTOCOL_NAME_LABEL_LEN
+            && name.chars().all(|c| c.is_ascii_graphic());
+        let mut name = if printable { name.to_string() } else { "invalid".to_string() };
+
+        let mut rejections = self.protocol_rejections_total.lock().unwrap();
+        if rejections.len() >= MAX_PROTOCOL_REJECTION_LABELS && !rejections.contains_key(&(name.clone(), level)) {
+            name = "other".to_string();
+        }
+        *rejections.entry((name, level)).or_default() += 1;
+    }
+
+    /// 记录一个被包类型过滤拒绝的包
+    pub fn record_packet_firewall_denied(&self, packet_type: u8) {
+        self.packet_firewall_denied_total[packet_type as usize & 0x0f].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次准入拒绝
+    pub fn record_admission_rejection(&self, reason: AdmissionRejection) {
+        self.admission_rejected_total[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 更新租户的准入队列长度
+    pub fn set_admission_queue_depth(&self, tenant: &str, depth: usize) {
+        let mut depths = self.admission_queue_depth.lock().unwrap();
+        if depth == 0 {
+            depths.remove(tenant);
+        } else {
+            depths.insert(tenant.to_string(), depth);
+        }
+    }
+
+    /// 以 Prometheus 文本格式输出所有指标
+    pub fn render_prometheus(&self) -> String {
+        let mut sink = PrometheusSink::default();
+        self.emit(&mut sink);
+        sink.out
+    }
+
+    /// 以 OpenMetrics 文本格式输出所有指标 (带 exemplar)
+    pub fn render_openmetrics(&self) -> String {
+        let mut sink = PrometheusSink { open_metrics: true, ..Default::default() };
+        self.emit(&mut sink);
+        sink.out.push_str("# EOF\n");
+        sink.out
+    }
+
+    /// 以 JSON 对象输出所有指标 (GET /overview): 无标签的样本为数值, 带标签的样本为 {标签..., "value"} 数组, 不含直方图的桶
+    pub fn render_json(&self) -> serde_json::Value {
+        let mut sink = JsonSink::default();
+        self.emit(&mut sink);
+        serde_json::Value::Object(sink.out)
+    }
+
+    /// 把所有指标交给输出目标, Prometheus 和 StatsD 共用这一份指标定义
+    pub fn emit(&self, sink: &mut dyn MetricsSink) {
+        emit_gauge(
+            sink,
+            "active_connections",
+            "Connections currently forwarded to a backend",
+            self.active_connections.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connections_total",
+            "Connections forwarded to a backend since startup",
+            self.connections_total.load(Ordering::Relaxed),
+        );
+        emit_gauge(
+            sink,
+            "worker_pool_busy",
+            "Worker pool tasks currently handling a connection",
+            self.worker_pool_busy.load(Ordering::Relaxed),
+        );
+        emit_gauge(
+            sink,
+            "worker_pool_queued",
+            "Accepted connections waiting for a worker pool task",
+            self.worker_pool_queued.load(Ordering::Relaxed),
+        );
+        emit_gauge(
+            sink,
+            "throttled_connections",
+            "Active connections with a bandwidth limit applied",
+            self.throttled_connections.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connection_denied_acl_total",
+            "Connections rejected by the access control list",
+            self.connection_denied_acl_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connection_denied_client_id_total",
+            "Connections rejected because the client ID is on the runtime denylist",
+            self.connection_denied_client_id_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "tracked_client_ids_evicted_total",
+            "Client ID entries evicted because more than max_tracked_client_ids were tracked",
+            self.tracked_client_ids_evicted_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "forced_clean_session_total",
+            "CONNECTs rewritten to a clean session by force_clean_session",
+            self.forced_clean_session_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "keepalive_required_rejected_total",
+            "Connections rejected by require_keepalive because keep-alive was disabled",
+            self.keepalive_required_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "topic_routing_session_rejected_total",
+            "Connections rejected by topic routing because they asked for a persistent session",
+            self.topic_routing_session_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "client_id_prefix_rejected_total",
+            "Connections rejected by client_id_prefix (missing certificate field or prefixed ID over max_client_id_len)",
+            self.client_id_prefix_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "idle_reaped_total",
+            "Connections closed by the adapter after idle_timeout_ms without traffic",
+            self.idle_reaped_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "drain_closed_total",
+            "Connections closed by the adapter during a drain (qos_drain_grace_ms)",
+            self.drain_closed_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "drain_forced_total",
+            "Drain closes that still had QoS exchanges in flight when the grace expired",
+            self.drain_forced_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connections_closed_quota_total",
+            "Connections closed after forwarding more than max_bytes_per_connection",
+            self.connections_closed_quota_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "backend_close_disconnects_total",
+            "DISCONNECT packets sent to MQTT 5.0 clients when the backend closed the connection",
+            self.backend_close_disconnects_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "dns_cache_hits_total",
+            "Backend address lookups answered from the DNS cache",
+            self.dns_cache_hits_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "dns_cache_misses_total",
+            "Backend address lookups that went to the resolver (first lookup or expired entry)",
+            self.dns_cache_misses_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "splice_connections_total",
+            "Connections forwarded with zero-copy splice(2) (splice_forward)",
+            self.splice_connections_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "event_publish_total",
+            "Adapter-closed events published to the backend event topic",
+            self.event_publish_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "event_publish_dropped_total",
+            "Adapter-closed events dropped because the event control connection was down",
+            self.event_publish_dropped_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "health_publish_total",
+            "Retained adapter health messages published to the backend health topic",
+            self.health_publish_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connection_rate_limited_total",
+            "Connections rejected by the global connect admission rate",
+            self.connection_rate_limited_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "backend_migrations_total",
+            "Connections migrated to another backend through the admin API",
+            self.backend_migrations_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "shadow_errors_total",
+            "Shadow backend connect, write or overload errors",
+            self.shadow_errors_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "warm_pool_hits_total",
+            "Clients forwarded over a pre-warmed backend connection",
+            self.warm_pool_hits_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "warm_pool_discarded_total",
+            "Pre-warmed backend connections discarded as stale or closed",
+            self.warm_pool_discarded_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "v310_deprecated_total",
+            "MQTT 3.1.0 (MQIsdp) CONNECTs, upgraded before the sunset date and rejected after it",
+            self.v310_deprecated_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connect_flags_rejected_total",
+            "Connections closed because their CONNECT flags violate the spec (strict_connect_flags)",
+            self.connect_flags_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "backend_connect_retries_total",
+            "Backend connect attempts retried on a fresh connection after the connect or CONNECT write failed",
+            self.backend_connect_retries_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "forwarded_bytes_total",
+            "Bytes forwarded between clients and backends in both directions",
+            self.forwarded_bytes_total.load(Ordering::Relaxed),
+        );
+        emit_gauge(
+            sink,
+            "forwarded_bytes_per_sec",
+            "Aggregate forwarding throughput over the last second",
+            self.forwarded_bytes_per_sec.load(Ordering::Relaxed) as i64,
+        );
+        emit_counter(
+            sink,
+            "connect_flood_detected_total",
+            "Connect floods detected (rate above baseline times multiplier)",
+            self.connect_flood_detected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "connect_flood_rejected_total",
+            "Connections rejected by the tightened admission rate during a connect flood",
+            self.connect_flood_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "handler_panics_total",
+            "Panics caught in per-connection handlers",
+            self.handler_panics_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "handshake_aborted_total",
+            "Connections closed by the client before a complete CONNECT was received",
+            self.handshake_aborted_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "malformed_remaining_length_total",
+            "Connections rejected because the CONNECT remaining length used more than max_remaining_length_bytes bytes",
+            self.malformed_remaining_length_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "second_connect_total",
+            "Connections closed by the adapter after the client sent a second CONNECT",
+            self.second_connect_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "publish_too_large_total",
+            "Connections closed by the adapter after the client sent a PUBLISH larger than max_publish_size",
+            self.publish_too_large_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "passthrough_connections_total",
+            "Connections forwarded without protocol detection by a passthrough listener",
+            self.passthrough_connections_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "tarpit_total",
+            "Rejected connections held open by the tarpit before closing",
+            self.tarpit_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "tarpit_overflow_total",
+            "Rejected connections closed immediately because the tarpit was full",
+            self.tarpit_overflow_total.load(Ordering::Relaxed),
+        );
+        emit_gauge(
+            sink,
+            "tarpit_active",
+            "Rejected connections currently held open by the tarpit",
+            self.tarpit_active.load(Ordering::Relaxed),
+        );
+        emit_gauge(
+            sink,
+            "connect_flood_active",
+            "Whether a connect flood alert is active",
+            self.connect_flood_active.load(Ordering::Relaxed),
+        );
+        sink.family("connect_rate_per_sec", "New connections per second over the last flood detection sample", MetricKind::Gauge);
+        sink.sample("connect_rate_per_sec", &[], f64::from_bits(self.connect_rate_current.load(Ordering::Relaxed)));
+        sink.family("connect_rate_baseline_per_sec", "Smoothed baseline of new connections per second", MetricKind::Gauge);
+        sink.sample("connect_rate_baseline_per_sec", &[], f64::from_bits(self.connect_rate_baseline.load(Ordering::Relaxed)));
+        sink.family("accept_errors_total", "Failed accept() calls on adapter listeners", MetricKind::Counter);
+        for kind in AcceptErrorKind::ALL {
+            sink.sample("accept_errors_total", &[("kind", kind.as_str())], self.accept_errors_total[kind as usize].load(Ordering::Relaxed) as f64);
+        }
+        sink.family("tls_handshake_failures_total", "Failed TLS handshakes on the adapter TLS listener", MetricKind::Counter);
+        for reason in HandshakeFailure::ALL {
+            sink.sample("tls_handshake_failures_total", &[("reason", reason.as_str())], self.tls_handshake_failures_total[reason as usize].load(Ordering::Relaxed) as f64);
+        }
+        emit_counter(
+            sink,
+            "tls_early_data_rejected_total",
+            "TLS connections closed because the ClientHello offered 0-RTT early data (reject_early_data)",
+            self.tls_early_data_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "tls_renegotiation_rejected_total",
+            "TLS connections closed after a renegotiation attempt",
+            self.tls_renegotiation_rejected_total.load(Ordering::Relaxed),
+        );
+        emit_counter(
+            sink,
+            "plaintext_on_tls_listener_total",
+            "Plaintext connections closed on the TLS listener (require_tls)",
+            self.plaintext_on_tls_listener_total.load(Ordering::Relaxed),
+        );
+        sink.family("tls_connections_total", "Completed TLS handshakes by negotiated version and cipher suite", MetricKind::Counter);
+        for ((version, cipher), count) in self.tls_connections_total.lock().unwrap().iter() {
+            sink.sample("tls_connections_total", &[("version", version), ("cipher", cipher)], *count as f64);
+        }
+        sink.family("websocket_connections_total", "Connections upgraded to MQTT over WebSocket by transport", MetricKind::Counter);
+        for (transport, count) in ["ws", "wss"].iter().zip(&self.websocket_connections_total) {
+            sink.sample("websocket_connections_total", &[("transport", transport)], count.load(Ordering::Relaxed) as f64);
+        }
+        sink.family("bad_handshake_total", "Connections dropped before a complete CONNECT was received", MetricKind::Counter);
+        for reason in BadHandshake::ALL {
+            sink.sample("bad_handshake_total", &[("reason", reason.as_str())], self.bad_handshake_total[reason as usize].load(Ordering::Relaxed) as f64);
+        }
+        sink.family("version_not_allowed_total", "CONNECTs rejected because the MQTT version is not in allowed_versions", MetricKind::Counter);
+        for version in MqttVersion::ALL {
+            sink.sample("version_not_allowed_total", &[("version", version.as_str())], self.version_not_allowed_total[version as usize].load(Ordering::Relaxed) as f64);
+        }
+        sink.family("protocol_rejections_total", "CONNECTs rejected for an unknown protocol name or level", MetricKind::Counter);
+        for ((name, level), count) in self.protocol_rejections_total.lock().unwrap().iter() {
+            sink.sample("protocol_rejections_total", &[("name", name), ("level", &level.to_string())], *count as f64);
+        }
+        sink.family("packet_firewall_denied_total", "Client packets denied by the packet firewall by packet type", MetricKind::Counter);
+        for (packet_type, name) in PACKET_TYPE_NAMES.iter().enumerate().skip(1) {
+            sink.sample("packet_firewall_denied_total", &[("packet_type", name)], self.packet_firewall_denied_total[packet_type].load(Ordering::Relaxed) as f64);
+        }
+        sink.family("admission_rejected_total", "Connections rejected by the per-tenant admission queue", MetricKind::Counter);
+        for reason in AdmissionRejection::ALL {
+            sink.sample("admission_rejected_total", &[("reason", reason.as_str())], self.admission_rejected_total[reason as usize].load(Ordering::Relaxed) as f64);
+        }
+        sink.family("admission_queue_depth", "Connections waiting in the admission queue per tenant", MetricKind::Gauge);
+        for (tenant, depth) in self.admission_queue_depth.lock().unwrap().iter() {
+            sink.sample("admission_queue_depth", &[("tenant", tenant)], *depth as f64);
+        }
+        emit_histogram(
+            sink,
+            "mqtt_connack_latency_seconds",
+            "Time from forwarding CONNECT to the first byte from the backend",
+            &self.connack_latency,
+        );
+    }
+}
+
+/// 指标类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// 累计值, 只增不减
+    Counter,
+    /// 当前值
+    Gauge,
+    /// 直方图: `_bucket` 样本 (带 `le` 标签) 以及累计的 `_sum` 和 `_count`
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// 指标输出目标
+/// `Metrics::emit` 对每个指标先调用一次 `family`, 再为它的每个样本调用 `sample`
+pub trait MetricsSink {
+    /// 开始一个指标
+    fn family(&mut self, name: &str, help: &str, kind: MetricKind);
+    /// 当前指标的一个样本, counter 为累计值
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64);
+    /// 带 exemplar 的样本, 不支持 exemplar 的输出目标忽略它
+    fn sample_with_exemplar(&mut self, name: &str, labels: &[(&str, &str)], value: f64, _exemplar: Option<&Exemplar>) {
+        self.sample(name, labels, value);
+    }
+}
+
+/// Prometheus 文本格式
+/// `open_metrics` 时输出 OpenMetrics 文本格式: counter 的 HELP/TYPE 行使用去掉 `_total` 的指标族名, 样本带 exemplar
+#[derive(Default)]
+struct PrometheusSink {
+    out: String,
+    open_metrics: bool,
+}
+
+impl MetricsSink for PrometheusSink {
+    fn family(&mut self, name: &str, help: &str, kind: MetricKind) {
+        let name = match kind {
+            MetricKind::Counter if self.open_metrics => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind.as_str());
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.sample_with_exemplar(name, labels, value, None);
+    }
+
+    fn sample_with_exemplar(&mut self, name: &str, labels: &[(&str, &str)], value: f64, exemplar: Option<&Exemplar>) {
+        let _ = write!(self.out, "{}", name);
+        for (i, (key, label)) in labels.iter().enumerate() {
+            let separator = if i == 0 { '{' } else { ',' };
+            let _ = write!(self.out, "{}{}=\"{}\"", separator, key, escape_label(label));
+        }
+        if !labels.is_empty() {
+            self.out.push('}');
+        }
+        let _ = write!(self.out, " {}", value);
+        if self.open_metrics
+            && let Some(exemplar) = exemplar
+        {
+            let _ = write!(self.out, " # {{connection_id=\"{}\"", exemplar.connection_id);
+            if let Some(trace_id) = &exemplar.trace_id {
+                let _ = write!(self.out, ",trace_id=\"{}\"", trace_id);
+            }
+            let _ = write!(self.out, "}} {} {:.3}", exemplar.value, exemplar.timestamp);
+        }
+        self.out.push('\n');
+    }
+}
+
+/// JSON 对象
+#[derive(Default)]
+struct JsonSink {
+    out: serde_json::Map<String, serde_json::Value>,
+}
+
+impl MetricsSink for JsonSink {
+    fn family(&mut self, _name: &str, _help: &str, _kind: MetricKind) {}
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        if name.ends_with("_bucket") {
+            return;
+        }
+        if labels.is_empty() {
+            self.out.insert(name.to_string(), value.into());
+            return;
+        }
+        let mut sample: serde_json::Map<String, serde_json::Value> = labels
+            .iter()
+            .map(|(key, label)| (key.to_string(), label.to_string().into()))
+            .collect();
+        sample.insert("value".to_string(), value.into());
+        let samples = self.out.entry(name.to_string()).or_insert_with(|| serde_json::Value::Array(Vec::new()));
+        if let serde_json::Value::Array(samples) = samples {
+            samples.push(sample.into());
+        }
+    }
+}
+
+/// 转义 Prometheus 标签值 (租户名来自客户端 ID, 可能包含任意字符)
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn emit_gauge(sink: &mut dyn MetricsSink, name: &str, help: &str, value: i64) {
+    sink.family(name, help, MetricKind::Gauge);
+    sink.sample(name, &[], value as f64);
+}
+
+fn emit_counter(sink: &mut dyn MetricsSink, name: &str, help: &str, value: u64) {
+    sink.family(name, help, MetricKind::Counter);
+    sink.sample(name, &[], value as f64);
+}
+
+fn emit_histogram(sink: &mut dyn MetricsSink, name: &str, help: &str, histogram: &Histogram) {
+    sink.family(name, help, MetricKind::Histogram);
+    let bucket_name = format!("{}_bucket", name);
+    let exemplars = histogram.exemplars.lock().unwrap().clone();
+    for ((bucket, upper), exemplar) in histogram.buckets.iter().zip(LATENCY_BUCKETS).zip(&exemplars) {
+        sink.sample_with_exemplar(&bucket_name, &[("le", &upper.to_string())], bucket.load(Ordering::Relaxed) as f64, exemplar.as_ref());
+    }
+    let count = histogram.count.load(Ordering::Relaxed);
+    sink.sample_with_exemplar(&bucket_name, &[("le", "+Inf")], count as f64, exemplars[LATENCY_BUCKETS.len()].as_ref());
+    sink.sample(&format!("{}_sum", name), &[], histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+    sink.sample(&format!("{}_count", name), &[], count as f64);
+}
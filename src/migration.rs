@@ -0,0 +1,289 @@
+// 后端连接迁移
+// 在客户端不断开的情况下, 把空闲的 clean session 连接切换到另一个后端 broker
+
+use log::{debug, info};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+use crate::codec::{encode_packet, read_remaining_length};
+use crate::events::CloseReason;
+use crate::happy_eyeballs;
+use crate::metrics::metrics;
+use crate::qos_drain::{self, DrainClose};
+use crate::packet_firewall::{FirewallDenial, FirewallVerdict, PacketFirewall};
+use crate::rate_limit::TokenBucket;
+use crate::request_response;
+use crate::runtime::RuntimeState;
+use crate::shadow::ShadowSink;
+use crate::source_bind::SourceBind;
+use crate::tap::{Direction, InflightTracker, PacketTap, packet_type};
+
+/// 连接新后端、重放 CONNECT 和订阅的总时限
+const MIGRATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 正常断开 (DISCONNECT, 剩余长度 0), 3.1.1 和 5.0 通用
+const DISCONNECT: [u8; 2] = [0xE0, 0x00];
+
+/// 管理接口发给转发任务的迁移请求
+pub struct MigrateRequest {
+    /// 新后端地址 (host:port)
+    pub backend: String,
+    /// 迁移结果, 失败时为原因说明
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+/// 可迁移连接的转发上下文
+pub struct MigratableSession {
+    /// 连接 ID (用于更新连接列表)
+    pub connection_id: u64,
+    /// 转发给后端的完整 CONNECT 包 (已升级为 3.1.1 或 5.0)
+    pub connect_packet: Vec<u8>,
+    /// broker 不保留会话 (见 `ConnectPacket::has_transient_session`)
+    pub transient_session: bool,
+    pub runtime: Arc<RuntimeState>,
+    pub control: mpsc::Receiver<MigrateRequest>,
+    /// 启用请求/响应日志时为 (脱敏后的) 客户端 ID
+    pub request_response_log: Option<String>,
+    /// 连接新后端时绑定的本地地址
+    pub source: Option<Arc<SourceBind>>,
+    /// 排空时等待 QoS 交互结束后断开连接的宽限, None = 排空时不断开 (`qos_drain_grace_ms`)
+    pub qos_drain_grace: Option<Duration>,
+}
+
+/// 转发过程中的包级状态
+#[derive(Default)]
+struct StreamState {
+    client_tap: PacketTap,
+    broker_tap: PacketTap,
+    inflight: InflightTracker,
+    /// 客户端发送过的 SUBSCRIBE/UNSUBSCRIBE, 迁移时按顺序重放
+    subscriptions: Vec<Vec<u8>>,
+    /// 有订阅包超出了 tap 保留的前缀, 无法重放
+    subscriptions_truncated: bool,
+    /// 请求/响应日志中的客户端 ID, 未启用时为 None
+    request_response_log: Option<String>,
+}
+
+impl StreamState {
+    fn observe(&mut self, direction: Direction, data: &[u8]) {
+        let tap = match direction {
+            Direction::ClientToBroker => &mut self.client_tap,
+            Direction::BrokerToClient => &mut self.broker_tap,
+        };
+        for packet in tap.feed(data) {
+            self.inflight.observe(direction, &packet);
+            if let Some(client_id) = &self.request_response_log {
+                request_response::log_publish(client_id, direction, &packet);
+            }
+
+            let packet_type = packet.packet_type();
+            if direction == Direction::ClientToBroker
+                && matches!(packet_type, packet_type::SUBSCRIBE | packet_type::UNSUBSCRIBE)
+            {
+                if packet.is_complete() {
+                    self.subscriptions.push(packet.to_bytes());
+                } else {
+                    self.subscriptions_truncated = true;
+                }
+            }
+        }
+    }
+
+    /// 检查当前是否可以安全切换后端
+    fn check_idle(&self, session: &MigratableSession) -> Result<(), String> {
+        if !session.transient_session {
+            return Err("client session is not clean (broker-side state would be lost)".to_string());
+        }
+        if self.client_tap.is_broken() || self.broker_tap.is_broken() {
+            return Err("packet stream could not be parsed".to_string());
+        }
+        if !self.client_tap.at_boundary() || !self.broker_tap.at_boundary() {
+            return Err("a packet is partially transferred".to_string());
+        }
+        if !self.inflight.is_idle() {
+            return Err("QoS > 0 or subscribe exchange in flight".to_string());
+        }
+        if self.subscriptions_truncated {
+            return Err("a SUBSCRIBE packet was too large to record for replay".to_string());
+        }
+        Ok(())
+    }
+
+    /// 没有未完成的交互 (排空时据此断开连接)
+    fn quiesced(&self) -> bool {
+        !self.client_tap.is_broken() && !self.broker_tap.is_broken() && self.inflight.is_idle()
+    }
+}
+
+/// 单任务双向转发, 同时处理迁移请求
+/// 两个方向在同一个任务中轮流处理, 才能在包边界上原子地切换后端
+pub async fn forward_with_migration<S>(
+    mut client_stream: S,
+    mut broker_stream: TcpStream,
+    limiter: Option<Arc<TokenBucket>>,
+    total_limiter: Option<Arc<TokenBucket>>,
+    mut shadow: Option<ShadowSink>,
+    mut firewall: Option<PacketFirewall>,
+    mut session: MigratableSession,
+) -> std::io::Result<CloseReason>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut state = StreamState { request_response_log: session.request_response_log.clone(), ..Default::default() };
+    let mut client_buffer = [0u8; 8192];
+    let mut broker_buffer = [0u8; 8192];
+    let mut drain = session.runtime.drain_signal();
+    // 排空开始后的强制断开时间
+    let mut drain_deadline: Option<Instant> = None;
+
+    loop {
+        if drain_deadline.is_some() && state.quiesced() {
+            qos_drain::record(DrainClose::Quiesced);
+            return Ok(CloseReason::Drained);
+        }
+        tokio::select! {
+            read = client_stream.read(&mut client_buffer) => {
+                let n = read?;
+                if n == 0 {
+                    return Ok(CloseReason::ClientClosed);
+                }
+                // 被丢弃的包也不计入会话状态, 迁移时不会重放
+                let filtered;
+                let data = match firewall.as_mut().map(|firewall| firewall.filter(&client_buffer[..n])) {
+                    None | Some(FirewallVerdict::Forward) => &client_buffer[..n],
+                    Some(FirewallVerdict::Filtered(bytes)) => {
+                        filtered = bytes;
+                        &filtered[..]
+                    }
+                    Some(FirewallVerdict::Close(FirewallDenial::PublishTooLarge(_))) => return Ok(CloseReason::PublishTooLarge),
+                    Some(FirewallVerdict::Close(_)) => return Ok(CloseReason::PacketDenied),
+                };
+                if data.is_empty() {
+                    continue;
+                }
+                if let Some(limiter) = &limiter {
+                    limiter.acquire(data.len()).await;
+                }
+                if let Some(total_limiter) = &total_limiter {
+                    total_limiter.acquire(data.len()).await;
+                }
+                metrics().forwarded_bytes_total.fetch_add(data.len() as u64, Ordering::Relaxed);
+                state.observe(Direction::ClientToBroker, data);
+                broker_stream.write_all(data).await?;
+                if let Some(shadow) = &mut shadow {
+                    shadow.send(data);
+                }
+            }
+            read = broker_stream.read(&mut broker_buffer) => {
+                let n = read?;
+                if n == 0 {
+                    return Ok(CloseReason::BackendClosed);
+                }
+                if let Some(limiter) = &limiter {
+                    limiter.acquire(n).await;
+                }
+                if let Some(total_limiter) = &total_limiter {
+                    total_limiter.acquire(n).await;
+                }
+                metrics().forwarded_bytes_total.fetch_add(n as u64, Ordering::Relaxed);
+                state.observe(Direction::BrokerToClient, &broker_buffer[..n]);
+                client_stream.write_all(&broker_buffer[..n]).await?;
+            }
+            Some(request) = session.control.recv() => {
+                let result = match state.check_idle(&session) {
+                    Ok(()) => {
+                        let connect = tokio::time::timeout(
+                            MIGRATE_TIMEOUT,
+                            connect_backend(&request.backend, &session, &state.subscriptions),
+                        ).await;
+                        match connect {
+                            Ok(Ok((new_stream, to_client))) => {
+                                let mut old_stream = std::mem::replace(&mut broker_stream, new_stream);
+                                let _ = old_stream.write_all(&DISCONNECT).await;
+                                let _ = old_stream.shutdown().await;
+
+                                // 旧后端停在包边界, 新后端的包可以直接接续
+                                state.observe(Direction::BrokerToClient, &to_client);
+                                client_stream.write_all(&to_client).await?;
+
+                                session.runtime.set_connection_backend(session.connection_id, &request.backend);
+                                metrics().backend_migrations_total.fetch_add(1, Ordering::Relaxed);
+                                info!("Connection {} migrated to backend {}", session.connection_id, request.backend);
+                                Ok(())
+                            }
+                            Ok(Err(e)) => Err(e),
+                            Err(_) => Err(format!("timed out after {:?}", MIGRATE_TIMEOUT)),
+                        }
+                    }
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = &result {
+                    info!("Connection {} not migrated to {}: {}", session.connection_id, request.backend, e);
+                }
+                let _ = request.reply.send(result);
+            }
+            true = drain_started(&mut drain), if drain_deadline.is_none() && session.qos_drain_grace.is_some() => {
+                drain_deadline = session.qos_drain_grace.map(|grace| Instant::now() + grace);
+            }
+            _ = tokio::time::sleep_until(drain_deadline.unwrap_or_else(Instant::now)), if drain_deadline.is_some() => {
+                debug!("Connection {} still has QoS exchanges in flight after the drain grace, closing it", session.connection_id);
+                qos_drain::record(DrainClose::Forced);
+                return Ok(CloseReason::Drained);
+            }
+        }
+    }
+}
+
+/// 等待排空开始, 运行时已释放时返回 false
+async fn drain_started(drain: &mut watch::Receiver<bool>) -> bool {
+    drain.wait_for(|draining| *draining).await.is_ok()
+}
+
+/// 连接新后端并重放 CONNECT 和订阅
+/// 返回新连接和重放期间新后端发来的其它包 (如保留消息), 由调用方在切换后转发给客户端
+async fn connect_backend(
+    backend: &str,
+    session: &MigratableSession,
+    subscriptions: &[Vec<u8>],
+) -> Result<(TcpStream, Vec<u8>), String> {
+    let mut stream = happy_eyeballs::connect(backend, session.source.as_ref()).await
+        .map_err(|e| format!("failed to connect to {}: {}", backend, e))?;
+    stream.write_all(&session.connect_packet).await.map_err(|e| e.to_string())?;
+
+    let (header, connack) = read_packet(&mut stream).await.map_err(|e| e.to_string())?;
+    // CONNACK 第 2 字节为返回码 (3.1.1) 或原因码 (5.0), 0 表示成功
+    if header >> 4 != 2 || connack.get(1) != Some(&0) {
+        return Err(format!("backend {} refused CONNECT", backend));
+    }
+
+    for packet in subscriptions {
+        stream.write_all(packet).await.map_err(|e| e.to_string())?;
+    }
+    let mut pending_acks = subscriptions.len();
+    let mut to_client = Vec::new();
+    while pending_acks > 0 {
+        let (header, body) = read_packet(&mut stream).await.map_err(|e| e.to_string())?;
+        match header >> 4 {
+            packet_type::SUBACK | packet_type::UNSUBACK => pending_acks -= 1,
+            _ => to_client.extend_from_slice(&encode_packet(header, &body)),
+        }
+    }
+
+    debug!("Replayed CONNECT and {} subscription packets to {}", subscriptions.len(), backend);
+    Ok((stream, to_client))
+}
+
+/// 读取一个完整的包, 返回固定头首字节和剩余部分
+async fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await?;
+    let length = read_remaining_length(stream).await?;
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body).await?;
+    Ok((header, body))
+}
@@ -5,14 +5,19 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use log::{info, warn, debug};
 
-/// 启动 MQTT 3.1.0 适配器监听器
+use crate::accept_backoff::{AcceptBackoff, accept_with_backoff};
+use crate::packet_firewall::PACKET_TYPE_NAMES;
+
+/// 启动 MQTT 3.1.0 适配器监听器 ([adapters] 中 type = "legacy" 的实例)
 /// 将 MQTT 3.1.0 协议升级为 3.1.1 后转发到主 broker
-pub async fn start_mqtt31_adapter(listen_port: u16, forward_port: u16) -> std::io::Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", listen_port)).await?;
-    info!("MQTT 3.1.0 adapter listening on 0.0.0.0:{} (forwards to 127.0.0.1:{})", listen_port, forward_port);
+pub async fn start_mqtt31_adapter(listen: &str, forward_port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    info!("MQTT 3.1.0 adapter listening on {} (forwards to 127.0.0.1:{})", listener.local_addr()?, forward_port);
     
+    // accept 失败 (如文件描述符耗尽) 不结束监听器, 退避后重试
+    let mut backoff = AcceptBackoff::default();
     loop {
-        let (client_stream, client_addr) = listener.accept().await?;
+        let (client_stream, client_addr) = accept_with_backoff(&mut backoff, || listener.accept()).await;
         debug!("MQTT 3.1.0 adapter: New connection from {}", client_addr);
         
         let forward_addr = format!("127.0.0.1:{}", forward_port);
@@ -26,10 +31,7 @@ pub async fn start_mqtt31_adapter(listen_port: u16, forward_port: u16) -> std::i
 
 /// 处理单个 MQTT 3.1.0 客户端连接
 async fn handle_mqtt31_client(mut client_stream: TcpStream, forward_addr: String) -> std::io::Result<()> {
-    // 连接到真正的 MQTT broker
-    let mut broker_stream = TcpStream::connect(&forward_addr).await?;
-    
-    // 读取客户端的 CONNECT 包
+    // 读取客户端的 CONNECT 包, 校验通过之后才连接 broker, 非法输入不会占用后端连接
     let mut first_byte = [0u8; 1];
     client_stream.read_exact(&mut first_byte).await?;
     
@@ -37,7 +39,7 @@ async fn handle_mqtt31_client(mut client_stream: TcpStream, forward_addr: String
     if first_byte[0] >> 4 != 1 {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
-            "Expected CONNECT packet"
+            format!("Expected CONNECT packet, got first byte 0x{:02X} (packet type {})", first_byte[0], PACKET_TYPE_NAMES[(first_byte[0] >> 4) as usize])
         ));
     }
     
@@ -60,46 +62,43 @@ async fn handle_mqtt31_client(mut client_stream: TcpStream, forward_addr: String
     // MQTT 3.1.1 的协议名称是 "MQTT" (4 字节)
     let protocol_name_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
     
-    if protocol_name_len == 6 && &payload[2..8] == b"MQIsdp" {
+    let forward_payload = if protocol_name_len == 6 && &payload[2..8] == b"MQIsdp" {
         // 这是 MQTT 3.1.0 客户端!
         info!("Detected MQTT 3.1.0 client, upgrading to 3.1.1");
         
         // 协议版本应该是 3
-        if payload[8] == 3 {
-            // 转换为 MQTT 3.1.1 格式
-            let mut new_payload = Vec::new();
-            
-            // 新的协议名称: "MQTT" (4 字节)
-            new_payload.extend_from_slice(&[0, 4]); // 长度
-            new_payload.extend_from_slice(b"MQTT"); // 协议名
-            new_payload.push(4); // MQTT 3.1.1 的协议级别是 4
-            
-            // 复制剩余的字段 (从连接标志开始)
-            new_payload.extend_from_slice(&payload[9..]);
-            
-            // 重新计算剩余长度
-            let new_remaining_length = new_payload.len();
-            
-            // 发送转换后的 CONNECT 包到 broker
-            broker_stream.write_u8(first_byte[0]).await?;
-            write_remaining_length(&mut broker_stream, new_remaining_length).await?;
-            broker_stream.write_all(&new_payload).await?;
-            broker_stream.flush().await?;
-            
-            debug!("Upgraded MQTT 3.1.0 CONNECT to 3.1.1");
-        } else {
+        if payload[8] != 3 {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 format!("Unknown MQIsdp version: {}", payload[8])
             ));
         }
+        
+        // 转换为 MQTT 3.1.1 格式
+        let mut new_payload = Vec::new();
+        
+        // 新的协议名称: "MQTT" (4 字节)
+        new_payload.extend_from_slice(&[0, 4]); // 长度
+        new_payload.extend_from_slice(b"MQTT"); // 协议名
+        new_payload.push(4); // MQTT 3.1.1 的协议级别是 4
+        
+        // 复制剩余的字段 (从连接标志开始)
+        new_payload.extend_from_slice(&payload[9..]);
+        debug!("Upgraded MQTT 3.1.0 CONNECT to 3.1.1");
+        new_payload
     } else {
         // 不是 MQTT 3.1.0,直接转发原始数据
-        broker_stream.write_u8(first_byte[0]).await?;
-        write_remaining_length(&mut broker_stream, remaining_length).await?;
-        broker_stream.write_all(&payload).await?;
-        broker_stream.flush().await?;
-    }
+        payload
+    };
+    
+    // CONNECT 校验通过, 连接到真正的 MQTT broker
+    let mut broker_stream = TcpStream::connect(&forward_addr).await?;
+    
+    // 发送 (转换后的) CONNECT 包到 broker, 剩余长度按转发的负载重新计算
+    broker_stream.write_u8(first_byte[0]).await?;
+    write_remaining_length(&mut broker_stream, forward_payload.len()).await?;
+    broker_stream.write_all(&forward_payload).await?;
+    broker_stream.flush().await?;
     
     // 双向转发剩余数据
     let (mut client_read, mut client_write) = client_stream.into_split();
@@ -190,3 +189,126 @@ async fn write_remaining_length(stream: &mut TcpStream, mut length: usize) -> st
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::sync::Mutex;
+
+    /// 只记录连接数和收到字节的后端
+    struct MockBackend {
+        address: String,
+        accepted: Arc<AtomicUsize>,
+        received: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl MockBackend {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let accepted = Arc::new(AtomicUsize::new(0));
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let (counter, sink) = (accepted.clone(), received.clone());
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let sink = sink.clone();
+                    tokio::spawn(async move {
+                        let mut buffer = [0u8; 1024];
+                        while let Ok(n) = stream.read(&mut buffer).await {
+                            if n == 0 {
+                                break;
+                            }
+                            sink.lock().await.extend_from_slice(&buffer[..n]);
+                        }
+                    });
+                }
+            });
+            MockBackend { address, accepted, received }
+        }
+
+        fn accepted(&self) -> usize {
+            self.accepted.load(Ordering::SeqCst)
+        }
+
+        async fn wait_for_bytes(&self, len: usize) -> Vec<u8> {
+            tokio::time::timeout(Duration::from_secs(2), async {
+                loop {
+                    let received = self.received.lock().await.clone();
+                    if received.len() >= len {
+                        return received;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("backend did not receive the expected bytes")
+        }
+    }
+
+    /// 建立一对 TCP 连接, 服务端一侧交给 handle_mqtt31_client
+    async fn connect_client(backend: &MockBackend) -> (TcpStream, tokio::task::JoinHandle<std::io::Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let handle = tokio::spawn(handle_mqtt31_client(server, backend.address.clone()));
+        (client, handle)
+    }
+
+    /// MQTT 3.1.0 的 CONNECT 包 (协议名 MQIsdp, 级别 3)
+    fn mqisdp_connect(client_id: &str) -> Vec<u8> {
+        let mut payload = vec![0, 6];
+        payload.extend_from_slice(b"MQIsdp");
+        payload.extend_from_slice(&[3, 0x02, 0, 60]);
+        payload.extend_from_slice(&(client_id.len() as u16).to_be_bytes());
+        payload.extend_from_slice(client_id.as_bytes());
+        let mut packet = vec![0x10, payload.len() as u8];
+        packet.extend_from_slice(&payload);
+        packet
+    }
+
+    #[tokio::test]
+    async fn non_connect_first_packet_opens_no_backend_connection() {
+        let backend = MockBackend::start().await;
+        let (mut client, handle) = connect_client(&backend).await;
+        // 未连接就 PUBLISH
+        client.write_all(&[0x30, 0x05, 0x00, 0x01, b't', b'h', b'i']).await.unwrap();
+        let error = handle.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Expected CONNECT packet, got first byte 0x30 (packet type publish)");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn unknown_mqisdp_version_opens_no_backend_connection() {
+        let backend = MockBackend::start().await;
+        let (mut client, handle) = connect_client(&backend).await;
+        let mut packet = mqisdp_connect("legacy");
+        packet[10] = 9;
+        client.write_all(&packet).await.unwrap();
+        assert!(handle.await.unwrap().unwrap_err().to_string().contains("Unknown MQIsdp version: 9"));
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn mqisdp_connect_is_upgraded_and_forwarded() {
+        let backend = MockBackend::start().await;
+        let (mut client, _handle) = connect_client(&backend).await;
+        client.write_all(&mqisdp_connect("legacy")).await.unwrap();
+        // 升级后的 CONNECT: 协议名 MQTT, 级别 4, 其余字段原样保留, 剩余长度少 2 字节
+        let mut expected = vec![0x10, 18, 0, 4];
+        expected.extend_from_slice(b"MQTT");
+        expected.extend_from_slice(&[4, 0x02, 0, 60, 0, 6]);
+        expected.extend_from_slice(b"legacy");
+        assert_eq!(backend.wait_for_bytes(expected.len()).await, expected);
+        assert_eq!(backend.accepted(), 1);
+
+        // CONNECT 之后的数据原样转发
+        client.write_all(&[0xC0, 0x00]).await.unwrap();
+        assert_eq!(&backend.wait_for_bytes(expected.len() + 2).await[expected.len()..], &[0xC0, 0x00]);
+    }
+}
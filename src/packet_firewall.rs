@@ -0,0 +1,259 @@
+// 包类型过滤 (packet firewall)
+// 按监听器配置允许或拒绝客户端发往 broker 的 MQTT 包类型, 被拒绝的包断开连接或从转发流中丢弃;
+// 同时限制 PUBLISH 的长度, 超过上限时在转发之前断开连接
+
+use log::debug;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use crate::adapter_config::{PacketFirewallAction, PacketFirewallConfig};
+use crate::metrics::metrics;
+use crate::tap::{PacketTap, packet_type};
+
+/// 包类型名称, 下标为固定头中的包类型 (0 为保留值)
+pub const PACKET_TYPE_NAMES: [&str; 16] = [
+    "reserved", "connect", "connack", "publish", "puback", "pubrec", "pubrel", "pubcomp",
+    "subscribe", "suback", "unsubscribe", "unsuback", "pingreq", "pingresp", "disconnect", "auth",
+];
+
+/// 一个监听器的过滤规则, 该监听器上的连接共用
+#[derive(Debug)]
+pub struct FirewallRules {
+    /// 按包类型是否允许
+    allowed: [bool; 16],
+    /// 按 allow 列表配置 (否则为 deny 列表)
+    allow_list: bool,
+    action: PacketFirewallAction,
+    /// PUBLISH 的最大剩余长度
+    max_publish_size: Option<usize>,
+}
+
+impl FirewallRules {
+    /// 包类型过滤和 PUBLISH 长度限制都不配置时返回 None (不过滤); allow 和 deny 同时配置或包类型名称无法识别时报错
+    pub fn from_config(config: Option<&PacketFirewallConfig>, max_publish_size: Option<usize>) -> std::io::Result<Option<Arc<Self>>> {
+        if config.is_none() && max_publish_size.is_none() {
+            return Ok(None);
+        }
+        let default_config = PacketFirewallConfig::default();
+        let config = config.unwrap_or(&default_config);
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        let (names, listed) = match (config.allow.is_empty(), config.deny.is_empty()) {
+            (false, false) => return Err(invalid("packet_firewall: configure either allow or deny, not both".to_string())),
+            (false, true) => (&config.allow, true),
+            (true, _) => (&config.deny, false),
+        };
+
+        let mut allowed = [!listed; 16];
+        for name in names {
+            let packet_type = PACKET_TYPE_NAMES[1..]
+                .iter()
+                .position(|known| name.eq_ignore_ascii_case(known))
+                .ok_or_else(|| invalid(format!("packet_firewall: unknown packet type {:?}", name)))?;
+            allowed[packet_type + 1] = listed;
+        }
+        Ok(Some(Arc::new(FirewallRules { allowed, allow_list: listed, action: config.action, max_publish_size })))
+    }
+
+    /// 启动日志中的描述: 配置的列表、处理方式和 PUBLISH 长度上限
+    pub fn describe(&self) -> String {
+        let listed: Vec<&str> = (1..16)
+            .filter(|&packet_type| self.allowed[packet_type] == self.allow_list)
+            .map(|packet_type| PACKET_TYPE_NAMES[packet_type])
+            .collect();
+        let action = match self.action {
+            PacketFirewallAction::Close => "close",
+            PacketFirewallAction::Discard => "discard",
+        };
+        let mut description = match (self.allow_list, listed.is_empty()) {
+            (true, _) => format!("allows only {} ({} on denied packets)", listed.join(", "), action),
+            (false, false) => format!("denies {} ({} on denied packets)", listed.join(", "), action),
+            (false, true) => "all packet types allowed".to_string(),
+        };
+        if let Some(max) = self.max_publish_size {
+            description.push_str(&format!(", PUBLISH at most {} bytes", max));
+        }
+        description
+    }
+}
+
+/// 过滤一块数据的结果
+#[derive(Debug)]
+pub enum FirewallVerdict {
+    /// 原样转发
+    Forward,
+    /// 转发去掉被丢弃的包之后的字节 (可能为空)
+    Filtered(Vec<u8>),
+    /// 断开连接, 该块数据不转发
+    Close(FirewallDenial),
+}
+
+/// 断开连接的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallDenial {
+    /// 被拒绝的包类型
+    PacketType(u8),
+    /// PUBLISH 声明的剩余长度超过 max_publish_size
+    PublishTooLarge(usize),
+    /// 流无法解析
+    Malformed,
+}
+
+/// 单个连接上客户端发往 broker 方向的过滤
+#[derive(Debug)]
+pub struct PacketFirewall {
+    rules: Arc<FirewallRules>,
+    tap: PacketTap,
+}
+
+impl PacketFirewall {
+    pub fn new(rules: Arc<FirewallRules>) -> Self {
+        PacketFirewall { rules, tap: PacketTap::default() }
+    }
+
+    /// 在转发之前过滤一块数据; 包的类型在固定头首字节即可确定, 不需要等待包结束
+    /// PUBLISH 的剩余长度字段读完即检查长度, 跨越多块数据的包在长度字段所在的块被拒绝; 无法解析的流无法保证过滤, 断开连接
+    pub fn filter(&mut self, data: &[u8]) -> FirewallVerdict {
+        let spans = self.tap.feed_spans(data);
+        if self.tap.is_broken() {
+            return FirewallVerdict::Close(FirewallDenial::Malformed);
+        }
+
+        let mut filtered: Option<Vec<u8>> = None;
+        let mut offset = 0;
+        for span in spans {
+            let bytes = &data[offset..offset + span.len];
+            offset += span.len;
+            let packet_type = span.packet_type();
+            if self.rules.allowed[packet_type as usize] {
+                if packet_type == packet_type::PUBLISH
+                    && let (Some(max), Some(declared)) = (self.rules.max_publish_size, span.remaining_length)
+                    && declared > max
+                {
+                    debug!("Packet firewall: PUBLISH of {} bytes exceeds max_publish_size {}", declared, max);
+                    metrics().publish_too_large_total.fetch_add(1, Ordering::Relaxed);
+                    return FirewallVerdict::Close(FirewallDenial::PublishTooLarge(declared));
+                }
+                if let Some(filtered) = &mut filtered {
+                    filtered.extend_from_slice(bytes);
+                }
+                continue;
+            }
+
+            if span.starts_packet {
+                metrics().record_packet_firewall_denied(packet_type);
+            }
+            if self.rules.action == PacketFirewallAction::Close {
+                return FirewallVerdict::Close(FirewallDenial::PacketType(packet_type));
+            }
+            if span.starts_packet {
+                debug!("Packet firewall: discarding {} packet", PACKET_TYPE_NAMES[packet_type as usize]);
+            }
+            // 第一个被丢弃的包: 之前的字节原样保留
+            filtered.get_or_insert_with(|| data[..offset - span.len].to_vec());
+        }
+
+        match filtered {
+            Some(filtered) => FirewallVerdict::Filtered(filtered),
+            None => FirewallVerdict::Forward,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode_packet;
+
+    fn rules(allow: &[&str], deny: &[&str], action: PacketFirewallAction) -> Arc<FirewallRules> {
+        let config = PacketFirewallConfig {
+            allow: allow.iter().map(|name| name.to_string()).collect(),
+            deny: deny.iter().map(|name| name.to_string()).collect(),
+            action,
+        };
+        FirewallRules::from_config(Some(&config), None).unwrap().unwrap()
+    }
+
+    /// 各包类型的最小包 (固定头的标志位按规范设置)
+    fn packet_of_type(packet_type: u8) -> Vec<u8> {
+        let flags = match packet_type {
+            packet_type::PUBREL | packet_type::SUBSCRIBE | packet_type::UNSUBSCRIBE => 0x02,
+            _ => 0x00,
+        };
+        vec![packet_type << 4 | flags, 0x00]
+    }
+
+    fn subscribe(topic_filter: &str) -> Vec<u8> {
+        let mut body = vec![0x00, 0x01, 0x00, topic_filter.len() as u8];
+        body.extend_from_slice(topic_filter.as_bytes());
+        body.push(0x00);
+        encode_packet(0x82, &body)
+    }
+
+    fn publish(topic: &str) -> Vec<u8> {
+        let mut body = vec![0x00, topic.len() as u8];
+        body.extend_from_slice(topic.as_bytes());
+        body.extend_from_slice(b"payload");
+        encode_packet(0x30, &body)
+    }
+
+    #[test]
+    fn each_packet_type_follows_the_allow_and_deny_lists() {
+        for listed in 1..16u8 {
+            let name = PACKET_TYPE_NAMES[listed as usize];
+            let allow = rules(&[name], &[], PacketFirewallAction::Close);
+            let deny = rules(&[], &[name.to_uppercase().as_str()], PacketFirewallAction::Close);
+            for packet_type in 1..16u8 {
+                let packet = packet_of_type(packet_type);
+                let allowed = PacketFirewall::new(allow.clone()).filter(&packet);
+                let denied = PacketFirewall::new(deny.clone()).filter(&packet);
+                if packet_type == listed {
+                    assert!(matches!(allowed, FirewallVerdict::Forward), "allow {}: {} -> {:?}", name, packet_type, allowed);
+                    assert!(matches!(denied, FirewallVerdict::Close(FirewallDenial::PacketType(t)) if t == listed), "deny {}: {:?}", name, denied);
+                } else {
+                    assert!(matches!(allowed, FirewallVerdict::Close(FirewallDenial::PacketType(t)) if t == packet_type), "allow {}: {} -> {:?}", name, packet_type, allowed);
+                    assert!(matches!(denied, FirewallVerdict::Forward), "deny {}: {} -> {:?}", name, packet_type, denied);
+                }
+            }
+        }
+    }
+
+    /// 规则只按包类型判断: 被拒绝的 SUBSCRIBE 不论主题过滤器都被拒绝, 允许的 PUBLISH 不论主题都转发
+    #[test]
+    fn decisions_ignore_topics_and_topic_filters() {
+        let rules = rules(&[], &["subscribe"], PacketFirewallAction::Close);
+        for topic_filter in ["sensors/temp", "sensors/+/temp", "#", "$SYS/#"] {
+            let verdict = PacketFirewall::new(rules.clone()).filter(&subscribe(topic_filter));
+            assert!(matches!(verdict, FirewallVerdict::Close(FirewallDenial::PacketType(packet_type::SUBSCRIBE))), "{}: {:?}", topic_filter, verdict);
+        }
+        for topic in ["sensors/temp", "$SYS/broker", "a"] {
+            let verdict = PacketFirewall::new(rules.clone()).filter(&publish(topic));
+            assert!(matches!(verdict, FirewallVerdict::Forward), "{}: {:?}", topic, verdict);
+        }
+    }
+
+    #[test]
+    fn discard_removes_only_the_denied_packets() {
+        let mut firewall = PacketFirewall::new(rules(&[], &["subscribe"], PacketFirewallAction::Discard));
+        let chunk = [publish("a"), subscribe("b/#"), packet_of_type(packet_type::PINGREQ)].concat();
+        match firewall.filter(&chunk) {
+            FirewallVerdict::Filtered(bytes) => assert_eq!(bytes, [publish("a"), packet_of_type(packet_type::PINGREQ)].concat()),
+            verdict => panic!("{:?}", verdict),
+        }
+        // 跨越两块数据的 SUBSCRIBE: 两部分都被丢弃
+        let split = subscribe("c/#");
+        assert!(matches!(firewall.filter(&split[..3]), FirewallVerdict::Filtered(bytes) if bytes.is_empty()));
+        match firewall.filter(&[&split[3..], &publish("d")[..]].concat()) {
+            FirewallVerdict::Filtered(bytes) => assert_eq!(bytes, publish("d")),
+            verdict => panic!("{:?}", verdict),
+        }
+    }
+
+    #[test]
+    fn invalid_lists_are_config_errors() {
+        let both = PacketFirewallConfig { allow: vec!["publish".to_string()], deny: vec!["subscribe".to_string()], ..PacketFirewallConfig::default() };
+        assert!(FirewallRules::from_config(Some(&both), None).is_err());
+        let unknown = PacketFirewallConfig { deny: vec!["subscribes".to_string()], ..PacketFirewallConfig::default() };
+        assert!(FirewallRules::from_config(Some(&unknown), None).is_err());
+        assert!(FirewallRules::from_config(None, None).unwrap().is_none());
+    }
+}
@@ -0,0 +1,133 @@
+// 单连接包序列记录
+// 启用 `packet_trace_max_packets` 后每个连接的两个方向只跟踪包边界 (不复制包体);
+// 通过管理接口对某一个连接开启记录后, 才保存该连接转发的包类型、长度和时间, 记满后自动停止
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::events::now_ms;
+use crate::packet_firewall::PACKET_TYPE_NAMES;
+use crate::tap::{Direction, PacketTap, TappedPacket};
+
+/// 一个连接的记录, 两个方向和管理接口共享
+#[derive(Default)]
+pub struct PacketTrace {
+    /// 正在记录; 只在持有 `state` 的锁时修改, 未记录时转发路径不加锁
+    armed: AtomicBool,
+    state: Mutex<TraceState>,
+}
+
+#[derive(Default)]
+struct TraceState {
+    limit: usize,
+    armed_at: Option<Instant>,
+    packets: Vec<TracedPacket>,
+}
+
+/// 记录的一个包
+#[derive(Debug, Clone, Serialize)]
+pub struct TracedPacket {
+    /// client_to_broker / broker_to_client
+    pub direction: &'static str,
+    #[serde(rename = "type")]
+    pub packet_type: &'static str,
+    /// 整个包的字节数 (含固定头)
+    pub size: usize,
+    /// 包的最后一个字节转发完成时距开始记录的时间 (毫秒)
+    pub elapsed_ms: f64,
+    /// 同一时刻的 Unix 时间 (毫秒)
+    pub timestamp_ms: u64,
+}
+
+/// 记录的当前状态 (GET /connections/{id}/trace)
+#[derive(Debug, Clone)]
+pub struct TraceSnapshot {
+    /// 仍在记录 (未记满也未停止)
+    pub armed: bool,
+    pub limit: usize,
+    pub packets: Vec<TracedPacket>,
+}
+
+impl PacketTrace {
+    /// 开始记录最多 `limit` 个包, 丢弃上一次的记录
+    pub fn arm(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        *state = TraceState { limit, armed_at: Some(Instant::now()), packets: Vec::with_capacity(limit.min(1024)) };
+        self.armed.store(true, Ordering::Relaxed);
+    }
+
+    /// 停止记录, 返回已记录的包
+    pub fn disarm(&self) -> TraceSnapshot {
+        let mut state = self.state.lock().unwrap();
+        self.armed.store(false, Ordering::Relaxed);
+        TraceSnapshot { armed: false, limit: state.limit, packets: std::mem::take(&mut state.packets) }
+    }
+
+    pub fn snapshot(&self) -> TraceSnapshot {
+        let state = self.state.lock().unwrap();
+        TraceSnapshot { armed: self.armed.load(Ordering::Relaxed), limit: state.limit, packets: state.packets.clone() }
+    }
+
+    /// 一个方向的包边界跟踪, 两个方向都需要
+    pub fn tap(self: &Arc<Self>, direction: Direction) -> TraceTap {
+        TraceTap { tap: PacketTap::headers_only(), direction, trace: self.clone() }
+    }
+
+    fn record(&self, direction: Direction, packets: &[TappedPacket]) {
+        let mut state = self.state.lock().unwrap();
+        // 加锁之后再检查一次: 期间可能已停止或记满
+        if !self.armed.load(Ordering::Relaxed) {
+            return;
+        }
+        let elapsed_ms = state.armed_at.map_or(0.0, |armed_at| armed_at.elapsed().as_micros() as f64 / 1000.0);
+        let timestamp_ms = now_ms();
+        for packet in packets {
+            if state.packets.len() >= state.limit {
+                break;
+            }
+            state.packets.push(TracedPacket {
+                direction: match direction {
+                    Direction::ClientToBroker => "client_to_broker",
+                    Direction::BrokerToClient => "broker_to_client",
+                },
+                packet_type: PACKET_TYPE_NAMES[packet.packet_type() as usize],
+                size: packet_size(packet.remaining_length),
+                elapsed_ms,
+                timestamp_ms,
+            });
+        }
+        if state.packets.len() >= state.limit {
+            self.armed.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 固定头 (首字节 + 剩余长度字段) 加剩余长度
+fn packet_size(remaining_length: usize) -> usize {
+    let length_bytes = match remaining_length {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    };
+    1 + length_bytes + remaining_length
+}
+
+/// 单方向的包边界跟踪, 连接被选中记录时把结束的包记入共享的记录
+pub struct TraceTap {
+    tap: PacketTap,
+    direction: Direction,
+    trace: Arc<PacketTrace>,
+}
+
+impl TraceTap {
+    /// 在数据转发之后调用; 未记录时也要喂入, 保持包边界同步
+    pub fn feed(&mut self, data: &[u8]) {
+        let packets = self.tap.feed(data);
+        if !packets.is_empty() && self.trace.armed.load(Ordering::Relaxed) {
+            self.trace.record(self.direction, &packets);
+        }
+    }
+}
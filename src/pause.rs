@@ -0,0 +1,87 @@
+// 单个连接的转发暂停
+// 管理接口暂停连接后两个方向的转发循环都停止读写 (连接保持打开, 数据留在内核缓冲区中), 恢复或到期后继续
+
+use log::debug;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// 转发状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseState {
+    Running,
+    /// 暂停到 `until` 自动恢复, None 表示直到手动恢复
+    Paused { until: Option<Instant> },
+}
+
+impl PauseState {
+    /// 按 `max_pause` 计算的暂停状态, 零表示不限制
+    pub fn paused_for(max_pause: Duration) -> Self {
+        PauseState::Paused { until: (!max_pause.is_zero()).then(|| Instant::now() + max_pause) }
+    }
+
+    /// 当前是否处于暂停中 (已到期的暂停视为已恢复)
+    pub fn is_paused(self) -> bool {
+        match self {
+            PauseState::Running => false,
+            PauseState::Paused { until } => until.is_none_or(|until| Instant::now() < until),
+        }
+    }
+}
+
+/// 管理接口一侧, 登记在连接列表中
+pub type PauseControl = watch::Sender<PauseState>;
+
+/// 转发循环一侧
+#[derive(Clone)]
+pub struct PauseGate {
+    state: watch::Receiver<PauseState>,
+}
+
+impl PauseGate {
+    /// 创建暂停控制和供两个方向使用的等待端
+    pub fn channel() -> (PauseControl, PauseGate) {
+        let (control, state) = watch::channel(PauseState::Running);
+        (control, PauseGate { state })
+    }
+
+    /// 暂停期间等待, 恢复、到期或连接已注销时返回
+    pub async fn wait_resumed(&mut self) {
+        loop {
+            let current = *self.state.borrow_and_update();
+            let PauseState::Paused { until } = current else {
+                return;
+            };
+            if !current.is_paused() {
+                return;
+            }
+            let expired = async {
+                match until {
+                    Some(until) => tokio::time::sleep_until(until).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = expired => {
+                    debug!("Forwarding pause expired, resuming");
+                    return;
+                }
+                changed = self.state.changed() => if changed.is_err() {
+                    return;
+                },
+            }
+        }
+    }
+
+    /// 当前是否处于暂停中
+    pub fn is_paused(&self) -> bool {
+        self.state.borrow().is_paused()
+    }
+
+    /// 暂停状态被修改时返回, 用于打断正在等待的读
+    pub async fn changed(&mut self) {
+        if self.state.changed().await.is_err() {
+            std::future::pending().await
+        }
+    }
+}
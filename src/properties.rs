@@ -0,0 +1,139 @@
+// MQTT 5.0 属性解析与编码
+// 属性块格式: 变长整数长度 + 若干 (标识符, 值)
+
+use crate::codec::{
+    decode_variable_int, encode_variable_int, read_binary, read_string, read_u16, read_u32,
+    truncated, write_binary,
+};
+
+/// 响应主题 (Response Topic) 标识符
+pub const RESPONSE_TOPIC: u8 = 0x08;
+/// 对比数据 (Correlation Data) 标识符
+pub const CORRELATION_DATA: u8 = 0x09;
+/// 会话过期间隔 (Session Expiry Interval) 标识符
+pub const SESSION_EXPIRY_INTERVAL: u8 = 0x11;
+/// 遗嘱延迟间隔 (Will Delay Interval) 标识符, 只出现在遗嘱属性中
+pub const WILL_DELAY_INTERVAL: u8 = 0x18;
+/// 服务端保活时间 (Server Keep Alive) 标识符
+pub const SERVER_KEEP_ALIVE: u8 = 0x13;
+/// 请求响应信息 (Request Response Information) 标识符
+pub const REQUEST_RESPONSE_INFORMATION: u8 = 0x19;
+/// 最大 QoS (Maximum QoS) 标识符
+pub const MAXIMUM_QOS: u8 = 0x24;
+/// 保留消息可用 (Retain Available) 标识符
+pub const RETAIN_AVAILABLE: u8 = 0x25;
+/// 最大报文长度 (Maximum Packet Size) 标识符
+pub const MAXIMUM_PACKET_SIZE: u8 = 0x27;
+/// 用户属性 (User Property) 标识符
+pub const USER_PROPERTY: u8 = 0x26;
+
+/// 属性值
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Byte(u8),
+    U16(u16),
+    U32(u32),
+    VarInt(usize),
+    String(String),
+    Binary(Vec<u8>),
+    Pair(String, String),
+}
+
+/// 有序属性列表 (保留原始顺序, 重新编码时不改变其它属性)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Properties(pub Vec<(u8, PropertyValue)>);
+
+impl Properties {
+    /// 从 `buf[*pos..]` 解析属性块 (含长度前缀), 并前移 `pos`
+    pub fn decode(buf: &[u8], pos: &mut usize) -> std::io::Result<Properties> {
+        let (len, len_bytes) = decode_variable_int(buf.get(*pos..).ok_or_else(truncated)?)?;
+        *pos += len_bytes;
+        let end = *pos + len;
+        let block = buf.get(..end).ok_or_else(truncated)?;
+
+        let mut properties = Vec::new();
+        while *pos < end {
+            let id = block[*pos];
+            *pos += 1;
+            let value = match id {
+                0x01 | 0x17 | 0x19 | 0x24 | 0x25 | 0x28 | 0x29 | 0x2A => {
+                    let byte = *block.get(*pos).ok_or_else(truncated)?;
+                    *pos += 1;
+                    PropertyValue::Byte(byte)
+                }
+                0x13 | 0x21 | 0x22 | 0x23 => PropertyValue::U16(read_u16(block, pos)?),
+                0x02 | 0x11 | 0x18 | 0x27 => PropertyValue::U32(read_u32(block, pos)?),
+                0x0B => {
+                    let (value, n) = decode_variable_int(block.get(*pos..).ok_or_else(truncated)?)?;
+                    *pos += n;
+                    PropertyValue::VarInt(value)
+                }
+                0x03 | 0x08 | 0x12 | 0x15 | 0x1A | 0x1C | 0x1F => {
+                    PropertyValue::String(read_string(block, pos)?)
+                }
+                0x09 | 0x16 => PropertyValue::Binary(read_binary(block, pos)?.to_vec()),
+                USER_PROPERTY => {
+                    let key = read_string(block, pos)?;
+                    let value = read_string(block, pos)?;
+                    PropertyValue::Pair(key, value)
+                }
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Unknown property identifier: 0x{:02X}", id)
+                    ));
+                }
+            };
+            properties.push((id, value));
+        }
+
+        if *pos != end {
+            return Err(truncated());
+        }
+
+        Ok(Properties(properties))
+    }
+
+    /// 编码属性块 (含长度前缀)
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut block = Vec::new();
+        for (id, value) in &self.0 {
+            block.push(*id);
+            match value {
+                PropertyValue::Byte(byte) => block.push(*byte),
+                PropertyValue::U16(v) => block.extend_from_slice(&v.to_be_bytes()),
+                PropertyValue::U32(v) => block.extend_from_slice(&v.to_be_bytes()),
+                PropertyValue::VarInt(v) => encode_variable_int(*v, &mut block),
+                PropertyValue::String(s) => write_binary(s.as_bytes(), &mut block),
+                PropertyValue::Binary(b) => write_binary(b, &mut block),
+                PropertyValue::Pair(k, v) => {
+                    write_binary(k.as_bytes(), &mut block);
+                    write_binary(v.as_bytes(), &mut block);
+                }
+            }
+        }
+        encode_variable_int(block.len(), out);
+        out.extend_from_slice(&block);
+    }
+
+    /// 查找第一个指定标识符的属性
+    pub fn get(&self, id: u8) -> Option<&PropertyValue> {
+        self.0.iter().find(|(pid, _)| *pid == id).map(|(_, value)| value)
+    }
+
+    /// 设置属性: 已存在则替换第一个, 否则追加
+    pub fn set(&mut self, id: u8, value: PropertyValue) {
+        match self.0.iter_mut().find(|(pid, _)| *pid == id) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((id, value)),
+        }
+    }
+
+    /// 所有用户属性 (键, 值)
+    pub fn user_properties(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().filter_map(|(_, value)| match value {
+            PropertyValue::Pair(k, v) => Some((k.as_str(), v.as_str())),
+            _ => None,
+        })
+    }
+}
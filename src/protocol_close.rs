@@ -0,0 +1,123 @@
+// 客户端违规断开时通知 MQTT 5.0 客户端
+// 客户端发往 broker 的方向检测到违规 (重复 CONNECT、PUBLISH 超过 max_publish_size) 后不再转发, 由 broker 发往客户端的方向
+// 在包边界给客户端发送带原因码的 DISCONNECT 再关闭: 重复 CONNECT 为 0x82 (Protocol Error), PUBLISH 过长为 0x95 (Packet too large)
+
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+use crate::tap::{Direction, PacketTap};
+
+/// 适配器断开连接的客户端违规
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolViolation {
+    SecondConnect,
+    PublishTooLarge,
+}
+
+impl ProtocolViolation {
+    fn reason_code(self) -> u8 {
+        match self {
+            ProtocolViolation::SecondConnect => 0x82,
+            ProtocolViolation::PublishTooLarge => 0x95,
+        }
+    }
+}
+
+/// 一个 MQTT 5.0 连接的违规通知, 两个方向共享
+#[derive(Default)]
+pub struct ProtocolClose {
+    violation: Arc<watch::Sender<Option<ProtocolViolation>>>,
+}
+
+impl ProtocolClose {
+    /// 一个方向的通知, 两个方向都需要
+    pub fn tap(&self, direction: Direction) -> ProtocolCloseTap {
+        let boundary = (direction == Direction::BrokerToClient).then(PacketTap::headers_only);
+        ProtocolCloseTap { violation: self.violation.clone(), boundary }
+    }
+}
+
+/// 单方向的违规通知
+pub struct ProtocolCloseTap {
+    violation: Arc<watch::Sender<Option<ProtocolViolation>>>,
+    /// broker 发往客户端的方向: 跟踪包边界, DISCONNECT 只能插在两个包之间
+    boundary: Option<PacketTap>,
+}
+
+impl ProtocolCloseTap {
+    /// 客户端发往 broker 的方向检测到违规时调用
+    pub fn report(&self, violation: ProtocolViolation) {
+        self.violation.send_replace(Some(violation));
+    }
+
+    /// 在数据转发之后调用
+    pub fn feed(&mut self, data: &[u8]) {
+        if let Some(boundary) = &mut self.boundary {
+            boundary.feed(data);
+        }
+    }
+
+    /// 等待另一方向报告违规
+    pub async fn violated(&self) -> ProtocolViolation {
+        let mut violation = self.violation.subscribe();
+        let reported = violation.wait_for(Option::is_some).await.map(|violation| *violation);
+        match reported {
+            Ok(Some(violation)) => violation,
+            // 发送端与该方向共存, 不会先被丢弃
+            _ => std::future::pending().await,
+        }
+    }
+
+    /// 报告违规后结束该方向: 发往客户端的方向正好位于包边界时先发送 DISCONNECT
+    pub async fn close<W: AsyncWrite + Unpin>(&self, writer: &mut W, violation: ProtocolViolation) {
+        if let Some(boundary) = &self.boundary
+            && boundary.at_boundary()
+        {
+            let _ = writer.write_all(&[0xE0, 0x01, violation.reason_code()]).await;
+            let _ = writer.flush().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn report_wakes_the_other_direction_and_sends_disconnect() {
+        for (violation, reason_code) in [(ProtocolViolation::SecondConnect, 0x82), (ProtocolViolation::PublishTooLarge, 0x95)] {
+            let close = ProtocolClose::default();
+            let client = close.tap(Direction::ClientToBroker);
+            let mut broker = close.tap(Direction::BrokerToClient);
+            broker.feed(&[0x20, 0x03, 0x00, 0x00, 0x00]);
+            let waiter = tokio::spawn(async move {
+                let violation = broker.violated().await;
+                let mut written = Vec::new();
+                broker.close(&mut written, violation).await;
+                (violation, written)
+            });
+            client.report(violation);
+            let (reported, written) = tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+            assert_eq!(reported, violation);
+            assert_eq!(written, [0xE0, 0x01, reason_code]);
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnect_is_sent_only_at_a_packet_boundary() {
+        let close = ProtocolClose::default();
+        let mut broker = close.tap(Direction::BrokerToClient);
+        // PUBLISH 只转发了一部分: 插入 DISCONNECT 会破坏流
+        broker.feed(&[0x30, 0x10, 0x00]);
+        let mut written = Vec::new();
+        broker.close(&mut written, ProtocolViolation::SecondConnect).await;
+        assert!(written.is_empty());
+
+        // 客户端发往 broker 的方向不发送 DISCONNECT
+        let client = close.tap(Direction::ClientToBroker);
+        client.close(&mut written, ProtocolViolation::SecondConnect).await;
+        assert!(written.is_empty());
+    }
+}
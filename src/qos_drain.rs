@@ -0,0 +1,117 @@
+// 排空时按 QoS 交互关闭连接
+// 排空开始后, 连接在没有未完成的 QoS 1/2 (和订阅) 交互时立即关闭, 最多等待 `qos_drain_grace_ms`, 之后强制关闭
+
+use log::debug;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+use tokio::time::Instant;
+
+use crate::metrics::metrics;
+use crate::tap::{Direction, InflightTracker, PacketTap};
+
+/// 两个转发方向共享的在途交互状态
+#[derive(Default)]
+struct Inflight {
+    state: Mutex<InflightState>,
+    /// 每处理完一块数据通知一次, 排空时据此重新检查
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct InflightState {
+    tracker: InflightTracker,
+    /// 包流无法解析, 之后无法判断是否空闲
+    broken: bool,
+}
+
+impl Inflight {
+    fn is_idle(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        !state.broken && state.tracker.is_idle()
+    }
+}
+
+/// 单方向的包解析, 把完整的包记入共享的在途状态
+pub struct InflightTap {
+    tap: PacketTap,
+    direction: Direction,
+    inflight: Arc<Inflight>,
+}
+
+impl InflightTap {
+    /// 在数据转发之后调用
+    pub fn feed(&mut self, data: &[u8]) {
+        let packets = self.tap.feed(data);
+        {
+            let mut state = self.inflight.state.lock().unwrap();
+            for packet in &packets {
+                state.tracker.observe(self.direction, packet);
+            }
+            state.broken |= self.tap.is_broken();
+        }
+        self.inflight.changed.notify_waiters();
+    }
+}
+
+/// 一个连接的排空等待
+pub struct QosDrain {
+    signal: watch::Receiver<bool>,
+    grace: Duration,
+    inflight: Arc<Inflight>,
+}
+
+/// 排空关闭的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainClose {
+    /// 在宽限内没有未完成的交互
+    Quiesced,
+    /// 宽限到期时仍有未完成的交互
+    Forced,
+}
+
+impl QosDrain {
+    pub fn new(signal: watch::Receiver<bool>, grace: Duration) -> Self {
+        QosDrain { signal, grace, inflight: Arc::new(Inflight::default()) }
+    }
+
+    /// 一个方向的包解析, 两个方向都需要
+    pub fn tap(&self, direction: Direction) -> InflightTap {
+        InflightTap { tap: PacketTap::default(), direction, inflight: self.inflight.clone() }
+    }
+
+    /// 等待排空开始, 再等待交互结束或宽限到期; 不排空时不返回
+    pub async fn closed(mut self) -> DrainClose {
+        if self.signal.wait_for(|draining| *draining).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        let deadline = Instant::now() + self.grace;
+        let close = loop {
+            // 先登记通知再检查, 检查之后的变化不会错过
+            let changed = self.inflight.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if self.inflight.is_idle() {
+                break DrainClose::Quiesced;
+            }
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep_until(deadline) => {
+                    debug!("QoS exchanges still in flight after the {:?} drain grace, closing the connection", self.grace);
+                    break DrainClose::Forced;
+                }
+            }
+        };
+        record(close);
+        close
+    }
+}
+
+/// 更新排空关闭的计数
+pub fn record(close: DrainClose) {
+    metrics().drain_closed_total.fetch_add(1, Ordering::Relaxed);
+    if close == DrainClose::Forced {
+        metrics().drain_forced_total.fetch_add(1, Ordering::Relaxed);
+    }
+}
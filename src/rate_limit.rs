@@ -0,0 +1,85 @@
+// 令牌桶限速器
+// 用于限制单个连接和所有连接合计的转发带宽
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 令牌桶
+/// 桶容量等于每秒速率 (允许 1 秒的突发)。令牌可以被透支,
+/// 透支部分按速率折算为等待时间,因此大块数据也能按平均速率通过
+/// 每次预留立即扣减,后到的调用方等待更久,多个连接共用一个桶时按到达顺序放行,不会饿死
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// 创建速率为 `rate_per_sec` 字节/秒的令牌桶
+    pub fn new(rate_per_sec: u64) -> Self {
+        let rate = rate_per_sec.max(1) as f64;
+        TokenBucket {
+            rate,
+            capacity: rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// 创建指定速率和突发容量的令牌桶
+    pub fn with_burst(rate_per_sec: u64, burst: u64) -> Self {
+        let bucket = TokenBucket::new(rate_per_sec);
+        let capacity = burst.max(1) as f64;
+        bucket.state.lock().unwrap().tokens = capacity;
+        TokenBucket { capacity, ..bucket }
+    }
+
+    /// 取出 `amount` 个令牌,不足时等待
+    /// 锁只在计算等待时间时持有,不会跨越 await,
+    /// 因此两个转发方向共用同一个桶也不会互相死锁
+    pub async fn acquire(&self, amount: usize) {
+        if let Some(wait) = self.reserve(amount, None)
+            && !wait.is_zero()
+        {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// 预留 `amount` 个令牌, 返回需要等待的时间 (`Duration::ZERO` 表示立即可用)
+    /// 需要等待超过 `max_wait` 时不预留并返回 `None`
+    pub fn try_reserve(&self, amount: usize, max_wait: Duration) -> Option<Duration> {
+        self.reserve(amount, Some(max_wait))
+    }
+
+    /// 预留令牌并返回等待时间; 设置了 `max_wait` 且等待超过它时不预留
+    fn reserve(&self, amount: usize, max_wait: Option<Duration>) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.capacity);
+        state.last_refill = now;
+
+        let remaining = state.tokens - amount as f64;
+        let wait = if remaining < 0.0 {
+            Duration::from_secs_f64(-remaining / self.rate)
+        } else {
+            Duration::ZERO
+        };
+
+        if let Some(max_wait) = max_wait
+            && wait > max_wait
+        {
+            return None;
+        }
+
+        state.tokens = remaining;
+        Some(wait)
+    }
+}
@@ -0,0 +1,79 @@
+// 请求/响应 (RPC-over-MQTT) 诊断日志
+// 启用 log_request_response 时, 在 debug 级别记录 5.0 客户端请求的 Response Information
+// 以及转发的 PUBLISH 中的 Response Topic / Correlation Data, 不改变转发的字节
+
+use log::{debug, trace};
+
+use crate::codec::{read_string, read_u16};
+use crate::properties::{CORRELATION_DATA, Properties, PropertyValue, REQUEST_RESPONSE_INFORMATION, RESPONSE_TOPIC};
+use crate::tap::{Direction, PacketTap, TappedPacket, packet_type};
+
+/// 记录 CONNECT 中的 Request Response Information
+pub fn log_connect(client_id: &str, properties: &Properties) {
+    if properties.get(REQUEST_RESPONSE_INFORMATION) == Some(&PropertyValue::Byte(1)) {
+        debug!("Client {:?} requested Response Information in CONNECT", client_id);
+    }
+}
+
+/// 记录带 Response Topic 的 PUBLISH
+/// 包体超出 tap 保留的前缀导致属性不完整时跳过
+pub fn log_publish(client_id: &str, direction: Direction, packet: &TappedPacket) {
+    if packet.packet_type() != packet_type::PUBLISH {
+        return;
+    }
+    let (topic, properties) = match publish_properties(packet) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            trace!("Skipping PUBLISH properties for client {:?}: {}", client_id, e);
+            return;
+        }
+    };
+    let Some(PropertyValue::String(response_topic)) = properties.get(RESPONSE_TOPIC) else {
+        return;
+    };
+    let correlation_bytes = match properties.get(CORRELATION_DATA) {
+        Some(PropertyValue::Binary(data)) => data.len(),
+        _ => 0,
+    };
+    let action = match direction {
+        Direction::ClientToBroker => "published",
+        Direction::BrokerToClient => "received",
+    };
+    debug!(
+        "Client {:?} {} request on {:?} with response topic {:?} (correlation data: {} bytes)",
+        client_id, action, topic, response_topic, correlation_bytes
+    );
+}
+
+/// 解析 PUBLISH 可变头中的主题名和属性
+fn publish_properties(packet: &TappedPacket) -> std::io::Result<(String, Properties)> {
+    let mut pos = 0;
+    let topic = read_string(&packet.prefix, &mut pos)?;
+    if packet.qos() > 0 {
+        read_u16(&packet.prefix, &mut pos)?;
+    }
+    Ok((topic, Properties::decode(&packet.prefix, &mut pos)?))
+}
+
+/// 单方向转发流的 PUBLISH 日志
+pub struct RequestResponseTap {
+    client_id: String,
+    direction: Direction,
+    tap: PacketTap,
+}
+
+impl RequestResponseTap {
+    pub fn new(client_id: String, direction: Direction) -> Self {
+        RequestResponseTap { client_id, direction, tap: PacketTap::default() }
+    }
+
+    /// 喂入转发的字节
+    pub fn feed(&mut self, data: &[u8]) {
+        if self.tap.is_broken() {
+            return;
+        }
+        for packet in self.tap.feed(data) {
+            log_publish(&self.client_id, self.direction, &packet);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+// broker → 客户端方向的响应改写
+// 目前只改写 MQTT 5.0 的 CONNACK, 把适配器策略合并到 broker 声明的能力中 (只收紧, 不放宽)
+
+use crate::adapter_config::AdapterConfig;
+use crate::codec::truncated;
+use crate::connect_packet::ConnectPacket;
+use crate::properties::{
+    MAXIMUM_PACKET_SIZE, MAXIMUM_QOS, Properties, PropertyValue, RETAIN_AVAILABLE, SERVER_KEEP_ALIVE,
+};
+
+/// CONNACK 改写器
+#[derive(Debug, Clone, Default)]
+pub struct ResponseRewriter {
+    /// 最大保活时间 (秒), 超过时通过 Server Keep Alive 属性下调
+    max_keep_alive: Option<u16>,
+    /// 最大 QoS (0 或 1), 低于 broker 声明的 Maximum QoS 时下调
+    max_qos: Option<u8>,
+    /// 最大报文长度, 小于 broker 声明的 Maximum Packet Size 时下调
+    max_packet_size: Option<u32>,
+    /// 为 false 时声明 Retain Available = 0
+    retain_available: bool,
+}
+
+impl ResponseRewriter {
+    pub fn new(config: &AdapterConfig) -> Self {
+        ResponseRewriter {
+            max_keep_alive: config.max_keepalive_sec,
+            // Maximum QoS 属性只能是 0 或 1, 2 等同于不限制
+            max_qos: config.max_qos.filter(|qos| *qos < 2),
+            max_packet_size: config.max_packet_size,
+            retain_available: config.retain_available,
+        }
+    }
+
+    /// 是否需要拦截该连接的 CONNACK
+    pub fn applies_to(&self, connect: &ConnectPacket) -> bool {
+        connect.protocol_level == 5
+            && (self.max_keep_alive.is_some()
+                || self.max_qos.is_some()
+                || self.max_packet_size.is_some()
+                || !self.retain_available)
+    }
+
+    /// 改写 5.0 CONNACK 的剩余部分 (不含固定头), 返回新的剩余部分
+    /// 失败的 CONNACK (原因码 >= 0x80) 原样返回
+    pub fn rewrite_connack(&self, connect: &ConnectPacket, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        let (flags, reason_code) = match payload {
+            [flags, reason_code, ..] => (*flags, *reason_code),
+            _ => return Err(truncated()),
+        };
+        if reason_code >= 0x80 {
+            return Ok(payload.to_vec());
+        }
+
+        let mut pos = 2;
+        let mut properties = if pos < payload.len() {
+            Properties::decode(payload, &mut pos)?
+        } else {
+            Properties::default()
+        };
+
+        if let Some(max) = self.max_keep_alive {
+            // 生效的保活时间: broker 下发的 Server Keep Alive, 否则为客户端请求值
+            let effective = match properties.get(SERVER_KEEP_ALIVE) {
+                Some(PropertyValue::U16(v)) => *v,
+                _ => connect.keep_alive,
+            };
+            // 0 表示不启用保活, 对上限而言等同于无穷大
+            // 转发前的 CONNECT 已把超过上限的保活时间改为上限, 等于上限时同样下发, 让客户端按上限发送 PINGREQ
+            if effective == 0 || effective >= max {
+                properties.set(SERVER_KEEP_ALIVE, PropertyValue::U16(max));
+            }
+        }
+
+        // 以下属性缺省时分别表示 QoS 2、不限长度、支持保留消息
+        if let Some(max_qos) = self.max_qos {
+            let advertised = match properties.get(MAXIMUM_QOS) {
+                Some(PropertyValue::Byte(v)) => *v,
+                _ => 2,
+            };
+            if max_qos < advertised {
+                properties.set(MAXIMUM_QOS, PropertyValue::Byte(max_qos));
+            }
+        }
+        if let Some(max_packet_size) = self.max_packet_size {
+            let advertised = match properties.get(MAXIMUM_PACKET_SIZE) {
+                Some(PropertyValue::U32(v)) => Some(*v),
+                _ => None,
+            };
+            if advertised.is_none_or(|advertised| max_packet_size < advertised) {
+                properties.set(MAXIMUM_PACKET_SIZE, PropertyValue::U32(max_packet_size));
+            }
+        }
+        if !self.retain_available {
+            properties.set(RETAIN_AVAILABLE, PropertyValue::Byte(0));
+        }
+
+        let mut out = vec![flags, reason_code];
+        properties.encode(&mut out);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_variable_int, encode_packet};
+    use crate::connect_packet::{connect_payload, parse_connect};
+    use crate::properties::USER_PROPERTY;
+
+    fn rewriter(max_keepalive_sec: Option<u16>) -> ResponseRewriter {
+        let config = AdapterConfig { max_keepalive_sec, ..AdapterConfig::default() };
+        ResponseRewriter::new(&config)
+    }
+
+    fn connect(protocol_level: u8, keep_alive: u16) -> ConnectPacket {
+        parse_connect(&connect_payload(protocol_level, keep_alive, "client", None, None)).unwrap()
+    }
+
+    /// 解码改写后的 CONNACK 剩余部分: 标志、原因码和属性, 属性块必须正好用完剩余部分
+    fn decode(payload: &[u8]) -> (u8, u8, Properties) {
+        let mut pos = 2;
+        let properties = Properties::decode(payload, &mut pos).unwrap();
+        assert_eq!(pos, payload.len(), "property length must cover the rest of the CONNACK");
+        (payload[0], payload[1], properties)
+    }
+
+    #[test]
+    fn injects_server_keep_alive_into_empty_connack() {
+        let out = rewriter(Some(60)).rewrite_connack(&connect(5, 300), &[0x00, 0x00, 0x00]).unwrap();
+        let (flags, reason_code, properties) = decode(&out);
+        assert_eq!((flags, reason_code), (0x00, 0x00));
+        assert_eq!(properties.get(SERVER_KEEP_ALIVE), Some(&PropertyValue::U16(60)));
+
+        // 重新编码固定头后剩余长度与改写后的长度一致
+        let packet = encode_packet(0x20, &out);
+        assert_eq!(decode_variable_int(&packet[1..]).unwrap(), (out.len(), 1));
+    }
+
+    #[test]
+    fn keep_alive_zero_is_capped() {
+        let out = rewriter(Some(60)).rewrite_connack(&connect(5, 0), &[0x00, 0x00]).unwrap();
+        assert_eq!(decode(&out).2.get(SERVER_KEEP_ALIVE), Some(&PropertyValue::U16(60)));
+    }
+
+    #[test]
+    fn overrides_larger_broker_value_and_keeps_other_properties() {
+        let mut broker = Properties::default();
+        broker.set(MAXIMUM_QOS, PropertyValue::Byte(1));
+        broker.set(SERVER_KEEP_ALIVE, PropertyValue::U16(120));
+        let mut connack = vec![0x01, 0x00];
+        broker.encode(&mut connack);
+
+        let out = rewriter(Some(60)).rewrite_connack(&connect(5, 30), &connack).unwrap();
+        let (flags, _, properties) = decode(&out);
+        assert_eq!(flags, 0x01, "session present flag is preserved");
+        assert_eq!(
+            properties.0,
+            vec![(MAXIMUM_QOS, PropertyValue::Byte(1)), (SERVER_KEEP_ALIVE, PropertyValue::U16(60))],
+        );
+    }
+
+    #[test]
+    fn merges_broker_properties_with_injected_ones() {
+        let config = AdapterConfig {
+            max_keepalive_sec: Some(60),
+            max_packet_size: Some(1024),
+            retain_available: false,
+            ..AdapterConfig::default()
+        };
+        // broker 的 CONNACK: Maximum Packet Size 65536、Topic Alias Maximum 10、Assigned Client Identifier、User Property
+        let broker: &[u8] = &[
+            0x20, 0x1B, 0x00, 0x00, 0x18,
+            0x27, 0x00, 0x01, 0x00, 0x00,
+            0x22, 0x00, 0x0A,
+            0x12, 0x00, 0x06, b'a', b'u', b't', b'o', b'-', b'1',
+            0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v',
+        ];
+        let out = ResponseRewriter::new(&config).rewrite_connack(&connect(5, 300), &broker[2..]).unwrap();
+
+        // Maximum Packet Size 原位下调, 其它 broker 属性原样保留, Server Keep Alive 和 Retain Available 依次追加
+        let expected: &[u8] = &[
+            0x20, 0x20, 0x00, 0x00, 0x1D,
+            0x27, 0x00, 0x00, 0x04, 0x00,
+            0x22, 0x00, 0x0A,
+            0x12, 0x00, 0x06, b'a', b'u', b't', b'o', b'-', b'1',
+            0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v',
+            0x13, 0x00, 0x3C,
+            0x25, 0x00,
+        ];
+        assert_eq!(encode_packet(0x20, &out), expected);
+    }
+
+    #[test]
+    fn injected_properties_can_grow_the_remaining_length_encoding() {
+        let config = AdapterConfig { max_keepalive_sec: Some(60), retain_available: false, ..AdapterConfig::default() };
+        // 属性块 122 字节 (一个 116 字节值的 User Property), 剩余长度 125 用 1 字节编码
+        let mut broker = Properties::default();
+        broker.set(USER_PROPERTY, PropertyValue::Pair("k".to_string(), "v".repeat(116)));
+        let mut connack = vec![0x00, 0x00];
+        broker.encode(&mut connack);
+        assert_eq!(encode_packet(0x20, &connack)[..5], [0x20, 125, 0x00, 0x00, 122]);
+
+        let out = ResponseRewriter::new(&config).rewrite_connack(&connect(5, 300), &connack).unwrap();
+        let packet = encode_packet(0x20, &out);
+        // 追加 5 字节后剩余长度 130 需要 2 字节编码, 属性长度 127 仍为 1 字节
+        assert_eq!(packet[..5], [0x20, 0x82, 0x01, 0x00, 0x00]);
+        assert_eq!(packet[5], 127);
+        assert_eq!(packet.len(), 3 + 130);
+        assert_eq!(packet[packet.len() - 5..], [0x13, 0x00, 0x3C, 0x25, 0x00]);
+    }
+
+    #[test]
+    fn keeps_smaller_broker_value() {
+        let mut broker = Properties::default();
+        broker.set(SERVER_KEEP_ALIVE, PropertyValue::U16(30));
+        let mut connack = vec![0x00, 0x00];
+        broker.encode(&mut connack);
+
+        let out = rewriter(Some(60)).rewrite_connack(&connect(5, 300), &connack).unwrap();
+        assert_eq!(decode(&out).2.get(SERVER_KEEP_ALIVE), Some(&PropertyValue::U16(30)));
+    }
+
+    #[test]
+    fn failed_connack_is_untouched() {
+        let connack = [0x00, 0x87, 0x00];
+        let out = rewriter(Some(60)).rewrite_connack(&connect(5, 300), &connack).unwrap();
+        assert_eq!(out, connack);
+    }
+
+    #[test]
+    fn applies_only_to_v5_with_a_policy() {
+        assert!(rewriter(Some(60)).applies_to(&connect(5, 300)));
+        assert!(!rewriter(Some(60)).applies_to(&connect(4, 300)));
+        assert!(!rewriter(None).applies_to(&connect(5, 300)));
+    }
+}
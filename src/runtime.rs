@@ -0,0 +1,384 @@
+// 运行时状态
+// 适配器和管理接口共享, 可以在运行时通过管理接口修改
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::sync::{broadcast, mpsc, watch};
+
+use crate::adapter_config::AdapterConfig;
+use crate::admission::AdmissionQueue;
+use crate::client_registry::ClientIdRegistry;
+use crate::denylist::ClientIdDenylist;
+use crate::events::{CloseReason, ConnectionEvent, EVENT_CHANNEL_CAPACITY, RECENT_EVENTS_CAPACITY, now_ms};
+use crate::metrics::metrics;
+use crate::migration::MigrateRequest;
+use crate::packet_trace::PacketTrace;
+use crate::pause::{PauseControl, PauseState};
+use crate::self_test::SelfTestStatus;
+use crate::tls::TlsInfo;
+
+/// 运行时可变状态
+pub struct RuntimeState {
+    /// 生效的适配器配置, POST /reload 替换其中可热加载的字段
+    config: ArcSwap<AdapterConfig>,
+    /// 维护模式: 拒绝新连接, 已有连接不受影响
+    maintenance: AtomicBool,
+    /// 启动自检的结果 (`startup_self_test`), 完成之前实例未就绪
+    self_test: Mutex<SelfTestStatus>,
+    /// 排空: 关闭监听器不再 accept, 已有连接继续转发直到关闭 (不可撤销)
+    drain: watch::Sender<bool>,
+    /// 已 accept 且尚未处理完毕的连接 (包括握手中和排队中的连接)
+    open_connections: Arc<AtomicUsize>,
+    /// 已转发到后端的活动连接
+    connections: Mutex<HashMap<u64, ConnectionEntry>>,
+    /// 连接事件广播 (GET /events)
+    events: broadcast::Sender<ConnectionEvent>,
+    /// 最近的连接事件 (GET /recent), 按发布顺序
+    recent_events: Mutex<VecDeque<ConnectionEvent>>,
+    /// 活动连接上限和租户配额 (未配置 [adapter.admission] 时为 None)
+    admission: Option<Arc<AdmissionQueue>>,
+    /// 运行时拒绝的客户端 ID (POST/DELETE /deny/client-id/{id})
+    denylist: ClientIdDenylist,
+    /// 按客户端 ID 记录的状态 (最多 `max_tracked_client_ids` 个 ID)
+    clients: ClientIdRegistry<ClientRecord>,
+}
+
+/// 一个客户端 ID 的状态
+#[derive(Default)]
+struct ClientRecord {
+    /// 最近一个使用该 ID 的活动连接, 用于发现同一 ID 的新连接接管旧连接
+    connection: Option<u64>,
+}
+
+/// 活动连接信息 (GET /connections)
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer: String,
+    pub client_id: String,
+    pub version: &'static str,
+    /// 当前转发的后端地址
+    pub backend: String,
+    /// 是否接受迁移请求
+    pub migratable: bool,
+    /// 转发是否被管理接口暂停
+    pub paused: bool,
+    /// TLS 监听器上的连接协商的版本和密码套件
+    pub tls: Option<TlsInfo>,
+}
+
+struct ConnectionEntry {
+    info: ConnectionInfo,
+    /// 转发任务的迁移请求通道 (未启用迁移时为 None)
+    control: Option<mpsc::Sender<MigrateRequest>>,
+    /// 转发暂停控制 (启用迁移的连接不支持暂停, 为 None)
+    pause: Option<PauseControl>,
+    /// 包序列记录 (未启用 packet_trace_max_packets 或启用迁移的连接为 None)
+    trace: Option<Arc<PacketTrace>>,
+}
+
+/// 已 accept 的连接计数守卫, 连接处理完毕 (离开作用域) 时减一
+pub struct OpenConnection(Arc<AtomicUsize>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 连接注册守卫, 离开作用域时从列表中移除并发布 disconnected 事件
+pub struct ConnectionGuard<'a> {
+    runtime: &'a RuntimeState,
+    id: u64,
+    client_id: String,
+    /// 登记时仍然打开的同一客户端 ID 的连接
+    replaced: Option<u64>,
+    /// 未设置时按出错处理 (处理函数提前返回错误)
+    close_reason: CloseReason,
+}
+
+impl ConnectionGuard<'_> {
+    /// 记录正常结束的原因
+    pub fn set_close_reason(&mut self, reason: CloseReason) {
+        self.close_reason = reason;
+    }
+
+    /// 该连接登记时同一客户端 ID 仍然打开的连接 (broker 会用新连接接管会话)
+    /// 该 ID 的记录被淘汰过时无法发现 (见 `ClientIdRegistry`)
+    pub fn replaced(&self) -> Option<u64> {
+        self.replaced
+    }
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.runtime.connections.lock().unwrap().remove(&self.id);
+        // 该 ID 的最后一个连接关闭后删除记录, 表中只保留有活动连接的 ID;
+        // 已被同一 ID 的新连接接管时记录属于新连接, 保持不变
+        if !self.client_id.is_empty() {
+            self.runtime.clients.remove_if(&self.client_id, |record| record.connection == Some(self.id));
+        }
+        metrics().active_connections.fetch_sub(1, Ordering::Relaxed);
+        self.runtime.publish_event(ConnectionEvent::Disconnected {
+            id: self.id,
+            reason: self.close_reason,
+            timestamp_ms: now_ms(),
+        });
+    }
+}
+
+impl RuntimeState {
+    pub fn new(config: &AdapterConfig) -> std::io::Result<Self> {
+        Ok(RuntimeState {
+            config: ArcSwap::from_pointee(config.clone()),
+            maintenance: AtomicBool::new(config.maintenance),
+            self_test: Mutex::new(if config.startup_self_test { SelfTestStatus::Running } else { SelfTestStatus::Disabled }),
+            drain: watch::channel(false).0,
+            open_connections: Arc::new(AtomicUsize::new(0)),
+            connections: Mutex::new(HashMap::new()),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            recent_events: Mutex::new(VecDeque::with_capacity(RECENT_EVENTS_CAPACITY)),
+            admission: config.admission.as_ref().map(|admission| Arc::new(AdmissionQueue::new(admission))),
+            denylist: ClientIdDenylist::load(config.client_id_denylist_path.as_deref())?,
+            clients: ClientIdRegistry::new(config.max_tracked_client_ids),
+        })
+    }
+
+    /// 生效的适配器配置; 已开始处理的连接继续使用它读取时的配置
+    pub fn config(&self) -> Arc<AdapterConfig> {
+        self.config.load_full()
+    }
+
+    /// 替换生效的适配器配置 (POST /reload)
+    pub fn set_config(&self, config: AdapterConfig) {
+        self.config.store(Arc::new(config));
+    }
+
+    /// 当前记录状态的客户端 ID 数 (不超过 `max_tracked_client_ids`)
+    pub fn tracked_client_ids(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// 客户端 ID 拒绝列表
+    pub fn denylist(&self) -> &ClientIdDenylist {
+        &self.denylist
+    }
+
+    /// 准入队列 (GET /tenants 查看每个租户的连接数)
+    pub fn admission(&self) -> Option<&Arc<AdmissionQueue>> {
+        self.admission.as_ref()
+    }
+
+    /// 是否处于维护模式
+    pub fn maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// 切换维护模式
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn self_test(&self) -> SelfTestStatus {
+        *self.self_test.lock().unwrap()
+    }
+
+    pub fn set_self_test(&self, status: SelfTestStatus) {
+        *self.self_test.lock().unwrap() = status;
+    }
+
+    /// 开始排空, 已经在排空时返回 false
+    pub fn start_drain(&self) -> bool {
+        self.drain.send_if_modified(|draining| !std::mem::replace(draining, true))
+    }
+
+    /// 是否正在排空
+    pub fn draining(&self) -> bool {
+        *self.drain.borrow()
+    }
+
+    /// accept 循环等待排空开始
+    pub fn drain_signal(&self) -> watch::Receiver<bool> {
+        self.drain.subscribe()
+    }
+
+    /// 记录一个已 accept 的连接, 守卫离开作用域时减一
+    pub fn track_open(&self) -> OpenConnection {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(self.open_connections.clone())
+    }
+
+    /// 已 accept 且尚未处理完毕的连接数
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::Relaxed)
+    }
+
+    /// 登记活动连接并发布 connected 事件
+    pub fn register_connection(
+        &self,
+        info: ConnectionInfo,
+        control: Option<mpsc::Sender<MigrateRequest>>,
+        pause: Option<PauseControl>,
+        trace: Option<Arc<PacketTrace>>,
+    ) -> ConnectionGuard<'_> {
+        let id = info.id;
+        let client_id = info.client_id.clone();
+        // 空客户端 ID 由 broker 分配, 每个连接都不同
+        let previous = (!client_id.is_empty())
+            .then(|| self.clients.with_entry(&client_id, |record| record.connection.replace(id)))
+            .flatten();
+        self.publish_event(ConnectionEvent::Connected {
+            id,
+            peer: info.peer.clone(),
+            client_id: info.client_id.clone(),
+            version: info.version,
+            backend: info.backend.clone(),
+            timestamp_ms: now_ms(),
+        });
+        let replaced = {
+            let mut connections = self.connections.lock().unwrap();
+            connections.insert(id, ConnectionEntry { info, control, pause, trace });
+            previous.filter(|previous| connections.contains_key(previous))
+        };
+        metrics().active_connections.fetch_add(1, Ordering::Relaxed);
+        metrics().connections_total.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { runtime: self, id, client_id, replaced, close_reason: CloseReason::Error }
+    }
+
+    /// 发布连接事件 (没有订阅者时直接丢弃), 同时保存到最近事件
+    pub fn publish_event(&self, event: ConnectionEvent) {
+        {
+            let mut recent = self.recent_events.lock().unwrap();
+            if recent.len() == RECENT_EVENTS_CAPACITY {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+        let _ = self.events.send(event);
+    }
+
+    /// 最近的连接事件, 最早的在前
+    pub fn recent_events(&self) -> Vec<ConnectionEvent> {
+        self.recent_events.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// 订阅连接事件
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// 所有活动连接 (按连接 ID 排序)
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<_> = self.connections.lock().unwrap()
+            .values()
+            .map(|entry| ConnectionInfo {
+                paused: entry.pause.as_ref().is_some_and(|pause| pause.borrow().is_paused()),
+                ..entry.info.clone()
+            })
+            .collect();
+        connections.sort_by_key(|info| info.id);
+        connections
+    }
+
+    /// 查找连接的迁移请求通道
+    /// 连接不存在时返回 None, 连接未启用迁移时返回 Some(None)
+    pub fn migration_control(&self, id: u64) -> Option<Option<mpsc::Sender<MigrateRequest>>> {
+        self.connections.lock().unwrap()
+            .get(&id)
+            .map(|entry| entry.control.clone())
+    }
+
+    /// 查找连接的包序列记录
+    /// 连接不存在时返回 None, 连接不支持记录时返回 Some(None)
+    pub fn packet_trace(&self, id: u64) -> Option<Option<Arc<PacketTrace>>> {
+        self.connections.lock().unwrap()
+            .get(&id)
+            .map(|entry| entry.trace.clone())
+    }
+
+    /// 使用该客户端 ID 的活动连接中 ID 最小的一个
+    pub fn connection_id_for_client(&self, client_id: &str) -> Option<u64> {
+        self.connections.lock().unwrap()
+            .values()
+            .filter(|entry| entry.info.client_id == client_id)
+            .map(|entry| entry.info.id)
+            .min()
+    }
+
+    /// 暂停或恢复连接的转发
+    /// 连接不存在时返回 None, 连接不支持暂停 (启用了迁移) 时返回 Some(false)
+    pub fn set_paused(&self, id: u64, state: PauseState) -> Option<bool> {
+        self.connections.lock().unwrap()
+            .get(&id)
+            .map(|entry| match &entry.pause {
+                Some(pause) => {
+                    pause.send_replace(state);
+                    true
+                }
+                None => false,
+            })
+    }
+
+    /// 迁移成功后更新连接的后端地址
+    pub fn set_connection_backend(&self, id: u64, backend: &str) {
+        if let Some(entry) = self.connections.lock().unwrap().get_mut(&id) {
+            entry.info.backend = backend.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(id: u64, client_id: &str) -> ConnectionInfo {
+        ConnectionInfo {
+            id,
+            peer: "127.0.0.1:50000".to_string(),
+            client_id: client_id.to_string(),
+            version: "3.1.1",
+            backend: "127.0.0.1:1883".to_string(),
+            migratable: false,
+            paused: false,
+            tls: None,
+        }
+    }
+
+    /// 客户端连接后断开, 远超上限的不同 ID 依次进出: 记录随连接删除, 不淘汰仍在线的 ID
+    #[test]
+    fn client_churn_past_the_cap_evicts_nothing() {
+        const CAPACITY: usize = 10;
+        let runtime = RuntimeState::new(&AdapterConfig { max_tracked_client_ids: CAPACITY, ..AdapterConfig::default() }).unwrap();
+        // 占满上限减一的在线连接, 留一个位置给进出的连接
+        let online: Vec<_> = (0..CAPACITY as u64 - 1)
+            .map(|id| runtime.register_connection(connection(id, &format!("online-{}", id)), None, None, None))
+            .collect();
+        for id in 100..100 + 10 * CAPACITY as u64 {
+            drop(runtime.register_connection(connection(id, &format!("churn-{}", id)), None, None, None));
+        }
+        assert_eq!(runtime.tracked_client_ids(), CAPACITY - 1);
+
+        // 在线 ID 的记录仍在: 同一 ID 的新连接被识别为接管
+        for (id, _guard) in online.into_iter().enumerate() {
+            let takeover = runtime.register_connection(connection(1_000 + id as u64, &format!("online-{}", id)), None, None, None);
+            assert_eq!(takeover.replaced(), Some(id as u64));
+        }
+        assert_eq!(runtime.tracked_client_ids(), 0);
+    }
+
+    #[test]
+    fn last_connection_of_an_id_removes_its_record() {
+        let runtime = RuntimeState::new(&AdapterConfig::default()).unwrap();
+        let first = runtime.register_connection(connection(1, "sensor"), None, None, None);
+        let second = runtime.register_connection(connection(2, "sensor"), None, None, None);
+        assert_eq!(second.replaced(), Some(1));
+        // 被接管的旧连接关闭, 记录属于新连接, 保持不变
+        drop(first);
+        assert_eq!(runtime.tracked_client_ids(), 1);
+        drop(second);
+        assert_eq!(runtime.tracked_client_ids(), 0);
+    }
+}
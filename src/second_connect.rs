@@ -0,0 +1,37 @@
+// 重复 CONNECT 检测
+// 客户端在已建立的连接上再次发送 CONNECT 是协议错误; 启用 reject_second_connect 时在转发前识别, 由适配器断开连接
+// MQTT 5.0 客户端断开前先收到 DISCONNECT (0x82 Protocol Error), 见 protocol_close
+
+use crate::tap::{PacketTap, packet_type};
+
+/// 客户端发往 broker 方向的重复 CONNECT 检测
+/// 转发的流从第一个 CONNECT 之后开始 (第一个 CONNECT 已由握手检查读出), 流中出现的 CONNECT 都是第二个
+#[derive(Debug, Default)]
+pub struct SecondConnectDetector {
+    tap: PacketTap,
+}
+
+impl SecondConnectDetector {
+    /// 喂入将要转发的一块数据, 其中开始了一个 CONNECT 时返回 true (不等待包结束, 包体不会被转发)
+    pub fn feed(&mut self, data: &[u8]) -> bool {
+        if self.tap.is_broken() {
+            return false;
+        }
+        let finished = self.tap.feed(data);
+        finished.iter().any(|packet| packet.packet_type() == packet_type::CONNECT)
+            || self.tap.pending_header().is_some_and(|header| header >> 4 == packet_type::CONNECT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_a_connect_after_other_packets() {
+        let mut detector = SecondConnectDetector::default();
+        assert!(!detector.feed(&[0xC0, 0x00]));
+        // 只收到 CONNECT 的固定头也算检测到
+        assert!(detector.feed(&[0x30, 0x02, 0x00, 0x00, 0x10]));
+    }
+}
@@ -0,0 +1,88 @@
+// 启动自检
+// 监听器开始 accept 后, 经由适配器自己的明文端口建立一个合成的 MQTT 3.1.1 连接, 确认后端返回接受连接的 CONNACK;
+// 自检完成之前 GET /healthz 返回 503, 失败时记录错误并可选择退出进程
+
+use log::{error, info, warn};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+
+use crate::event_publish::{CONNECT_TIMEOUT, connect_control};
+use crate::runtime::RuntimeState;
+
+/// 自检连接的保活时间 (秒), 连接在收到 CONNACK 后立即断开
+const SELF_TEST_KEEP_ALIVE_SEC: u16 = 10;
+
+/// 自检状态, 参与 GET /healthz 的就绪判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTestStatus {
+    /// 未启用 `startup_self_test`
+    Disabled,
+    /// 等待或正在自检
+    Running,
+    Passed,
+    Failed,
+    /// 启动时处于维护模式, 自检连接会被拒绝, 没有执行
+    Skipped,
+}
+
+impl SelfTestStatus {
+    /// 未完成或失败的自检使实例处于未就绪状态
+    pub fn blocks_readiness(self) -> bool {
+        matches!(self, SelfTestStatus::Running | SelfTestStatus::Failed)
+    }
+}
+
+/// 自检连接的目标: 监听地址为通配地址时连接同一地址族的回环地址
+fn target(listen: SocketAddr) -> SocketAddr {
+    let ip = match listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, listen.port())
+}
+
+/// 执行一次自检并记录结果; `exit_on_failure` 为 true 时失败即退出进程
+pub async fn run(listen: SocketAddr, runtime: Arc<RuntimeState>, exit_on_failure: bool) {
+    if runtime.maintenance() {
+        warn!("Startup self-test skipped: the adapter started in maintenance mode and rejects new connections");
+        runtime.set_self_test(SelfTestStatus::Skipped);
+        return;
+    }
+    let target = target(listen);
+    let client_id = format!("adapter-self-test-{}", std::process::id());
+    let started = Instant::now();
+    let address = target.to_string();
+    let connect = connect_control(&address, None, None, &client_id, SELF_TEST_KEEP_ALIVE_SEC);
+    let result = match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(mut stream)) => {
+            // DISCONNECT, 后端不保留自检连接的会话和遗嘱
+            let _ = stream.write_all(&[0xE0, 0x00]).await;
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no CONNACK within {:?}", CONNECT_TIMEOUT)),
+    };
+    match result {
+        Ok(()) => {
+            info!("Startup self-test passed: {} accepted a synthetic CONNECT in {:?}", target, started.elapsed());
+            runtime.set_self_test(SelfTestStatus::Passed);
+        }
+        Err(e) => {
+            error!(
+                "Startup self-test FAILED: a synthetic CONNECT through {} did not get an accepting CONNACK ({}); \
+                 check the backend, the access list and authentication settings",
+                target, e
+            );
+            runtime.set_self_test(SelfTestStatus::Failed);
+            if exit_on_failure {
+                error!("Exiting because startup_self_test_exit_on_failure is set");
+                std::process::exit(1);
+            }
+        }
+    }
+}
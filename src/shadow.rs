@@ -0,0 +1,98 @@
+// 影子后端
+// 把客户端的 CONNECT (以及可选的后续流量) 复制到另一个 broker, 丢弃它的响应, 用于在真实负载下验证新版本
+
+use log::debug;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::adapter_config::ShadowConfig;
+use crate::happy_eyeballs;
+use crate::metrics::metrics;
+use crate::source_bind::SourceBind;
+
+/// 连接影子后端的时限
+const SHADOW_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 复制给影子后端的数据
+/// 只使用 `try_send`, 影子后端跟不上或出错时停止复制, 不会阻塞在线转发
+pub struct ShadowSink {
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+    forward_traffic: bool,
+}
+
+impl ShadowSink {
+    /// 为一个连接启动影子转发任务, 先发送 `connect_packet`
+    /// 返回的 `ShadowSink` 被丢弃时影子连接随之关闭
+    pub fn spawn(config: &ShadowConfig, connect_packet: Vec<u8>, source: Option<Arc<SourceBind>>) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_chunks.max(1));
+        tokio::spawn(run_shadow(config.backend.clone(), connect_packet, source, rx));
+        ShadowSink { tx: Some(tx), forward_traffic: config.forward_traffic }
+    }
+
+    /// 复制一块客户端发往 broker 的数据
+    pub fn send(&mut self, data: &[u8]) {
+        if !self.forward_traffic {
+            return;
+        }
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match tx.try_send(data.to_vec()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("Shadow backend is falling behind, stopped copying traffic for this connection");
+                metrics().shadow_errors_total.fetch_add(1, Ordering::Relaxed);
+                self.tx = None;
+            }
+            // 影子任务已经退出 (错误已计数)
+            Err(TrySendError::Closed(_)) => self.tx = None,
+        }
+    }
+}
+
+/// 影子连接: 发送 CONNECT 和复制的数据, 读取并丢弃响应
+async fn run_shadow(
+    backend: String,
+    connect_packet: Vec<u8>,
+    source: Option<Arc<SourceBind>>,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+) {
+    let connect = happy_eyeballs::connect(&backend, source.as_ref());
+    let mut stream = match tokio::time::timeout(SHADOW_CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return shadow_error(&backend, &e.to_string()),
+        Err(_) => return shadow_error(&backend, "connect timed out"),
+    };
+    if let Err(e) = stream.write_all(&connect_packet).await {
+        return shadow_error(&backend, &e.to_string());
+    }
+
+    let mut discard = [0u8; 4096];
+    loop {
+        tokio::select! {
+            data = rx.recv() => match data {
+                Some(data) => {
+                    if let Err(e) = stream.write_all(&data).await {
+                        return shadow_error(&backend, &e.to_string());
+                    }
+                }
+                // 客户端连接已结束
+                None => break,
+            },
+            read = stream.read(&mut discard) => match read {
+                Ok(0) => return shadow_error(&backend, "connection closed by shadow backend"),
+                Ok(_) => {}
+                Err(e) => return shadow_error(&backend, &e.to_string()),
+            },
+        }
+    }
+}
+
+fn shadow_error(backend: &str, message: &str) {
+    debug!("Shadow backend {}: {}", backend, message);
+    metrics().shadow_errors_total.fetch_add(1, Ordering::Relaxed);
+}
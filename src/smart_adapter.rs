@@ -2,257 +2,3062 @@
 // 在单个端口上自动检测 MQTT 3.1.0, 3.1.1, 5.0 协议
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use log::{info, warn, debug, error};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures_util::FutureExt;
+use log::{info, warn, debug, error, log, trace};
+use std::panic::AssertUnwindSafe;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::Instrument;
+
+use crate::accept_backoff::{AcceptBackoff, accept_with_backoff};
+use crate::access::AccessList;
+use crate::adapter_config::{AdapterConfig, ListenerMode, ObserverOn};
+use crate::admission::AdmissionRejection;
+use crate::auth::{AuthDecision, AuthRequest, Authenticator, NonceAuthenticator};
+use crate::backend_close::{BackendCloseNotice, BackendCloseTap, ReaderEnd};
+use crate::byte_quota::{ByteQuota, QuotaTap};
+use crate::cert_routing::CertRouter;
+use crate::classify::{Protocol, read_and_classify};
+use crate::connack::{ConnackReason, encode_connack, encode_connack_accepted};
+use crate::codec::{MAX_VARIABLE_INT_BYTES, encode_packet, encode_variable_int, read_remaining_length, read_remaining_length_bounded, truncated};
+use crate::connect_flood::FloodDetector;
+use crate::connect_packet::{ConnectPacket, encode_connect, parse_connect};
+use crate::connect_transform::{ConnectPipeline, TransformContext};
+use crate::control_push::{ControlPush, PushSocket};
+use crate::detection::DetectionPolicy;
+use crate::error::AdapterError;
+use crate::event_publish::EventPublisher;
+use crate::health_publish::HealthPublisher;
+use crate::events::{CloseReason, ConnectionEvent, now_ms};
+use crate::log_redact::LogRedactor;
+use crate::log_sampler::{AcceptSampler, LogSampler};
+use crate::metrics::metrics;
+use crate::migration::{MigratableSession, forward_with_migration};
+use crate::pause::PauseGate;
+use crate::rate_limit::TokenBucket;
+use crate::request_response::{self, RequestResponseTap};
+use crate::prefixed_stream::PrefixedStream;
+use crate::qos_drain::{InflightTap, QosDrain};
+use crate::response_rewriter::ResponseRewriter;
+use crate::packet_firewall::{FirewallDenial, FirewallRules, FirewallVerdict, PACKET_TYPE_NAMES, PacketFirewall};
+use crate::protocol_close::{ProtocolClose, ProtocolCloseTap, ProtocolViolation};
+use crate::packet_trace::{PacketTrace, TraceTap};
+use crate::second_connect::SecondConnectDetector;
+use crate::self_test;
+use crate::shadow::ShadowSink;
+use crate::source_bind::SourceBind;
+use crate::socket_buffers::SocketBuffers;
+use crate::source_ip_routing::SourceIpRouter;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+use crate::splice;
+use crate::runtime::{ConnectionInfo, RuntimeState};
+use crate::tap::Direction;
+use crate::tarpit::Tarpit;
+use crate::telemetry::next_connection_id;
+use crate::happy_eyeballs;
+use crate::idle::{IdleHandle, IdleReaper, IdleTracker};
+use crate::listener;
+use crate::tls::{AdapterTlsStream, TlsHandshaker, TlsPeer, TlsStart};
+use crate::topic_routing::TopicRouter;
+use crate::warm_pool::WarmPool;
+use crate::weighted_routing::WeightedRouter;
+use crate::worker_pool::WorkerPool;
+use crate::websocket::{self, WebSocketStream};
 
 /// MQTT 协议版本
-#[derive(Debug, Clone, Copy)]
-enum MqttVersion {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqttVersion {
     V310,  // MQTT 3.1.0 (MQIsdp)
     V311,  // MQTT 3.1.1
     V500,  // MQTT 5.0
 }
 
+impl MqttVersion {
+    pub const ALL: [MqttVersion; 3] = [MqttVersion::V310, MqttVersion::V311, MqttVersion::V500];
+
+    /// `allowed_versions` 配置和 `version_not_allowed_total` 标签中的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MqttVersion::V310 => "3.1.0",
+            MqttVersion::V311 => "3.1.1",
+            MqttVersion::V500 => "5.0",
+        }
+    }
+
+    /// 解析 `allowed_versions`, 未知的版本名返回错误
+    pub fn parse_allowed(names: &[String]) -> std::io::Result<Vec<MqttVersion>> {
+        names
+            .iter()
+            .map(|name| {
+                MqttVersion::ALL.into_iter().find(|version| version.as_str() == name).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("unknown MQTT version {:?} in allowed_versions (expected \"3.1.0\", \"3.1.1\" or \"5.0\")", name),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// 5.0 CONNECT 可变头开头: 协议名称 "MQTT" 和协议级别 5
+const V5_PROTOCOL_HEADER: [u8; 7] = [0x00, 0x04, b'M', b'Q', b'T', b'T', 5];
+
+/// 适配器共享状态, 所有连接和监听器共用
+struct AdapterState {
+    access_list: AccessList,
+    /// 明文监听器和 TLS 监听器的协议识别策略
+    detection: DetectionPolicy,
+    tls_detection: DetectionPolicy,
+    /// 全局 CONNECT 准入速率 (未配置则不限制)
+    connect_limiter: Option<TokenBucket>,
+    /// CONNECT 洪水检测 (未配置则不检测)
+    flood_detector: Option<Arc<FloodDetector>>,
+    /// 所有连接共享的转发带宽上限 (未配置则不限制)
+    total_limiter: Option<Arc<TokenBucket>>,
+    /// 运行时状态, 其中保存生效的配置 (见 `config`)
+    runtime: Arc<RuntimeState>,
+    /// 认证钩子 (未配置则不认证)
+    authenticator: Option<Box<dyn Authenticator>>,
+    /// CONNACK 改写
+    response_rewriter: ResponseRewriter,
+    /// 转发前的 CONNECT 改写
+    connect_pipeline: ConnectPipeline,
+    /// 连接错误日志采样
+    error_log: LogSampler,
+    /// 成功连接的采样日志
+    accept_log: AcceptSampler,
+    /// CONNECT 字段日志脱敏
+    log_redactor: LogRedactor,
+    /// 按主题前缀选择后端 (未配置则立即连接默认后端)
+    topic_router: Option<TopicRouter>,
+    /// 按源 IP 选择后端 (未配置则使用默认后端)
+    source_ip_router: Option<SourceIpRouter>,
+    /// 按权重选择后端 (未配置则使用默认后端)
+    weighted_router: Option<WeightedRouter>,
+    /// TLS 连接按客户端证书字段选择后端 (未配置则使用默认后端)
+    cert_router: Option<CertRouter>,
+    /// 默认后端的预热连接池 (未配置则每次单独连接)
+    warm_pool: Option<Arc<WarmPool>>,
+    /// 后端连接绑定的本地地址 (未配置则由系统选择)
+    source_bind: Option<Arc<SourceBind>>,
+    /// 连接工作池 (未配置则每个连接一个任务)
+    worker_pool: Option<WorkerPool>,
+    /// 被拒绝连接的延迟关闭 (未配置则立即关闭)
+    tarpit: Option<Tarpit>,
+    /// 客户端和后端连接的收发缓冲区大小 (未配置则使用系统默认值)
+    socket_buffers: Option<SocketBuffers>,
+    /// 空闲连接检测 (idle_timeout_ms 为 0 时不检测)
+    idle_reaper: Option<Arc<IdleReaper>>,
+    /// 明文监听器和 TLS 监听器的包类型过滤和 PUBLISH 长度限制 (未配置则不过滤)
+    packet_firewall: Option<Arc<FirewallRules>>,
+    tls_packet_firewall: Option<Arc<FirewallRules>>,
+}
+
+impl AdapterState {
+    /// 按运行时中生效的配置创建共享状态, 检查互相冲突的配置
+    fn new(forward_port: u16, runtime: Arc<RuntimeState>) -> std::io::Result<Self> {
+        let config = runtime.config();
+        check_conflicts(&config)?;
+        let connect_rate = &config.connect_rate;
+        let source_bind = SourceBind::from_config(config.forward_bind_addr.as_deref(), config.forward_bind_port_range)?
+            .map(Arc::new);
+        Ok(AdapterState {
+            access_list: AccessList::from_config(&config.access)?,
+            detection: DetectionPolicy::from_config(&config, None)?,
+            tls_detection: DetectionPolicy::from_config(&config, config.tls.as_ref().and_then(|tls| tls.detection.as_ref()))?,
+            connect_limiter: (connect_rate.max_connects_per_sec > 0)
+                .then(|| TokenBucket::with_burst(connect_rate.max_connects_per_sec, connect_rate.burst)),
+            total_limiter: (config.throttle.max_total_bytes_per_sec > 0)
+                .then(|| Arc::new(TokenBucket::new(config.throttle.max_total_bytes_per_sec))),
+            authenticator: config.auth.nonce.as_ref()
+                .map(|nonce| Box::new(NonceAuthenticator::new(nonce)) as Box<dyn Authenticator>),
+            response_rewriter: ResponseRewriter::new(&config),
+            connect_pipeline: ConnectPipeline::from_config(&config)?,
+            error_log: LogSampler::new(module_path!(), Duration::from_secs(config.error_log_window_sec)),
+            accept_log: AcceptSampler::new(config.logging.accept_log_sample_rate)?,
+            log_redactor: LogRedactor::from_config(&config.logging)?,
+            topic_router: config.topic_routing.as_ref().map(TopicRouter::new),
+            source_ip_router: config.source_ip_routing.as_ref().map(SourceIpRouter::new),
+            weighted_router: config.weighted_routing.as_ref().map(WeightedRouter::from_config).transpose()?.flatten(),
+            cert_router: config.tls.as_ref().map(CertRouter::from_config).transpose()?.flatten(),
+            flood_detector: config.connect_flood.as_ref().map(|flood| Arc::new(FloodDetector::new(flood))),
+            warm_pool: config.warm_pool.as_ref()
+                .map(|pool| Arc::new(WarmPool::new(format!("127.0.0.1:{}", forward_port), source_bind.clone(), pool))),
+            source_bind,
+            worker_pool: (config.worker_pool_size > 0).then(|| WorkerPool::new(config.worker_pool_size)),
+            tarpit: (config.tarpit_ms > 0)
+                .then(|| Tarpit::new(Duration::from_millis(config.tarpit_ms), config.tarpit_max_sockets)),
+            socket_buffers: SocketBuffers::from_config(config.so_rcvbuf, config.so_sndbuf)?,
+            idle_reaper: IdleReaper::from_config(&config)?,
+            packet_firewall: FirewallRules::from_config(config.packet_firewall.as_ref(), config.max_publish_size)?,
+            tls_packet_firewall: match &config.tls {
+                Some(tls) => FirewallRules::from_config(tls.packet_firewall.as_ref(), tls.max_publish_size)?,
+                None => None,
+            },
+            runtime,
+        })
+    }
+
+    /// 生效的配置: 启动后只有可热加载的字段 (`config_reload::RELOADABLE_FIELDS`) 会变化,
+    /// 其余字段与创建共享状态时相同
+    fn config(&self) -> Arc<AdapterConfig> {
+        self.runtime.config()
+    }
+}
+
+/// 检查智能适配器的全部设置 (只解析, 不绑定端口、不启动任务), POST /reload 在应用新配置之前调用
+pub fn check_config(config: &AdapterConfig) -> std::io::Result<()> {
+    check_conflicts(config)?;
+    AccessList::from_config(&config.access)?;
+    DetectionPolicy::from_config(config, None)?;
+    DetectionPolicy::from_config(config, config.tls.as_ref().and_then(|tls| tls.detection.as_ref()))?;
+    ConnectPipeline::from_config(config)?;
+    AcceptSampler::new(config.logging.accept_log_sample_rate)?;
+    LogRedactor::from_config(&config.logging)?;
+    config.weighted_routing.as_ref().map(WeightedRouter::from_config).transpose()?;
+    SourceBind::from_config(config.forward_bind_addr.as_deref(), config.forward_bind_port_range)?;
+    SocketBuffers::from_config(config.so_rcvbuf, config.so_sndbuf)?;
+    IdleReaper::from_config(config)?;
+    FirewallRules::from_config(config.packet_firewall.as_ref(), config.max_publish_size)?;
+    if let Some(tls) = &config.tls {
+        TlsHandshaker::from_config(tls)?;
+        CertRouter::from_config(tls)?;
+        FirewallRules::from_config(tls.packet_firewall.as_ref(), tls.max_publish_size)?;
+    }
+    Ok(())
+}
+
+/// 检查单个组件无法发现的设置错误: 取值范围和互相冲突的设置
+fn check_conflicts(config: &AdapterConfig) -> std::io::Result<()> {
+    if !(1..=MAX_VARIABLE_INT_BYTES).contains(&config.max_remaining_length_bytes) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("max_remaining_length_bytes must be between 1 and {}", MAX_VARIABLE_INT_BYTES),
+        ));
+    }
+    if config.source_ip_routing.as_ref().is_some_and(|routing| !routing.backends.is_empty())
+        && config.weighted_routing.as_ref().is_some_and(|routing| !routing.backends.is_empty())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "source_ip_routing and weighted_routing cannot both be configured (use weighted_routing.sticky for per-IP affinity)",
+        ));
+    }
+    Ok(())
+}
+
 /// 启动智能 MQTT 适配器
 /// 在单个端口上自动检测并处理所有 MQTT 版本
 pub async fn start_smart_mqtt_adapter(
-    listen_port: u16,
     forward_port: u16,  // 统一的 broker 端口
+    runtime: Arc<RuntimeState>,
+    mut backend_ready: watch::Receiver<bool>,
 ) -> std::io::Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", listen_port)).await?;
-    info!("Smart MQTT adapter listening on 0.0.0.0:{}", listen_port);
-    info!("  - Auto-detects MQTT 3.1.0, 3.1.1, and 5.0");
-    info!("  - Upgrades MQTT 3.1.0 to 3.1.1 transparently");
+    let config = runtime.config();
+    let tls = match &config.tls {
+        Some(tls_config) => Some((
+            TlsHandshaker::from_config(tls_config)?,
+            listener::bind(&tls_config.listen, config.reuse_port).await?,
+        )),
+        None => None,
+    };
+    let state = Arc::new(AdapterState::new(forward_port, runtime)?);
     
-    loop {
-        let (client_stream, client_addr) = listener.accept().await?;
-        debug!("Smart adapter: New connection from {}", client_addr);
-        
-        let forward_addr = format!("127.0.0.1:{}", forward_port);
-        
-        tokio::spawn(async move {
-            if let Err(e) = handle_smart_client(client_stream, forward_addr).await {
-                warn!("Smart adapter error: {}", e);
-            }
-        });
+    let mut listeners = listener::bind(&state.config().listen, state.config().reuse_port).await?;
+    info!("Smart MQTT adapter listening on {}", listeners[0].local_addr()?);
+    match state.config().mode {
+        ListenerMode::Smart => info!("  - Detection: {}", state.detection.describe()),
+        ListenerMode::Passthrough => info!("  - Passthrough: forwards raw bytes to the backend without protocol detection"),
     }
-}
-
-/// 处理单个客户端连接,自动检测协议版本
-async fn handle_smart_client(
-    mut client_stream: TcpStream,
-    forward_addr: String,
-) -> std::io::Result<()> {
-    // 读取 CONNECT 包的固定头
-    let mut first_byte = [0u8; 1];
-    client_stream.read_exact(&mut first_byte).await?;
-    
-    // 检查是否是 CONNECT 包 (固定头 0x10)
-    if first_byte[0] >> 4 != 1 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Expected CONNECT packet"
-        ));
+    if let Some(rules) = &state.packet_firewall {
+        info!("  - Packet firewall: {}", rules.describe());
     }
-    
-    // 读取剩余长度
-    let remaining_length = read_remaining_length(&mut client_stream).await?;
-    
-    // 读取完整的 CONNECT 包负载
-    let mut payload = vec![0u8; remaining_length];
-    client_stream.read_exact(&mut payload).await?;
-    
-    // 检测协议版本
-    let (mqtt_version, modified_payload) = detect_and_convert_protocol(&payload)?;
-    
-    // 记录协议版本
-    let version_name = match mqtt_version {
-        MqttVersion::V310 => {
-            info!("Detected MQTT 3.1.0 client, upgrading to 3.1.1");
-            "3.1.0→3.1.1"
+    if let Some((handshaker, tls_listeners)) = &tls {
+        info!("Smart MQTT adapter listening on {} (TLS)", tls_listeners[0].local_addr()?);
+        info!("  - TLS: at most {} concurrent handshakes", handshaker.max_concurrent());
+        info!("  - TLS: detection {}", state.tls_detection.describe());
+        if state.config().tls.as_ref().is_some_and(|tls_config| tls_config.client_ca_path.is_some()) {
+            info!("  - TLS: client certificates required (mutual TLS)");
         }
-        MqttVersion::V311 => {
-            info!("Detected MQTT 3.1.1 client");
-            "3.1.1"
+        if let Some(router) = &state.cert_router {
+            info!("  - TLS: routing by client certificate {} ({} backends)", router.attribute().as_str(), router.backend_count());
         }
-        MqttVersion::V500 => {
-            info!("Detected MQTT 5.0 client");
-            "5.0"
+        if let Some(rules) = &state.tls_packet_firewall {
+            info!("  - TLS: packet firewall {}", rules.describe());
         }
-    };
+    }
+    if listeners.len() > 1 {
+        info!("  - SO_REUSEPORT: {} accept loops per listener", listeners.len());
+    }
+    if state.worker_pool.is_some() {
+        info!("  - Worker pool: {} tasks handle connections", state.config().worker_pool_size);
+    }
+    if state.config().splice_forward {
+        log_splice_support();
+    }
     
-    // 连接到 broker (rumqttd 会自动识别 3.1.1 和 5.0)
-    let mut broker_stream = TcpStream::connect(&forward_addr).await
-        .map_err(|e| {
-            error!("Failed to connect to backend broker ({}): {}", version_name, e);
-            e
-        })?;
+    tokio::spawn(crate::metrics::run_throughput_sampler());
+    if let Some(flood) = &state.flood_detector {
+        let flood = flood.clone();
+        tokio::spawn(async move { flood.run_sampler().await });
+    }
     
-    // 发送(可能修改过的) CONNECT 包
-    broker_stream.write_u8(first_byte[0]).await?;
-    write_remaining_length(&mut broker_stream, modified_payload.len()).await?;
-    broker_stream.write_all(&modified_payload).await?;
-    broker_stream.flush().await?;
+    // 周期输出被采样合并的错误汇总
+    if !state.error_log.window().is_zero() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(state.error_log.window());
+            loop {
+                interval.tick().await;
+                state.error_log.flush();
+            }
+        });
+    }
     
-    debug!("Forwarded CONNECT packet to {} broker", version_name);
+    // 等待后端 broker 监听就绪后再开始 accept (期间的连接在 backlog 中排队)
+    let wait_started = Instant::now();
+    let ready_timeout = Duration::from_millis(state.config().backend_ready_timeout_ms);
+    match tokio::time::timeout(ready_timeout, backend_ready.wait_for(|ready| *ready)).await {
+        Ok(Ok(_)) => info!("Smart adapter: backend ready after {:?}, accepting connections", wait_started.elapsed()),
+        _ => warn!("Smart adapter: backend not ready after {:?}, accepting connections anyway", wait_started.elapsed()),
+    }
     
-    // 双向转发剩余数据
-    bidirectional_forward(client_stream, broker_stream).await?;
+    if let Some(pool) = &state.warm_pool {
+        tokio::spawn(pool.clone().run_refill());
+    }
     
-    Ok(())
-}
-
-/// 检测 MQTT 协议版本并转换 (如果需要)
-/// 返回: (协议版本, 可能修改后的负载)
-fn detect_and_convert_protocol(payload: &[u8]) -> std::io::Result<(MqttVersion, Vec<u8>)> {
-    if payload.len() < 8 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "CONNECT packet too short"
-        ));
+    if let Some(reaper) = &state.idle_reaper {
+        tokio::spawn(reaper.clone().run_sweeper());
     }
     
-    // 读取协议名称长度
-    let protocol_name_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    if let Some(event_publish) = &state.config().event_publish {
+        let publisher = EventPublisher::new(
+            event_publish,
+            format!("127.0.0.1:{}", forward_port),
+            state.source_bind.clone(),
+            state.config().backend_auth.clone(),
+        );
+        tokio::spawn(publisher.run(state.runtime.subscribe_events()));
+    }
     
-    if payload.len() < 2 + protocol_name_len + 1 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Invalid CONNECT packet"
+    if let Some(health_publish) = &state.config().health_publish {
+        let publisher = HealthPublisher::new(
+            health_publish,
+            format!("127.0.0.1:{}", forward_port),
+            state.source_bind.clone(),
+            state.config().backend_auth.clone(),
+            state.runtime.clone(),
+        );
+        tokio::spawn(publisher.run());
+    }
+    
+    // 启动自检: 连接在 backlog 中排队, 下面的 accept 循环开始后处理
+    if state.config().startup_self_test {
+        tokio::spawn(self_test::run(
+            listeners[0].local_addr()?,
+            state.runtime.clone(),
+            state.config().startup_self_test_exit_on_failure,
         ));
     }
     
-    let protocol_name = &payload[2..2 + protocol_name_len];
-    let protocol_level = payload[2 + protocol_name_len];
+    // 启用 reuse_port 时每个监听器一个 accept 循环, 共享状态和指标
+    if let Some((handshaker, tls_listeners)) = tls {
+        for tls_listener in tls_listeners {
+            let state = state.clone();
+            let tls = Some(handshaker.clone());
+            tokio::spawn(async move {
+                if let Err(e) = accept_loop(tls_listener, tls, forward_port, state).await {
+                    error!("Smart adapter TLS listener failed: {}", e);
+                }
+            });
+        }
+    }
     
-    // 检测协议版本
-    match (protocol_name, protocol_level) {
-        // MQTT 3.1.0: MQIsdp, level 3
-        (b"MQIsdp", 3) => {
-            // 需要转换为 MQTT 3.1.1
-            let mut new_payload = Vec::new();
-            
-            // 新的协议名称: "MQTT" (4 字节)
-            new_payload.extend_from_slice(&[0, 4]);
-            new_payload.extend_from_slice(b"MQTT");
-            new_payload.push(4); // MQTT 3.1.1 协议级别
-            
-            // 复制剩余字段
-            new_payload.extend_from_slice(&payload[2 + protocol_name_len + 1..]);
-            
-            Ok((MqttVersion::V310, new_payload))
+    let listener = listeners.pop().expect("listener::bind returns at least one listener");
+    for extra_listener in listeners {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = accept_loop(extra_listener, None, forward_port, state).await {
+                error!("Smart adapter listener failed: {}", e);
+            }
+        });
+    }
+    accept_loop(listener, None, forward_port, state).await
+}
+
+/// 监听器的 accept 循环, `tls` 不为空时先完成 TLS 握手
+/// 开始排空时取出 backlog 中已完成握手的连接后关闭监听器, 处理完这些连接后返回
+async fn accept_loop(
+    listener: TcpListener,
+    tls: Option<TlsHandshaker>,
+    forward_port: u16,
+    state: Arc<AdapterState>,
+) -> std::io::Result<()> {
+    let connect_queue_timeout = Duration::from_millis(state.config().connect_rate.queue_timeout_ms);
+    let mut backoff = AcceptBackoff::default();
+    let mut drain = state.runtime.drain_signal();
+    let mut listener = Some(listener);
+    let mut backlog = Vec::new().into_iter();
+    
+    loop {
+        let accepted = match &listener {
+            // accept 失败 (如文件描述符耗尽) 不结束监听器, 退避后重试; 退避期间开始排空时立即处理排空
+            Some(active) => tokio::select! {
+                accepted = accept_with_backoff(&mut backoff, || active.accept()) => Some(accepted),
+                Ok(_) = drain.wait_for(|draining| *draining) => None,
+            },
+            None => match backlog.next() {
+                Some(pending) => Some(pending),
+                None => return Ok(()),
+            },
+        };
+        let Some((client_stream, client_addr)) = accepted else {
+            let active = listener.take().expect("listener is open until the drain starts");
+            let local_addr = active.local_addr()?;
+            // 关闭 SO_REUSEPORT 监听器时内核重置其 backlog 中的连接, 所以先取出再关闭
+            let pending = take_backlog(&active);
+            drop(active);
+            info!("Draining: listener {} closed, {} connections taken from the backlog", local_addr, pending.len());
+            backlog = pending.into_iter();
+            continue;
+        };
+
+        debug!("Smart adapter: New connection from {}", client_addr);
+        if let Some(buffers) = &state.socket_buffers {
+            buffers.apply(&client_stream);
+        }
+        if let Some(flood) = &state.flood_detector {
+            flood.record_accept();
         }
         
-        // MQTT 3.1.1: MQTT, level 4
-        (b"MQTT", 4) => {
-            Ok((MqttVersion::V311, payload.to_vec()))
+        // 访问控制: 不允许的地址直接关闭
+        if !state.access_list.is_allowed(client_addr.ip()) {
+            debug!("Smart adapter: Connection from {} denied by ACL", client_addr);
+            metrics().connection_denied_acl_total.fetch_add(1, Ordering::Relaxed);
+            close_rejected(&state, client_stream);
+            continue;
         }
         
-        // MQTT 5.0: MQTT, level 5
-        (b"MQTT", 5) => {
-            Ok((MqttVersion::V500, payload.to_vec()))
+        // 洪水告警期间的临时准入速率: 超出直接关闭, 不排队
+        if let Some(flood) = &state.flood_detector
+            && !flood.admit()
+        {
+            debug!("Smart adapter: Connection from {} rejected during connect flood", client_addr);
+            metrics().connect_flood_rejected_total.fetch_add(1, Ordering::Relaxed);
+            close_rejected(&state, client_stream);
+            continue;
         }
         
-        _ => {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Unknown MQTT protocol: {:?}, level {}", 
-                    String::from_utf8_lossy(protocol_name), protocol_level)
-            ))
+        // 全局准入速率: 在 accept 循环中预留令牌 (按到达顺序), 需要等待的连接在自己的任务中等待, 不阻塞 accept;
+        // 需要等待超过 queue_timeout_ms 的连接直接拒绝
+        let mut admission_wait = Duration::ZERO;
+        if let Some(limiter) = &state.connect_limiter {
+            match limiter.try_reserve(1, connect_queue_timeout) {
+                Some(wait) => admission_wait = wait,
+                None => {
+                    debug!("Smart adapter: Connection from {} rejected by connect rate limit", client_addr);
+                    metrics().connection_rate_limited_total.fetch_add(1, Ordering::Relaxed);
+                    close_rejected(&state, client_stream);
+                    continue;
+                }
+            }
+        }
+        
+        // 按源 IP 或权重选择后端, 未配置时转发到本地 broker
+        let forward_addr = state.source_ip_router.as_ref()
+            .and_then(|router| router.route(client_addr.ip()))
+            .or_else(|| state.weighted_router.as_ref().map(|router| router.route(client_addr.ip())))
+            .map_or_else(|| format!("127.0.0.1:{}", forward_port), str::to_string);
+        let pool_state = state.clone();
+        let state = state.clone();
+        let tls = tls.clone();
+        let open = state.runtime.track_open();
+        
+        // 每个连接一个追踪 span, 覆盖 accept 到连接关闭
+        let connection_id = next_connection_id();
+        let span = tracing::debug_span!(
+            "connection",
+            connection_id,
+            peer = %client_addr,
+            version = tracing::field::Empty,
+            client_id = tracing::field::Empty,
+        );
+        
+        let job = async move {
+            let _open = open;
+            if !admission_wait.is_zero() {
+                tokio::time::sleep(admission_wait).await;
+            }
+            let push_socket = push_socket(&state, &client_stream);
+            let result = match tls {
+                Some(handshaker) => match handshaker.accept(client_stream, client_addr).await {
+                    Ok(start) => handle_tls_start(start, push_socket, client_addr, connection_id, forward_addr, state.clone()).await,
+                    Err((reason, message)) => {
+                        // 握手失败时连接随之关闭, 不读取 CONNECT
+                        debug!("TLS handshake with {} failed ({}): {}", client_addr, reason.as_str(), message);
+                        metrics().record_tls_handshake_failure(reason);
+                        return;
+                    }
+                },
+                None => match state.config().mode {
+                    ListenerMode::Smart => handle_smart_client(client_stream, push_socket, client_addr, connection_id, forward_addr, state.clone(), None).await,
+                    ListenerMode::Passthrough => handle_passthrough_client(client_stream, client_addr, connection_id, forward_addr, state.clone()).await,
+                },
+            };
+            
+            if let Err(e) = result {
+                state.error_log.log(e.category(), e.log_level(), &format!("Smart adapter error: {}", e));
+                state.runtime.publish_event(ConnectionEvent::Error {
+                    id: connection_id,
+                    peer: client_addr.to_string(),
+                    category: e.category(),
+                    message: e.to_string(),
+                    timestamp_ms: now_ms(),
+                });
+            }
+        }.instrument(span);
+        let job = catch_handler_panic(connection_id, client_addr, job);
+        
+        match &pool_state.worker_pool {
+            Some(pool) => pool.submit(Box::pin(job)).await,
+            None => {
+                tokio::spawn(job);
+            }
         }
     }
 }
 
-/// 双向转发数据流
-async fn bidirectional_forward(
-    client_stream: TcpStream,
-    broker_stream: TcpStream,
-) -> std::io::Result<()> {
-    let (mut client_read, mut client_write) = client_stream.into_split();
-    let (mut broker_read, mut broker_write) = broker_stream.into_split();
-    
-    let client_to_broker = tokio::spawn(async move {
-        let mut buffer = [0u8; 8192];
-        loop {
-            match client_read.read(&mut buffer).await {
-                Ok(0) => break,
-                Ok(n) => {
-                    if broker_write.write_all(&buffer[..n]).await.is_err() {
-                        break;
-                    }
-                }
-                Err(_) => break,
+/// TLS 监听器上的连接: 握手完成后按 TLS 连接处理; 明文连接按 require_tls 断开, 或按明文监听器处理
+async fn handle_tls_start(
+    start: TlsStart,
+    push_socket: Option<PushSocket>,
+    client_addr: SocketAddr,
+    connection_id: u64,
+    forward_addr: String,
+    state: Arc<AdapterState>,
+) -> Result<(), AdapterError> {
+    match start {
+        TlsStart::Established(tls_stream) => {
+            let tls_peer = TlsPeer::negotiated(&tls_stream);
+            let tls_info = tls_peer.info;
+            log!(
+                state.config().logging.connection_log_level.level(),
+                "TLS connection from {}: TLS {} with {}", client_addr, tls_info.version, tls_info.cipher
+            );
+            metrics().record_tls_connection(tls_info);
+            let forward_addr = route_by_certificate(&state, &tls_stream, client_addr, forward_addr);
+            handle_smart_client(*tls_stream, push_socket, client_addr, connection_id, forward_addr, state, Some(tls_peer)).await
+        }
+        TlsStart::Probe => {
+            trace!("Smart adapter: TLS connection closed before sending data (health probe)");
+            Ok(())
+        }
+        TlsStart::Plaintext(client_stream, protocol) => {
+            if state.config().tls.as_ref().is_none_or(|tls| tls.require_tls) {
+                warn!("Plaintext {} connection from {} on the TLS listener, closing (require_tls)", protocol.as_str(), client_addr);
+                metrics().plaintext_on_tls_listener_total.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
             }
+            handle_smart_client(client_stream, push_socket, client_addr, connection_id, forward_addr, state, None).await
         }
-    });
-    
-    let broker_to_client = tokio::spawn(async move {
-        let mut buffer = [0u8; 8192];
-        loop {
-            match broker_read.read(&mut buffer).await {
-                Ok(0) => break,
-                Ok(n) => {
-                    if client_write.write_all(&buffer[..n]).await.is_err() {
-                        break;
-                    }
+    }
+}
+
+/// 不阻塞地取出监听器 backlog 中所有已完成 TCP 握手的连接
+fn take_backlog(listener: &TcpListener) -> Vec<(TcpStream, SocketAddr)> {
+    let socket = socket2::SockRef::from(listener);
+    let mut pending = Vec::new();
+    loop {
+        // 监听器是非阻塞的, backlog 为空时返回 WouldBlock
+        let (accepted, addr) = match socket.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::WouldBlock {
+                    warn!("Draining: accept from the backlog failed: {}", e);
                 }
-                Err(_) => break,
+                return pending;
             }
+        };
+        let stream = std::net::TcpStream::from(accepted);
+        let converted = stream.set_nonblocking(true).and_then(|()| TcpStream::from_std(stream));
+        match (converted, addr.as_socket()) {
+            (Ok(stream), Some(addr)) => pending.push((stream, addr)),
+            (Err(e), _) => warn!("Draining: dropping a connection taken from the backlog: {}", e),
+            (Ok(_), None) => {}
         }
-    });
+    }
+}
+
+/// 按客户端证书字段选择后端, 未配置或没有匹配时使用 `default_addr`
+fn route_by_certificate(state: &AdapterState, stream: &AdapterTlsStream, client_addr: SocketAddr, default_addr: String) -> String {
+    let Some(router) = &state.cert_router else {
+        return default_addr;
+    };
+    match router.route(stream) {
+        (value, Some(backend)) => {
+            debug!("Routing TLS client {} to {} by certificate {} {:?}", client_addr, backend, router.attribute().as_str(), value);
+            backend.to_string()
+        }
+        (value, None) => {
+            debug!("No certificate route for TLS client {} ({} {:?}), using {}", client_addr, router.attribute().as_str(), value, default_addr);
+            default_addr
+        }
+    }
+}
+
+/// 关闭在 accept 循环中被拒绝的连接, 配置了 tarpit 时延迟关闭
+fn close_rejected(state: &AdapterState, client_stream: TcpStream) {
+    if let Some(tarpit) = &state.tarpit {
+        tarpit.hold(client_stream, Vec::new());
+    }
+}
+
+/// 回复拒绝连接的 CONNACK 并关闭, 配置了 tarpit 时延迟后再回复
+async fn reject_connect<S>(state: &AdapterState, mut client_stream: S, connack: Vec<u8>) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin + Send + 'static,
+{
+    match &state.tarpit {
+        Some(tarpit) => {
+            tarpit.hold(client_stream, connack);
+            Ok(())
+        }
+        None => {
+            client_stream.write_all(&connack).await?;
+            client_stream.shutdown().await
+        }
+    }
+}
+
+/// 连接处理的 panic 边界: 记录带连接 ID 的日志并计数, 连接随之关闭
+/// 不捕获时 panic 只结束当前任务, 但在工作池中会让该工作任务永久退出
+async fn catch_handler_panic(connection_id: u64, client_addr: SocketAddr, handler: impl Future<Output = ()>) {
+    // 连接状态 (ConnectionGuard、准入许可等) 由 Drop 释放, 展开后不会留下不一致的状态
+    if let Err(panic) = AssertUnwindSafe(handler).catch_unwind().await {
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("non-string panic payload");
+        error!("Smart adapter: handler for connection {} from {} panicked: {}", connection_id, client_addr, message);
+        metrics().handler_panics_total.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 握手检查失败原因 (`bad_handshake_total` 的 reason 标签)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadHandshake {
+    /// 首字节不是 CONNECT, 也不是可识别的其它协议
+    NotConnect,
+    /// 识别出的协议在该监听器上不支持 (TLS、WebSocket、PROXY)
+    UnsupportedProtocol,
+    /// 超过 `banner_grace_ms` 或 `connect_deadline_ms` 仍未收到完整的 CONNECT
+    Deadline,
+}
+
+impl BadHandshake {
+    pub const ALL: [BadHandshake; 3] = [BadHandshake::NotConnect, BadHandshake::UnsupportedProtocol, BadHandshake::Deadline];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BadHandshake::NotConnect => "not_connect",
+            BadHandshake::UnsupportedProtocol => "unsupported_protocol",
+            BadHandshake::Deadline => "deadline",
+        }
+    }
+}
+
+/// 通过握手检查的连接: 完整的 CONNECT 已读出, 之后的字节仍在 `stream` 中
+struct Handshake<S> {
+    stream: PrefixedStream<S>,
+    /// 底层的客户端 TCP 套接字副本 (TLS、WebSocket 之下), 由 `accept_connect` 填入
+    push_socket: Option<PushSocket>,
+    first_byte: u8,
+    payload: Vec<u8>,
+}
+
+/// 握手检查的结果
+enum Validated<S> {
+    /// 读出了 CONNECT
+    Mqtt(Handshake<S>),
+    /// WebSocket 升级请求, HTTP 请求仍在流中未读
+    WebSocket(PrefixedStream<S>),
+}
+
+/// 读出 CONNECT 的连接: 直接在传输层上, 或在 WebSocket 内
+enum Accepted<S: AsyncRead + AsyncWrite + Unpin + Send> {
+    Stream(Handshake<S>),
+    WebSocket(Box<Handshake<WebSocketStream<PrefixedStream<S>>>>),
+}
+
+/// 处理单个客户端连接: 先通过握手检查读出 CONNECT, 再连接后端
+/// 所有多协议功能共用这一个入口
+/// `tls` 为 TLS 监听器上协商的版本和密码套件, 明文连接为 None; `push_socket` 为 `client_stream` 底层的 TCP 套接字 (见 `push_socket`)
+async fn handle_smart_client<S>(
+    client_stream: S,
+    push_socket: Option<PushSocket>,
+    client_addr: SocketAddr,
+    connection_id: u64,
+    forward_addr: String,
+    state: Arc<AdapterState>,
+    tls: Option<TlsPeer>,
+) -> Result<(), AdapterError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let accepted_at = Instant::now();
+    tracing::debug!("accepted");
+    
+    let accepted = match detection_policy(&state, tls.is_some()).connect_deadline {
+        None => accept_connect(client_stream, push_socket, client_addr, &state, tls.is_some()).await,
+        Some(deadline) => tokio::time::timeout(
+            deadline,
+            accept_connect(client_stream, push_socket, client_addr, &state, tls.is_some()),
+        )
+        .await
+        .map_err(|_| {
+            metrics().record_bad_handshake(BadHandshake::Deadline);
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("no complete CONNECT within connect_deadline_ms ({} ms)", deadline.as_millis()),
+            )
+        })?,
+    };
+    // 客户端在 CONNECT 完整之前断开不是适配器的错误: 已计入 handshake_aborted_total, 只记录 debug 日志
+    let accepted = match accepted {
+        Err(AdapterError::HandshakeAborted(e)) => {
+            debug!("Client {} disconnected before sending a complete CONNECT: {}", client_addr, e);
+            return Ok(());
+        }
+        accepted => accepted?,
+    };
+    tracing::debug!(elapsed_ms = elapsed_ms(accepted_at), "connect_received");
+    
+    match accepted {
+        None => Ok(()),
+        Some(Accepted::Stream(handshake)) => {
+            handle_mqtt_client(handshake, client_addr, connection_id, forward_addr, state, accepted_at, tls).await
+        }
+        Some(Accepted::WebSocket(handshake)) => {
+            handle_mqtt_client(*handshake, client_addr, connection_id, forward_addr, state, accepted_at, tls).await
+        }
+    }
+}
+
+/// passthrough 监听器: 不读取 CONNECT, 连接后端后从第一个字节起原样双向转发
+/// accept 循环中的访问控制、准入速率和按源 IP 路由仍然生效, 基于 CONNECT 的功能全部跳过
+async fn handle_passthrough_client(
+    client_stream: TcpStream,
+    client_addr: SocketAddr,
+    connection_id: u64,
+    forward_addr: String,
+    state: Arc<AdapterState>,
+) -> Result<(), AdapterError> {
+    // 无法回复 CONNACK, 维护模式下直接关闭
+    if state.runtime.maintenance() {
+        debug!("Passthrough connection from {} closed: maintenance mode", client_addr);
+        return Ok(());
+    }
+    let broker_stream = happy_eyeballs::connect_timeout(&forward_addr, state.source_bind.as_ref(), backend_connect_timeout(&state))
+        .await
+        .map_err(AdapterError::BackendConnect)?;
+    if let Some(buffers) = &state.socket_buffers {
+        buffers.apply(&broker_stream);
+    }
+    tracing::debug!("backend_connected");
+    metrics().passthrough_connections_total.fetch_add(1, Ordering::Relaxed);
+    if state.accept_log.sampled(connection_id) {
+        info!("Sampled connection {} from {}: passthrough", connection_id, client_addr);
+    }
+    
+    // 没有客户端 ID, 只能使用全局的单连接限速
+    let rate_limit = state.config().throttle.max_bytes_per_sec;
+    let throttle = Throttle {
+        connection: (rate_limit > 0).then(|| Arc::new(TokenBucket::new(rate_limit))),
+        total: state.total_limiter.clone(),
+    };
+    let (pause_control, pause_gate) = PauseGate::channel();
+    let idle = state.idle_reaper.as_ref().map(|reaper| reaper.register(connection_id, Some(pause_gate.clone())));
+    let info = ConnectionInfo {
+        id: connection_id,
+        peer: client_addr.to_string(),
+        client_id: String::new(),
+        version: "passthrough",
+        backend: forward_addr,
+        migratable: false,
+        paused: false,
+        tls: None,
+    };
+    let trace = packet_trace(&state);
+    let mut registration = state.runtime.register_connection(info, None, Some(pause_control), trace.clone());
     
-    // 等待任一方向关闭
-    tokio::select! {
-        _ = client_to_broker => {},
-        _ = broker_to_client => {},
+    let half_close_grace = (state.config().half_close_grace_ms > 0).then(|| Duration::from_millis(state.config().half_close_grace_ms));
+    let firewall = state.packet_firewall.clone().map(PacketFirewall::new);
+    let control_push = push_socket(&state, &client_stream);
+    let taps = ForwardTaps {
+        idle,
+        firewall,
+        control_push,
+        qos_drain: qos_drain(&state),
+        // 不知道客户端的协议版本, 超过配额时直接关闭
+        byte_quota: byte_quota(&state, false),
+        packet_trace: trace,
+        splice: state.config().splice_forward,
+        ..ForwardTaps::default()
+    };
+    let close_reason = bidirectional_forward(client_stream, broker_stream, throttle, taps, half_close_grace, Some(pause_gate)).await?;
+    match close_reason {
+        CloseReason::Idle => info!("Passthrough connection from {} was idle, connection closed", client_addr),
+        CloseReason::Drained => info!("Passthrough connection from {} closed by the drain", client_addr),
+        CloseReason::PacketDenied => info!("Passthrough connection from {} sent a denied packet, connection closed", client_addr),
+        CloseReason::PublishTooLarge => {
+            info!("Passthrough connection from {} sent a PUBLISH over max_publish_size, connection closed", client_addr);
+        }
+        CloseReason::QuotaExceeded => {
+            info!("Passthrough connection from {} exceeded max_bytes_per_connection, connection closed", client_addr);
+        }
+        _ => {}
     }
+    registration.set_close_reason(close_reason);
     
     Ok(())
 }
 
-/// 读取 MQTT 剩余长度字段
-async fn read_remaining_length(stream: &mut TcpStream) -> std::io::Result<usize> {
-    let mut multiplier = 1;
-    let mut value = 0;
+/// 读出 CONNECT: WebSocket 升级请求先完成握手, 再在解包后的流上做同样的握手检查
+/// 返回 None 表示连接已经处理完毕
+async fn accept_connect<S>(
+    client_stream: S,
+    push_socket: Option<PushSocket>,
+    client_addr: SocketAddr,
+    state: &AdapterState,
+    tls: bool,
+) -> Result<Option<Accepted<S>>, AdapterError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let upgrade = match validate_handshake(client_stream, client_addr, state).await? {
+        None => return Ok(None),
+        Some(Validated::Mqtt(handshake)) => return Ok(Some(Accepted::Stream(Handshake { push_socket, ..handshake }))),
+        Some(Validated::WebSocket(_)) if !state.config().websocket => {
+            metrics().record_bad_handshake(BadHandshake::UnsupportedProtocol);
+            return Err(AdapterError::UnsupportedProtocol { protocol: Protocol::WebSocket.as_str() });
+        }
+        Some(Validated::WebSocket(upgrade)) => upgrade,
+    };
     
-    loop {
-        let mut byte = [0u8; 1];
-        stream.read_exact(&mut byte).await?;
-        
-        value += ((byte[0] & 127) as usize) * multiplier;
-        multiplier *= 128;
-        
-        if byte[0] & 128 == 0 {
-            break;
+    let ws_stream = websocket::accept(upgrade).await?;
+    debug!("WebSocket upgrade from {} ({})", client_addr, if tls { "wss" } else { "ws" });
+    metrics().record_websocket_connection(tls);
+    match validate_handshake(ws_stream, client_addr, state).await? {
+        None => Ok(None),
+        Some(Validated::Mqtt(handshake)) => Ok(Some(Accepted::WebSocket(Box::new(Handshake { push_socket, ..handshake })))),
+        // WebSocket 内不会再有升级请求
+        Some(Validated::WebSocket(_)) => {
+            metrics().record_bad_handshake(BadHandshake::UnsupportedProtocol);
+            Err(AdapterError::UnsupportedProtocol { protocol: Protocol::WebSocket.as_str() })
         }
-        
-        if multiplier > 128 * 128 * 128 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid remaining length"
-            ));
+    }
+}
+
+/// 握手检查: 按开头的字节识别协议, 只接受 MQTT CONNECT (或 WebSocket 升级请求), 并读出完整的 CONNECT
+/// 返回 None 表示连接已经处理完毕 (健康检查, 或已回复的超长 CONNECT)
+async fn validate_handshake<S>(
+    mut client_stream: S,
+    client_addr: SocketAddr,
+    state: &AdapterState,
+) -> Result<Option<Validated<S>>, AdapterError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let classified = match state.config().banner_grace_ms {
+        0 => read_and_classify(&mut client_stream).await.map_err(handshake_read_error)?,
+        grace_ms => tokio::time::timeout(Duration::from_millis(grace_ms), read_and_classify(&mut client_stream))
+            .await
+            .map_err(|_| {
+                metrics().record_bad_handshake(BadHandshake::Deadline);
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("no protocol identified within banner_grace_ms ({} ms)", grace_ms),
+                )
+            })?
+            .map_err(handshake_read_error)?,
+    };
+    let Some((protocol, peeked)) = classified else {
+        // 未发送任何数据就关闭: 负载均衡器的 TCP 健康检查,静默关闭
+        // (发送了部分数据后断开的客户端仍然按错误处理)
+        trace!("Smart adapter: Connection closed before sending data (health probe)");
+        return Ok(None);
+    };
+    
+    match protocol {
+        Protocol::Mqtt => {}
+        Protocol::WebSocket => return Ok(Some(Validated::WebSocket(PrefixedStream::new(peeked, client_stream)))),
+        Protocol::Tls | Protocol::ProxyV1 | Protocol::ProxyV2 => {
+            metrics().record_bad_handshake(BadHandshake::UnsupportedProtocol);
+            return Err(AdapterError::UnsupportedProtocol { protocol: protocol.as_str() });
+        }
+        Protocol::Unknown | Protocol::NeedMoreData => {
+            metrics().record_bad_handshake(BadHandshake::NotConnect);
+            return Err(AdapterError::NotConnect { first_byte: peeked[0] });
+        }
+    }
+    
+    // 已读取的字节由 PrefixedStream 重放
+    let mut client_stream = PrefixedStream::new(peeked, client_stream);
+    
+    // 读取 CONNECT 包的固定头 (类型已由协议识别确认)
+    let first_byte = client_stream.read_u8().await.map_err(handshake_read_error)?;
+    
+    // 读取剩余长度, 最多 `max_remaining_length_bytes` 个字节
+    let max_length_bytes = state.config().max_remaining_length_bytes;
+    let remaining_length = match read_remaining_length_bounded(&mut client_stream, max_length_bytes).await {
+        Ok(length) => length,
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            warn!(
+                "Suspicious CONNECT from {}: remaining length longer than {} bytes (max_remaining_length_bytes)",
+                client_addr, max_length_bytes
+            );
+            metrics().malformed_remaining_length_total.fetch_add(1, Ordering::Relaxed);
+            return Err(e.into());
+        }
+        Err(e) => return Err(handshake_read_error(e)),
+    };
+    
+    // 超过 max_packet_size 的 CONNECT 不读取负载, 只读协议头判断是否回复 5.0 CONNACK
+    if let Some(max_packet_size) = state.config().max_packet_size {
+        let mut length_bytes = Vec::new();
+        encode_variable_int(remaining_length, &mut length_bytes);
+        let packet_size = 1 + length_bytes.len() + remaining_length;
+        if packet_size > max_packet_size as usize {
+            info!(
+                "Rejecting {}-byte CONNECT from {} (max_packet_size {})",
+                packet_size, client_addr, max_packet_size
+            );
+            let mut header = vec![0u8; remaining_length.min(V5_PROTOCOL_HEADER.len())];
+            client_stream.read_exact(&mut header).await.map_err(handshake_read_error)?;
+            if header == V5_PROTOCOL_HEADER {
+                client_stream.write_all(&encode_connack(5, ConnackReason::PacketTooLarge)).await?;
+            }
+            client_stream.shutdown().await?;
+            return Ok(None);
         }
     }
     
-    Ok(value)
+    // 读取完整的 CONNECT 包负载
+    let mut payload = vec![0u8; remaining_length];
+    client_stream.read_exact(&mut payload).await.map_err(handshake_read_error)?;
+    
+    Ok(Some(Validated::Mqtt(Handshake { stream: client_stream, push_socket: None, first_byte, payload })))
 }
 
-/// 写入 MQTT 剩余长度字段
-async fn write_remaining_length(stream: &mut TcpStream, mut length: usize) -> std::io::Result<()> {
-    loop {
-        let mut byte = (length % 128) as u8;
-        length /= 128;
-        
-        if length > 0 {
-            byte |= 128;
+/// 握手期间的读取错误: 客户端中途断开 (EOF、连接重置) 计为 `HandshakeAborted`, 其余按普通的网络或格式错误处理
+fn handshake_read_error(e: std::io::Error) -> AdapterError {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset => {
+            metrics().handshake_aborted_total.fetch_add(1, Ordering::Relaxed);
+            AdapterError::HandshakeAborted(e)
         }
-        
-        stream.write_u8(byte).await?;
-        
-        if length == 0 {
-            break;
+        _ => e.into(),
+    }
+}
+
+/// 处理已读出 CONNECT 的 MQTT 客户端连接, 自动检测协议版本
+async fn handle_mqtt_client<S>(
+    handshake: Handshake<S>,
+    client_addr: SocketAddr,
+    connection_id: u64,
+    forward_addr: String,
+    state: Arc<AdapterState>,
+    accepted_at: Instant,
+    tls: Option<TlsPeer>,
+) -> Result<(), AdapterError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let Handshake { stream: mut client_stream, push_socket, first_byte, payload } = handshake;
+    let first_byte = [first_byte];
+    
+    // 检测协议版本, 按监听器的识别策略
+    let detection = detection_policy(&state, tls.is_some());
+    let (mqtt_version, modified_payload) = detect_and_convert_protocol(&payload, detection).inspect_err(|e| {
+        if let AdapterError::UnknownProtocol { name, level } = e {
+            log_protocol_rejection(client_addr, &payload, name, *level);
         }
+    })?;
+    if let Err(violation) = detection.check_connect_flags(&payload) {
+        info!("Rejecting CONNECT from {}: {} (strict_connect_flags)", client_addr, violation);
+        metrics().connect_flags_rejected_total.fetch_add(1, Ordering::Relaxed);
+        return Err(AdapterError::MalformedPacket(format!("invalid CONNECT flags: {}", violation)));
     }
     
-    Ok(())
+    // 记录协议版本
+    let connection_log_level = state.config().logging.connection_log_level.level();
+    let version_name = match mqtt_version {
+        MqttVersion::V310 if detection.upgrade_v310 => {
+            log!(connection_log_level, "Detected MQTT 3.1.0 client, upgrading to 3.1.1");
+            "3.1.0→3.1.1"
+        }
+        MqttVersion::V310 => {
+            log!(connection_log_level, "Detected MQTT 3.1.0 client, forwarding unchanged");
+            "3.1.0"
+        }
+        MqttVersion::V311 => {
+            log!(connection_log_level, "Detected MQTT 3.1.1 client");
+            "3.1.1"
+        }
+        MqttVersion::V500 => {
+            log!(connection_log_level, "Detected MQTT 5.0 client");
+            "5.0"
+        }
+    };
+    if state.accept_log.sampled(connection_id) {
+        match &tls {
+            Some(peer) => info!("Sampled connection {} from {}: MQTT {} over TLS {}", connection_id, client_addr, version_name, peer.info.version),
+            None => info!("Sampled connection {} from {}: MQTT {}", connection_id, client_addr, version_name),
+        }
+    }
+    
+    let mut connect = parse_connect(&modified_payload)?;
+    // 以下日志中的客户端 ID 和用户名都经过脱敏
+    let log_client_id = state.log_redactor.client_id(&connect.client_id);
+    debug!("CONNECT from {}: {}", client_addr, state.log_redactor.connect_summary(&connect));
+    let span = tracing::Span::current();
+    span.record("version", version_name);
+    span.record("client_id", &*log_client_id);
+    tracing::debug!(elapsed_ms = elapsed_ms(accepted_at), "connect_parsed");
+    
+    // 协议版本白名单: 回复 CONNACK 不接受的协议版本 (3.x 为 0x01, 5.0 为 0x84)
+    if !detection.allows(mqtt_version) {
+        info!(
+            "Rejecting MQTT {} client {:?} from {}: version not in allowed_versions",
+            mqtt_version.as_str(), log_client_id, client_addr
+        );
+        metrics().record_version_not_allowed(mqtt_version);
+        publish_adapter_closed(&state, connection_id, client_addr, &connect, "version_not_allowed");
+        client_stream.write_all(&encode_connack(connect.protocol_level, ConnackReason::UnacceptableProtocolVersion)).await?;
+        client_stream.shutdown().await?;
+        return Ok(());
+    }
+    
+    // MQTT 3.1.0 停用: 停用日期之前升级并警告, 之后拒绝
+    if mqtt_version == MqttVersion::V310 {
+        metrics().v310_deprecated_total.fetch_add(1, Ordering::Relaxed);
+        if detection.v310_sunset.is_past() {
+            info!("Rejecting MQTT 3.1.0 client {:?} from {}: MQIsdp support has been sunset", log_client_id, client_addr);
+            publish_adapter_closed(&state, connection_id, client_addr, &connect, "v310_sunset");
+            client_stream.write_all(&encode_connack(connect.protocol_level, ConnackReason::UnacceptableProtocolVersion)).await?;
+            client_stream.shutdown().await?;
+            return Ok(());
+        }
+        warn!(
+            "Deprecated MQTT 3.1.0 client {:?} from {}: MQIsdp support is being sunset{}",
+            log_client_id,
+            client_addr,
+            state.config().v310_sunset_date.as_deref().map(|date| format!(" on {}", date)).unwrap_or_default(),
+        );
+    }
+    
+    // 维护模式: 拒绝新连接 (5.0 回复 CONNACK 0x88, 3.x 直接关闭)
+    if state.runtime.maintenance() {
+        info!("Maintenance mode: rejecting client {:?} from {}", log_client_id, client_addr);
+        publish_adapter_closed(&state, connection_id, client_addr, &connect, "maintenance");
+        if connect.protocol_level == 5 {
+            client_stream.write_all(&encode_connack(5, ConnackReason::ServerUnavailable)).await?;
+        }
+        client_stream.shutdown().await?;
+        return Ok(());
+    }
+    
+    // 运行时拒绝列表: 回复 CONNACK Not authorized
+    if state.runtime.denylist().contains(&connect.client_id) {
+        info!("Denylist: rejecting client {:?} from {}", log_client_id, client_addr);
+        metrics().connection_denied_client_id_total.fetch_add(1, Ordering::Relaxed);
+        publish_adapter_closed(&state, connection_id, client_addr, &connect, "denylist");
+        reject_connect(&state, client_stream, encode_connack(connect.protocol_level, ConnackReason::NotAuthorized)).await?;
+        return Ok(());
+    }
+    
+    // 认证钩子: 拒绝时直接回复 CONNACK, 不连接 broker
+    if let Some(authenticator) = &state.authenticator {
+        let request = AuthRequest { peer: client_addr, connect: &connect, log_client_id: &log_client_id };
+        if let AuthDecision::Deny(reason) = authenticator.authenticate(&request) {
+            publish_adapter_closed(&state, connection_id, client_addr, &connect, "auth_denied");
+            reject_connect(&state, client_stream, encode_connack(connect.protocol_level, reason)).await?;
+            return Ok(());
+        }
+    }
+    
+    // 活动连接上限: 满时按租户排队, 许可在连接结束时释放
+    let _admission = match state.runtime.admission() {
+        Some(admission) => {
+            let tenant = admission.tenant_of(&connect.client_id);
+            match admission.admit(tenant).await {
+                Ok(permit) => Some(permit),
+                Err(rejection) => {
+                    info!(
+                        "Admission: rejecting client {:?} from {} ({})",
+                        log_client_id, client_addr, rejection.as_str()
+                    );
+                    publish_adapter_closed(&state, connection_id, client_addr, &connect, rejection.closed_reason());
+                    // 租户配额: 3.x 没有对应的返回码, 直接关闭
+                    let reason = match rejection {
+                        AdmissionRejection::QuotaExceeded => ConnackReason::QuotaExceeded,
+                        _ => ConnackReason::ServerBusy,
+                    };
+                    if reason != ConnackReason::QuotaExceeded || connect.protocol_level == 5 {
+                        client_stream.write_all(&encode_connack(connect.protocol_level, reason)).await?;
+                    }
+                    client_stream.shutdown().await?;
+                    return Ok(());
+                }
+            }
+        }
+        None => None,
+    };
+    
+    // CONNECT 改写管道: 之后的逻辑 (包括代替 broker 回复的 CONNACK) 都按改写后的 CONNECT 处理
+    // 限速规则和连接列表仍按客户端自己的 ID (client_id_prefix 只改写转发给后端的 CONNECT)
+    let log_client_id = log_client_id.into_owned();
+    let client_id = connect.client_id.clone();
+    let modified_payload = if state.connect_pipeline.is_empty() {
+        modified_payload
+    } else {
+        let context = TransformContext { peer: client_addr, log_client_id: &log_client_id, tls: tls.as_ref() };
+        if let Err(rejection) = state.connect_pipeline.apply(&mut connect, &context) {
+            publish_adapter_closed(&state, connection_id, client_addr, &connect, rejection.transform);
+            reject_connect(&state, client_stream, encode_connack(connect.protocol_level, rejection.reason)).await?;
+            return Ok(());
+        }
+        encode_connect(&modified_payload, &connect)?
+    };
+    
+    // 按主题路由: 先代替 broker 接受连接, 按第一个 PUBLISH/SUBSCRIBE 的主题选择后端
+    // 这打破了 "收到 CONNECT 立即连接后端" 的前提: 客户端收到的是适配器编码的 CONNACK,
+    // 没有后端的 CONNACK 属性, 会话存在标志总是 0, 之后后端的 CONNACK 只检查不转发
+    let mut forward_addr = forward_addr;
+    let mut deferred_packets = Vec::new();
+    if let Some(router) = &state.topic_router {
+        // 保留会话的连接在回复 CONNACK 时还不知道会话在哪个后端上, 无法如实应答, 只接受临时会话
+        if !connect.has_transient_session() {
+            info!(
+                "Topic routing: rejecting client {:?} from {}: persistent sessions are not supported",
+                log_client_id, client_addr
+            );
+            metrics().topic_routing_session_rejected_total.fetch_add(1, Ordering::Relaxed);
+            publish_adapter_closed(&state, connection_id, client_addr, &connect, "topic_routing_persistent_session");
+            client_stream.write_all(&encode_connack(connect.protocol_level, ConnackReason::ImplementationSpecificError)).await?;
+            client_stream.shutdown().await?;
+            return Ok(());
+        }
+        let mut connack = encode_connack_accepted(connect.protocol_level);
+        if state.response_rewriter.applies_to(&connect) {
+            connack = encode_packet(connack[0], &state.response_rewriter.rewrite_connack(&connect, &connack[2..])?);
+        }
+        client_stream.write_all(&connack).await?;
+        
+        let Some(deferred) = router.wait_for_topic(&mut client_stream, connect.protocol_level).await? else {
+            debug!("Client {:?} disconnected before sending a topic", log_client_id);
+            return Ok(());
+        };
+        if let Some(backend) = router.route(&deferred.topic) {
+            forward_addr = backend.to_string();
+        }
+        debug!("Routing client {:?} to {} by topic {:?}", log_client_id, forward_addr, deferred.topic);
+        deferred_packets = deferred.buffered;
+    }
+    
+    // 连接到 broker (rumqttd 会自动识别 3.1.1 和 5.0) 并发送(可能修改过的) CONNECT 包
+    let connect_packet = encode_packet(first_byte[0], &modified_payload);
+    let (mut broker_stream, connack_latency) = connect_backend(&state, &forward_addr, &connect_packet, accepted_at).await?;
+    
+    debug!("Forwarded CONNECT packet to {} broker", version_name);
+    let verify_connack = state.config().observer_on == ObserverOn::Connack;
+    
+    if state.topic_router.is_some() {
+        // 客户端已经收到适配器的 CONNACK, 后端的 CONNACK 只检查不转发
+        let (connack_header, connack) = read_backend_connack(&state, &mut broker_stream).await?;
+        
+        if connack_header >> 4 != 2 || connack.get(1) != Some(&0) {
+            if verify_connack && let Some(&reason_code) = connack.get(1) {
+                publish_connect_rejected(&state, connection_id, client_addr, &connect, &forward_addr, reason_code);
+            }
+            return Err(AdapterError::BackendConnect(std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                format!("backend {} refused CONNECT for a topic-routed client", forward_addr),
+            )));
+        }
+    } else {
+        // broker 的首个响应 (CONNACK) 已在 connect_backend 中 peek 到, 数据仍由转发循环发给客户端
+        if let Some(connack_latency) = connack_latency {
+            tracing::debug!(elapsed_ms = elapsed_ms(accepted_at), "connack_received");
+        
+            metrics().connack_latency.observe(connack_latency, connection_id);
+            let threshold = state.config().slow_connack_threshold_ms;
+            if threshold > 0 && connack_latency.as_millis() >= threshold as u128 {
+                warn!(
+                    "Slow CONNACK from backend for client {:?}: {:.1} ms (threshold {} ms)",
+                    log_client_id, connack_latency.as_secs_f64() * 1000.0, threshold
+                );
+            }
+        }
+        
+        // 改写 5.0 CONNACK 或检查原因码: 完整读取 broker 的 CONNACK, (改写后) 再发给客户端
+        let rewrite_connack = state.response_rewriter.applies_to(&connect);
+        if rewrite_connack || verify_connack {
+            let (connack_header, mut connack) = read_backend_connack(&state, &mut broker_stream).await?;
+        
+            if connack_header >> 4 != 2 {
+                return Err(AdapterError::MalformedPacket("Expected CONNACK packet from backend".to_string()));
+            }
+            // 3.1.1 的返回码和 5.0 的原因码都在标志字节之后
+            let reason_code = *connack.get(1).ok_or_else(truncated)?;
+        
+            if rewrite_connack {
+                connack = state.response_rewriter.rewrite_connack(&connect, &connack)?;
+                debug!("Rewrote CONNACK for client {:?}", log_client_id);
+            }
+            client_stream.write_all(&encode_packet(connack_header, &connack)).await?;
+        
+            // 被拒绝的连接不登记, 订阅者只会收到 connect_rejected
+            if verify_connack && reason_code != 0 {
+                info!("Backend {} rejected client {:?} with reason code 0x{:02x}", forward_addr, log_client_id, reason_code);
+                publish_connect_rejected(&state, connection_id, client_addr, &connect, &forward_addr, reason_code);
+                client_stream.shutdown().await?;
+                return Ok(());
+            }
+        } else if connect.protocol_level != 5 && state.config().max_keepalive_sec.is_some() {
+            debug!("max_keepalive_sec not enforced for MQTT 3.x client {:?} (no Server Keep Alive in 3.x)", log_client_id);
+        }
+    }
+    
+    // 按客户端 ID 确定带宽限制
+    let rate_limit = state.config().throttle.limit_for(&client_id);
+    let limiter = (rate_limit > 0).then(|| {
+        debug!("Throttling client {:?} to {} bytes/s", log_client_id, rate_limit);
+        Arc::new(TokenBucket::new(rate_limit))
+    });
+    
+    // 登记到连接列表 (GET /connections), 启用迁移时附带迁移请求通道
+    // 未启用迁移时附带暂停控制 (迁移模式的转发循环不支持暂停)
+    let (control_tx, control_rx) = if state.config().backend_migration {
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+    let (pause_control, pause_gate) = match control_tx {
+        None => {
+            let (control, gate) = PauseGate::channel();
+            (Some(control), Some(gate))
+        }
+        Some(_) => (None, None),
+    };
+    let firewall = match &tls {
+        Some(_) => state.tls_packet_firewall.clone(),
+        None => state.packet_firewall.clone(),
+    }
+    .map(PacketFirewall::new);
+    let info = ConnectionInfo {
+        id: connection_id,
+        peer: client_addr.to_string(),
+        client_id,
+        version: version_name,
+        backend: forward_addr.clone(),
+        migratable: control_tx.is_some(),
+        paused: false,
+        tls: tls.map(|peer| peer.info),
+    };
+    // 包序列记录同样不支持迁移模式
+    let trace = control_tx.is_none().then(|| packet_trace(&state)).flatten();
+    let mut registration = state.runtime.register_connection(info, control_tx, pause_control, trace.clone());
+    if let Some(previous) = registration.replaced() {
+        info!(
+            "Client {:?} from {} connected while connection {} with the same client ID is still open; the broker takes over the session",
+            log_client_id, client_addr, previous
+        );
+    }
+    
+    // 影子后端: 复制 CONNECT 和之后客户端发往 broker 的数据, 不影响在线连接
+    let shadow = state.config().shadow.as_ref()
+        .map(|shadow| ShadowSink::spawn(shadow, encode_packet(first_byte[0], &modified_payload), state.source_bind.clone()));
+    
+    // 请求/响应诊断日志 (只解析 5.0 连接)
+    let request_response_log = (state.config().log_request_response && connect.protocol_level == 5).then(|| {
+        request_response::log_connect(&log_client_id, &connect.properties);
+        log_client_id.to_string()
+    });
+    
+    // 双向转发剩余数据 (先发送延迟连接期间缓存的包)
+    let client_stream = PrefixedStream::new(deferred_packets, client_stream);
+    let close_reason = if let Some(control) = control_rx {
+        let session = MigratableSession {
+            connection_id,
+            connect_packet: encode_packet(first_byte[0], &modified_payload),
+            transient_session: connect.has_transient_session(),
+            runtime: state.runtime.clone(),
+            control,
+            request_response_log,
+            source: state.source_bind.clone(),
+            qos_drain_grace: (state.config().qos_drain_grace_ms > 0).then(|| Duration::from_millis(state.config().qos_drain_grace_ms)),
+        };
+        forward_with_migration(client_stream, broker_stream, limiter, state.total_limiter.clone(), shadow, firewall, session).await?
+    } else {
+        let half_close_grace = (state.config().half_close_grace_ms > 0).then(|| Duration::from_millis(state.config().half_close_grace_ms));
+        let throttle = Throttle { connection: limiter, total: state.total_limiter.clone() };
+        let idle = state.idle_reaper.as_ref().map(|reaper| reaper.register(connection_id, pause_gate.clone()));
+        let reject_second_connect = state.config().reject_second_connect;
+        let protocol_close = (mqtt_version == MqttVersion::V500 && (reject_second_connect || firewall.is_some())).then(ProtocolClose::default);
+        let taps = ForwardTaps {
+            shadow,
+            request_response_log,
+            reject_second_connect,
+            protocol_close,
+            idle,
+            firewall,
+            control_push: push_socket,
+            qos_drain: qos_drain(&state),
+            byte_quota: byte_quota(&state, mqtt_version == MqttVersion::V500),
+            packet_trace: trace,
+            backend_close: (state.config().disconnect_on_backend_close && mqtt_version == MqttVersion::V500)
+                .then(BackendCloseNotice::default),
+            splice: state.config().splice_forward,
+        };
+        bidirectional_forward(client_stream, broker_stream, throttle, taps, half_close_grace, pause_gate).await?
+    };
+    match close_reason {
+        CloseReason::SecondConnect => {
+            warn!("Client {:?} from {} sent a second CONNECT, connection closed", log_client_id, client_addr);
+            metrics().second_connect_total.fetch_add(1, Ordering::Relaxed);
+        }
+        CloseReason::Idle => info!("Client {:?} from {} was idle, connection closed", log_client_id, client_addr),
+        CloseReason::Drained => info!("Client {:?} from {} closed by the drain", log_client_id, client_addr),
+        CloseReason::PacketDenied => info!("Client {:?} from {} sent a denied packet, connection closed", log_client_id, client_addr),
+        CloseReason::PublishTooLarge => {
+            info!("Client {:?} from {} sent a PUBLISH over max_publish_size, connection closed", log_client_id, client_addr);
+        }
+        CloseReason::QuotaExceeded => {
+            info!("Client {:?} from {} exceeded max_bytes_per_connection, connection closed", log_client_id, client_addr);
+        }
+        _ => {}
+    }
+    registration.set_close_reason(close_reason);
+    
+    Ok(())
+}
+
+/// 连接后端并发送 CONNECT, 优先使用预热连接; 返回连接和 CONNACK 首字节到达的延迟
+/// 连接失败 (包括超过 `backend_connect_timeout_ms`)、CONNECT 写入失败、超过 `backend_connack_timeout_ms` 没有响应或后端在响应之前关闭时丢弃该连接, 在新连接上重新发送完整的 CONNECT
+/// (最多 `backend_connect_retries` 次), 所以后端不会在同一个连接上收到重复或写了一半的 CONNECT
+/// 最后一次尝试时后端未响应就关闭, 仍返回该连接 (延迟为 None), 由转发循环结束连接
+async fn connect_backend(
+    state: &AdapterState,
+    forward_addr: &str,
+    connect_packet: &[u8],
+    accepted_at: Instant,
+) -> Result<(TcpStream, Option<Duration>), AdapterError> {
+    let retries = state.config().backend_connect_retries;
+    let mut attempt = 0;
+    loop {
+        let last_attempt = attempt >= retries;
+        // 预热连接只用于第一次尝试, 重试总是新建连接
+        let pooled = state.warm_pool.as_ref()
+            .filter(|pool| attempt == 0 && pool.backend() == forward_addr)
+            .and_then(|pool| pool.take());
+        let connected = match pooled {
+            Some(stream) => Ok(stream),
+            None => happy_eyeballs::connect_timeout(forward_addr, state.source_bind.as_ref(), backend_connect_timeout(state))
+                .await
+                .map_err(AdapterError::BackendConnect),
+        };
+        let e = match connected {
+            Ok(mut stream) => {
+                if let Some(buffers) = &state.socket_buffers {
+                    buffers.apply(&stream);
+                }
+                tracing::debug!(elapsed_ms = elapsed_ms(accepted_at), "backend_connected");
+                match send_connect(&mut stream, connect_packet, backend_connack_timeout(state)).await {
+                    Ok(Some(latency)) => return Ok((stream, Some(latency))),
+                    Ok(None) if last_attempt => return Ok((stream, None)),
+                    Ok(None) => AdapterError::BackendConnect(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "backend closed the connection before responding to CONNECT",
+                    )),
+                    // 接受了连接却不响应的后端按连接失败处理
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => AdapterError::BackendConnect(e),
+                    Err(e) => AdapterError::Io(e),
+                }
+            }
+            Err(e) => e,
+        };
+        
+        if last_attempt {
+            return Err(e);
+        }
+        attempt += 1;
+        metrics().backend_connect_retries_total.fetch_add(1, Ordering::Relaxed);
+        debug!("Backend {} attempt failed ({}), retrying ({}/{})", forward_addr, e, attempt, retries);
+        tokio::time::sleep(Duration::from_millis(state.config().backend_connect_retry_delay_ms)).await;
+    }
+}
+
+/// 连接所在监听器的协议识别策略
+fn detection_policy(state: &AdapterState, tls: bool) -> &DetectionPolicy {
+    if tls { &state.tls_detection } else { &state.detection }
+}
+
+/// 单次后端连接尝试的超时, `backend_connect_timeout_ms` 为 0 时使用系统的连接超时
+fn backend_connect_timeout(state: &AdapterState) -> Option<Duration> {
+    let timeout_ms = state.config().backend_connect_timeout_ms;
+    (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms))
+}
+
+/// 启动时说明 `splice_forward` 是否生效
+fn log_splice_support() {
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    if splice::available() {
+        info!("  - Zero-copy: splice(2) forwarding for plain TCP connections without packet inspection");
+    } else {
+        warn!("splice_forward is enabled but splice(2) is not available, using the regular forwarding loop");
+    }
+    #[cfg(not(all(feature = "splice", target_os = "linux")))]
+    warn!("splice_forward requires Linux and the splice feature, using the regular forwarding loop");
+}
+
+/// 控制包立即发送使用的客户端套接字副本, `nodelay_control_packets` 关闭或复制失败时为 None
+fn push_socket(state: &AdapterState, stream: &TcpStream) -> Option<PushSocket> {
+    if !state.config().nodelay_control_packets {
+        return None;
+    }
+    PushSocket::new(stream).inspect_err(|e| debug!("Failed to duplicate the client socket: {}", e)).ok()
+}
+
+/// 排空时按 QoS 交互关闭连接, `qos_drain_grace_ms` 为 0 时不启用
+fn qos_drain(state: &AdapterState) -> Option<QosDrain> {
+    let grace_ms = state.config().qos_drain_grace_ms;
+    (grace_ms > 0).then(|| QosDrain::new(state.runtime.drain_signal(), Duration::from_millis(grace_ms)))
+}
+
+/// 启用 `packet_trace_max_packets` 时每个连接的包序列记录, 由管理接口开启
+fn packet_trace(state: &AdapterState) -> Option<Arc<PacketTrace>> {
+    (state.config().packet_trace_max_packets > 0).then(|| Arc::new(PacketTrace::default()))
+}
+
+/// 启用 `max_bytes_per_connection` 时的流量配额, `disconnect` 为 true 时关闭前给客户端发送 DISCONNECT
+fn byte_quota(state: &AdapterState, disconnect: bool) -> Option<ByteQuota> {
+    let limit = state.config().throttle.max_bytes_per_connection;
+    (limit > 0).then(|| ByteQuota::new(limit, disconnect))
+}
+
+/// 发送 CONNECT 并等待 broker 的首个响应字节 (只 peek 不消费), 后端未响应就关闭时返回 None
+/// 超过 `connack_timeout` 仍没有响应时返回 `TimedOut`
+async fn send_connect(stream: &mut TcpStream, connect_packet: &[u8], connack_timeout: Option<Duration>) -> std::io::Result<Option<Duration>> {
+    stream.write_all(connect_packet).await?;
+    stream.flush().await?;
+    let connect_forwarded_at = Instant::now();
+    
+    let mut peek_buf = [0u8; 1];
+    let peeked = with_connack_timeout(connack_timeout, stream.peek(&mut peek_buf)).await?;
+    Ok((peeked > 0).then(|| connect_forwarded_at.elapsed()))
+}
+
+/// 等待后端 CONNACK 的超时, `backend_connack_timeout_ms` 为 0 时不限
+fn backend_connack_timeout(state: &AdapterState) -> Option<Duration> {
+    let timeout_ms = state.config().backend_connack_timeout_ms;
+    (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms))
+}
+
+/// 在 `connack_timeout` 内完成读取后端 CONNACK 的操作, 超时返回 `TimedOut`
+async fn with_connack_timeout<T>(
+    connack_timeout: Option<Duration>,
+    read: impl Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    let Some(connack_timeout) = connack_timeout else {
+        return read.await;
+    };
+    tokio::time::timeout(connack_timeout, read).await.unwrap_or_else(|_| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("backend did not send CONNACK within {:?}", connack_timeout),
+        ))
+    })
+}
+
+/// 完整读取后端的 CONNACK (固定头首字节和剩余部分), 整体不超过 `backend_connack_timeout_ms`
+/// 超时计为后端故障
+async fn read_backend_connack(state: &AdapterState, stream: &mut TcpStream) -> Result<(u8, Vec<u8>), AdapterError> {
+    let read = async {
+        let header = stream.read_u8().await?;
+        let length = read_remaining_length(&mut *stream).await?;
+        let mut connack = vec![0u8; length];
+        stream.read_exact(&mut connack).await?;
+        Ok((header, connack))
+    };
+    with_connack_timeout(backend_connack_timeout(state), read).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::TimedOut => AdapterError::BackendConnect(e),
+        _ => AdapterError::Io(e),
+    })
+}
+
+/// 向事件订阅者发布后端拒绝连接
+fn publish_connect_rejected(
+    state: &AdapterState,
+    connection_id: u64,
+    client_addr: SocketAddr,
+    connect: &ConnectPacket,
+    backend: &str,
+    reason_code: u8,
+) {
+    state.runtime.publish_event(ConnectionEvent::ConnectRejected {
+        id: connection_id,
+        peer: client_addr.to_string(),
+        client_id: connect.client_id.clone(),
+        backend: backend.to_string(),
+        reason_code,
+        timestamp_ms: now_ms(),
+    });
+}
+
+/// 向事件订阅者发布适配器主动关闭连接, `reason` 为固定的原因名称
+fn publish_adapter_closed(state: &AdapterState, connection_id: u64, client_addr: SocketAddr, connect: &ConnectPacket, reason: &'static str) {
+    state.runtime.publish_event(ConnectionEvent::AdapterClosed {
+        id: connection_id,
+        peer: client_addr.to_string(),
+        client_id: connect.client_id.clone(),
+        reason,
+        timestamp_ms: now_ms(),
+    });
+}
+
+/// 从 `since` 起经过的毫秒数
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// 检测 MQTT 协议版本并按识别策略转换
+/// `lenient_legacy` 时接受 MQIsdp 的非标准变体 (见 `legacy_name_quirks`), `upgrade_v310` 时把 3.1.0 改写为 3.1.1
+/// 返回: (协议版本, 可能修改后的负载)
+pub fn detect_and_convert_protocol(payload: &[u8], policy: &DetectionPolicy) -> Result<(MqttVersion, Vec<u8>), AdapterError> {
+    if payload.len() < 8 {
+        return Err(AdapterError::MalformedPacket("CONNECT packet too short".to_string()));
+    }
+    
+    // 读取协议名称长度
+    let protocol_name_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    
+    if payload.len() < 2 + protocol_name_len + 1 {
+        return Err(AdapterError::MalformedPacket("Invalid CONNECT packet".to_string()));
+    }
+    
+    let protocol_name = &payload[2..2 + protocol_name_len];
+    let protocol_level = payload[2 + protocol_name_len];
+    
+    // 宽松模式: 旧设备发送的 MQIsdp 变体按 MQIsdp 处理, 升级时协议名总是改写为 "MQTT"
+    let quirks = (policy.lenient_legacy && protocol_level == 3)
+        .then(|| legacy_name_quirks(protocol_name))
+        .flatten()
+        .filter(|quirks| !quirks.is_empty());
+    let protocol_name = match quirks {
+        Some(quirks) => {
+            info!(
+                "Accepted non-standard MQTT 3.1.0 protocol name {:?} (lenient_legacy: {})",
+                String::from_utf8_lossy(protocol_name), quirks.join(", ")
+            );
+            b"MQIsdp".as_slice()
+        }
+        None => protocol_name,
+    };
+    
+    // 检测协议版本
+    match (protocol_name, protocol_level) {
+        // MQTT 3.1.0: MQIsdp, level 3
+        (b"MQIsdp", 3) if !policy.upgrade_v310 => {
+            Ok((MqttVersion::V310, payload.to_vec()))
+        }
+        (b"MQIsdp", 3) => {
+            // 需要转换为 MQTT 3.1.1
+            let mut new_payload = Vec::new();
+            
+            // 新的协议名称: "MQTT" (4 字节)
+            new_payload.extend_from_slice(&[0, 4]);
+            new_payload.extend_from_slice(b"MQTT");
+            new_payload.push(4); // MQTT 3.1.1 协议级别
+            
+            // 复制剩余字段
+            new_payload.extend_from_slice(&payload[2 + protocol_name_len + 1..]);
+            
+            Ok((MqttVersion::V310, new_payload))
+        }
+        
+        // MQTT 3.1.1: MQTT, level 4
+        (b"MQTT", 4) => {
+            Ok((MqttVersion::V311, payload.to_vec()))
+        }
+        
+        // MQTT 5.0: MQTT, level 5
+        (b"MQTT", 5) => {
+            Ok((MqttVersion::V500, payload.to_vec()))
+        }
+        
+        _ => {
+            Err(AdapterError::UnknownProtocol {
+                name: String::from_utf8_lossy(protocol_name).into_owned(),
+                level: protocol_level,
+            })
+        }
+    }
+}
+
+/// 宽松模式下容忍的 MQIsdp 协议名差异: 大小写不同、末尾多出 NUL 字节
+/// 返回容忍了哪些差异 (精确匹配时为空), 不是 MQIsdp 的变体时返回 None
+fn legacy_name_quirks(name: &[u8]) -> Option<Vec<&'static str>> {
+    let mut quirks = Vec::new();
+    
+    let trimmed_len = name.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+    let trimmed = &name[..trimmed_len];
+    if trimmed_len != name.len() {
+        quirks.push("trailing NUL");
+    }
+    
+    if trimmed != b"MQIsdp" {
+        if !trimmed.eq_ignore_ascii_case(b"MQIsdp") {
+            return None;
+        }
+        quirks.push("letter case");
+    }
+    Some(quirks)
+}
+
+/// 协议被拒绝时 debug 日志中最多输出的 CONNECT 字节数
+const PROTOCOL_DUMP_LIMIT: usize = 32;
+
+/// 记录无法识别的协议: 计入 `protocol_rejections_total`, 并在 debug 级别输出 CONNECT 开头的字节
+/// (协议名和级别的 warn 日志由调用方的错误日志输出)
+/// 只输出到连接标志和保活时间为止, 不会包含之后的客户端 ID、用户名和密码
+fn log_protocol_rejection(client_addr: SocketAddr, payload: &[u8], name: &str, level: u8) {
+    metrics().record_protocol_rejection(name, level);
+    
+    // 协议名长度 + 协议名 + 级别 + 连接标志 + 保活时间
+    let protocol_name_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    let dump_len = (2 + protocol_name_len + 4).min(PROTOCOL_DUMP_LIMIT).min(payload.len());
+    let dump: Vec<String> = payload[..dump_len].iter().map(|byte| format!("{:02x}", byte)).collect();
+    debug!(
+        "Rejected protocol {:?} level {} from {}: CONNECT variable header {} ({} of {} bytes)",
+        name, level, client_addr, dump.join(" "), dump_len, payload.len()
+    );
+}
+
+/// 转发使用的令牌桶
+#[derive(Clone)]
+struct Throttle {
+    /// 该连接两个方向共用
+    connection: Option<Arc<TokenBucket>>,
+    /// 所有连接共享
+    total: Option<Arc<TokenBucket>>,
+}
+
+impl Throttle {
+    /// 先按单连接限速再取全局令牌, 等待单连接限速时不占用全局带宽
+    async fn acquire(&self, amount: usize) {
+        if let Some(connection) = &self.connection {
+            connection.acquire(amount).await;
+        }
+        if let Some(total) = &self.total {
+            total.acquire(amount).await;
+        }
+    }
+}
+
+/// 转发时对数据的附加处理, 除包类型过滤外都不改变转发的字节
+#[derive(Default)]
+struct ForwardTaps {
+    /// 接收客户端发往 broker 的数据副本
+    shadow: Option<ShadowSink>,
+    /// 不为空时记录两个方向的 Response Topic (日志中使用的客户端 ID)
+    request_response_log: Option<String>,
+    /// 检测客户端发往 broker 方向的第二个 CONNECT, 检测到时断开连接
+    reject_second_connect: bool,
+    /// 因重复 CONNECT 或 PUBLISH 过长断开时, 先给 MQTT 5.0 客户端发送 DISCONNECT
+    protocol_close: Option<ProtocolClose>,
+    /// 空闲检测: 两个方向转发数据时更新活动时间, 空闲超时时断开连接
+    idle: Option<IdleHandle>,
+    /// 客户端发往 broker 方向的包类型过滤和 PUBLISH 长度限制
+    firewall: Option<PacketFirewall>,
+    /// 客户端套接字, 不为空时两个方向的控制包都立即发送 (`nodelay_control_packets`)
+    control_push: Option<PushSocket>,
+    /// 排空时等待两个方向的 QoS 交互结束后断开连接
+    qos_drain: Option<QosDrain>,
+    /// 两个方向合计的流量配额, 超过时断开连接 (`max_bytes_per_connection`)
+    byte_quota: Option<ByteQuota>,
+    /// 管理接口按连接开启的包序列记录 (`packet_trace_max_packets`)
+    packet_trace: Option<Arc<PacketTrace>>,
+    /// 后端意外断开时先给 MQTT 5.0 客户端发送 DISCONNECT (`disconnect_on_backend_close`)
+    backend_close: Option<BackendCloseNotice>,
+    /// 没有其它需要检查数据的处理时, 明文 TCP 连接用 splice 转发 (`splice_forward`)
+    #[cfg_attr(not(all(feature = "splice", target_os = "linux")), allow(dead_code))]
+    splice: bool,
+}
+
+/// 单方向转发的附加处理
+#[derive(Default)]
+struct DirectionTaps {
+    shadow: Option<ShadowSink>,
+    request_response: Option<RequestResponseTap>,
+    second_connect: Option<SecondConnectDetector>,
+    protocol_close: Option<ProtocolCloseTap>,
+    idle: Option<Arc<IdleTracker>>,
+    firewall: Option<PacketFirewall>,
+    control_push: Option<ControlPush>,
+    inflight: Option<InflightTap>,
+    quota: Option<QuotaTap>,
+    trace: Option<TraceTap>,
+    backend_close: Option<BackendCloseTap>,
+}
+
+/// 超过流量配额或客户端违规后, 最多等待这么久把 DISCONNECT 发给客户端
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 双向转发数据流
+/// `half_close_grace` 不为空时, 一个方向 EOF 后只关闭该方向的写端, 另一方向最多再转发这么久
+async fn bidirectional_forward<S>(
+    client_stream: S,
+    broker_stream: TcpStream,
+    throttle: Throttle,
+    taps: ForwardTaps,
+    half_close_grace: Option<Duration>,
+    pause: Option<PauseGate>,
+) -> std::io::Result<CloseReason>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let throttled = throttle.connection.is_some();
+    if throttled {
+        metrics().throttled_connections.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    let half_close = half_close_grace.is_some();
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    let spliced = spawn_splice(client_stream, broker_stream, &taps, &throttle, half_close, &pause);
+    #[cfg(not(all(feature = "splice", target_os = "linux")))]
+    let spliced: Result<_, (S, TcpStream)> = Err((client_stream, broker_stream));
+    let (mut client_to_broker, mut broker_to_client) = match spliced {
+        Ok(handles) => handles,
+        Err((client_stream, broker_stream)) => {
+            let broker_push = taps.control_push.as_ref().and_then(|_| {
+                PushSocket::new(&broker_stream).inspect_err(|e| debug!("Failed to duplicate the broker socket: {}", e)).ok()
+            });
+            let (client_read, client_write) = tokio::io::split(client_stream);
+            let (broker_read, broker_write) = broker_stream.into_split();
+            
+            let (client_tap, broker_tap) = match taps.request_response_log {
+                Some(client_id) => (
+                    Some(RequestResponseTap::new(client_id.clone(), Direction::ClientToBroker)),
+                    Some(RequestResponseTap::new(client_id, Direction::BrokerToClient)),
+                ),
+                None => (None, None),
+            };
+            let client_taps = DirectionTaps {
+                shadow: taps.shadow,
+                request_response: client_tap,
+                second_connect: taps.reject_second_connect.then(SecondConnectDetector::default),
+                idle: taps.idle.as_ref().map(IdleHandle::tracker),
+                firewall: taps.firewall,
+                control_push: broker_push.map(ControlPush::new),
+                inflight: taps.qos_drain.as_ref().map(|drain| drain.tap(Direction::ClientToBroker)),
+                quota: taps.byte_quota.as_ref().map(|quota| quota.tap(Direction::ClientToBroker)),
+                trace: taps.packet_trace.as_ref().map(|trace| trace.tap(Direction::ClientToBroker)),
+                backend_close: taps.backend_close.as_ref().map(|notice| notice.tap(Direction::ClientToBroker)),
+                protocol_close: taps.protocol_close.as_ref().map(|close| close.tap(Direction::ClientToBroker)),
+            };
+            let broker_taps = DirectionTaps {
+                request_response: broker_tap,
+                idle: taps.idle.as_ref().map(IdleHandle::tracker),
+                control_push: taps.control_push.map(ControlPush::new),
+                inflight: taps.qos_drain.as_ref().map(|drain| drain.tap(Direction::BrokerToClient)),
+                quota: taps.byte_quota.as_ref().map(|quota| quota.tap(Direction::BrokerToClient)),
+                trace: taps.packet_trace.as_ref().map(|trace| trace.tap(Direction::BrokerToClient)),
+                backend_close: taps.backend_close.as_ref().map(|notice| notice.tap(Direction::BrokerToClient)),
+                protocol_close: taps.protocol_close.as_ref().map(|close| close.tap(Direction::BrokerToClient)),
+                ..DirectionTaps::default()
+            };
+            (
+                tokio::spawn(forward_loop(client_read, broker_write, throttle.clone(), client_taps, half_close, pause.clone())),
+                tokio::spawn(forward_loop(broker_read, client_write, throttle, broker_taps, half_close, pause)),
+            )
+        }
+    };
+    
+    let idle_expired = async {
+        match &taps.idle {
+            Some(idle) => idle.expired().await,
+            None => std::future::pending().await,
+        }
+    };
+    let drained = async {
+        match taps.qos_drain {
+            Some(drain) => drain.closed().await,
+            None => std::future::pending().await,
+        }
+    };
+    
+    // 等待任一方向关闭、超过流量配额、空闲超时或排空
+    let close_reason = tokio::select! {
+        ended = &mut client_to_broker => match ended {
+            Ok(ForwardEnd::SecondConnect) => CloseReason::SecondConnect,
+            Ok(ForwardEnd::Denied(FirewallDenial::PublishTooLarge(_))) => CloseReason::PublishTooLarge,
+            Ok(ForwardEnd::Denied(_)) => CloseReason::PacketDenied,
+            Ok(ForwardEnd::QuotaExceeded) => CloseReason::QuotaExceeded,
+            _ => CloseReason::ClientClosed,
+        },
+        ended = &mut broker_to_client => match ended {
+            Ok(ForwardEnd::QuotaExceeded) => CloseReason::QuotaExceeded,
+            Ok(ForwardEnd::Violation(ProtocolViolation::SecondConnect)) => CloseReason::SecondConnect,
+            Ok(ForwardEnd::Violation(ProtocolViolation::PublishTooLarge)) => CloseReason::PublishTooLarge,
+            _ => CloseReason::BackendClosed,
+        },
+        _ = idle_expired => {
+            metrics().idle_reaped_total.fetch_add(1, Ordering::Relaxed);
+            CloseReason::Idle
+        }
+        _ = drained => CloseReason::Drained,
+    };
+    
+    // 另一方向: 半关闭时继续转发, 直到它也读到 EOF 或宽限到期; 否则立即结束
+    // 适配器因重复 CONNECT、被拒绝的包、流量配额、空闲或排空断开时没有宽限 (丢弃 JoinHandle 不会停止任务, 必须显式 abort)
+    let (mut remaining, half_close_grace) = match close_reason {
+        CloseReason::ClientClosed => (broker_to_client, half_close_grace),
+        CloseReason::SecondConnect | CloseReason::PublishTooLarge if taps.protocol_close.is_none() => (broker_to_client, None),
+        CloseReason::PacketDenied => (broker_to_client, None),
+        CloseReason::Idle | CloseReason::Drained => {
+            client_to_broker.abort();
+            (broker_to_client, None)
+        }
+        // 两个方向都会结束; 先给 broker 发往客户端的方向发送 DISCONNECT 的时间
+        CloseReason::QuotaExceeded | CloseReason::SecondConnect | CloseReason::PublishTooLarge => {
+            if close_reason == CloseReason::QuotaExceeded {
+                metrics().connections_closed_quota_total.fetch_add(1, Ordering::Relaxed);
+            }
+            if !broker_to_client.is_finished()
+                && tokio::time::timeout(DISCONNECT_TIMEOUT, &mut broker_to_client).await.is_err()
+            {
+                broker_to_client.abort();
+            }
+            (client_to_broker, None)
+        }
+        _ => (client_to_broker, half_close_grace),
+    };
+    if let Some(grace) = half_close_grace
+        && tokio::time::timeout(grace, &mut remaining).await.is_ok()
+    {
+        debug!("Connection half-closed cleanly within the {:?} grace", grace);
+    } else {
+        if half_close_grace.is_some() {
+            debug!("Half-close grace expired, closing the connection");
+        }
+        remaining.abort();
+    }
+    
+    if throttled {
+        metrics().throttled_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+    
+    Ok(close_reason)
+}
+
+/// 单方向转发的结束原因
+enum ForwardEnd {
+    /// 读端关闭或写端出错
+    Closed,
+    /// 检测到第二个 CONNECT, 该块数据未转发
+    SecondConnect,
+    /// 包类型过滤拒绝了一个包, 该块数据未转发
+    Denied(FirewallDenial),
+    /// 两个方向合计超过流量配额
+    QuotaExceeded,
+    /// 另一方向检测到客户端违规
+    Violation(ProtocolViolation),
+}
+
+/// 单方向转发,直到读端关闭或写端出错
+async fn forward_loop<R, W>(
+    mut reader: R,
+    mut writer: W,
+    throttle: Throttle,
+    mut taps: DirectionTaps,
+    shutdown_on_eof: bool,
+    mut pause: Option<PauseGate>,
+) -> ForwardEnd
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = [0u8; 8192];
+    loop {
+        // 另一方向超过流量配额或检测到客户端违规时, 该方向也不再读取
+        let read = if taps.quota.is_none() && taps.protocol_close.is_none() {
+            read_resumed(&mut reader, &mut buffer, &mut pause).await
+        } else {
+            let quota_exceeded = async {
+                match &taps.quota {
+                    Some(quota) => quota.exceeded().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let violated = async {
+                match &taps.protocol_close {
+                    Some(close) => close.violated().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                biased;
+                _ = quota_exceeded => {
+                    if let Some(quota) = &taps.quota {
+                        quota.close(&mut writer).await;
+                    }
+                    return ForwardEnd::QuotaExceeded;
+                }
+                violation = violated => {
+                    if let Some(close) = &taps.protocol_close {
+                        close.close(&mut writer, violation).await;
+                    }
+                    return ForwardEnd::Violation(violation);
+                }
+                read = read_resumed(&mut reader, &mut buffer, &mut pause) => read,
+            }
+        };
+        match read {
+            Ok(0) => {
+                if let Some(tap) = &taps.backend_close {
+                    tap.reader_ended(&mut writer, ReaderEnd::Closed).await;
+                }
+                // 把 FIN 转给对端, 对端仍可继续发送
+                if shutdown_on_eof {
+                    let _ = writer.shutdown().await;
+                }
+                break;
+            }
+            Ok(n) => {
+                if let Some(idle) = &taps.idle {
+                    idle.touch();
+                }
+                // 先过滤, 之后的处理只看到实际转发的字节
+                let filtered;
+                let data = match taps.firewall.as_mut().map(|firewall| firewall.filter(&buffer[..n])) {
+                    None | Some(FirewallVerdict::Forward) => &buffer[..n],
+                    Some(FirewallVerdict::Filtered(bytes)) => {
+                        filtered = bytes;
+                        &filtered[..]
+                    }
+                    Some(FirewallVerdict::Close(denial)) => {
+                        match denial {
+                            FirewallDenial::PacketType(packet_type) => {
+                                debug!("Packet firewall: closing the connection on a {} packet", PACKET_TYPE_NAMES[packet_type as usize]);
+                            }
+                            FirewallDenial::PublishTooLarge(_) => {
+                                if let Some(close) = &taps.protocol_close {
+                                    close.report(ProtocolViolation::PublishTooLarge);
+                                }
+                            }
+                            FirewallDenial::Malformed => {}
+                        }
+                        return ForwardEnd::Denied(denial);
+                    }
+                };
+                if data.is_empty() {
+                    continue;
+                }
+                // 在转发之前检测, 同一次读取中位于 CONNECT 之前的字节也不再转发
+                if let Some(detector) = &mut taps.second_connect
+                    && detector.feed(data)
+                {
+                    if let Some(close) = &taps.protocol_close {
+                        close.report(ProtocolViolation::SecondConnect);
+                    }
+                    return ForwardEnd::SecondConnect;
+                }
+                throttle.acquire(data.len()).await;
+                metrics().forwarded_bytes_total.fetch_add(data.len() as u64, Ordering::Relaxed);
+                if writer.write_all(data).await.is_err() {
+                    break;
+                }
+                // TLS / WebSocket 写端可能缓存数据, 先刷新再让内核立即发送
+                if let Some(push) = &mut taps.control_push
+                    && push.has_control_packet(data)
+                {
+                    if writer.flush().await.is_err() {
+                        break;
+                    }
+                    push.push();
+                }
+                if let Some(shadow) = &mut taps.shadow {
+                    shadow.send(data);
+                }
+                if let Some(tap) = &mut taps.request_response {
+                    tap.feed(data);
+                }
+                if let Some(tap) = &mut taps.inflight {
+                    tap.feed(data);
+                }
+                if let Some(tap) = &mut taps.trace {
+                    tap.feed(data);
+                }
+                if let Some(tap) = &mut taps.backend_close {
+                    tap.feed(data);
+                }
+                if let Some(close) = &mut taps.protocol_close {
+                    close.feed(data);
+                }
+                if let Some(quota) = &mut taps.quota
+                    && quota.feed(data)
+                {
+                    quota.close(&mut writer).await;
+                    return ForwardEnd::QuotaExceeded;
+                }
+            }
+            Err(_) => {
+                if let Some(tap) = &taps.backend_close {
+                    tap.reader_ended(&mut writer, ReaderEnd::Failed).await;
+                }
+                break;
+            }
+        }
+    }
+    ForwardEnd::Closed
+}
+
+/// 读取一块数据; 暂停期间不读取, 数据留在内核缓冲区中, 对端最终因 TCP 窗口写满而阻塞
+async fn read_resumed<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut [u8], pause: &mut Option<PauseGate>) -> std::io::Result<usize> {
+    let Some(pause) = pause else {
+        return reader.read(buffer).await;
+    };
+    loop {
+        pause.wait_resumed().await;
+        tokio::select! {
+            read = reader.read(buffer) => return read,
+            // 读取时被暂停: 放弃这次读取 (尚未读到数据) 回到循环顶部等待恢复
+            _ = pause.changed() => {}
+        }
+    }
+}
+
+/// 不需要检查转发数据的明文 TCP 连接用 splice 转发两个方向, 其余连接 (或未启用 `splice_forward`) 返回原来的流
+/// 空闲检测、限速和暂停不需要读取数据, 仍然生效
+#[cfg(all(feature = "splice", target_os = "linux"))]
+fn spawn_splice<S: 'static>(
+    client_stream: S,
+    broker_stream: TcpStream,
+    taps: &ForwardTaps,
+    throttle: &Throttle,
+    half_close: bool,
+    pause: &Option<PauseGate>,
+) -> Result<(tokio::task::JoinHandle<ForwardEnd>, tokio::task::JoinHandle<ForwardEnd>), (S, TcpStream)> {
+    let inspects = taps.shadow.is_some()
+        || taps.request_response_log.is_some()
+        || taps.reject_second_connect
+        || taps.firewall.is_some()
+        || taps.control_push.is_some()
+        || taps.qos_drain.is_some()
+        || taps.byte_quota.is_some()
+        || taps.packet_trace.is_some()
+        || taps.backend_close.is_some();
+    if !taps.splice || inspects || !splice::available() {
+        return Err((client_stream, broker_stream));
+    }
+    let (pending, client_stream) = match splice::into_tcp(client_stream) {
+        Ok(parts) => parts,
+        Err(client_stream) => return Err((client_stream, broker_stream)),
+    };
+    metrics().splice_connections_total.fetch_add(1, Ordering::Relaxed);
+    
+    let client = Arc::new(client_stream);
+    let broker = Arc::new(broker_stream);
+    let idle = || taps.idle.as_ref().map(IdleHandle::tracker);
+    Ok((
+        tokio::spawn(splice_loop(client.clone(), broker.clone(), pending, throttle.clone(), idle(), half_close, pause.clone())),
+        tokio::spawn(splice_loop(broker, client, Vec::new(), throttle.clone(), idle(), half_close, pause.clone())),
+    ))
+}
+
+/// 单方向的 splice 转发, 先写出 `pending` (已读出但尚未转发的字节), 直到读端关闭或写端出错
+#[cfg(all(feature = "splice", target_os = "linux"))]
+async fn splice_loop(
+    reader: Arc<TcpStream>,
+    writer: Arc<TcpStream>,
+    pending: Vec<u8>,
+    throttle: Throttle,
+    idle: Option<Arc<IdleTracker>>,
+    shutdown_on_eof: bool,
+    mut pause: Option<PauseGate>,
+) -> ForwardEnd {
+    if !pending.is_empty() && splice::write_all(&writer, &pending).await.is_err() {
+        return ForwardEnd::Closed;
+    }
+    let Ok(mut pipe) = splice::Pipe::new() else {
+        return ForwardEnd::Closed;
+    };
+    loop {
+        // 与 forward_loop 相同: 暂停期间不读取
+        let filled = match &mut pause {
+            Some(pause) => {
+                pause.wait_resumed().await;
+                tokio::select! {
+                    filled = pipe.fill(&reader) => filled,
+                    _ = pause.changed() => continue,
+                }
+            }
+            None => pipe.fill(&reader).await,
+        };
+        match filled {
+            Ok(0) => {
+                if shutdown_on_eof {
+                    let _ = socket2::SockRef::from(&*writer).shutdown(std::net::Shutdown::Write);
+                }
+                break;
+            }
+            Ok(n) => {
+                if let Some(idle) = &idle {
+                    idle.touch();
+                }
+                throttle.acquire(n).await;
+                metrics().forwarded_bytes_total.fetch_add(n as u64, Ordering::Relaxed);
+                if pipe.drain(&writer, n).await.is_err() {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    ForwardEnd::Closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter_config::{AdmissionConfig, CertAttribute, CertRoutingConfig, PacketFirewallConfig, ThrottleConfig, TlsConfig, TopicRoutingConfig};
+    use crate::properties::{PropertyValue, SESSION_EXPIRY_INTERVAL};
+    use tokio_rustls::rustls;
+    use crate::connect_packet::connect_payload;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::task::JoinHandle;
+
+    fn adapter_state(config: AdapterConfig) -> Arc<AdapterState> {
+        let runtime = Arc::new(RuntimeState::new(&config).unwrap());
+        Arc::new(AdapterState::new(0, runtime).unwrap())
+    }
+
+    /// 模拟后端 broker: 统计连接数并记录收到的字节, 收到数据后回复接受的 3.1.1 CONNACK
+    struct MockBackend {
+        address: String,
+        accepted: Arc<AtomicUsize>,
+        received: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl MockBackend {
+        async fn start() -> Self {
+            Self::start_dropping(0).await
+        }
+
+        /// 前 `drop_first` 个连接接受后立即关闭, 不读取任何数据
+        async fn start_dropping(drop_first: usize) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let accepted = Arc::new(AtomicUsize::new(0));
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let (accepted_count, received_bytes) = (accepted.clone(), received.clone());
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    if accepted_count.fetch_add(1, Ordering::SeqCst) < drop_first {
+                        drop(stream);
+                        continue;
+                    }
+                    let received = received_bytes.clone();
+                    tokio::spawn(async move {
+                        let mut buf = [0u8; 4096];
+                        let mut acknowledged = false;
+                        while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                            received.lock().unwrap().extend_from_slice(&buf[..n]);
+                            if !acknowledged {
+                                acknowledged = true;
+                                if stream.write_all(&encode_connack_accepted(4)).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+            MockBackend { address, accepted, received }
+        }
+
+        fn accepted(&self) -> usize {
+            self.accepted.load(Ordering::SeqCst)
+        }
+
+        /// 等待后端收到至少 `len` 个字节
+        async fn wait_for_bytes(&self, len: usize) -> Vec<u8> {
+            let started = Instant::now();
+            loop {
+                let received = self.received.lock().unwrap().clone();
+                if received.len() >= len || started.elapsed() > Duration::from_secs(5) {
+                    return received;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    /// 建立一条本地 TCP 连接, 在适配器侧对其运行 `handle_smart_client`, 返回客户端侧
+    async fn connect_client(state: Arc<AdapterState>, backend: &str) -> (TcpStream, JoinHandle<Result<(), AdapterError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        let socket = push_socket(&state, &stream);
+        let backend = backend.to_string();
+        let handler = tokio::spawn(handle_smart_client(stream, socket, peer, next_connection_id(), backend, state, None));
+        (client, handler)
+    }
+
+    /// 完整的 3.1.1 CONNECT 包
+    fn connect_packet(client_id: &str) -> Vec<u8> {
+        encode_packet(0x10, &connect_payload(4, 60, client_id, None, None))
+    }
+
+    /// 协议名为 `name`、级别 3 的 CONNECT 负载 (不含固定头)
+    fn legacy_connect(name: &[u8]) -> Vec<u8> {
+        let mut payload = (name.len() as u16).to_be_bytes().to_vec();
+        payload.extend_from_slice(name);
+        payload.extend_from_slice(&[3, 0x02, 0x00, 0x3C, 0x00, 0x03]);
+        payload.extend_from_slice(b"dev");
+        payload
+    }
+
+    fn legacy_policy(lenient_legacy: bool) -> DetectionPolicy {
+        let config = AdapterConfig { lenient_legacy, upgrade_v310: true, ..AdapterConfig::default() };
+        DetectionPolicy::from_config(&config, None).unwrap()
+    }
+
+    /// 严格模式拒绝该协议名; 宽松模式按 MQIsdp 接受, 记录 `quirks`, 并升级为 3.1.1
+    fn assert_lenient_variant(name: &[u8], quirks: &[&str]) {
+        let payload = legacy_connect(name);
+        match detect_and_convert_protocol(&payload, &legacy_policy(false)) {
+            Err(AdapterError::UnknownProtocol { level: 3, .. }) => {}
+            other => panic!("strict mode must reject {:?}: {:?}", name, other.map(|(version, _)| version)),
+        }
+
+        assert_eq!(legacy_name_quirks(name).as_deref(), Some(quirks));
+        let (version, converted) = detect_and_convert_protocol(&payload, &legacy_policy(true)).unwrap();
+        assert_eq!(version, MqttVersion::V310);
+        assert_eq!(converted, [&[0x00, 0x04][..], b"MQTT", &[4, 0x02, 0x00, 0x3C, 0x00, 0x03], b"dev"].concat());
+    }
+
+    #[test]
+    fn lenient_accepts_lowercase_mqisdp() {
+        assert_lenient_variant(b"mqisdp", &["letter case"]);
+    }
+
+    #[test]
+    fn lenient_accepts_uppercase_mqisdp() {
+        assert_lenient_variant(b"MQISDP", &["letter case"]);
+    }
+
+    #[test]
+    fn lenient_accepts_mqisdp_with_trailing_nul() {
+        assert_lenient_variant(b"MQIsdp\0", &["trailing NUL"]);
+        assert_lenient_variant(b"MQIsdp\0\0", &["trailing NUL"]);
+    }
+
+    #[test]
+    fn lenient_accepts_both_quirks_together() {
+        assert_lenient_variant(b"mqIsDp\0", &["trailing NUL", "letter case"]);
+    }
+
+    #[test]
+    fn lenient_still_rejects_other_names() {
+        for name in [&b"MQIsdx"[..], b"MQIsd", b"\0MQIsdp", b"MQTT", b"\0"] {
+            assert!(
+                matches!(detect_and_convert_protocol(&legacy_connect(name), &legacy_policy(true)), Err(AdapterError::UnknownProtocol { .. })),
+                "{:?}", name,
+            );
+        }
+        // 精确的 MQIsdp 在两种模式下都接受
+        for lenient in [false, true] {
+            assert!(detect_and_convert_protocol(&legacy_connect(b"MQIsdp"), &legacy_policy(lenient)).is_ok());
+        }
+    }
+
+    fn deadline_config(connect_deadline_ms: u64) -> AdapterConfig {
+        AdapterConfig { connect_deadline_ms, ..AdapterConfig::default() }
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_non_connect_first_byte() {
+        let backend = MockBackend::start().await;
+        let (mut client, handler) = connect_client(adapter_state(deadline_config(1_000)), &backend.address).await;
+        // 未连接就发送 PUBLISH
+        client.write_all(&encode_packet(0x30, b"\x00\x01tpayload")).await.unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(matches!(result, Err(AdapterError::NotConnect { first_byte: 0x30 })), "{:?}", result);
+        assert_eq!(backend.accepted(), 0, "no backend connection before a valid CONNECT");
+    }
+
+    /// 剩余长度超过 4 字节的 CONNECT 在连接后端之前拒绝
+    #[tokio::test]
+    async fn handshake_rejects_bad_remaining_length_without_backend_connection() {
+        let backend = MockBackend::start().await;
+        let (mut client, handler) = connect_client(adapter_state(deadline_config(1_000)), &backend.address).await;
+        client.write_all(&[0x10, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).await.unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(matches!(result, Err(AdapterError::MalformedPacket(_))), "{:?}", result);
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    /// max_remaining_length_bytes = 2 时, 3 字节的剩余长度在读取负载和连接后端之前拒绝
+    #[tokio::test]
+    async fn handshake_honours_max_remaining_length_bytes() {
+        let backend = MockBackend::start().await;
+        let config = AdapterConfig { max_remaining_length_bytes: 2, ..deadline_config(1_000) };
+        let (mut client, handler) = connect_client(adapter_state(config), &backend.address).await;
+        let rejected_before = metrics().malformed_remaining_length_total.load(Ordering::Relaxed);
+        client.write_all(&[0x10, 0x80, 0x80, 0x01]).await.unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(matches!(result, Err(AdapterError::MalformedPacket(_))), "{:?}", result);
+        assert!(metrics().malformed_remaining_length_total.load(Ordering::Relaxed) > rejected_before);
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    /// 协议名称未知的 CONNECT 在连接后端之前拒绝
+    #[tokio::test]
+    async fn handshake_rejects_unknown_protocol_without_backend_connection() {
+        let backend = MockBackend::start().await;
+        let (mut client, handler) = connect_client(adapter_state(deadline_config(1_000)), &backend.address).await;
+        let mut packet = connect_packet("scanner");
+        packet[4..8].copy_from_slice(b"MQXX");
+        client.write_all(&packet).await.unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(matches!(&result, Err(AdapterError::UnknownProtocol { name, .. }) if name == "MQXX"), "{:?}", result);
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn handshake_drops_slow_partial_connect_at_deadline() {
+        let backend = MockBackend::start().await;
+        let (mut client, handler) = connect_client(adapter_state(deadline_config(200)), &backend.address).await;
+        let packet = connect_packet("slow-client");
+        client.write_all(&packet[..packet.len() / 2]).await.unwrap();
+
+        let started = Instant::now();
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(
+            matches!(&result, Err(AdapterError::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut),
+            "{:?}",
+            result
+        );
+        assert!(started.elapsed() < Duration::from_secs(1), "dropped after {:?}", started.elapsed());
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn handshake_accepts_connect_completed_just_under_deadline() {
+        let backend = MockBackend::start().await;
+        let (mut client, handler) = connect_client(adapter_state(deadline_config(500)), &backend.address).await;
+        let packet = connect_packet("almost-late");
+        client.write_all(&packet[..packet.len() / 2]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        client.write_all(&packet[packet.len() / 2..]).await.unwrap();
+
+        let received = backend.wait_for_bytes(packet.len()).await;
+        assert_eq!(received, packet, "backend must receive the CONNECT unchanged");
+        let mut connack = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+        assert_eq!(connack.to_vec(), encode_connack_accepted(4));
+        assert!(!handler.is_finished(), "connection must stay open after the handshake");
+        handler.abort();
+    }
+
+    /// CONNECT 与紧随其后的 PUBLISH、SUBSCRIBE 在同一次写入中到达: 握手时多读的字节也必须原样转发
+    #[tokio::test]
+    async fn pipelined_packets_after_connect_reach_the_backend() {
+        let backend = MockBackend::start().await;
+        let (mut client, handler) = connect_client(adapter_state(deadline_config(1_000)), &backend.address).await;
+        let mut pipelined = connect_packet("pipelined");
+        pipelined.extend_from_slice(&encode_packet(0x30, b"\x00\x05topicpayload"));
+        pipelined.extend_from_slice(&encode_packet(0x82, b"\x00\x01\x00\x05topic\x00"));
+        client.write_all(&pipelined).await.unwrap();
+
+        let received = backend.wait_for_bytes(pipelined.len()).await;
+        assert_eq!(received, pipelined);
+        handler.abort();
+    }
+
+    /// 两个方向合计超过 max_bytes_per_connection 时断开: 配额内的包照常转发, 5.0 客户端先收到 DISCONNECT 0x97
+    #[tokio::test]
+    async fn exceeding_the_byte_quota_closes_the_connection() {
+        for protocol_level in [5, 4] {
+            let backend = MockBackend::start().await;
+            let config = AdapterConfig {
+                throttle: ThrottleConfig { max_bytes_per_connection: 64, ..ThrottleConfig::default() },
+                ..AdapterConfig::default()
+            };
+            let (mut client, handler) = connect_client(adapter_state(config), &backend.address).await;
+            let connect = encode_packet(0x10, &connect_payload(protocol_level, 60, "quota", None, None));
+            client.write_all(&connect).await.unwrap();
+            let mut connack = [0u8; 4];
+            tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+
+            let small = encode_packet(0x30, b"\x00\x01tsmall");
+            client.write_all(&small).await.unwrap();
+            assert!(backend.wait_for_bytes(connect.len() + small.len()).await.ends_with(&small));
+
+            let closed_before = metrics().connections_closed_quota_total.load(Ordering::Relaxed);
+            client.write_all(&encode_packet(0x30, &[b'x'; 100])).await.unwrap();
+            let mut reply = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+            if protocol_level == 5 {
+                assert_eq!(reply, [0xE0, 0x01, 0x97]);
+            } else {
+                assert!(reply.is_empty(), "{:02x?}", reply);
+            }
+            assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+            assert!(metrics().connections_closed_quota_total.load(Ordering::Relaxed) > closed_before);
+        }
+    }
+
+    /// 包类型过滤只作用于客户端发往 broker 的方向: broker 下发的 PUBLISH 照常到达客户端, 客户端发送的 PUBLISH 断开连接
+    #[tokio::test]
+    async fn packet_firewall_filters_only_the_client_direction() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend_address = listener.local_addr().unwrap().to_string();
+        let downstream = encode_packet(0x30, b"\x00\x04downhello");
+        let backend_reply = [encode_connack_accepted(4), downstream.clone()].concat();
+        let backend = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            stream.write_all(&backend_reply).await.unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+            [&buf[..n], &rest[..]].concat()
+        });
+
+        let config = AdapterConfig {
+            packet_firewall: Some(PacketFirewallConfig { deny: vec!["publish".to_string()], ..PacketFirewallConfig::default() }),
+            ..AdapterConfig::default()
+        };
+        let (mut client, handler) = connect_client(adapter_state(config), &backend_address).await;
+        let connect = connect_packet("firewalled");
+        client.write_all(&connect).await.unwrap();
+        let mut received = vec![0u8; 4 + downstream.len()];
+        tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut received)).await.unwrap().unwrap();
+        assert_eq!(&received[4..], &downstream[..]);
+
+        client.write_all(&encode_packet(0x30, b"\x00\x02upnope")).await.unwrap();
+        let mut reply = Vec::new();
+        tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+        assert!(reply.is_empty(), "{:02x?}", reply);
+        assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+        let backend_received = tokio::time::timeout(Duration::from_secs(2), backend).await.unwrap().unwrap();
+        assert_eq!(backend_received, connect, "the denied PUBLISH must not reach the backend");
+    }
+
+    /// max_publish_size 的边界: 正好等于上限的 PUBLISH 照常转发, 多一个字节时断开, 5.0 客户端先收到 DISCONNECT 0x95
+    #[tokio::test]
+    async fn publish_over_max_publish_size_closes_the_connection() {
+        const LIMIT: usize = 32;
+        for protocol_level in [5, 4] {
+            let backend = MockBackend::start().await;
+            let config = AdapterConfig { max_publish_size: Some(LIMIT), ..AdapterConfig::default() };
+            let (mut client, handler) = connect_client(adapter_state(config), &backend.address).await;
+            let connect = encode_packet(0x10, &connect_payload(protocol_level, 60, "limited", None, None));
+            client.write_all(&connect).await.unwrap();
+            let mut connack = [0u8; 4];
+            tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+
+            // 主题 "t", 5.0 带空的属性, 其余为负载, 剩余长度为 `len`
+            let publish = |len: usize| {
+                let mut body = vec![0x00, 0x01, b't'];
+                if protocol_level == 5 {
+                    body.push(0x00);
+                }
+                body.resize(len, b'x');
+                encode_packet(0x30, &body)
+            };
+            let at_limit = publish(LIMIT);
+            client.write_all(&at_limit).await.unwrap();
+            assert_eq!(backend.wait_for_bytes(connect.len() + at_limit.len()).await, [connect.clone(), at_limit.clone()].concat());
+
+            let rejected_before = metrics().publish_too_large_total.load(Ordering::Relaxed);
+            client.write_all(&publish(LIMIT + 1)).await.unwrap();
+            let mut reply = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+            if protocol_level == 5 {
+                assert_eq!(reply, [0xE0, 0x01, 0x95]);
+            } else {
+                assert!(reply.is_empty(), "{:02x?}", reply);
+            }
+            assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+            assert!(metrics().publish_too_large_total.load(Ordering::Relaxed) > rejected_before);
+            assert_eq!(backend.received.lock().unwrap().len(), connect.len() + at_limit.len(), "the oversized PUBLISH must not be forwarded");
+        }
+    }
+
+    /// 回复 CONNACK 后等待 `close` 再关闭连接的后端; `reset` 时以 RST 关闭
+    async fn closing_backend(reset: bool) -> (String, tokio::sync::oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (close, closed) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            assert!(stream.read(&mut buf).await.unwrap() > 0);
+            stream.write_all(&encode_connack_accepted(4)).await.unwrap();
+            let _ = closed.await;
+            if reset {
+                socket2::SockRef::from(&stream).set_linger(Some(Duration::ZERO)).unwrap();
+            }
+        });
+        (address, close)
+    }
+
+    /// disconnect_on_backend_close: 后端在 CONNACK 之后断开时, 5.0 客户端先收到 DISCONNECT
+    /// (正常关闭 0x8B, 连接被重置 0x89), 3.1.1 客户端直接断开
+    #[tokio::test]
+    async fn backend_closing_after_connack_notifies_v5_clients() {
+        for (protocol_level, reset, expected) in
+            [(5, false, &[0xE0, 0x01, 0x8B][..]), (5, true, &[0xE0, 0x01, 0x89]), (4, false, &[]), (4, true, &[])]
+        {
+            let (backend_address, close_backend) = closing_backend(reset).await;
+            let config = AdapterConfig { disconnect_on_backend_close: true, ..AdapterConfig::default() };
+            let (mut client, handler) = connect_client(adapter_state(config), &backend_address).await;
+            client.write_all(&encode_packet(0x10, &connect_payload(protocol_level, 60, "orphaned", None, None))).await.unwrap();
+            let mut connack = [0u8; 4];
+            tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+
+            let notified_before = metrics().backend_close_disconnects_total.load(Ordering::Relaxed);
+            close_backend.send(()).unwrap();
+            let mut reply = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+            assert_eq!(reply, expected, "MQTT level {}, reset {}", protocol_level, reset);
+            assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+            if !expected.is_empty() {
+                assert!(metrics().backend_close_disconnects_total.load(Ordering::Relaxed) > notified_before);
+            }
+        }
+    }
+
+    /// reject_second_connect: 同一连接上的第二个 CONNECT 不转发, 连接被关闭; 5.0 客户端先收到 DISCONNECT 0x82
+    #[tokio::test]
+    async fn second_connect_closes_the_connection() {
+        for protocol_level in [5, 4] {
+            let backend = MockBackend::start().await;
+            let config = AdapterConfig { reject_second_connect: true, ..AdapterConfig::default() };
+            let (mut client, handler) = connect_client(adapter_state(config), &backend.address).await;
+            let connect = encode_packet(0x10, &connect_payload(protocol_level, 60, "twice", None, None));
+            client.write_all(&connect).await.unwrap();
+            let mut connack = [0u8; 4];
+            tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+
+            let detected_before = metrics().second_connect_total.load(Ordering::Relaxed);
+            client.write_all(&connect).await.unwrap();
+            let mut reply = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+            if protocol_level == 5 {
+                assert_eq!(reply, [0xE0, 0x01, 0x82]);
+            } else {
+                assert!(reply.is_empty(), "{:02x?}", reply);
+            }
+            assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+            assert!(metrics().second_connect_total.load(Ordering::Relaxed) > detected_before);
+            assert_eq!(*backend.received.lock().unwrap(), connect, "the second CONNECT must not be forwarded");
+        }
+    }
+
+    fn retry_config(backend_connect_retries: u32) -> AdapterConfig {
+        AdapterConfig { backend_connect_retries, backend_connect_retry_delay_ms: 10, ..AdapterConfig::default() }
+    }
+
+    /// 后端接受连接后立即关闭: 在新连接上重新发送完整的 CONNECT, 最终的后端只收到一份, 客户端收到它的 CONNACK
+    #[tokio::test]
+    async fn backend_closing_on_accept_is_retried_with_one_whole_connect() {
+        let backend = MockBackend::start_dropping(2).await;
+        let retries_before = metrics().backend_connect_retries_total.load(Ordering::Relaxed);
+        let (mut client, handler) = connect_client(adapter_state(retry_config(2)), &backend.address).await;
+        let connect = connect_packet("retried");
+        client.write_all(&connect).await.unwrap();
+        let mut connack = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+        assert_eq!(connack, [0x20, 0x02, 0x00, 0x00]);
+        assert_eq!(backend.accepted(), 3);
+        assert!(metrics().backend_connect_retries_total.load(Ordering::Relaxed) >= retries_before + 2);
+
+        let publish = encode_packet(0x30, b"\x00\x01tpayload");
+        client.write_all(&publish).await.unwrap();
+        assert_eq!(backend.wait_for_bytes(connect.len() + publish.len()).await, [connect, publish].concat());
+        handler.abort();
+    }
+
+    /// 重试用完时客户端的连接被直接关闭, 不会收到 CONNACK
+    #[tokio::test]
+    async fn exhausted_retries_close_the_client() {
+        let backend = MockBackend::start_dropping(usize::MAX).await;
+        let (mut client, handler) = connect_client(adapter_state(retry_config(1)), &backend.address).await;
+        client.write_all(&connect_packet("exhausted")).await.unwrap();
+        let mut reply = Vec::new();
+        tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+        assert!(reply.is_empty(), "{:02x?}", reply);
+        assert_eq!(backend.accepted(), 2);
+        assert!(backend.received.lock().unwrap().is_empty());
+        // 最后一次尝试的 CONNECT 可能写入成功也可能遇到连接重置, 处理函数的结果不固定
+        let _ = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+    }
+
+    /// 发送半个 CONNECT 后断开: 处理函数正常结束, 计入 handshake_aborted_total, 不连接后端
+    #[tokio::test]
+    async fn client_dropping_mid_connect_aborts_the_handshake_quietly() {
+        let backend = MockBackend::start().await;
+        let aborted_before = metrics().handshake_aborted_total.load(Ordering::Relaxed);
+        let (mut client, handler) = connect_client(adapter_state(AdapterConfig::default()), &backend.address).await;
+        let packet = connect_packet("half");
+        client.write_all(&packet[..packet.len() / 2]).await.unwrap();
+        drop(client);
+
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(metrics().handshake_aborted_total.load(Ordering::Relaxed) > aborted_before);
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    /// allowed_versions: 列表中的版本照常转发; 其余版本收到 CONNACK 0x01 (3.x) / 0x84 (5.0) 后关闭, 不连接后端
+    #[tokio::test]
+    async fn allowed_versions_gate_each_version() {
+        let connects = [
+            (MqttVersion::V310, encode_packet(0x10, &legacy_connect(b"MQIsdp"))),
+            (MqttVersion::V311, connect_packet("versions")),
+            (MqttVersion::V500, encode_packet(0x10, &connect_payload(5, 60, "versions", None, None))),
+        ];
+        for allowed in [&["5.0"][..], &["3.1.1", "5.0"], &["3.1.0"], &["3.1.0", "3.1.1", "5.0"]] {
+            for (version, connect) in &connects {
+                let backend = MockBackend::start().await;
+                let config = AdapterConfig {
+                    allowed_versions: allowed.iter().map(|version| version.to_string()).collect(),
+                    ..AdapterConfig::default()
+                };
+                let (mut client, handler) = connect_client(adapter_state(config), &backend.address).await;
+                let rejected = &metrics().version_not_allowed_total[*version as usize];
+                let rejected_before = rejected.load(Ordering::Relaxed);
+                client.write_all(connect).await.unwrap();
+
+                if allowed.contains(&version.as_str()) {
+                    let mut connack = [0u8; 4];
+                    tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+                    assert_eq!(connack, [0x20, 0x02, 0x00, 0x00], "{:?} with {:?}", version, allowed);
+                    assert_eq!(backend.accepted(), 1);
+                    handler.abort();
+                    continue;
+                }
+                let mut reply = Vec::new();
+                tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+                let expected: &[u8] = match version {
+                    MqttVersion::V500 => &[0x20, 0x03, 0x00, 0x84, 0x00],
+                    _ => &[0x20, 0x02, 0x00, 0x01],
+                };
+                assert_eq!(reply, expected, "{:?} with {:?}", version, allowed);
+                assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+                assert_eq!(backend.accepted(), 0);
+                assert!(rejected.load(Ordering::Relaxed) > rejected_before);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_allowed_version_is_a_config_error() {
+        let names = ["3.1.1".to_string(), "3.1".to_string()];
+        assert_eq!(MqttVersion::parse_allowed(&names).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(check_config(&AdapterConfig { allowed_versions: names.to_vec(), ..AdapterConfig::default() }).is_err());
+    }
+
+    /// 处理函数 panic 时只结束该连接: 同一个工作任务继续处理之后的连接, 并计入 handler_panics_total
+    #[tokio::test]
+    async fn panicking_handler_does_not_stop_the_worker() {
+        let pool = WorkerPool::new(1);
+        let peer: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let panics_before = metrics().handler_panics_total.load(Ordering::Relaxed);
+        pool.submit(Box::pin(catch_handler_panic(next_connection_id(), peer, async { panic!("handler bug") }))).await;
+
+        let (handled, next_handled) = tokio::sync::oneshot::channel();
+        pool.submit(Box::pin(catch_handler_panic(next_connection_id(), peer, async move {
+            let _ = handled.send(());
+        })))
+        .await;
+        tokio::time::timeout(Duration::from_secs(2), next_handled).await.unwrap().unwrap();
+        assert!(metrics().handler_panics_total.load(Ordering::Relaxed) > panics_before);
+    }
+
+    /// 一对已连接的本地 TCP 流
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connected = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        (connected, accepted)
+    }
+
+    /// 直接转发两条 TCP 连接, 返回客户端侧、broker 侧和转发任务
+    async fn forward_pair(half_close_grace: Option<Duration>) -> (TcpStream, TcpStream, JoinHandle<std::io::Result<CloseReason>>) {
+        let (client, adapter_client) = tcp_pair().await;
+        let (adapter_broker, broker) = tcp_pair().await;
+        let throttle = Throttle { connection: None, total: None };
+        let forward = tokio::spawn(bidirectional_forward(adapter_client, adapter_broker, throttle, ForwardTaps::default(), half_close_grace, None));
+        (client, broker, forward)
+    }
+
+    /// 客户端关闭写端后, broker 发往客户端的方向在宽限内继续转发, 宽限到期后连接关闭
+    #[tokio::test]
+    async fn half_closed_client_keeps_receiving_until_the_grace_ends() {
+        let grace = Duration::from_millis(500);
+        let (mut client, mut broker, forward) = forward_pair(Some(grace)).await;
+        client.write_all(b"hello").await.unwrap();
+        let half_closed_at = Instant::now();
+        client.shutdown().await.unwrap();
+
+        // 客户端方向的 EOF 只关闭发往 broker 的写端
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(2), broker.read_to_end(&mut received)).await.unwrap().unwrap();
+        assert_eq!(received, b"hello");
+
+        // broker 仍在发送, 客户端在宽限内照常收到
+        for i in 0..3u8 {
+            broker.write_all(&[i]).await.unwrap();
+            let mut byte = [0u8; 1];
+            tokio::time::timeout(Duration::from_secs(1), client.read_exact(&mut byte)).await.unwrap().unwrap();
+            assert_eq!(byte, [i]);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // broker 没有关闭, 宽限到期后转发结束
+        let reason = tokio::time::timeout(Duration::from_secs(2), forward).await.unwrap().unwrap().unwrap();
+        assert_eq!(reason, CloseReason::ClientClosed);
+        assert!(half_closed_at.elapsed() >= grace);
+        let _ = broker.write_all(b"late").await;
+        let mut rest = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut rest)).await.unwrap();
+        assert!(rest.is_empty(), "{:02x?}", rest);
+    }
+
+    /// 未配置宽限时, 客户端的 EOF 立即结束两个方向
+    #[tokio::test]
+    async fn without_grace_the_client_eof_closes_both_directions() {
+        let (mut client, mut broker, forward) = forward_pair(None).await;
+        client.shutdown().await.unwrap();
+        let reason = tokio::time::timeout(Duration::from_millis(500), forward).await.unwrap().unwrap().unwrap();
+        assert_eq!(reason, CloseReason::ClientClosed);
+
+        let _ = broker.write_all(b"late").await;
+        let mut rest = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut rest)).await.unwrap();
+        assert!(rest.is_empty(), "{:02x?}", rest);
+    }
+
+    /// 租户配额 1 时, 同一租户的第二个连接: 5.0 客户端收到 CONNACK 0x97, 3.x 客户端被直接关闭
+    #[tokio::test]
+    async fn tenant_quota_rejects_second_connection() {
+        let backend = MockBackend::start().await;
+        let config = AdapterConfig {
+            admission: Some(AdmissionConfig { max_connections_per_tenant: 1, ..AdmissionConfig::default() }),
+            ..AdapterConfig::default()
+        };
+        let state = adapter_state(config);
+        let (mut first, first_handler) = connect_client(state.clone(), &backend.address).await;
+        first.write_all(&connect_packet("acme-1")).await.unwrap();
+        backend.wait_for_bytes(1).await;
+
+        for protocol_level in [5, 4] {
+            let (mut second, second_handler) = connect_client(state.clone(), &backend.address).await;
+            second.write_all(&encode_packet(0x10, &connect_payload(protocol_level, 60, "acme-2", None, None))).await.unwrap();
+            let mut reply = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), second.read_to_end(&mut reply)).await.unwrap().unwrap();
+            if protocol_level == 5 {
+                assert_eq!(reply, encode_connack(5, ConnackReason::QuotaExceeded));
+                assert_eq!(reply[3], 0x97);
+            } else {
+                assert!(reply.is_empty(), "3.x clients are closed without a CONNACK: {:02x?}", reply);
+            }
+            assert!(second_handler.await.unwrap().is_ok());
+        }
+        assert_eq!(backend.accepted(), 1);
+        first_handler.abort();
+    }
+
+    fn topic_routing_config(backends: &[(&str, &str)]) -> AdapterConfig {
+        let backends = backends.iter().map(|(prefix, backend)| (prefix.to_string(), backend.to_string())).collect();
+        AdapterConfig {
+            topic_routing: Some(TopicRoutingConfig { backends, ..TopicRoutingConfig::default() }),
+            ..AdapterConfig::default()
+        }
+    }
+
+    /// 5.0 CONNECT, 按参数设置 Clean Start 和会话过期间隔
+    fn v5_connect_packet(client_id: &str, clean_start: bool, session_expiry: Option<u32>) -> Vec<u8> {
+        let payload = connect_payload(5, 60, client_id, None, None);
+        let mut connect = parse_connect(&payload).unwrap();
+        connect.clean_session = clean_start;
+        if let Some(interval) = session_expiry {
+            connect.properties.set(SESSION_EXPIRY_INTERVAL, PropertyValue::U32(interval));
+        }
+        encode_packet(0x10, &encode_connect(&payload, &connect).unwrap())
+    }
+
+    /// 按主题路由时保留会话的连接: 回复 CONNACK 0x83 (3.x 为 0x03) 并关闭, 不连接任何后端
+    #[tokio::test]
+    async fn topic_routing_rejects_persistent_sessions() {
+        let backend = MockBackend::start().await;
+        let state = adapter_state(topic_routing_config(&[("sensors/", &backend.address)]));
+        let mut persistent_v3 = connect_packet("persistent-v3");
+        persistent_v3[9] &= !0x02;
+        let cases = [
+            (4, persistent_v3),
+            (5, v5_connect_packet("resume", false, None)),
+            (5, v5_connect_packet("expiring", true, Some(3600))),
+        ];
+        for (protocol_level, packet) in cases {
+            let (mut client, handler) = connect_client(state.clone(), &backend.address).await;
+            client.write_all(&packet).await.unwrap();
+            let mut reply = Vec::new();
+            tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+            assert_eq!(reply, encode_connack(protocol_level, ConnackReason::ImplementationSpecificError));
+            assert_eq!(reply[3], if protocol_level == 5 { 0x83 } else { 0x03 });
+            assert!(handler.await.unwrap().is_ok());
+        }
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    /// 临时会话: 适配器先回复自己的 CONNACK, 收到第一个 PUBLISH 后才连接按主题选出的后端并重放
+    #[tokio::test]
+    async fn topic_routing_defers_transient_sessions_to_the_matching_backend() {
+        let default_backend = MockBackend::start().await;
+        let sensors = MockBackend::start().await;
+        let state = adapter_state(topic_routing_config(&[("sensors/", &sensors.address)]));
+        for (client_id, packet) in [
+            ("transient-v3", connect_packet("transient-v3")),
+            ("transient-v5", v5_connect_packet("transient-v5", true, Some(0))),
+        ] {
+            let received_before = sensors.received.lock().unwrap().clone();
+            let (mut client, handler) = connect_client(state.clone(), &default_backend.address).await;
+            client.write_all(&packet).await.unwrap();
+            let protocol_level = if client_id.ends_with("v5") { 5 } else { 4 };
+            let mut connack = vec![0u8; encode_connack_accepted(protocol_level).len()];
+            tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+            assert_eq!(connack, encode_connack_accepted(protocol_level));
+
+            let publish = encode_packet(0x30, b"\x00\x0bsensors/onepayload");
+            client.write_all(&publish).await.unwrap();
+            let mut expected = received_before;
+            expected.extend_from_slice(&packet);
+            expected.extend_from_slice(&publish);
+            assert_eq!(sensors.wait_for_bytes(expected.len()).await, expected);
+            handler.abort();
+        }
+        assert_eq!(sensors.accepted(), 2);
+        assert_eq!(default_backend.accepted(), 0);
+    }
+
+    fn fixture(name: &str) -> String {
+        format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// 使用测试证书的 TLS 监听器配置
+    fn tls_config() -> TlsConfig {
+        TlsConfig { cert_path: fixture("server_cert.pem"), key_path: fixture("ec_pkcs8.pem"), ..TlsConfig::default() }
+    }
+
+    /// 按 TLS 监听器的处理方式接受一条本地 TCP 连接 (`TlsHandshaker::accept` 和 `handle_tls_start`), 返回客户端侧的 TCP 连接
+    /// 握手失败时任务返回失败原因
+    async fn connect_tls_client(state: Arc<AdapterState>, backend: &str) -> (TcpStream, JoinHandle<Result<(), AdapterError>>) {
+        let handshaker = TlsHandshaker::from_config(state.config().tls.as_ref().expect("TLS listener configured")).unwrap();
+        connect_tls_client_with(handshaker, state, backend).await
+    }
+
+    async fn connect_tls_client_with(
+        handshaker: TlsHandshaker,
+        state: Arc<AdapterState>,
+        backend: &str,
+    ) -> (TcpStream, JoinHandle<Result<(), AdapterError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        let backend = backend.to_string();
+        let handler = tokio::spawn(async move {
+            let socket = push_socket(&state, &stream);
+            let start = handshaker.accept(stream, peer).await.map_err(|(reason, message)| {
+                AdapterError::Io(std::io::Error::other(format!("TLS handshake failed ({}): {}", reason.as_str(), message)))
+            })?;
+            handle_tls_start(start, socket, peer, next_connection_id(), backend, state).await
+        });
+        (client, handler)
+    }
+
+    /// 只信任测试 CA 的根证书列表
+    fn test_roots() -> rustls::RootCertStore {
+        let mut roots = rustls::RootCertStore::empty();
+        let pem = std::fs::read(fixture("ca_cert.pem")).unwrap();
+        for cert in rustls_pemfile::certs(&mut pem.as_slice()).unwrap() {
+            roots.add(&rustls::Certificate(cert)).unwrap();
+        }
+        roots
+    }
+
+    /// 信任测试 CA 的 TLS 客户端配置
+    fn tls_client_config() -> rustls::ClientConfig {
+        rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(test_roots()).with_no_client_auth()
+    }
+
+    async fn tls_client(tcp: TcpStream, config: rustls::ClientConfig) -> tokio_rustls::client::TlsStream<TcpStream> {
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        connector.connect(rustls::ServerName::try_from("localhost").unwrap(), tcp).await.unwrap()
+    }
+
+    fn tls_state(tls: TlsConfig) -> Arc<AdapterState> {
+        adapter_state(AdapterConfig { tls: Some(tls), ..AdapterConfig::default() })
+    }
+
+    /// 连接后不发送任何数据的客户端在 handshake_timeout_ms 到达时断开, 计为握手超时
+    #[tokio::test]
+    async fn silent_tls_client_is_dropped_at_handshake_timeout() {
+        let backend = MockBackend::start().await;
+        let state = tls_state(TlsConfig { handshake_timeout_ms: 200, ..tls_config() });
+        let started = Instant::now();
+        let (_client, handler) = connect_tls_client(state, &backend.address).await;
+
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(matches!(&result, Err(e) if e.to_string().contains("TLS handshake failed (timeout)")), "{:?}", result);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(1), "dropped after {:?}", elapsed);
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    /// 等待首批数据的连接占用握手许可: 许可用完时其它连接按 handshake_queue_timeout_ms 排队后被拒绝
+    #[tokio::test]
+    async fn silent_tls_client_holds_a_handshake_permit() {
+        let backend = MockBackend::start().await;
+        let tls = TlsConfig { handshake_timeout_ms: 1_000, max_concurrent_handshakes: 1, handshake_queue_timeout_ms: 100, ..tls_config() };
+        let handshaker = TlsHandshaker::from_config(&tls).unwrap();
+        let state = tls_state(tls);
+        let (_silent, silent_handler) = connect_tls_client_with(handshaker.clone(), state.clone(), &backend.address).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // 许可在读取连接开头之前获取, 第二个连接是否发送 ClientHello 不影响结果
+        let (_queued, queued_handler) = connect_tls_client_with(handshaker, state, &backend.address).await;
+        let result = tokio::time::timeout(Duration::from_secs(2), queued_handler).await.unwrap().unwrap();
+        assert!(matches!(&result, Err(e) if e.to_string().contains("TLS handshake failed (overloaded)")), "{:?}", result);
+        assert!(!silent_handler.is_finished(), "the silent client keeps its permit until the handshake timeout");
+        silent_handler.abort();
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    /// 未发送数据就关闭的健康检查和 require_tls 时的明文连接都不连接后端
+    #[tokio::test]
+    async fn probes_and_plaintext_on_the_tls_listener_open_no_backend_connection() {
+        let backend = MockBackend::start().await;
+        let state = tls_state(tls_config());
+        let (client, handler) = connect_tls_client(state.clone(), &backend.address).await;
+        drop(client);
+        assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+
+        let rejected_before = metrics().plaintext_on_tls_listener_total.load(Ordering::Relaxed);
+        let (mut client, handler) = connect_tls_client(state, &backend.address).await;
+        client.write_all(&connect_packet("plaintext")).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap().is_ok());
+        assert!(metrics().plaintext_on_tls_listener_total.load(Ordering::Relaxed) > rejected_before);
+        assert_eq!(backend.accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn wss_client_reaches_the_backend() {
+        use async_tungstenite::tungstenite::Message;
+        use async_tungstenite::tungstenite::client::IntoClientRequest;
+        use futures_util::{SinkExt, StreamExt};
+
+        let backend = MockBackend::start().await;
+        let config = AdapterConfig { websocket: true, tls: Some(tls_config()), ..AdapterConfig::default() };
+        let (tcp, handler) = connect_tls_client(adapter_state(config), &backend.address).await;
+        let tls = tls_client(tcp, tls_client_config()).await;
+
+        let mut request = "wss://localhost/mqtt".into_client_request().unwrap();
+        request.headers_mut().insert("Sec-WebSocket-Protocol", "mqtt".parse().unwrap());
+        let (mut ws, response) = async_tungstenite::tokio::client_async(request, tls).await.unwrap();
+        assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "mqtt");
+
+        // CONNECT 和随后的 PUBLISH 分别放在两个 WebSocket 帧中
+        let connect = connect_packet("wss-client");
+        let publish = encode_packet(0x30, b"\x00\x0asensors/t1hello over wss");
+        ws.send(Message::Binary(connect.clone())).await.unwrap();
+        let connack = tokio::time::timeout(Duration::from_secs(2), ws.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(connack, Message::Binary(encode_connack_accepted(4)));
+        ws.send(Message::Binary(publish.clone())).await.unwrap();
+
+        let expected = [connect, publish].concat();
+        assert_eq!(backend.wait_for_bytes(expected.len()).await, expected, "backend must receive the unwrapped MQTT bytes");
+        assert_eq!(backend.accepted(), 1);
+
+        ws.close(None).await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(2), handler).await.unwrap().unwrap();
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    /// 以 `name` 客户端证书 (testdata/tls) 做双向 TLS 的客户端配置
+    fn mtls_client_config(name: &str) -> rustls::ClientConfig {
+        let pem = std::fs::read(fixture(name)).unwrap();
+        let chain = rustls_pemfile::certs(&mut pem.as_slice()).unwrap().into_iter().map(rustls::Certificate).collect();
+        let pem = std::fs::read(fixture("client_key.pem")).unwrap();
+        let key = rustls::PrivateKey(rustls_pemfile::pkcs8_private_keys(&mut pem.as_slice()).unwrap().remove(0));
+        rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(test_roots()).with_client_auth_cert(chain, key).unwrap()
+    }
+
+    #[tokio::test]
+    async fn client_certificate_ou_selects_the_backend() {
+        let sensors = MockBackend::start().await;
+        let gateways = MockBackend::start().await;
+        let default = MockBackend::start().await;
+        let tls = TlsConfig {
+            client_ca_path: Some(fixture("ca_cert.pem")),
+            cert_routing: Some(CertRoutingConfig {
+                attribute: CertAttribute::OrganizationalUnit,
+                backends: [("sensors", &sensors), ("gateways", &gateways)]
+                    .into_iter()
+                    .map(|(ou, backend)| (ou.to_string(), backend.address.clone()))
+                    .collect(),
+            }),
+            ..tls_config()
+        };
+        let state = adapter_state(AdapterConfig { tls: Some(tls), ..AdapterConfig::default() });
+
+        let mut handlers = Vec::new();
+        for (certificate, client_id, backend) in [
+            ("client_sensors.pem", "sensor-1", &sensors),
+            ("client_gateways.pem", "gateway-1", &gateways),
+            ("client_no_ou.pem", "plain-1", &default),
+        ] {
+            let (tcp, handler) = connect_tls_client(state.clone(), &default.address).await;
+            let mut client = tls_client(tcp, mtls_client_config(certificate)).await;
+            let connect = connect_packet(client_id);
+            client.write_all(&connect).await.unwrap();
+            assert_eq!(backend.wait_for_bytes(connect.len()).await, connect, "{} must be routed by its OU", certificate);
+            let mut connack = [0u8; 4];
+            tokio::time::timeout(Duration::from_secs(2), client.read_exact(&mut connack)).await.unwrap().unwrap();
+            handlers.push((client, handler));
+        }
+        assert_eq!((sensors.accepted(), gateways.accepted(), default.accepted()), (1, 1, 1));
+        for (_, handler) in handlers {
+            handler.abort();
+        }
+    }
 }
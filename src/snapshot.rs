@@ -0,0 +1,138 @@
+// 运行时配置快照
+// 把生效的配置和运行时状态 (维护模式、拒绝列表) 写成一个完整的配置文件, 以该文件启动即可复现当时的状态
+
+use log::{info, warn};
+use rumqttd::Config;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::Path;
+
+use crate::adapter_config::{AdapterConfig, redact_secrets, strip_nulls};
+use crate::events::now_ms;
+use crate::runtime::RuntimeState;
+
+/// 快照中的运行时状态 (配置文件的 [runtime] 段), 启动时覆盖配置中的对应设置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeSnapshot {
+    /// 快照时的维护模式, 覆盖 [adapter] 的 maintenance
+    pub maintenance: bool,
+    /// 快照时拒绝列表中的客户端 ID, 启动时加入拒绝列表
+    pub denied_client_ids: Vec<String>,
+    /// 快照时间 (毫秒时间戳), 只用于记录
+    pub taken_at_ms: u64,
+}
+
+impl RuntimeSnapshot {
+    /// 当前的运行时状态
+    pub fn capture(runtime: &RuntimeState) -> Self {
+        RuntimeSnapshot {
+            maintenance: runtime.maintenance(),
+            denied_client_ids: runtime.denylist().ids(),
+            taken_at_ms: now_ms(),
+        }
+    }
+
+    /// 把快照中的运行时状态应用到刚创建的运行时
+    pub fn restore(&self, runtime: &RuntimeState) -> std::io::Result<()> {
+        runtime.set_maintenance(self.maintenance);
+        for client_id in &self.denied_client_ids {
+            runtime.denylist().insert(client_id)?;
+        }
+        info!(
+            "Restored runtime state from snapshot taken at {}: maintenance {}, {} denied client IDs",
+            self.taken_at_ms,
+            if self.maintenance { "enabled" } else { "disabled" },
+            self.denied_client_ids.len()
+        );
+        Ok(())
+    }
+}
+
+/// 生成快照文件的内容 (TOML): broker 配置在顶层, 与 config.toml 的结构相同, 后面是 [adapter] 和 [runtime]
+/// `include_secrets` 为 false 时敏感字段替换为 `<redacted>`
+pub fn render(broker: &Config, adapter: &AdapterConfig, runtime: &RuntimeSnapshot, include_secrets: bool) -> Result<String, String> {
+    let mut document = serde_json::to_value(broker).map_err(|e| e.to_string())?;
+    let Value::Object(map) = &mut document else {
+        return Err("broker configuration is not a table".to_string());
+    };
+    map.insert("adapter".to_string(), json!(adapter));
+    map.insert("runtime".to_string(), json!(runtime));
+    if !include_secrets {
+        redact_secrets(&mut document);
+    }
+    // TOML 没有 null, 未设置的可选字段直接省略
+    strip_nulls(&mut document);
+    toml::to_string_pretty(&document).map_err(|e| e.to_string())
+}
+
+/// 写入临时文件后重命名, 避免中途失败留下不完整的快照
+pub fn write(path: &str, content: &str) -> std::io::Result<()> {
+    let temp = format!("{}.tmp", path);
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp, Path::new(path))
+}
+
+/// 以未包含敏感字段的快照启动时, 被隐藏的密码等字段无法使用, 给出警告
+pub fn warn_redacted(config_content: &str) {
+    if config_content.contains("\"<redacted>\"") {
+        warn!("Configuration contains <redacted> values (snapshot taken without include_secrets); the affected credentials will not work");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter_config::{AppConfig, BackendAuthConfig};
+
+    fn shipped_config() -> (Config, AdapterConfig) {
+        let content = include_str!("../config.toml");
+        let app_config: AppConfig = toml::from_str(content).unwrap();
+        (toml::from_str(content).unwrap(), app_config.adapter)
+    }
+
+    #[test]
+    fn snapshot_round_trips_config_and_runtime_state() {
+        let (broker, mut adapter) = shipped_config();
+        adapter.reject_second_connect = true;
+        adapter.max_publish_size = Some(4096);
+        let runtime = RuntimeState::new(&adapter).unwrap();
+        runtime.set_maintenance(true);
+        for client_id in ["flooder-2", "flooder-1"] {
+            runtime.denylist().insert(client_id).unwrap();
+        }
+        let captured = RuntimeSnapshot::capture(&runtime);
+
+        let content = render(&broker, &adapter, &captured, true).unwrap();
+        let restored_broker: Config = toml::from_str(&content).unwrap();
+        let restored: AppConfig = toml::from_str(&content).unwrap();
+        assert_eq!(serde_json::to_value(&restored_broker).unwrap(), serde_json::to_value(&broker).unwrap());
+        assert_eq!(serde_json::to_value(&restored.adapter).unwrap(), serde_json::to_value(&adapter).unwrap());
+
+        let snapshot = restored.runtime.expect("snapshot has a [runtime] table");
+        assert_eq!(snapshot.taken_at_ms, captured.taken_at_ms);
+        let fresh = RuntimeState::new(&restored.adapter).unwrap();
+        assert!(!fresh.maintenance());
+        snapshot.restore(&fresh).unwrap();
+        assert!(fresh.maintenance());
+        assert_eq!(fresh.denylist().ids(), ["flooder-1", "flooder-2"]);
+    }
+
+    #[test]
+    fn snapshot_without_secrets_redacts_credentials() {
+        let (broker, mut adapter) = shipped_config();
+        adapter.backend_auth = Some(BackendAuthConfig {
+            username: "adapter".to_string(),
+            password: Some("s3cret".to_string()),
+            ..BackendAuthConfig::default()
+        });
+        let content = render(&broker, &adapter, &RuntimeSnapshot::default(), false).unwrap();
+        assert!(!content.contains("s3cret"), "{}", content);
+        let restored: AppConfig = toml::from_str(&content).unwrap();
+        assert_eq!(restored.adapter.backend_auth.unwrap().password.as_deref(), Some("<redacted>"));
+        assert!(render(&broker, &adapter, &RuntimeSnapshot::default(), true).unwrap().contains("s3cret"));
+    }
+}
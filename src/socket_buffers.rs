@@ -0,0 +1,76 @@
+// 套接字缓冲区大小
+// 按配置设置客户端和后端连接的 SO_RCVBUF / SO_SNDBUF, 用于高延迟高带宽的链路; 内核可能调整请求的大小
+
+use log::{debug, info, warn};
+use socket2::{Domain, SockRef, Socket, Type};
+use std::io::ErrorKind;
+use tokio::net::TcpStream;
+
+/// 连接的收发缓冲区大小
+pub struct SocketBuffers {
+    rcvbuf: Option<usize>,
+    sndbuf: Option<usize>,
+}
+
+impl SocketBuffers {
+    /// 两项都未配置时返回 None (使用系统默认值和自动调整)
+    pub fn from_config(rcvbuf: Option<usize>, sndbuf: Option<usize>) -> std::io::Result<Option<Self>> {
+        for (name, size) in [("so_rcvbuf", rcvbuf), ("so_sndbuf", sndbuf)] {
+            if size == Some(0) {
+                return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("{} must be greater than 0", name)));
+            }
+        }
+        if rcvbuf.is_none() && sndbuf.is_none() {
+            return Ok(None);
+        }
+        let buffers = SocketBuffers { rcvbuf, sndbuf };
+        buffers.probe()?;
+        Ok(Some(buffers))
+    }
+
+    /// 在临时套接字上设置一次, 记录内核实际生效的大小; 超过系统上限时警告
+    fn probe(&self) -> std::io::Result<()> {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
+        if let Some(size) = self.rcvbuf {
+            socket.set_recv_buffer_size(size)?;
+            let applied = socket.recv_buffer_size()?;
+            info!("Socket buffers: so_rcvbuf {} requested, {} applied by the kernel", size, applied);
+            check_limit("so_rcvbuf", size, "/proc/sys/net/core/rmem_max");
+        }
+        if let Some(size) = self.sndbuf {
+            socket.set_send_buffer_size(size)?;
+            let applied = socket.send_buffer_size()?;
+            info!("Socket buffers: so_sndbuf {} requested, {} applied by the kernel", size, applied);
+            check_limit("so_sndbuf", size, "/proc/sys/net/core/wmem_max");
+        }
+        Ok(())
+    }
+
+    /// 设置连接的缓冲区大小, 失败时保留系统默认值
+    pub fn apply(&self, stream: &TcpStream) {
+        let socket = SockRef::from(stream);
+        if let Some(size) = self.rcvbuf
+            && let Err(e) = socket.set_recv_buffer_size(size)
+        {
+            debug!("Failed to set SO_RCVBUF to {}: {}", size, e);
+        }
+        if let Some(size) = self.sndbuf
+            && let Err(e) = socket.set_send_buffer_size(size)
+        {
+            debug!("Failed to set SO_SNDBUF to {}: {}", size, e);
+        }
+    }
+}
+
+/// Linux 把超过 rmem_max / wmem_max 的请求截断到上限 (不报错), 其它系统没有这些文件时跳过检查
+fn check_limit(name: &str, size: usize, limit_path: &str) {
+    let Some(limit) = std::fs::read_to_string(limit_path).ok().and_then(|limit| limit.trim().parse::<usize>().ok()) else {
+        return;
+    };
+    if size > limit {
+        warn!(
+            "Socket buffers: {} {} exceeds {} ({}), the kernel caps it; raise the sysctl to use the full size",
+            name, size, limit_path, limit
+        );
+    }
+}
@@ -0,0 +1,89 @@
+// 后端连接的源地址
+// 按配置在连接前绑定本地地址 (和端口范围), 用于防火墙规则或按源地址路由的网络环境
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use tokio::net::{TcpSocket, TcpStream};
+
+/// 后端连接绑定的本地地址
+pub struct SourceBind {
+    /// 为空时按目标地址族使用未指定地址 (只限制端口)
+    ip: Option<IpAddr>,
+    /// 本地端口范围 (含两端), 为空时由系统分配临时端口
+    ports: Option<(u16, u16)>,
+    /// 下一次从范围内的哪个偏移开始尝试, 在连接之间轮转
+    next_port: AtomicU16,
+}
+
+impl SourceBind {
+    /// 未配置地址和端口范围时返回 None (使用系统默认的源地址和临时端口)
+    pub fn from_config(addr: Option<&str>, port_range: Option<[u16; 2]>) -> std::io::Result<Option<Self>> {
+        let invalid = |message: String| std::io::Error::new(ErrorKind::InvalidInput, message);
+        let ip = addr
+            .map(|addr| addr.parse::<IpAddr>().map_err(|e| invalid(format!("Invalid forward_bind_addr {:?}: {}", addr, e))))
+            .transpose()?;
+        let ports = match port_range {
+            Some([first, last]) if first == 0 || first > last => {
+                return Err(invalid(format!("Invalid forward_bind_port_range [{}, {}]", first, last)));
+            }
+            Some([first, last]) => Some((first, last)),
+            None => None,
+        };
+        if ip.is_none() && ports.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(SourceBind { ip, ports, next_port: AtomicU16::new(0) }))
+    }
+
+    /// 从绑定的地址连接目标
+    /// 配置了端口范围时从上次之后的端口开始依次尝试, 端口被占用 (包括同一四元组已存在) 时换下一个,
+    /// 范围内所有端口都不可用时返回 `AddrInUse`
+    pub async fn connect(&self, target: SocketAddr) -> std::io::Result<TcpStream> {
+        let ip = match self.ip {
+            Some(ip) if ip.is_ipv4() != target.is_ipv4() => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("forward_bind_addr {} cannot connect to {}", ip, target),
+                ));
+            }
+            Some(ip) => ip,
+            None if target.is_ipv4() => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            None => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        let Some((first, last)) = self.ports else {
+            return bound_socket(SocketAddr::new(ip, 0))?.connect(target).await;
+        };
+
+        let count = u32::from(last - first) + 1;
+        let start = u32::from(self.next_port.fetch_add(1, Ordering::Relaxed)) % count;
+        for offset in 0..count {
+            let port = first + ((start + offset) % count) as u16;
+            let socket = match bound_socket(SocketAddr::new(ip, port)) {
+                Ok(socket) => socket,
+                Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            };
+            match socket.connect(target).await {
+                Ok(stream) => return Ok(stream),
+                // 该端口到目标的连接已存在
+                Err(e) if matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(std::io::Error::new(
+            ErrorKind::AddrInUse,
+            format!("no free source port in {}-{} on {} for {}", first, last, ip, target),
+        ))
+    }
+}
+
+/// 创建绑定到本地地址的套接字
+/// SO_REUSEADDR 允许复用处于 TIME_WAIT 的端口, 与已有连接冲突时由 connect 报错
+fn bound_socket(local: SocketAddr) -> std::io::Result<TcpSocket> {
+    let socket = if local.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    socket.bind(local)?;
+    Ok(socket)
+}
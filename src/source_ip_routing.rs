@@ -0,0 +1,130 @@
+// 按源 IP 选择后端
+// 对客户端 IP 做 rendezvous 哈希, 同一地址总是连接同一后端; 在 accept 时决定, 不需要读取 CONNECT
+
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+use crate::adapter_config::SourceIpRoutingConfig;
+
+/// 源 IP 路由表
+pub struct SourceIpRouter {
+    backends: Vec<String>,
+}
+
+impl SourceIpRouter {
+    pub fn new(config: &SourceIpRoutingConfig) -> Self {
+        SourceIpRouter { backends: config.backends.clone() }
+    }
+
+    /// 为客户端地址选择后端, 没有配置后端时返回 None (使用默认后端)
+    /// 每个后端按 (IP, 后端地址) 的哈希打分, 取最高分: 结果只取决于地址和后端列表,
+    /// 重启和多个实例之间一致, 增删一个后端只会改变原本分到该后端的地址
+    pub fn route(&self, ip: IpAddr) -> Option<&str> {
+        let key = address_key(ip);
+        self.backends
+            .iter()
+            .max_by_key(|backend| score(&key, backend))
+            .map(String::as_str)
+    }
+}
+
+/// IPv4 和 IPv6 统一编码为 "族 + 地址字节"; IPv4 映射的 IPv6 地址 (::ffff:a.b.c.d, 双栈监听器上的 IPv4 客户端) 按 IPv4 处理
+pub fn address_key(ip: IpAddr) -> Vec<u8> {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => [&[4u8][..], &v4.octets()].concat(),
+        IpAddr::V6(v6) => [&[6u8][..], &v6.octets()].concat(),
+    }
+}
+
+pub fn score(key: &[u8], backend: &str) -> u64 {
+    let digest = Sha256::new().chain_update(key).chain_update(backend.as_bytes()).finalize();
+    u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 digest is 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const BACKENDS: [&str; 3] = ["10.0.0.1:1883", "10.0.0.2:1883", "10.0.0.3:1883"];
+
+    fn router(backends: &[&str]) -> SourceIpRouter {
+        SourceIpRouter::new(&SourceIpRoutingConfig { backends: backends.iter().map(|backend| backend.to_string()).collect() })
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn routes_ipv4_and_ipv6_to_pinned_backends() {
+        // 哈希 (SHA-256 前 8 字节) 变化会让所有客户端换后端, 这里固定几个已知结果
+        let router = router(&BACKENDS);
+        for (address, backend) in [
+            ("192.0.2.1", "10.0.0.1:1883"),
+            ("192.0.2.2", "10.0.0.3:1883"),
+            ("192.0.2.3", "10.0.0.2:1883"),
+            ("2001:db8::1", "10.0.0.1:1883"),
+            ("2001:db8::3", "10.0.0.3:1883"),
+            ("2001:db8::4", "10.0.0.2:1883"),
+        ] {
+            assert_eq!(router.route(ip(address)), Some(backend), "{}", address);
+        }
+        assert_eq!(score(&address_key(ip("192.0.2.1")), "10.0.0.1:1883"), 16_795_479_998_478_650_085);
+    }
+
+    #[test]
+    fn routing_is_stable_while_backends_are_unchanged() {
+        let first = router(&BACKENDS);
+        let second = router(&BACKENDS);
+        for i in 0..=255u8 {
+            for address in [IpAddr::from([198, 51, 100, i]), IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, i as u16])] {
+                let backend = first.route(address);
+                assert_eq!(first.route(address), backend);
+                assert_eq!(second.route(address), backend, "{} must map identically across instances", address);
+            }
+        }
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_routes_like_ipv4() {
+        let router = router(&BACKENDS);
+        for i in 0..=255u8 {
+            let v4 = std::net::Ipv4Addr::new(203, 0, 113, i);
+            assert_eq!(router.route(IpAddr::V6(v4.to_ipv6_mapped())), router.route(IpAddr::V4(v4)));
+        }
+        assert_ne!(address_key(ip("::1")), address_key(ip("0.0.0.1")));
+    }
+
+    #[test]
+    fn spreads_addresses_over_all_backends() {
+        let router = router(&BACKENDS);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for i in 0..3000u32 {
+            let address = IpAddr::from((0x0a00_0000 + i).to_be_bytes());
+            *counts.entry(router.route(address).unwrap()).or_default() += 1;
+        }
+        for backend in BACKENDS {
+            let count = counts.get(backend).copied().unwrap_or(0);
+            assert!((800..=1200).contains(&count), "{} got {} of 3000 addresses", backend, count);
+        }
+    }
+
+    #[test]
+    fn removing_a_backend_only_moves_its_addresses() {
+        let all = router(&BACKENDS);
+        let remaining = router(&BACKENDS[..2]);
+        for i in 0..1000u32 {
+            let address = IpAddr::from((0xc000_0200 + i).to_be_bytes());
+            let before = all.route(address).unwrap();
+            if before != BACKENDS[2] {
+                assert_eq!(remaining.route(address), Some(before), "{} moved although its backend stayed", address);
+            }
+        }
+    }
+
+    #[test]
+    fn no_backends_uses_the_default() {
+        assert_eq!(router(&[]).route(ip("192.0.2.1")), None);
+    }
+}
@@ -0,0 +1,110 @@
+// splice 零拷贝转发 (Linux, `splice` feature)
+// 套接字之间经由内核管道搬运数据, 不复制到用户态; 只用于不需要检查转发数据的明文 TCP 连接
+
+use std::any::Any;
+use std::io::{Error, ErrorKind};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::OnceLock;
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+
+use crate::prefixed_stream::PrefixedStream;
+
+/// 每次 splice 最多搬运的字节数 (默认管道容量 64 KiB)
+const CHUNK: usize = 64 * 1024;
+
+/// 内核是否支持 splice (seccomp 或 gVisor 等环境中可能不可用), 首次调用时检测一次
+pub fn available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(probe)
+}
+
+/// 在两个管道之间搬运一个字节
+fn probe() -> bool {
+    let (Ok(source), Ok(target)) = (Pipe::new(), Pipe::new()) else {
+        return false;
+    };
+    let written = unsafe { libc::write(source.write_fd, [0u8].as_ptr().cast(), 1) };
+    written == 1 && splice(source.read_fd, target.write_fd, 1).is_ok_and(|n| n == 1)
+}
+
+/// 明文 TCP 客户端连接拆出 TCP 流和已读出但尚未转发的字节; TLS、WebSocket 等连接原样返回
+/// 支持适配器实际使用的流: 透传模式的 TcpStream 和智能模式的两层 PrefixedStream (预读数据, 延迟连接期间缓存的包)
+pub fn into_tcp<S: 'static>(stream: S) -> Result<(Vec<u8>, TcpStream), S> {
+    let stream: Box<dyn Any> = Box::new(stream);
+    let stream = match stream.downcast::<TcpStream>() {
+        Ok(tcp) => return Ok((Vec::new(), *tcp)),
+        Err(stream) => stream,
+    };
+    let stream = match stream.downcast::<PrefixedStream<PrefixedStream<TcpStream>>>() {
+        Ok(prefixed) => {
+            let (mut pending, inner) = prefixed.into_parts();
+            let (buffered, tcp) = inner.into_parts();
+            pending.extend_from_slice(&buffered);
+            return Ok((pending, tcp));
+        }
+        Err(stream) => stream,
+    };
+    Err(*stream.downcast::<S>().expect("stream type is unchanged"))
+}
+
+/// 把 `data` 全部写到套接字
+pub async fn write_all(to: &TcpStream, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let n = to.async_io(Interest::WRITABLE, || to.try_write(data)).await?;
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+/// 单方向转发使用的管道, 每次读入的数据在下一次读取之前全部写出, 管道在两次读取之间总是空的
+pub struct Pipe {
+    read_fd: RawFd,
+    write_fd: RawFd,
+}
+
+impl Pipe {
+    pub fn new() -> std::io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Pipe { read_fd: fds[0], write_fd: fds[1] })
+    }
+
+    /// 从套接字读入管道, 返回字节数, 0 表示对端关闭
+    pub async fn fill(&mut self, from: &TcpStream) -> std::io::Result<usize> {
+        from.async_io(Interest::READABLE, || splice(from.as_raw_fd(), self.write_fd, CHUNK)).await
+    }
+
+    /// 把管道中的 `len` 字节全部写到套接字
+    pub async fn drain(&mut self, to: &TcpStream, mut len: usize) -> std::io::Result<()> {
+        while len > 0 {
+            let n = to.async_io(Interest::WRITABLE, || splice(self.read_fd, to.as_raw_fd(), len)).await?;
+            if n == 0 {
+                return Err(Error::new(ErrorKind::WriteZero, "splice wrote no data"));
+            }
+            len -= n;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// 非阻塞的 splice, EAGAIN 映射为 WouldBlock (由 tokio 等待就绪后重试)
+fn splice(from: RawFd, to: RawFd, len: usize) -> std::io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    let n = unsafe { libc::splice(from, std::ptr::null_mut(), to, std::ptr::null_mut(), len, flags) };
+    if n < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(n as usize)
+}
@@ -0,0 +1,244 @@
+// StatsD 指标推送
+// 定期把共享的指标以 StatsD 行协议 (可选 DogStatsD 标签) 通过 UDP 发送, 与 /metrics 使用同一份指标定义
+
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::adapter_config::MetricsConfig;
+use crate::metrics::{MetricKind, MetricsSink, metrics};
+
+/// 单个 UDP 包的最大长度 (以太网 MTU 减去 IP/UDP 头, 避免分片)
+const MAX_DATAGRAM: usize = 1432;
+
+/// 推送任务: 每个 `flush_interval_ms` 发送一次所有指标
+pub async fn run_exporter(config: MetricsConfig) {
+    let socket = match connect(&config.statsd_target).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("StatsD exporter disabled: cannot reach {}: {}", config.statsd_target, e);
+            return;
+        }
+    };
+    info!("Pushing metrics to StatsD at {} every {} ms", config.statsd_target, config.flush_interval_ms);
+
+    let mut sink = StatsdSink::new(&config);
+    let mut interval = tokio::time::interval(Duration::from_millis(config.flush_interval_ms.max(1)));
+    loop {
+        interval.tick().await;
+        metrics().emit(&mut sink);
+        for datagram in sink.finish() {
+            if let Err(e) = socket.send(datagram.as_bytes()).await {
+                debug!("Failed to send StatsD datagram to {}: {}", config.statsd_target, e);
+            }
+        }
+    }
+}
+
+async fn connect(target: &str) -> std::io::Result<UdpSocket> {
+    let target = tokio::net::lookup_host(target).await?.next().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} did not resolve to an address", target))
+    })?;
+    let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// StatsD 行协议输出
+/// counter 以两次推送之间的增量发送 (`|c`), gauge 发送当前值 (`|g`); 直方图只发送 `_sum` 和 `_count` 的增量
+struct StatsdSink {
+    prefix: String,
+    tags: bool,
+    /// 当前指标的类型
+    kind: MetricKind,
+    /// 各 counter 上次推送时的累计值
+    previous: HashMap<String, f64>,
+    /// 上次推送过的 gauge, 本次消失的 (如清空的租户队列) 补发 0
+    previous_gauges: HashSet<(String, String)>,
+    current_gauges: HashSet<(String, String)>,
+    lines: Vec<String>,
+}
+
+impl StatsdSink {
+    fn new(config: &MetricsConfig) -> Self {
+        StatsdSink {
+            prefix: config.statsd_prefix.trim_end_matches('.').to_string(),
+            tags: config.statsd_tags,
+            kind: MetricKind::Gauge,
+            previous: HashMap::new(),
+            previous_gauges: HashSet::new(),
+            current_gauges: HashSet::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// 指标名和标签 (不含值和类型), 形如 `prefix.name|#key:value` 中 `|` 两侧的部分
+    fn metric_id(&self, name: &str, labels: &[(&str, &str)]) -> (String, String) {
+        let mut metric = if self.prefix.is_empty() { name.to_string() } else { format!("{}.{}", self.prefix, name) };
+        let mut tags = String::new();
+        for (i, (key, value)) in labels.iter().enumerate() {
+            if self.tags {
+                let _ = write!(tags, "{}{}:{}", if i == 0 { "|#" } else { "," }, key, sanitize(value));
+            } else {
+                let _ = write!(metric, ".{}", sanitize(value).replace('.', "_"));
+            }
+        }
+        (metric, tags)
+    }
+
+    /// 结束一次推送: 补发消失的 gauge, 返回按 `MAX_DATAGRAM` 分包的数据
+    fn finish(&mut self) -> Vec<String> {
+        for (metric, tags) in self.previous_gauges.difference(&self.current_gauges) {
+            self.lines.push(format!("{}:0|g{}", metric, tags));
+        }
+        self.previous_gauges = std::mem::take(&mut self.current_gauges);
+
+        let mut datagrams: Vec<String> = Vec::new();
+        for line in self.lines.drain(..) {
+            match datagrams.last_mut() {
+                Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM => {
+                    datagram.push('\n');
+                    datagram.push_str(&line);
+                }
+                _ => datagrams.push(line),
+            }
+        }
+        datagrams
+    }
+}
+
+impl MetricsSink for StatsdSink {
+    fn family(&mut self, _name: &str, _help: &str, kind: MetricKind) {
+        self.kind = kind;
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let (metric, tags) = self.metric_id(name, labels);
+        let (value, statsd_type) = match self.kind {
+            MetricKind::Gauge => {
+                self.current_gauges.insert((metric.clone(), tags.clone()));
+                (value, "g")
+            }
+            // 直方图的桶在 StatsD 中没有对应类型, 不发送
+            MetricKind::Histogram if name.ends_with("_bucket") => return,
+            MetricKind::Counter | MetricKind::Histogram => {
+                let previous = self.previous.insert(format!("{}{}", metric, tags), value).unwrap_or(0.0);
+                let delta = value - previous;
+                if delta <= 0.0 {
+                    return;
+                }
+                (delta, "c")
+            }
+        };
+        self.lines.push(format!("{}:{}|{}{}", metric, value, statsd_type, tags));
+    }
+}
+
+/// 去掉 StatsD 行协议中的分隔字符 (标签值可能来自客户端 ID)
+fn sanitize(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, ':' | '|' | '@' | '#' | ',' | '\n') || c.is_whitespace() { '_' } else { c }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sink(prefix: &str, tags: bool) -> StatsdSink {
+        StatsdSink::new(&MetricsConfig { statsd_prefix: prefix.to_string(), statsd_tags: tags, ..MetricsConfig::default() })
+    }
+
+    /// 一次推送的全部行
+    fn lines(sink: &mut StatsdSink) -> Vec<String> {
+        sink.finish().iter().flat_map(|datagram| datagram.lines().map(str::to_string).collect::<Vec<_>>()).collect()
+    }
+
+    #[test]
+    fn formats_counters_as_deltas() {
+        let mut sink = sink("mqtt_adapter", true);
+        sink.family("connections_total", "Connections", MetricKind::Counter);
+        sink.sample("connections_total", &[], 5.0);
+        assert_eq!(lines(&mut sink), ["mqtt_adapter.connections_total:5|c"]);
+
+        // 之后只发送增量, 没有变化时不发送
+        sink.family("connections_total", "Connections", MetricKind::Counter);
+        sink.sample("connections_total", &[], 8.0);
+        assert_eq!(lines(&mut sink), ["mqtt_adapter.connections_total:3|c"]);
+        sink.family("connections_total", "Connections", MetricKind::Counter);
+        sink.sample("connections_total", &[], 8.0);
+        assert!(lines(&mut sink).is_empty());
+    }
+
+    #[test]
+    fn formats_gauges_and_zeroes_vanished_ones() {
+        let mut sink = sink("mqtt_adapter", true);
+        sink.family("tenant_queue_depth", "Queued connections", MetricKind::Gauge);
+        sink.sample("tenant_queue_depth", &[("tenant", "a")], 2.0);
+        sink.sample("tenant_queue_depth", &[("tenant", "b")], 0.5);
+        assert_eq!(
+            lines(&mut sink),
+            ["mqtt_adapter.tenant_queue_depth:2|g|#tenant:a", "mqtt_adapter.tenant_queue_depth:0.5|g|#tenant:b"]
+        );
+
+        sink.family("tenant_queue_depth", "Queued connections", MetricKind::Gauge);
+        sink.sample("tenant_queue_depth", &[("tenant", "a")], 2.0);
+        assert_eq!(lines(&mut sink), ["mqtt_adapter.tenant_queue_depth:2|g|#tenant:a", "mqtt_adapter.tenant_queue_depth:0|g|#tenant:b"]);
+    }
+
+    #[test]
+    fn formats_histogram_sum_and_count_without_buckets() {
+        let mut sink = sink("", true);
+        sink.family("connect_latency_seconds", "Latency", MetricKind::Histogram);
+        sink.sample("connect_latency_seconds_bucket", &[("le", "0.1")], 3.0);
+        sink.sample("connect_latency_seconds_sum", &[], 0.25);
+        sink.sample("connect_latency_seconds_count", &[], 3.0);
+        assert_eq!(lines(&mut sink), ["connect_latency_seconds_sum:0.25|c", "connect_latency_seconds_count:3|c"]);
+    }
+
+    #[test]
+    fn prefix_trailing_dot_is_trimmed() {
+        let mut sink = sink("edge.", true);
+        sink.family("open_connections", "Open", MetricKind::Gauge);
+        sink.sample("open_connections", &[], 1.0);
+        assert_eq!(lines(&mut sink), ["edge.open_connections:1|g"]);
+    }
+
+    #[test]
+    fn formats_dogstatsd_tags() {
+        let mut sink = sink("mqtt_adapter", true);
+        sink.family("accept_errors_total", "Accept errors", MetricKind::Counter);
+        sink.sample("accept_errors_total", &[("kind", "fd_exhausted"), ("listener", "tcp")], 1.0);
+        assert_eq!(lines(&mut sink), ["mqtt_adapter.accept_errors_total:1|c|#kind:fd_exhausted,listener:tcp"]);
+    }
+
+    #[test]
+    fn labels_join_the_name_without_tags() {
+        let mut sink = sink("mqtt_adapter", false);
+        sink.family("connections_by_version_total", "Connections", MetricKind::Counter);
+        sink.sample("connections_by_version_total", &[("version", "3.1.1")], 2.0);
+        assert_eq!(lines(&mut sink), ["mqtt_adapter.connections_by_version_total.3_1_1:2|c"]);
+    }
+
+    #[test]
+    fn label_values_are_sanitized() {
+        let mut sink = sink("mqtt_adapter", true);
+        sink.family("tenant_connections", "Connections", MetricKind::Gauge);
+        sink.sample("tenant_connections", &[("tenant", "a:b|c#d,e f")], 1.0);
+        assert_eq!(lines(&mut sink), ["mqtt_adapter.tenant_connections:1|g|#tenant:a_b_c_d_e_f"]);
+    }
+
+    #[test]
+    fn splits_datagrams_at_the_mtu() {
+        let mut sink = sink("mqtt_adapter", true);
+        sink.family("tenant_connections", "Connections", MetricKind::Gauge);
+        for tenant in 0..200 {
+            sink.sample("tenant_connections", &[("tenant", &format!("tenant-{}", tenant))], 1.0);
+        }
+        let datagrams = sink.finish();
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= MAX_DATAGRAM));
+        assert_eq!(datagrams.iter().map(|datagram| datagram.lines().count()).sum::<usize>(), 200);
+    }
+}
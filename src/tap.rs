@@ -0,0 +1,284 @@
+// 转发流的包级解析 (packet tap)
+// 在不改变转发字节的前提下识别包边界, 供在途 QoS 跟踪等功能使用
+
+use std::collections::HashSet;
+
+/// 每个包最多保留的前缀字节数 (不含固定头)
+/// 足以覆盖常见 PUBLISH 的主题名和报文标识符, 以及大多数 SUBSCRIBE 包
+pub const PREFIX_LIMIT: usize = 1024;
+
+/// MQTT 控制包类型
+pub mod packet_type {
+    pub const CONNECT: u8 = 1;
+    pub const PUBLISH: u8 = 3;
+    pub const PUBACK: u8 = 4;
+    pub const PUBREC: u8 = 5;
+    pub const PUBREL: u8 = 6;
+    pub const PUBCOMP: u8 = 7;
+    pub const SUBSCRIBE: u8 = 8;
+    pub const SUBACK: u8 = 9;
+    pub const UNSUBSCRIBE: u8 = 10;
+    pub const UNSUBACK: u8 = 11;
+    pub const PINGREQ: u8 = 12;
+    pub const DISCONNECT: u8 = 14;
+}
+
+/// 解析出的一个完整包
+#[derive(Debug, Clone)]
+pub struct TappedPacket {
+    /// 固定头首字节
+    pub header: u8,
+    /// 剩余长度
+    pub remaining_length: usize,
+    /// 剩余部分的前 `PREFIX_LIMIT` 字节
+    pub prefix: Vec<u8>,
+}
+
+impl TappedPacket {
+    pub fn packet_type(&self) -> u8 {
+        self.header >> 4
+    }
+
+    /// 前缀是否包含完整的包体
+    pub fn is_complete(&self) -> bool {
+        self.prefix.len() == self.remaining_length
+    }
+
+    /// PUBLISH 的 QoS 等级
+    pub fn qos(&self) -> u8 {
+        (self.header >> 1) & 0x03
+    }
+
+    /// 报文标识符 (PUBLISH 需要 QoS > 0), 前缀不足时返回 None
+    pub fn packet_id(&self) -> Option<u16> {
+        let offset = if self.packet_type() == packet_type::PUBLISH {
+            let topic_len = u16::from_be_bytes([*self.prefix.first()?, *self.prefix.get(1)?]) as usize;
+            2 + topic_len
+        } else {
+            0
+        };
+        Some(u16::from_be_bytes([*self.prefix.get(offset)?, *self.prefix.get(offset + 1)?]))
+    }
+
+    /// 还原完整包字节 (仅在 `is_complete` 时有意义)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        crate::codec::encode_packet(self.header, &self.prefix)
+    }
+}
+
+/// 一块数据中属于同一个包的连续字节
+#[derive(Debug, Clone, Copy)]
+pub struct PacketSpan {
+    /// 所属包的固定头首字节
+    pub header: u8,
+    /// 字节数
+    pub len: usize,
+    /// 是否从包的第一个字节开始
+    pub starts_packet: bool,
+    /// 所属包的剩余长度, 到该片段结束时还没有读完剩余长度字段时为 None
+    pub remaining_length: Option<usize>,
+}
+
+impl PacketSpan {
+    pub fn packet_type(&self) -> u8 {
+        self.header >> 4
+    }
+}
+
+/// 增量包解析器, 逐块喂入转发的字节
+#[derive(Debug)]
+pub struct PacketTap {
+    state: TapState,
+    /// 每个包保留的前缀字节数
+    prefix_limit: usize,
+}
+
+impl Default for PacketTap {
+    fn default() -> Self {
+        PacketTap { state: TapState::default(), prefix_limit: PREFIX_LIMIT }
+    }
+}
+
+#[derive(Debug, Default)]
+enum TapState {
+    /// 等待固定头首字节
+    #[default]
+    Header,
+    /// 读取剩余长度
+    Length { header: u8, value: usize, multiplier: usize, bytes: usize },
+    /// 读取包体
+    Body { header: u8, remaining_length: usize, read: usize, prefix: Vec<u8> },
+    /// 流格式错误, 停止解析
+    Broken,
+}
+
+impl PacketTap {
+    /// 只识别包边界和固定头, 不保留包体 (解析出的包 `prefix` 为空)
+    pub fn headers_only() -> Self {
+        PacketTap { prefix_limit: 0, ..PacketTap::default() }
+    }
+
+    /// 喂入一块数据, 返回其中结束的完整包
+    pub fn feed(&mut self, mut data: &[u8]) -> Vec<TappedPacket> {
+        let mut packets = Vec::new();
+
+        while !data.is_empty() && !self.is_broken() {
+            let (consumed, packet) = self.step(data);
+            packets.extend(packet);
+            data = &data[consumed..];
+        }
+
+        packets
+    }
+
+    /// 喂入一块数据, 按所属的包切分 (包可能跨越多次调用); 流无法解析之后的字节不在返回的片段中
+    pub fn feed_spans(&mut self, mut data: &[u8]) -> Vec<PacketSpan> {
+        let mut spans = Vec::new();
+
+        while !data.is_empty() && !self.is_broken() {
+            let starts_packet = self.at_boundary();
+            let header = self.pending_header().unwrap_or(data[0]);
+            let (consumed, packet) = self.step(data);
+            let remaining_length = match (&packet, &self.state) {
+                (Some(packet), _) => Some(packet.remaining_length),
+                (None, TapState::Body { remaining_length, .. }) => Some(*remaining_length),
+                (None, _) => None,
+            };
+            spans.push(PacketSpan { header, len: consumed, starts_packet, remaining_length });
+            data = &data[consumed..];
+        }
+
+        spans
+    }
+
+    /// 解析到当前包结束或数据用完为止, 返回消耗的字节数和结束的包
+    fn step(&mut self, data: &[u8]) -> (usize, Option<TappedPacket>) {
+        let mut consumed = 0;
+
+        while consumed < data.len() {
+            let rest = &data[consumed..];
+            match &mut self.state {
+                TapState::Header => {
+                    self.state = TapState::Length { header: rest[0], value: 0, multiplier: 1, bytes: 0 };
+                    consumed += 1;
+                }
+                TapState::Length { header, value, multiplier, bytes } => {
+                    let byte = rest[0];
+                    consumed += 1;
+                    *value += (byte & 127) as usize * *multiplier;
+                    *multiplier *= 128;
+                    *bytes += 1;
+
+                    if byte & 128 == 0 {
+                        let (header, remaining_length) = (*header, *value);
+                        self.state = TapState::Body { header, remaining_length, read: 0, prefix: Vec::new() };
+                        if remaining_length == 0 {
+                            return (consumed, Some(self.finish()));
+                        }
+                    } else if *bytes >= 4 {
+                        self.state = TapState::Broken;
+                    }
+                }
+                TapState::Body { remaining_length, read, prefix, .. } => {
+                    let take = (*remaining_length - *read).min(rest.len());
+                    let keep = take.min(self.prefix_limit.saturating_sub(prefix.len()));
+                    prefix.extend_from_slice(&rest[..keep]);
+                    *read += take;
+                    consumed += take;
+
+                    if *read == *remaining_length {
+                        return (consumed, Some(self.finish()));
+                    }
+                }
+                TapState::Broken => break,
+            }
+        }
+
+        (consumed, None)
+    }
+
+    /// 是否正好位于包边界 (没有未结束的包)
+    pub fn at_boundary(&self) -> bool {
+        matches!(self.state, TapState::Header)
+    }
+
+    /// 正在读取的包 (已读到固定头首字节但未结束) 的首字节
+    pub fn pending_header(&self) -> Option<u8> {
+        match self.state {
+            TapState::Length { header, .. } | TapState::Body { header, .. } => Some(header),
+            TapState::Header | TapState::Broken => None,
+        }
+    }
+
+    /// 流是否已无法解析
+    pub fn is_broken(&self) -> bool {
+        matches!(self.state, TapState::Broken)
+    }
+
+    fn finish(&mut self) -> TappedPacket {
+        match std::mem::take(&mut self.state) {
+            TapState::Body { header, remaining_length, prefix, .. } => {
+                TappedPacket { header, remaining_length, prefix }
+            }
+            _ => unreachable!("finish() called outside of packet body"),
+        }
+    }
+}
+
+/// 包的发送方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ClientToBroker,
+    BrokerToClient,
+}
+
+/// 在途 QoS 1/2 交互和 SUBSCRIBE/UNSUBSCRIBE 跟踪
+/// 报文标识符按发起方分为两个空间: 客户端发起的和 broker 发起的
+#[derive(Debug, Default)]
+pub struct InflightTracker {
+    pending: HashSet<(Direction, u16)>,
+    /// 出现过无法识别报文标识符的包, 无法判断是否空闲
+    unknown: bool,
+}
+
+impl InflightTracker {
+    /// 按方向记录一个完整的包
+    pub fn observe(&mut self, direction: Direction, packet: &TappedPacket) {
+        use packet_type::*;
+
+        let packet_type = packet.packet_type();
+        let tracked = matches!(packet_type, PUBACK | PUBREC | PUBREL | PUBCOMP | SUBSCRIBE | SUBACK | UNSUBSCRIBE | UNSUBACK)
+            || (packet_type == PUBLISH && packet.qos() > 0);
+        if !tracked {
+            return;
+        }
+
+        let Some(id) = packet.packet_id() else {
+            self.unknown = true;
+            return;
+        };
+
+        // 应答方向的包对应另一方发起的交互
+        let peer = match direction {
+            Direction::ClientToBroker => Direction::BrokerToClient,
+            Direction::BrokerToClient => Direction::ClientToBroker,
+        };
+
+        match packet_type {
+            PUBLISH | SUBSCRIBE | UNSUBSCRIBE => {
+                self.pending.insert((direction, id));
+            }
+            // QoS 1 结束 / QoS 2 最后一步 / 订阅应答
+            PUBACK | PUBCOMP | SUBACK | UNSUBACK => {
+                self.pending.remove(&(peer, id));
+            }
+            // QoS 2 中间步骤, 交互仍未结束
+            _ => {}
+        }
+    }
+
+    /// 当前没有未完成的交互
+    pub fn is_idle(&self) -> bool {
+        !self.unknown && self.pending.is_empty()
+    }
+}
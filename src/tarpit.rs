@@ -0,0 +1,113 @@
+// 拒绝连接的 tarpit
+// 被拒绝的连接先保持打开一段时间再回复并关闭, 拖慢快速重试的扫描器; 滞留在单独的任务中, 不占用工作池和准入许可
+
+use log::debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::metrics::metrics;
+
+/// tarpit
+pub struct Tarpit {
+    delay: Duration,
+    max_sockets: usize,
+    /// 当前滞留的连接数
+    held: Arc<AtomicUsize>,
+}
+
+impl Tarpit {
+    pub fn new(delay: Duration, max_sockets: usize) -> Self {
+        Tarpit { delay, max_sockets, held: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    /// 接管被拒绝的连接: 空闲 `delay` 后写入 `farewell` (如失败的 CONNACK) 并关闭
+    /// 滞留的连接数已达上限时不再等待, 立即写入并关闭
+    pub fn hold<S>(&self, mut stream: S, farewell: Vec<u8>)
+    where
+        S: AsyncWrite + Unpin + Send + 'static,
+    {
+        let held = self.held.clone();
+        let delay = if held.fetch_add(1, Ordering::Relaxed) < self.max_sockets {
+            metrics().tarpit_total.fetch_add(1, Ordering::Relaxed);
+            metrics().tarpit_active.fetch_add(1, Ordering::Relaxed);
+            Some(self.delay)
+        } else {
+            held.fetch_sub(1, Ordering::Relaxed);
+            metrics().tarpit_overflow_total.fetch_add(1, Ordering::Relaxed);
+            None
+        };
+
+        tokio::spawn(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+                held.fetch_sub(1, Ordering::Relaxed);
+                metrics().tarpit_active.fetch_sub(1, Ordering::Relaxed);
+            }
+            // 客户端可能早已断开, 写入失败无需处理
+            let closed = async {
+                if !farewell.is_empty() {
+                    stream.write_all(&farewell).await?;
+                }
+                stream.shutdown().await
+            };
+            if let Err(e) = closed.await {
+                debug!("Tarpit: closing a rejected connection failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::time::Instant;
+
+    const FAREWELL: [u8; 4] = [0x20, 0x02, 0x00, 0x05];
+
+    /// 读到对端关闭, 返回收到的字节和从开始读取起经过的时间
+    async fn read_until_closed(client: &mut tokio::io::DuplexStream) -> (Vec<u8>, Duration) {
+        let started = Instant::now();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        (received, started.elapsed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replies_and_closes_after_the_delay() {
+        let delay = Duration::from_millis(5_000);
+        let tarpit = Tarpit::new(delay, 10);
+        let (mut client, server) = tokio::io::duplex(64);
+        tarpit.hold(server, FAREWELL.to_vec());
+        assert_eq!(tarpit.held.load(Ordering::Relaxed), 1);
+
+        // 延迟到期之前什么都收不到
+        let mut byte = [0u8; 1];
+        assert!(tokio::time::timeout(delay - Duration::from_millis(1), client.read(&mut byte)).await.is_err());
+
+        let (received, elapsed) = read_until_closed(&mut client).await;
+        assert_eq!(received, FAREWELL);
+        assert_eq!(elapsed, Duration::from_millis(1));
+        assert_eq!(tarpit.held.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_immediately_when_full() {
+        let tarpit = Tarpit::new(Duration::from_millis(5_000), 1);
+        let (mut held, server) = tokio::io::duplex(64);
+        tarpit.hold(server, FAREWELL.to_vec());
+        let overflow_before = metrics().tarpit_overflow_total.load(Ordering::Relaxed);
+        let (mut overflow, server) = tokio::io::duplex(64);
+        tarpit.hold(server, Vec::new());
+        assert!(metrics().tarpit_overflow_total.load(Ordering::Relaxed) > overflow_before);
+
+        let (received, elapsed) = read_until_closed(&mut overflow).await;
+        assert!(received.is_empty());
+        assert_eq!(elapsed, Duration::ZERO);
+        let (received, elapsed) = read_until_closed(&mut held).await;
+        assert_eq!(received, FAREWELL);
+        assert_eq!(elapsed, Duration::from_millis(5_000));
+    }
+}
@@ -0,0 +1,83 @@
+// 连接流程的分布式追踪
+// 每个连接一个 span,各阶段记录事件; 启用 `otel` feature 时通过 OTLP 导出
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::adapter_config::OtelConfig;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 分配连接关联 ID, 用于串联同一连接的日志和追踪
+pub fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// 当前 span 所属追踪的 trace id, 用作指标的 exemplar
+#[cfg(feature = "otel")]
+pub fn current_trace_id() -> Option<String> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context.is_valid().then(|| span_context.trace_id().to_string())
+}
+
+/// 未启用 `otel` feature 时没有 trace id
+#[cfg(not(feature = "otel"))]
+pub fn current_trace_id() -> Option<String> {
+    None
+}
+
+/// 初始化 OTLP 追踪导出
+#[cfg(feature = "otel")]
+pub fn init(config: &OtelConfig) {
+    use log::{error, info};
+    use opentelemetry::KeyValue;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let Some(endpoint) = &config.endpoint else {
+        return;
+    };
+
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            error!("Failed to create OTLP exporter for {}: {}", endpoint, e);
+            return;
+        }
+    };
+
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new(vec![
+            KeyValue::new("service.name", config.service_name.clone()),
+        ]))
+        .build();
+    let tracer = provider.tracer("rustmqttserverdemo");
+    opentelemetry::global::set_tracer_provider(provider);
+
+    match tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+    {
+        Ok(()) => info!("Exporting connection traces via OTLP to {}", endpoint),
+        Err(e) => error!("Failed to install tracing subscriber: {}", e),
+    }
+}
+
+/// 未启用 `otel` feature 时不导出追踪
+#[cfg(not(feature = "otel"))]
+pub fn init(config: &OtelConfig) {
+    if config.endpoint.is_some() {
+        log::warn!("[adapter.otel] endpoint is set but the binary was built without the `otel` feature");
+    }
+}
@@ -0,0 +1,399 @@
+// TLS 终结
+// 适配器在 TLS 端口上完成握手, 解密后的流按普通连接处理并以明文转发给 broker
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::net::SocketAddr;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{self, AlertDescription, Certificate, PrivateKey, ProtocolVersion, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+
+use crate::adapter_config::TlsConfig;
+use crate::classify::{Protocol, classify_first_bytes};
+use crate::tls_guard::TlsRecordGuard;
+
+/// 握手失败原因 (`tls_handshake_failures_total` 的 reason 标签)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    /// 客户端在握手中途断开
+    ClientAbort,
+    /// 证书校验失败 (包括客户端以证书相关告警拒绝服务端证书)
+    CertVerify,
+    /// 不是 TLS 流量, 或双方没有共同支持的版本/算法
+    ProtocolMismatch,
+    /// 客户端发送了其它致命告警
+    Alert,
+    /// 握手超时
+    Timeout,
+    /// 同时进行的握手数已满, 等待超过 `handshake_queue_timeout_ms`
+    Overloaded,
+    /// ClientHello 携带 0-RTT early data (`reject_early_data`)
+    EarlyData,
+    Other,
+}
+
+impl HandshakeFailure {
+    pub const ALL: [HandshakeFailure; 8] = [
+        HandshakeFailure::ClientAbort,
+        HandshakeFailure::CertVerify,
+        HandshakeFailure::ProtocolMismatch,
+        HandshakeFailure::Alert,
+        HandshakeFailure::Timeout,
+        HandshakeFailure::Overloaded,
+        HandshakeFailure::EarlyData,
+        HandshakeFailure::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HandshakeFailure::ClientAbort => "client_abort",
+            HandshakeFailure::CertVerify => "cert_verify",
+            HandshakeFailure::ProtocolMismatch => "protocol_mismatch",
+            HandshakeFailure::Alert => "alert",
+            HandshakeFailure::Timeout => "timeout",
+            HandshakeFailure::Overloaded => "overloaded",
+            HandshakeFailure::EarlyData => "early_data",
+            HandshakeFailure::Other => "other",
+        }
+    }
+
+    /// 按 tokio-rustls 返回的错误分类
+    fn classify(error: &std::io::Error) -> Self {
+        let tls_error = error.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>());
+        match tls_error {
+            Some(rustls::Error::InvalidCertificate(_) | rustls::Error::NoCertificatesPresented) => {
+                HandshakeFailure::CertVerify
+            }
+            Some(rustls::Error::AlertReceived(alert)) => match alert {
+                AlertDescription::BadCertificate
+                | AlertDescription::UnsupportedCertificate
+                | AlertDescription::CertificateRevoked
+                | AlertDescription::CertificateExpired
+                | AlertDescription::CertificateUnknown
+                | AlertDescription::UnknownCA => HandshakeFailure::CertVerify,
+                _ => HandshakeFailure::Alert,
+            },
+            Some(
+                rustls::Error::InvalidMessage(_)
+                | rustls::Error::InappropriateMessage { .. }
+                | rustls::Error::InappropriateHandshakeMessage { .. }
+                | rustls::Error::PeerIncompatible(_),
+            ) => HandshakeFailure::ProtocolMismatch,
+            Some(_) => HandshakeFailure::Other,
+            None => match error.kind() {
+                ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => {
+                    HandshakeFailure::ClientAbort
+                }
+                // 由 TlsRecordGuard 在读取 ClientHello 时拒绝
+                ErrorKind::PermissionDenied => HandshakeFailure::EarlyData,
+                _ => HandshakeFailure::Other,
+            },
+        }
+    }
+}
+
+/// 握手协商出的 TLS 版本和密码套件 (日志、GET /connections 和 `tls_connections_total`)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TlsInfo {
+    /// "1.2" / "1.3"
+    pub version: &'static str,
+    /// IANA 名称, 如 "TLS13_AES_256_GCM_SHA384"
+    pub cipher: &'static str,
+}
+
+impl TlsInfo {
+    /// 读取握手完成后的连接参数, 未知的值记为 "unknown"
+    pub fn negotiated(stream: &AdapterTlsStream) -> Self {
+        let connection = stream.get_ref().1;
+        let version = match connection.protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => "1.2",
+            Some(ProtocolVersion::TLSv1_3) => "1.3",
+            _ => "unknown",
+        };
+        let cipher = connection.negotiated_cipher_suite()
+            .and_then(|suite| suite.suite().as_str())
+            .unwrap_or("unknown");
+        TlsInfo { version, cipher }
+    }
+}
+
+/// TLS 监听器上的连接: 协商参数和客户端证书
+#[derive(Debug, Clone)]
+pub struct TlsPeer {
+    pub info: TlsInfo,
+    /// 客户端的终端证书 (DER), 未配置 client_ca_path 时客户端不提供证书, 为 None
+    pub certificate: Option<Vec<u8>>,
+}
+
+impl TlsPeer {
+    pub fn negotiated(stream: &AdapterTlsStream) -> Self {
+        let certificate = stream.get_ref().1
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .map(|cert| cert.0.clone());
+        TlsPeer { info: TlsInfo::negotiated(stream), certificate }
+    }
+}
+
+/// TLS 监听器上的连接: rustls 之下是检查 TLS 记录的 TCP 流
+pub type AdapterTlsStream = TlsStream<TlsRecordGuard>;
+
+/// 默认每个 CPU 核心允许同时进行的握手数
+/// 握手的大部分时间在等待网络往返, 一个核心在一个往返内可以完成几十次握手的计算
+const HANDSHAKES_PER_CORE: usize = 64;
+
+/// TLS 握手: 接受器、握手时限和同时进行的握手数上限, 所有 TLS 监听器共享
+#[derive(Clone)]
+pub struct TlsHandshaker {
+    acceptor: TlsAcceptor,
+    timeout: Duration,
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    queue_timeout: Duration,
+    reject_early_data: bool,
+}
+
+impl TlsHandshaker {
+    pub fn from_config(config: &TlsConfig) -> std::io::Result<Self> {
+        let max_concurrent = match config.max_concurrent_handshakes {
+            0 => num_cpus::get().max(1) * HANDSHAKES_PER_CORE,
+            max => max,
+        };
+        Ok(TlsHandshaker {
+            acceptor: load_acceptor(config)?,
+            timeout: Duration::from_millis(config.handshake_timeout_ms),
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            queue_timeout: Duration::from_millis(config.handshake_queue_timeout_ms),
+            reject_early_data: config.reject_early_data,
+        })
+    }
+
+    /// 同时进行的握手数上限
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    /// 检查连接开头并完成 TLS 握手, 握手数已满时最多等待 `queue_timeout`
+    /// 等待首批数据和握手共用同一个时限, 并且都占用握手许可: 连接后不发送数据的客户端同样在时限到达时断开
+    /// 失败时连接随 `stream` 一起关闭, 调用方不应再读取 CONNECT
+    pub async fn accept(&self, stream: TcpStream, peer: SocketAddr) -> Result<TlsStart, (HandshakeFailure, String)> {
+        // 许可在握手结束 (成功或失败) 时释放
+        let _permit = match tokio::time::timeout(self.queue_timeout, self.permits.acquire()).await {
+            Ok(Ok(permit)) => permit,
+            _ => {
+                return Err((
+                    HandshakeFailure::Overloaded,
+                    format!("{} handshakes already in progress", self.max_concurrent),
+                ));
+            }
+        };
+
+        let start = async {
+            let mut probe = [0u8; 16];
+            let peeked = stream.peek(&mut probe).await;
+            if matches!(peeked, Ok(0)) {
+                return Ok(TlsStart::Probe);
+            }
+            let protocol = peeked.map_or(Protocol::Tls, |n| classify_first_bytes(&probe[..n]));
+            if !matches!(protocol, Protocol::Tls | Protocol::NeedMoreData) {
+                return Ok(TlsStart::Plaintext(stream, protocol));
+            }
+
+            let stream = TlsRecordGuard::new(stream, peer, self.reject_early_data);
+            match self.acceptor.accept(stream).await {
+                Ok(mut stream) => {
+                    stream.get_mut().0.set_established();
+                    Ok(TlsStart::Established(Box::new(stream)))
+                }
+                Err(e) => Err((HandshakeFailure::classify(&e), e.to_string())),
+            }
+        };
+        tokio::time::timeout(self.timeout, start)
+            .await
+            .unwrap_or_else(|_| Err((HandshakeFailure::Timeout, format!("no handshake within {:?}", self.timeout))))
+    }
+}
+
+/// TLS 监听器上一条连接的开头
+pub enum TlsStart {
+    /// 握手完成
+    Established(Box<AdapterTlsStream>),
+    /// 未发送任何数据就关闭 (健康检查), 不计为握手失败
+    Probe,
+    /// 开头不是 TLS 记录的明文连接, 未读取任何数据, 由调用方按 `require_tls` 处理
+    Plaintext(TcpStream, Protocol),
+}
+
+/// 从 PEM 证书链和私钥创建 TLS 接受器
+fn load_acceptor(config: &TlsConfig) -> std::io::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert_path)?))?;
+    if certs.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("No certificates found in {}", config.cert_path),
+        ));
+    }
+
+    let key = load_private_key(&config.key_path, config.key_password.as_deref())?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca_path {
+        Some(path) => builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(load_client_roots(path)?).boxed()),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certs.into_iter().map(Certificate).collect(), key)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    // 不接受 0-RTT: early data 可以被重放, MQTT CONNECT 不能在握手完成之前生效; 会话票据中也不会声明 early data
+    server_config.max_early_data_size = 0;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// 读取校验客户端证书的 CA 证书
+fn load_client_roots(path: &str) -> std::io::Result<RootCertStore> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&certs);
+    if added == 0 {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, format!("No CA certificates found in {}", path)));
+    }
+    Ok(roots)
+}
+
+/// 加密的 PKCS#8 私钥 PEM 标签
+const ENCRYPTED_PKCS8_LABEL: &str = "ENCRYPTED PRIVATE KEY";
+
+/// 读取 PEM 私钥, 按 PEM 头识别格式:
+/// PKCS#8 (`PRIVATE KEY`)、PKCS#1 (`RSA PRIVATE KEY`)、SEC1 (`EC PRIVATE KEY`)
+/// 和加密的 PKCS#8 (`ENCRYPTED PRIVATE KEY`, 需要 `key_password`)
+fn load_private_key(path: &str, password: Option<&str>) -> std::io::Result<PrivateKey> {
+    let invalid = |message: String| std::io::Error::new(ErrorKind::InvalidData, message);
+    let pem = std::fs::read_to_string(path)?;
+
+    if let Some(block) = pem_block(&pem, ENCRYPTED_PKCS8_LABEL) {
+        let password = password.ok_or_else(|| {
+            invalid(format!("Private key in {} is encrypted but key_password is not set", path))
+        })?;
+        let (_, document) = pkcs8::der::Document::from_pem(block)
+            .map_err(|e| invalid(format!("Invalid encrypted private key in {}: {}", path, e)))?;
+        let encrypted = pkcs8::EncryptedPrivateKeyInfo::try_from(document.as_bytes())
+            .map_err(|e| invalid(format!("Invalid encrypted private key in {}: {}", path, e)))?;
+        let decrypted = encrypted.decrypt(password)
+            .map_err(|e| invalid(format!("Failed to decrypt private key in {} (wrong key_password?): {}", path, e)))?;
+        return Ok(PrivateKey(decrypted.as_bytes().to_vec()));
+    }
+
+    // 传统 OpenSSL 加密格式 (Proc-Type/DEK-Info 头) 不支持, 提示转换
+    if pem.contains("Proc-Type: 4,ENCRYPTED") {
+        return Err(invalid(format!(
+            "Private key in {} uses legacy OpenSSL encryption; convert it with `openssl pkcs8 -topk8 -in {} -out <new>`",
+            path, path
+        )));
+    }
+
+    for item in rustls_pemfile::read_all(&mut pem.as_bytes())? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    Err(invalid(format!(
+        "No private key found in {} (tried PEM blocks: PRIVATE KEY (PKCS#8), RSA PRIVATE KEY (PKCS#1), \
+         EC PRIVATE KEY (SEC1), ENCRYPTED PRIVATE KEY (encrypted PKCS#8))",
+        path
+    )))
+}
+
+/// 取出指定标签的第一个 PEM 块 (包括 BEGIN/END 行)
+fn pem_block<'a>(pem: &'a str, label: &str) -> Option<&'a str> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let start = pem.find(&begin)?;
+    let stop = start + pem[start..].find(&end)? + end.len();
+    Some(&pem[start..stop])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE_PASSWORD: &str = "fixture-password";
+
+    fn fixture(name: &str) -> String {
+        format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// 读取并确认 rustls 可以用该私钥签名
+    fn assert_loads(name: &str, password: Option<&str>) {
+        let key = load_private_key(&fixture(name), password)
+            .unwrap_or_else(|e| panic!("{} failed to load: {}", name, e));
+        rustls::sign::any_supported_type(&key)
+            .unwrap_or_else(|_| panic!("{} loaded but is not a usable signing key", name));
+    }
+
+    #[test]
+    fn loads_pkcs1_rsa_key() {
+        assert_loads("rsa_pkcs1.pem", None);
+    }
+
+    #[test]
+    fn loads_pkcs8_key() {
+        assert_loads("ec_pkcs8.pem", None);
+    }
+
+    #[test]
+    fn loads_sec1_ec_key() {
+        assert_loads("ec_sec1.pem", None);
+    }
+
+    #[test]
+    fn loads_encrypted_pkcs8_key_with_password() {
+        assert_loads("ec_pkcs8_encrypted.pem", Some(FIXTURE_PASSWORD));
+    }
+
+    #[test]
+    fn encrypted_pkcs8_key_matches_plain_key() {
+        let plain = load_private_key(&fixture("ec_pkcs8.pem"), None).unwrap();
+        let decrypted = load_private_key(&fixture("ec_pkcs8_encrypted.pem"), Some(FIXTURE_PASSWORD)).unwrap();
+        assert_eq!(plain, decrypted);
+    }
+
+    #[test]
+    fn encrypted_key_without_password_is_rejected() {
+        let error = load_private_key(&fixture("ec_pkcs8_encrypted.pem"), None).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("key_password is not set"), "{}", error);
+    }
+
+    #[test]
+    fn encrypted_key_with_wrong_password_is_rejected() {
+        let error = load_private_key(&fixture("ec_pkcs8_encrypted.pem"), Some("wrong")).unwrap_err();
+        assert!(error.to_string().contains("wrong key_password"), "{}", error);
+    }
+
+    #[test]
+    fn legacy_encrypted_key_is_rejected_with_hint() {
+        let error = load_private_key(&fixture("ec_legacy_encrypted.pem"), Some(FIXTURE_PASSWORD)).unwrap_err();
+        assert!(error.to_string().contains("openssl pkcs8 -topk8"), "{}", error);
+    }
+
+    #[test]
+    fn pem_without_key_is_rejected() {
+        let path = std::env::temp_dir().join(format!("tls-no-key-{}.pem", std::process::id()));
+        std::fs::write(&path, "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n").unwrap();
+        let error = load_private_key(path.to_str().unwrap(), None).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(error.to_string().contains("No private key found"), "{}", error);
+    }
+}
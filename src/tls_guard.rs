@@ -0,0 +1,182 @@
+// TLS 记录检查
+// 在 rustls 之下观察客户端发来的 TLS 记录: 拒绝携带 0-RTT early data 的 ClientHello (防止 CONNECT 被重放),
+// 握手完成后再出现的握手记录是 TLS 1.2 重新协商请求, 按可疑连接断开
+
+use log::warn;
+use std::io::{Error, ErrorKind, IoSlice};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::metrics::metrics;
+
+/// TLS 记录头长度: 类型 (1) + 版本 (2) + 长度 (2)
+const RECORD_HEADER_LEN: usize = 5;
+/// 握手记录的类型
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+/// early_data 扩展的类型 (RFC 8446 4.2.10)
+const EXTENSION_EARLY_DATA: u16 = 42;
+/// 收集 ClientHello 的上限, 超过时不再检查
+const MAX_CLIENT_HELLO_LEN: usize = 64 * 1024;
+
+/// 包装 TLS 连接底层的 TCP 流, 检查读到的 TLS 记录
+pub struct TlsRecordGuard {
+    inner: TcpStream,
+    peer: SocketAddr,
+    reject_early_data: bool,
+    header: [u8; RECORD_HEADER_LEN],
+    header_len: usize,
+    /// 当前记录尚未读到的字节数
+    remaining: usize,
+    /// 当前记录是否为握手记录
+    in_handshake_record: bool,
+    /// 正在收集的 ClientHello (握手消息), 检查完成后为 None
+    client_hello: Option<Vec<u8>>,
+    /// 握手已完成, 之后的握手记录视为重新协商
+    established: bool,
+}
+
+impl TlsRecordGuard {
+    pub fn new(inner: TcpStream, peer: SocketAddr, reject_early_data: bool) -> Self {
+        TlsRecordGuard {
+            inner,
+            peer,
+            reject_early_data,
+            header: [0; RECORD_HEADER_LEN],
+            header_len: 0,
+            remaining: 0,
+            in_handshake_record: false,
+            client_hello: Some(Vec::new()),
+            established: false,
+        }
+    }
+
+    /// TLS 握手完成后调用
+    pub fn set_established(&mut self) {
+        self.established = true;
+        self.client_hello = None;
+    }
+
+    /// 按记录边界解析新读到的字节
+    fn inspect(&mut self, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            if self.remaining == 0 {
+                let n = (RECORD_HEADER_LEN - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+                self.header_len += n;
+                data = &data[n..];
+                if self.header_len < RECORD_HEADER_LEN {
+                    return Ok(());
+                }
+                self.header_len = 0;
+                self.remaining = u16::from_be_bytes([self.header[3], self.header[4]]) as usize;
+                self.in_handshake_record = self.header[0] == CONTENT_TYPE_HANDSHAKE;
+                if self.in_handshake_record && self.established {
+                    warn!("Suspicious TLS renegotiation attempt from {}, closing the connection", self.peer);
+                    metrics().tls_renegotiation_rejected_total.fetch_add(1, Ordering::Relaxed);
+                    return Err(Error::new(ErrorKind::PermissionDenied, "TLS renegotiation is not allowed"));
+                }
+                continue;
+            }
+
+            let n = self.remaining.min(data.len());
+            if self.in_handshake_record
+                && let Some(client_hello) = &mut self.client_hello
+            {
+                client_hello.extend_from_slice(&data[..n]);
+            }
+            self.remaining -= n;
+            data = &data[n..];
+            if self.remaining == 0 && self.in_handshake_record {
+                self.check_client_hello()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// ClientHello 收集完整后检查一次, 消息跨多个记录时等待后续记录
+    fn check_client_hello(&mut self) -> std::io::Result<()> {
+        let Some(client_hello) = &self.client_hello else {
+            return Ok(());
+        };
+        if client_hello.len() >= 4 {
+            let message_len = u32::from_be_bytes([0, client_hello[1], client_hello[2], client_hello[3]]) as usize;
+            if client_hello.len() < 4 + message_len && client_hello.len() < MAX_CLIENT_HELLO_LEN {
+                return Ok(());
+            }
+        }
+        let offers_early_data = client_hello_extensions(client_hello)
+            .is_some_and(|extensions| extensions.contains(&EXTENSION_EARLY_DATA));
+        self.client_hello = None;
+        if offers_early_data && self.reject_early_data {
+            warn!("Suspicious TLS 1.3 early data (0-RTT) offered by {}, closing the connection", self.peer);
+            metrics().tls_early_data_rejected_total.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::new(ErrorKind::PermissionDenied, "TLS early data is not allowed"));
+        }
+        Ok(())
+    }
+}
+
+/// ClientHello 中的扩展类型, 消息被截断或不是 ClientHello 时返回 None
+fn client_hello_extensions(message: &[u8]) -> Option<Vec<u16>> {
+    // 握手消息类型 1 = ClientHello
+    if *message.first()? != 1 {
+        return None;
+    }
+    // 消息头 (4) + 版本 (2) + 随机数 (32)
+    let mut pos = 4 + 2 + 32;
+    let session_id_len = *message.get(pos)? as usize;
+    pos += 1 + session_id_len;
+    let cipher_suites_len = u16::from_be_bytes([*message.get(pos)?, *message.get(pos + 1)?]) as usize;
+    pos += 2 + cipher_suites_len;
+    let compression_len = *message.get(pos)? as usize;
+    pos += 1 + compression_len;
+    let extensions_len = u16::from_be_bytes([*message.get(pos)?, *message.get(pos + 1)?]) as usize;
+    pos += 2;
+    let extensions = message.get(pos..pos + extensions_len)?;
+
+    let mut types = Vec::new();
+    let mut pos = 0;
+    while pos + 4 <= extensions.len() {
+        types.push(u16::from_be_bytes([extensions[pos], extensions[pos + 1]]));
+        pos += 4 + u16::from_be_bytes([extensions[pos + 2], extensions[pos + 3]]) as usize;
+    }
+    Some(types)
+}
+
+impl AsyncRead for TlsRecordGuard {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.inspect(&buf.filled()[before..])?;
+        }
+        result
+    }
+}
+
+impl AsyncWrite for TlsRecordGuard {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,117 @@
+// 按主题前缀选择后端 (延迟连接)
+// CONNECT 不带主题, 因此适配器先代替 broker 接受连接, 缓存客户端的包直到第一个带主题的包,
+// 按主题选择后端后再连接并重放
+// 适配器代替 broker 应答的 CONNACK 不能反映后端上的会话, 因此只接受不保留会话的连接 (smart_adapter 中检查)
+
+use log::debug;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::adapter_config::TopicRoutingConfig;
+use crate::codec::{encode_packet, read_remaining_length, read_string};
+use crate::properties::Properties;
+use crate::tap::packet_type;
+
+/// 适配器代替 broker 应答的 PINGRESP
+const PINGRESP: [u8; 2] = [0xD0, 0x00];
+
+/// 主题前缀路由表
+pub struct TopicRouter {
+    /// (前缀, 后端地址), 按前缀长度降序, 取最长匹配
+    routes: Vec<(String, String)>,
+    max_buffer_bytes: usize,
+    max_wait: Duration,
+}
+
+/// 延迟连接的结果
+pub struct DeferredConnect {
+    /// 第一个带主题的包中的主题 (或订阅过滤器)
+    pub topic: String,
+    /// 选择后端之前收到的全部包 (原始字节), 需要在 CONNECT 之后重放
+    pub buffered: Vec<u8>,
+}
+
+impl TopicRouter {
+    pub fn new(config: &TopicRoutingConfig) -> Self {
+        let mut routes: Vec<_> = config.backends.iter()
+            .map(|(prefix, backend)| (prefix.clone(), backend.clone()))
+            .collect();
+        routes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        TopicRouter {
+            routes,
+            max_buffer_bytes: config.max_buffer_bytes,
+            max_wait: Duration::from_millis(config.max_wait_ms),
+        }
+    }
+
+    /// 按最长前缀匹配选择后端, 没有匹配时返回 None (使用默认后端)
+    pub fn route(&self, topic: &str) -> Option<&str> {
+        self.routes.iter()
+            .find(|(prefix, _)| topic.starts_with(prefix.as_str()))
+            .map(|(_, backend)| backend.as_str())
+    }
+
+    /// 缓存客户端的包直到第一个 PUBLISH/SUBSCRIBE
+    /// 期间的 PINGREQ 由适配器直接应答; 客户端发送 DISCONNECT 时返回 None。
+    /// 超过缓存上限或等待时间时返回错误, 连接应当关闭
+    pub async fn wait_for_topic<S>(&self, client_stream: &mut S, protocol_level: u8) -> std::io::Result<Option<DeferredConnect>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let wait = async {
+            let mut buffered = Vec::new();
+            loop {
+                let header = client_stream.read_u8().await?;
+                let length = read_remaining_length(client_stream).await?;
+                if buffered.len() + length > self.max_buffer_bytes {
+                    return Err(std::io::Error::other(format!(
+                        "more than {} bytes buffered before the first topic",
+                        self.max_buffer_bytes
+                    )));
+                }
+                let mut body = vec![0u8; length];
+                client_stream.read_exact(&mut body).await?;
+
+                match header >> 4 {
+                    packet_type::PINGREQ => {
+                        client_stream.write_all(&PINGRESP).await?;
+                        continue;
+                    }
+                    packet_type::DISCONNECT => return Ok(None),
+                    _ => {}
+                }
+
+                buffered.extend_from_slice(&encode_packet(header, &body));
+                if let Some(topic) = first_topic(header >> 4, &body, protocol_level)? {
+                    debug!("Deferred connect: first topic {:?} after {} buffered bytes", topic, buffered.len());
+                    return Ok(Some(DeferredConnect { topic, buffered }));
+                }
+            }
+        };
+
+        tokio::time::timeout(self.max_wait, wait).await.unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("no PUBLISH or SUBSCRIBE within {:?}", self.max_wait),
+            ))
+        })
+    }
+}
+
+/// PUBLISH 的主题或 SUBSCRIBE 的第一个订阅过滤器, 其它包返回 None
+fn first_topic(kind: u8, body: &[u8], protocol_level: u8) -> std::io::Result<Option<String>> {
+    let mut pos = 0;
+    match kind {
+        packet_type::PUBLISH => Ok(Some(read_string(body, &mut pos)?)),
+        packet_type::SUBSCRIBE => {
+            // 报文标识符, 5.0 还有属性
+            pos += 2;
+            if protocol_level == 5 {
+                Properties::decode(body, &mut pos)?;
+            }
+            Ok(Some(read_string(body, &mut pos)?))
+        }
+        _ => Ok(None),
+    }
+}
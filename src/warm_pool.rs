@@ -0,0 +1,201 @@
+// 后端预热连接池
+// 预先建立到默认后端的 TCP 连接, 新客户端直接取用; 空闲过久或已被关闭的连接在交给客户端前丢弃
+
+use log::debug;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::adapter_config::WarmPoolConfig;
+use crate::happy_eyeballs;
+use crate::metrics::metrics;
+use crate::source_bind::SourceBind;
+
+/// 预热连接的建立时限
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// 预热失败后的重试间隔
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// 预热连接池
+pub struct WarmPool {
+    backend: String,
+    source: Option<Arc<SourceBind>>,
+    size: usize,
+    max_idle: Duration,
+    /// 按建立时间排序, 队首最老
+    idle: Mutex<VecDeque<PooledConnection>>,
+    /// 取走连接后唤醒补充任务
+    refill: Notify,
+}
+
+struct PooledConnection {
+    stream: TcpStream,
+    connected_at: Instant,
+}
+
+impl WarmPool {
+    pub fn new(backend: String, source: Option<Arc<SourceBind>>, config: &WarmPoolConfig) -> Self {
+        WarmPool {
+            backend,
+            source,
+            size: config.size,
+            max_idle: Duration::from_millis(config.max_idle_ms),
+            idle: Mutex::new(VecDeque::new()),
+            refill: Notify::new(),
+        }
+    }
+
+    /// 池中连接的后端地址
+    pub fn backend(&self) -> &str {
+        &self.backend
+    }
+
+    /// 取出一个可用连接, 池为空时返回 None (调用方自行连接)
+    pub fn take(&self) -> Option<TcpStream> {
+        let stream = {
+            let mut idle = self.idle.lock().unwrap();
+            loop {
+                let connection = idle.pop_front()?;
+                if connection.connected_at.elapsed() >= self.max_idle {
+                    debug!("Discarding pooled backend connection idle for {:?}", connection.connected_at.elapsed());
+                } else if !is_alive(&connection.stream) {
+                    debug!("Discarding pooled backend connection closed by the backend");
+                } else {
+                    break connection.stream;
+                }
+                metrics().warm_pool_discarded_total.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        metrics().warm_pool_hits_total.fetch_add(1, Ordering::Relaxed);
+        self.refill.notify_one();
+        Some(stream)
+    }
+
+    /// 补充任务: 保持池中有 `size` 个连接, 并在连接到达空闲上限时替换
+    pub async fn run_refill(self: Arc<Self>) {
+        loop {
+            self.evict_stale();
+
+            let mut retry = false;
+            while self.idle.lock().unwrap().len() < self.size {
+                match tokio::time::timeout(CONNECT_TIMEOUT, happy_eyeballs::connect(&self.backend, self.source.as_ref())).await {
+                    Ok(Ok(stream)) => {
+                        let connection = PooledConnection { stream, connected_at: Instant::now() };
+                        self.idle.lock().unwrap().push_back(connection);
+                    }
+                    Ok(Err(e)) => {
+                        debug!("Failed to pre-warm backend connection to {}: {}", self.backend, e);
+                        retry = true;
+                        break;
+                    }
+                    Err(_) => {
+                        debug!("Timed out pre-warming backend connection to {}", self.backend);
+                        retry = true;
+                        break;
+                    }
+                }
+            }
+
+            let oldest = self.idle.lock().unwrap().front().map(|connection| connection.connected_at);
+            let mut wake_at = oldest.unwrap_or_else(Instant::now) + self.max_idle;
+            if retry {
+                wake_at = wake_at.min(Instant::now() + RETRY_DELAY);
+            }
+            tokio::select! {
+                _ = self.refill.notified() => {}
+                _ = tokio::time::sleep_until(wake_at) => {}
+            }
+        }
+    }
+
+    /// 丢弃到达空闲上限或已被关闭的连接
+    fn evict_stale(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        let before = idle.len();
+        idle.retain(|connection| connection.connected_at.elapsed() < self.max_idle && is_alive(&connection.stream));
+        let evicted = before - idle.len();
+        if evicted > 0 {
+            metrics().warm_pool_discarded_total.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 非阻塞检查连接是否仍然打开
+/// broker 在收到 CONNECT 之前不会发送数据, 连接可读说明已被关闭 (EOF/RST) 或状态异常
+fn is_alive(stream: &TcpStream) -> bool {
+    let mut probe = [0u8; 1];
+    matches!(stream.try_read(&mut probe), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn backend() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        (listener, address)
+    }
+
+    fn pool(backend: &str, size: usize, max_idle_ms: u64) -> WarmPool {
+        WarmPool::new(backend.to_string(), None, &WarmPoolConfig { size, max_idle_ms })
+    }
+
+    /// 直接放入一个指定年龄的连接, 返回其本地地址和后端侧的连接
+    async fn push(pool: &WarmPool, listener: &TcpListener, age: Duration) -> (std::net::SocketAddr, TcpStream) {
+        let stream = TcpStream::connect(pool.backend()).await.unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        let local = stream.local_addr().unwrap();
+        let connected_at = Instant::now().checked_sub(age).unwrap();
+        pool.idle.lock().unwrap().push_back(PooledConnection { stream, connected_at });
+        (local, accepted)
+    }
+
+    #[tokio::test]
+    async fn take_discards_aged_out_connection() {
+        let (listener, address) = backend().await;
+        let pool = pool(&address, 2, 1_000);
+        let (_aged, _aged_peer) = push(&pool, &listener, Duration::from_secs(5)).await;
+        let (fresh, _fresh_peer) = push(&pool, &listener, Duration::ZERO).await;
+
+        let taken = pool.take().expect("fresh connection should be handed out");
+        assert_eq!(taken.local_addr().unwrap(), fresh);
+        assert!(pool.take().is_none());
+    }
+
+    #[tokio::test]
+    async fn take_discards_connection_closed_by_backend() {
+        let (listener, address) = backend().await;
+        let pool = pool(&address, 1, 60_000);
+        let (_, peer) = push(&pool, &listener, Duration::ZERO).await;
+        drop(peer);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(pool.take().is_none());
+        assert!(pool.idle.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refill_replaces_aged_out_connection() {
+        let (listener, address) = backend().await;
+        let pool = Arc::new(pool(&address, 1, 200));
+        let refill = tokio::spawn(pool.clone().run_refill());
+
+        // 第一次预热
+        let (_first_peer, first) = tokio::time::timeout(Duration::from_secs(2), listener.accept()).await.unwrap().unwrap();
+        // 到达空闲上限后补充任务丢弃旧连接并重新连接
+        let (_second_peer, second) = tokio::time::timeout(Duration::from_secs(2), listener.accept()).await.unwrap().unwrap();
+        assert_ne!(first, second);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let taken = pool.take().expect("replacement connection should be pooled");
+        assert_eq!(taken.local_addr().unwrap(), second);
+        refill.abort();
+    }
+}
@@ -0,0 +1,142 @@
+// WebSocket 上的 MQTT
+// 完成 HTTP 升级握手后把二进制消息还原为字节流, 之后与 TCP 连接一样读取 CONNECT 并转发
+// 不支持任何扩展: 客户端请求的 permessage-deflate 等扩展在握手响应中不予确认, 客户端随之使用未压缩的帧
+
+use async_tungstenite::tokio::{TokioAdapter, accept_hdr_async};
+use async_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use async_tungstenite::tungstenite::http::HeaderValue;
+use log::debug;
+use tokio::io::{AsyncRead, AsyncWrite};
+use ws_stream_tungstenite::WsStream;
+
+/// 接受的 MQTT 子协议 (mqtt.js 等客户端会要求服务端回显)
+const SUBPROTOCOLS: [&str; 2] = ["mqtt", "mqttv3.1"];
+
+/// 解包后的 WebSocket 连接, 读写的都是 MQTT 字节流
+pub type WebSocketStream<S> = WsStream<TokioAdapter<S>>;
+
+/// 在 `stream` 上完成 WebSocket 升级握手 (HTTP 请求尚未被读取)
+pub async fn accept<S>(stream: S) -> std::io::Result<WebSocketStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    accept_hdr_async(stream, NegotiateSubprotocol)
+        .await
+        .map(WsStream::new)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("WebSocket handshake failed: {}", e)))
+}
+
+/// 回显客户端请求的第一个 MQTT 子协议 (客户端没有请求时不返回该头), 拒绝所有扩展
+struct NegotiateSubprotocol;
+
+impl Callback for NegotiateSubprotocol {
+    fn on_request(self, request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+        let offered = request
+            .headers()
+            .get_all("sec-websocket-protocol")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .find_map(|offered| SUBPROTOCOLS.into_iter().find(|protocol| *protocol == offered));
+        if let Some(protocol) = offered {
+            response.headers_mut().insert("sec-websocket-protocol", HeaderValue::from_static(protocol));
+        }
+
+        // 响应中没有 Sec-WebSocket-Extensions 即拒绝了客户端提议的扩展 (RFC 6455 9.1),
+        // 之后设置了 RSV1 (压缩) 的帧按协议错误关闭连接
+        let extensions: Vec<_> = request
+            .headers()
+            .get_all("sec-websocket-extensions")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if !extensions.is_empty() {
+            debug!("Declining WebSocket extensions {:?}, frames stay uncompressed", extensions);
+        }
+        response.headers_mut().remove("sec-websocket-extensions");
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::task::JoinHandle;
+
+    /// 请求 mqtt 子协议和 permessage-deflate 的升级请求
+    const UPGRADE: &str = "GET /mqtt HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\
+        Sec-WebSocket-Protocol: mqtt\r\n\
+        Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\
+        \r\n";
+
+    /// 发送升级请求, 返回响应头和服务端任务 (握手后读出第一块数据)
+    async fn handshake(request: &str) -> (DuplexStream, String, JoinHandle<std::io::Result<Vec<u8>>>) {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = accept(server).await?;
+            let mut buf = [0u8; 64];
+            let n = stream.read(&mut buf).await?;
+            Ok(buf[..n].to_vec())
+        });
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") && client.read(&mut byte).await.unwrap() == 1 {
+            response.push(byte[0]);
+        }
+        (client, String::from_utf8(response).unwrap().to_ascii_lowercase(), server)
+    }
+
+    /// 客户端发出的带掩码 (全零) 的帧, `first_byte` 为 FIN、RSV 和操作码
+    fn masked_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first_byte, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn declines_permessage_deflate() {
+        let (mut client, response, server) = handshake(UPGRADE).await;
+        assert!(response.starts_with("http/1.1 101"), "{}", response);
+        assert!(response.contains("sec-websocket-protocol: mqtt\r\n"), "{}", response);
+        assert!(!response.contains("sec-websocket-extensions"), "{}", response);
+
+        // 未压缩的二进制帧照常解包
+        client.write_all(&masked_frame(0x82, &[0x10, 0x00])).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(2), server).await.unwrap().unwrap().unwrap();
+        assert_eq!(received, [0x10, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn compressed_frame_after_the_decline_is_rejected() {
+        let (mut client, response, server) = handshake(UPGRADE).await;
+        assert!(!response.contains("sec-websocket-extensions"), "{}", response);
+
+        // RSV1 (permessage-deflate 的压缩标志) 未经协商, 按协议错误处理
+        client.write_all(&masked_frame(0xC2, &[0x10, 0x00])).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(2), server).await.unwrap().unwrap();
+        assert!(received.unwrap().is_empty(), "no data is delivered from the compressed frame");
+        // 服务端以 1002 (协议错误) 关闭 WebSocket 连接
+        let mut close = Vec::new();
+        tokio::time::timeout(Duration::from_secs(2), client.read_to_end(&mut close)).await.unwrap().unwrap();
+        assert_eq!(close[0], 0x88, "{:02x?}", close);
+        assert_eq!(close[2..4], 1002u16.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn request_without_a_key_is_rejected() {
+        let request = UPGRADE.replace("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n", "");
+        let (_client, response, server) = handshake(&request).await;
+        assert!(!response.starts_with("http/1.1 101"), "{}", response);
+        let error = tokio::time::timeout(Duration::from_secs(2), server).await.unwrap().unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,160 @@
+// 按权重选择后端
+// 每个新连接按权重随机选择一个后端 (灰度发布: 例如 10% 的连接发往新版本); 可选按源 IP 固定, 同一地址总是连接同一后端
+
+use rand::Rng;
+use std::net::IpAddr;
+
+use crate::adapter_config::WeightedRoutingConfig;
+use crate::source_ip_routing::{address_key, score};
+
+/// 权重路由表
+pub struct WeightedRouter {
+    /// (后端地址, 权重), 权重为 0 的后端不会被选中
+    backends: Vec<(String, u32)>,
+    total: u64,
+    sticky: bool,
+}
+
+impl WeightedRouter {
+    /// 权重和为 0 时返回错误; 没有配置后端时返回 None (使用默认后端)
+    pub fn from_config(config: &WeightedRoutingConfig) -> std::io::Result<Option<Self>> {
+        if config.backends.is_empty() {
+            return Ok(None);
+        }
+        let backends: Vec<(String, u32)> = config.backends.iter()
+            .map(|backend| (backend.address.clone(), backend.weight))
+            .collect();
+        let total = backends.iter().map(|(_, weight)| u64::from(*weight)).sum();
+        if total == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "weighted_routing: the sum of backend weights must be greater than 0",
+            ));
+        }
+        Ok(Some(WeightedRouter { backends, total, sticky: config.sticky }))
+    }
+
+    /// 为新连接选择后端
+    pub fn route(&self, ip: IpAddr) -> &str {
+        if self.sticky {
+            return self.route_sticky(ip);
+        }
+        self.route_random(&mut rand::thread_rng())
+    }
+
+    /// 按权重随机选择, 随机数源由调用方提供
+    fn route_random(&self, rng: &mut impl Rng) -> &str {
+        let mut pick = rng.gen_range(0..self.total);
+        for (backend, weight) in &self.backends {
+            if pick < u64::from(*weight) {
+                return backend;
+            }
+            pick -= u64::from(*weight);
+        }
+        unreachable!("pick is below the sum of weights")
+    }
+
+    /// 加权 rendezvous 哈希: 每个后端按 -权重 / ln(u) 打分 (u 由 (IP, 后端地址) 的哈希映射到 (0, 1)), 取最高分;
+    /// 各后端被选中的比例等于权重占比, 调整一个后端的权重只会迁移必要的地址
+    fn route_sticky(&self, ip: IpAddr) -> &str {
+        let key = address_key(ip);
+        self.backends
+            .iter()
+            .filter(|(_, weight)| *weight > 0)
+            .map(|(backend, weight)| {
+                let unit = ((score(&key, backend) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+                (backend, -f64::from(*weight) / unit.ln())
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(backend, _)| backend.as_str())
+            .expect("at least one backend has a non-zero weight")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter_config::WeightedBackend;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    const BACKENDS: [(&str, u32); 4] = [("10.0.0.1:1883", 70), ("10.0.0.2:1883", 20), ("10.0.0.3:1883", 0), ("10.0.0.4:1883", 10)];
+
+    fn router(sticky: bool) -> WeightedRouter {
+        let backends = BACKENDS.iter()
+            .map(|(address, weight)| WeightedBackend { address: address.to_string(), weight: *weight })
+            .collect();
+        WeightedRouter::from_config(&WeightedRoutingConfig { backends, sticky }).unwrap().unwrap()
+    }
+
+    /// 各后端被选中的比例与权重占比相差不超过 `tolerance`, 权重为 0 的后端从不被选中
+    fn assert_matches_weights(counts: &HashMap<&str, usize>, samples: usize, tolerance: f64) {
+        let total: u32 = BACKENDS.iter().map(|(_, weight)| weight).sum();
+        for (address, weight) in BACKENDS {
+            let count = counts.get(address).copied().unwrap_or(0);
+            if weight == 0 {
+                assert_eq!(count, 0, "{} has weight 0", address);
+                continue;
+            }
+            let share = count as f64 / samples as f64;
+            let expected = f64::from(weight) / f64::from(total);
+            assert!((share - expected).abs() < tolerance, "{}: share {:.3}, expected {:.3}", address, share, expected);
+        }
+    }
+
+    #[test]
+    fn random_picks_follow_the_weights() {
+        let router = router(false);
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let samples = 100_000;
+        let mut counts = HashMap::new();
+        for _ in 0..samples {
+            *counts.entry(router.route_random(&mut rng)).or_insert(0) += 1;
+        }
+        // 10 万次抽样的标准差不超过 0.0015
+        assert_matches_weights(&counts, samples, 0.01);
+    }
+
+    #[test]
+    fn sticky_picks_follow_the_weights() {
+        let router = router(true);
+        let samples = 20_000;
+        let mut counts = HashMap::new();
+        for i in 0..samples as u32 {
+            *counts.entry(router.route(IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i)))).or_insert(0) += 1;
+        }
+        assert_matches_weights(&counts, samples, 0.02);
+    }
+
+    #[test]
+    fn sticky_routing_is_stable_per_address() {
+        let router = router(true);
+        for i in 0..100u32 {
+            let ip = IpAddr::V4(Ipv4Addr::from(0xc000_0200 + i));
+            assert_eq!(router.route(ip), router.route(ip));
+        }
+    }
+
+    #[test]
+    fn only_backend_with_weight_is_always_picked() {
+        let backends = vec![
+            WeightedBackend { address: "10.0.0.1:1883".to_string(), weight: 0 },
+            WeightedBackend { address: "10.0.0.2:1883".to_string(), weight: 5 },
+        ];
+        for sticky in [false, true] {
+            let router = WeightedRouter::from_config(&WeightedRoutingConfig { backends: backends.clone(), sticky }).unwrap().unwrap();
+            for i in 0..1_000u32 {
+                assert_eq!(router.route(IpAddr::V4(Ipv4Addr::from(i))), "10.0.0.2:1883");
+            }
+        }
+    }
+
+    #[test]
+    fn zero_total_weight_is_rejected() {
+        let backends = vec![WeightedBackend { address: "10.0.0.1:1883".to_string(), weight: 0 }];
+        assert!(WeightedRouter::from_config(&WeightedRoutingConfig { backends, sticky: false }).is_err());
+        assert!(WeightedRouter::from_config(&WeightedRoutingConfig { backends: Vec::new(), sticky: false }).unwrap().is_none());
+    }
+}
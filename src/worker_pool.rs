@@ -0,0 +1,53 @@
+// 固定大小的连接工作池
+// 启用 worker_pool_size 时, accept 循环把连接放入有界队列, 由固定数量的任务依次处理, 代替每个连接一个任务
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::metrics::metrics;
+
+/// 一个连接的完整处理过程
+pub type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// 工作池
+/// 每个工作任务一次处理一个连接直到结束, 因此同时处理的连接数不超过 `size`;
+/// 队列 (容量同为 `size`) 满时 `submit` 等待, accept 循环随之暂停, 新连接留在内核 backlog 中
+pub struct WorkerPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl WorkerPool {
+    /// 创建工作池并启动 `size` 个工作任务
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        let (jobs, receiver) = mpsc::channel(size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..size {
+            tokio::spawn(run_worker(receiver.clone()));
+        }
+        WorkerPool { jobs }
+    }
+
+    /// 把连接放入队列, 队列满时等待
+    pub async fn submit(&self, job: Job) {
+        metrics().worker_pool_queued.fetch_add(1, Ordering::Relaxed);
+        // 工作任务与进程同生命周期, 发送不会失败
+        let _ = self.jobs.send(job).await;
+    }
+}
+
+async fn run_worker(receiver: Arc<Mutex<mpsc::Receiver<Job>>>) {
+    loop {
+        // 只在取任务时持有锁, 处理连接时其它工作任务可以继续取
+        let Some(job) = receiver.lock().await.recv().await else {
+            return;
+        };
+        metrics().worker_pool_queued.fetch_sub(1, Ordering::Relaxed);
+        metrics().worker_pool_busy.fetch_add(1, Ordering::Relaxed);
+        job.await;
+        metrics().worker_pool_busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}